@@ -0,0 +1,62 @@
+use anyhow::Result;
+use std::fmt::Debug;
+
+/// Arithmetic over a finite field of characteristic 2, as needed by the
+/// matrix code and [`crate::codec::reconstruct_shards::Codec`].
+///
+/// Shards are sequences of `Elem`, so a wider field gives both more shards
+/// (up to [`GaloisField::ORDER`]) and wider symbols.
+pub trait GaloisField: Send + Sync {
+    /// Field element; `Default` must be zero.
+    type Elem: Copy + Eq + Default + Debug + Send + Sync + 'static;
+
+    /// Number of elements; also the maximum number of shards `k + m`.
+    const ORDER: usize;
+    const ZERO: Self::Elem;
+    const ONE: Self::Elem;
+
+    /// The element whose integer representation is `i`, for `i < ORDER`.
+    fn element(i: usize) -> Self::Elem;
+
+    /// Addition, which is XOR in characteristic 2.
+    fn add(&self, a: Self::Elem, b: Self::Elem) -> Self::Elem;
+
+    fn mul(&self, a: Self::Elem, b: Self::Elem) -> Self::Elem;
+
+    fn inv(&self, a: Self::Elem) -> Result<Self::Elem>;
+
+    /// `g^power` for the field's primitive element `g`.
+    fn exp(&self, power: usize) -> Self::Elem;
+
+    /// `dst[i] += coef * src[i]` over the shorter of the two slices.
+    ///
+    /// This is the inner loop of encoding and reconstruction; fields with
+    /// lookup tables should override it.
+    fn mul_acc(&self, coef: Self::Elem, src: &[Self::Elem], dst: &mut [Self::Elem]) {
+        if coef == Self::ZERO {
+            return;
+        }
+        for (d, &s) in dst.iter_mut().zip(src) {
+            *d = self.add(*d, self.mul(coef, s));
+        }
+    }
+
+    /// Appends, for each `width`-symbol column of `columns`, the sum of
+    /// `coef * column[j]` over the `(j, coef)` terms.
+    ///
+    /// This is the inner loop of the interleaved reconstruction layout; fields
+    /// with lookup tables should override it.
+    fn dot_columns(
+        &self,
+        terms: &[(usize, Self::Elem)],
+        columns: &[Self::Elem],
+        width: usize,
+        out: &mut Vec<Self::Elem>,
+    ) {
+        out.extend(columns.chunks_exact(width).map(|column| {
+            terms.iter().fold(Self::ZERO, |acc, &(j, coef)| {
+                self.add(acc, self.mul(coef, column[j]))
+            })
+        }));
+    }
+}
@@ -1,29 +1,55 @@
 use anyhow::{Result, anyhow};
+use std::sync::OnceLock;
 
+use crate::algorithm::field::GaloisField;
+
+/// Log/antilog tables for GF(2^8) with the primitive polynomial `0x11d`.
+///
+/// Invariants relied upon by [`Gf256::mul`], [`Gf256::inv`] and
+/// [`Gf256::mul_table`]:
+/// - `log[x]` is in `0..255` for every nonzero `x` (only `log[0]` is `-1`);
+/// - `exp` has 512 entries, so `exp[log[a] + log[b]]` is in bounds for any
+///   two nonzero operands without reducing modulo 255.
+///
+/// These hold for tables built by [`Gf256::new`]. A table built from a
+/// non-primitive polynomial leaves `-1` sentinels behind, which debug builds
+/// catch with an assertion instead of silently reading the wrong entry.
 #[derive(Debug, Clone)]
 pub struct Gf256 {
     pub exp: Vec<u8>,
     pub log: Vec<i16>,
+    /// Every multiplication table, built on first use by [`Gf256::mul_tables_all`].
+    /// Derived from `exp`/`log` at that point; later edits to them are not seen.
+    mul_tables: OnceLock<Box<[[u8; 256]; 256]>>,
 }
 
 impl Gf256 {
+    /// Number of elements in the field; also the maximum number of shards `k + m`.
+    pub const FIELD_SIZE: usize = 256;
+    /// Primitive polynomial the tables are generated from.
+    pub const POLYNOMIAL: u16 = 0x11d;
+
     pub fn new() -> Self {
         let mut exp = vec![0u8; 512];
         let mut log = vec![-1i16; 256];
         let mut x: u16 = 1;
 
-        for i in 0..255 {
-            exp[i] = x as u8;
+        for (i, e) in exp.iter_mut().enumerate().take(255) {
+            *e = x as u8;
             log[x as usize] = i as i16;
             x <<= 1;
             if x & 0x100 != 0 {
-                x ^= 0x11d;
+                x ^= Self::POLYNOMIAL;
             }
         }
         for i in 255..512 {
             exp[i] = exp[i - 255];
         }
-        Gf256 { exp, log }
+        Gf256 {
+            exp,
+            log,
+            mul_tables: OnceLock::new(),
+        }
     }
 
     pub fn mul_table(&self, factor: u8) -> [u8; 256] {
@@ -31,24 +57,43 @@ impl Gf256 {
         if factor == 0 {
             return table;
         }
-        let log_factor = self.log[factor as usize] as i32;
+        let log_factor = self.checked_log(factor);
         for i in 0..=255 {
             if i > 0 {
-                let log_i = self.log[i as usize] as i32;
-                table[i as usize] = self.exp[(log_i + log_factor) as usize];
+                let log_i = self.checked_log(i as u8);
+                table[i as usize] = self.exp_at(log_i + log_factor);
             }
         }
         table
     }
 
+    /// All 256 multiplication tables, indexed by factor. Built once on first
+    /// call and kept for the lifetime of `self`, at a cost of 64 KiB.
+    pub fn mul_tables_all(&self) -> &[[u8; 256]; 256] {
+        self.mul_tables.get_or_init(|| {
+            let mut tables = Box::new([[0u8; 256]; 256]);
+            for (factor, table) in tables.iter_mut().enumerate() {
+                *table = self.mul_table(factor as u8);
+            }
+            tables
+        })
+    }
+
+    /// Borrowing counterpart of [`Gf256::mul_table`] backed by
+    /// [`Gf256::mul_tables_all`], so repeated coefficients never rebuild a table.
+    #[inline]
+    pub fn mul_table_ref(&self, factor: u8) -> &[u8; 256] {
+        &self.mul_tables_all()[factor as usize]
+    }
+
     #[inline]
     pub fn mul(&self, a: u8, b: u8) -> u8 {
         if a == 0 || b == 0 {
             0
         } else {
-            let la = self.log[a as usize] as i32;
-            let lb = self.log[b as usize] as i32;
-            self.exp[(la + lb) as usize]
+            let la = self.checked_log(a);
+            let lb = self.checked_log(b);
+            self.exp_at(la + lb)
         }
     }
 
@@ -57,8 +102,137 @@ impl Gf256 {
         if a == 0 {
             return Err(anyhow!("inverse of zero is undefined"));
         }
+        let la = self.checked_log(a);
+        Ok(self.exp_at(255 - la))
+    }
+
+    /// Inverses of all of `elems`, using Montgomery's trick: one inversion
+    /// of the running product plus three multiplications per element.
+    /// Fails if any element is zero.
+    pub fn batch_inv(&self, elems: &[u8]) -> Result<Vec<u8>> {
+        if let Some(i) = elems.iter().position(|&a| a == 0) {
+            return Err(anyhow!("inverse of zero is undefined (element {})", i));
+        }
+        // prefix[i] is the product of elems[..i].
+        let mut prefix = Vec::with_capacity(elems.len());
+        let mut acc = 1u8;
+        for &a in elems {
+            prefix.push(acc);
+            acc = self.mul(acc, a);
+        }
+        // Walk back, peeling one element off the inverted product each step.
+        let mut acc_inv = self.inv(acc)?;
+        let mut out = vec![0u8; elems.len()];
+        for i in (0..elems.len()).rev() {
+            out[i] = self.mul(acc_inv, prefix[i]);
+            acc_inv = self.mul(acc_inv, elems[i]);
+        }
+        Ok(out)
+    }
+
+    /// Looks up `log[a]` for a nonzero `a`, asserting in debug builds that the
+    /// table actually has an entry for it.
+    #[inline]
+    fn checked_log(&self, a: u8) -> i32 {
         let la = self.log[a as usize] as i32;
-        Ok(self.exp[(255 - la) as usize])
+        debug_assert!(
+            (0..255).contains(&la),
+            "log table has no valid entry for nonzero element {a:#04x} (got {la})"
+        );
+        la
+    }
+
+    #[inline]
+    fn exp_at(&self, index: i32) -> u8 {
+        debug_assert!(
+            index >= 0 && (index as usize) < self.exp.len(),
+            "exp table index {index} out of bounds (len {})",
+            self.exp.len()
+        );
+        self.exp[index as usize]
+    }
+}
+
+/// Adds `src` into `dst` in GF(2^8), i.e. `dst[i] ^= src[i]`, over the
+/// shorter of the two lengths.
+///
+/// Works a `u64` word at a time so the loop vectorizes the same way in every
+/// caller, rather than depending on how LLVM treats each byte loop.
+pub fn xor_slice(dst: &mut [u8], src: &[u8]) {
+    let len = dst.len().min(src.len());
+    let (dst, src) = (&mut dst[..len], &src[..len]);
+    let mut dst_words = dst.chunks_exact_mut(8);
+    let mut src_words = src.chunks_exact(8);
+    for (d, s) in (&mut dst_words).zip(&mut src_words) {
+        let x = u64::from_ne_bytes((&*d).try_into().unwrap())
+            ^ u64::from_ne_bytes(s.try_into().unwrap());
+        d.copy_from_slice(&x.to_ne_bytes());
+    }
+    for (d, &s) in dst_words
+        .into_remainder()
+        .iter_mut()
+        .zip(src_words.remainder())
+    {
+        *d ^= s;
+    }
+}
+
+impl GaloisField for Gf256 {
+    type Elem = u8;
+
+    const ORDER: usize = Gf256::FIELD_SIZE;
+    const ZERO: u8 = 0;
+    const ONE: u8 = 1;
+
+    fn element(i: usize) -> u8 {
+        debug_assert!(i < Self::ORDER, "{i} is not an element of GF(2^8)");
+        i as u8
+    }
+
+    #[inline]
+    fn add(&self, a: u8, b: u8) -> u8 {
+        a ^ b
+    }
+
+    #[inline]
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        Gf256::mul(self, a, b)
+    }
+
+    fn inv(&self, a: u8) -> Result<u8> {
+        Gf256::inv(self, a)
+    }
+
+    fn exp(&self, power: usize) -> u8 {
+        self.exp[power % 255]
+    }
+
+    fn mul_acc(&self, coef: u8, src: &[u8], dst: &mut [u8]) {
+        match coef {
+            0 => {}
+            1 => xor_slice(dst, src),
+            _ => {
+                let mult_table = self.mul_table_ref(coef);
+                for (d, &s) in dst.iter_mut().zip(src) {
+                    *d ^= mult_table[s as usize];
+                }
+            }
+        }
+    }
+
+    fn dot_columns(&self, terms: &[(usize, u8)], columns: &[u8], width: usize, out: &mut Vec<u8>) {
+        // One table per column position, the zero table for those without a
+        // term, so the fold zips without bounds checks.
+        let mut tables = vec![self.mul_table_ref(0); width];
+        for &(j, coef) in terms {
+            tables[j] = self.mul_table_ref(coef);
+        }
+        out.extend(columns.chunks_exact(width).map(|column| {
+            column
+                .iter()
+                .zip(&tables)
+                .fold(0u8, |acc, (&symbol, table)| acc ^ table[symbol as usize])
+        }));
     }
 }
 
@@ -0,0 +1,215 @@
+//! Linear algebra over a Galois field: products, inversion and the small
+//! helpers around them, on row-major [`Matrix`] values.
+//!
+//! Arithmetic takes the field as `gf`, like the codec does, so the same
+//! routines serve GF(2^8) and wider fields. Helpers that only rearrange
+//! elements are generic over the element type instead.
+
+use crate::algorithm::field::GaloisField;
+use anyhow::{Result, anyhow};
+
+/// A row-major matrix of field elements, bytes for the default GF(2^8).
+pub type Matrix<E = u8> = Vec<Vec<E>>;
+
+/// Computes the row vector `vec * mat`.
+///
+/// Each `vec[i]` scales a whole row of `mat`, so the field's bulk
+/// [`GaloisField::mul_acc`] is used once per nonzero vector element.
+pub fn mul_vec_matrix<F: GaloisField>(
+    gf: &F,
+    vec: &[F::Elem],
+    mat: &Matrix<F::Elem>,
+) -> Vec<F::Elem> {
+    let k = mat.len();
+    assert_ne!(k, 0, "Matrix cannot be empty");
+    let cols = mat[0].len();
+    assert_eq!(vec.len(), k, "Vector length must match matrix rows");
+
+    let mut result = vec![F::ZERO; cols];
+    for (&v_val, row) in vec.iter().zip(mat.iter()) {
+        gf.mul_acc(v_val, row, &mut result);
+    }
+    result
+}
+
+/// Computes the column vector `mat * vec` (the transposed counterpart of
+/// [`mul_vec_matrix`]).
+pub fn mul_matrix_vec<F: GaloisField>(
+    gf: &F,
+    mat: &Matrix<F::Elem>,
+    vec: &[F::Elem],
+) -> Vec<F::Elem> {
+    assert!(
+        mat.iter().all(|row| row.len() == vec.len()),
+        "Vector length must match matrix columns"
+    );
+    mat.iter()
+        .map(|row| {
+            row.iter()
+                .zip(vec.iter())
+                .fold(F::ZERO, |acc, (&a, &b)| gf.add(acc, gf.mul(a, b)))
+        })
+        .collect()
+}
+
+/// Computes the matrix product `a * b`.
+///
+/// Equal to [`mul_vec_matrix`] for every row of `a`, but each row of `b` is
+/// read once and accumulated into all output rows, rather than once per row
+/// of `a`.
+pub fn mul_matrix_matrix<F: GaloisField>(
+    gf: &F,
+    a: &Matrix<F::Elem>,
+    b: &Matrix<F::Elem>,
+) -> Matrix<F::Elem> {
+    assert_ne!(b.len(), 0, "Matrix cannot be empty");
+    assert!(
+        a.iter().all(|row| row.len() == b.len()),
+        "Row length of a must match the rows of b"
+    );
+    let mut result = vec![vec![F::ZERO; b[0].len()]; a.len()];
+    for (j, b_row) in b.iter().enumerate() {
+        for (out, a_row) in result.iter_mut().zip(a) {
+            gf.mul_acc(a_row[j], b_row, out);
+        }
+    }
+    result
+}
+
+/// The inverse of the square matrix `mat`, or an error if it is singular.
+pub fn invert_matrix<F: GaloisField>(gf: &F, mat: &[Vec<F::Elem>]) -> Result<Matrix<F::Elem>> {
+    let mut inverse = Matrix::new();
+    invert_matrix_into(gf, mat, &mut InversionScratch::new(), &mut inverse)?;
+    Ok(inverse)
+}
+
+/// Working memory for [`invert_matrix_into`]: the `n x 2n` augmented matrix,
+/// stored flat. Keeping one around avoids reallocating it for every inversion.
+#[derive(Debug, Clone, Default)]
+pub struct InversionScratch<E> {
+    aug: Vec<E>,
+}
+
+impl<E> InversionScratch<E> {
+    pub fn new() -> Self {
+        Self { aug: Vec::new() }
+    }
+}
+
+/// Like [`invert_matrix`], but works in `scratch` and writes the inverse into
+/// `out`, reusing both allocations when called repeatedly. `out` is left
+/// unspecified on error.
+pub fn invert_matrix_into<F: GaloisField>(
+    gf: &F,
+    mat: &[Vec<F::Elem>],
+    scratch: &mut InversionScratch<F::Elem>,
+    out: &mut Matrix<F::Elem>,
+) -> Result<()> {
+    let n = mat.len();
+    if n == 0 || mat.iter().any(|r| r.len() != n) {
+        return Err(anyhow!("Matrix must be square"));
+    }
+
+    let width = 2 * n;
+    let aug = &mut scratch.aug;
+    aug.clear();
+    aug.resize(n * width, F::ZERO);
+    for (r, row) in mat.iter().enumerate() {
+        aug[r * width..r * width + n].copy_from_slice(row);
+        aug[r * width + n + r] = F::ONE;
+    }
+
+    for col in 0..n {
+        let pivot_row = (col..n)
+            .find(|&r| aug[r * width + col] != F::ZERO)
+            .ok_or_else(|| anyhow!("Matrix is singular and cannot be inverted"))?;
+        if pivot_row != col {
+            let (upper, lower) = aug.split_at_mut(pivot_row * width);
+            upper[col * width..(col + 1) * width].swap_with_slice(&mut lower[..width]);
+        }
+
+        let (above, rest) = aug.split_at_mut(col * width);
+        let (pivot, below) = rest.split_at_mut(width);
+        let inv_pivot = gf.inv(pivot[col])?;
+        for v in pivot[col..].iter_mut() {
+            *v = gf.mul(inv_pivot, *v);
+        }
+
+        for target in above
+            .chunks_exact_mut(width)
+            .chain(below.chunks_exact_mut(width))
+        {
+            let factor = target[col];
+            if factor != F::ZERO {
+                gf.mul_acc(factor, &pivot[col..], &mut target[col..]);
+            }
+        }
+    }
+
+    out.resize_with(n, Vec::new);
+    for (row, aug_row) in out.iter_mut().zip(aug.chunks_exact(width)) {
+        row.clear();
+        row.extend_from_slice(&aug_row[n..]);
+    }
+    Ok(())
+}
+
+/// Row `i` of the `n x n` identity matrix.
+pub fn unit_row<F: GaloisField>(n: usize, i: usize) -> Vec<F::Elem> {
+    let mut row = vec![F::ZERO; n];
+    row[i] = F::ONE;
+    row
+}
+
+/// The `n x n` identity matrix, e.g. `identity::<Gf256>(n)`.
+pub fn identity<F: GaloisField>(n: usize) -> Matrix<F::Elem> {
+    (0..n).map(|r| unit_row::<F>(n, r)).collect()
+}
+
+/// Adds `row` to `basis`, rows in echelon form as (pivot column, row scaled
+/// to 1 there), unless it depends on them; returns whether it was added.
+/// Each row is reduced by the earlier ones before it is added, so reducing in
+/// insertion order never brings back an eliminated pivot.
+pub fn add_independent_row<F: GaloisField>(
+    gf: &F,
+    basis: &mut Vec<(usize, Vec<F::Elem>)>,
+    mut row: Vec<F::Elem>,
+) -> Result<bool> {
+    for (pivot, b) in basis.iter() {
+        let c = row[*pivot];
+        if c != F::ZERO {
+            for (x, &y) in row.iter_mut().zip(b) {
+                *x = gf.add(*x, gf.mul(c, y));
+            }
+        }
+    }
+    let Some(pivot) = row.iter().position(|&c| c != F::ZERO) else {
+        return Ok(false);
+    };
+    let scale = gf.inv(row[pivot])?;
+    for x in row.iter_mut() {
+        *x = gf.mul(*x, scale);
+    }
+    basis.push((pivot, row));
+    Ok(true)
+}
+
+/// The transpose of `mat`, which must be rectangular.
+pub fn transpose<E: Copy>(mat: &[Vec<E>]) -> Matrix<E> {
+    let cols = mat.first().map_or(0, Vec::len);
+    assert!(
+        mat.iter().all(|row| row.len() == cols),
+        "Matrix rows must all have the same length"
+    );
+    (0..cols)
+        .map(|c| mat.iter().map(|row| row[c]).collect())
+        .collect()
+}
+
+/// The matrix of `mat`'s elements at the given rows and columns, in the
+/// order listed.
+pub fn submatrix<E: Copy>(mat: &[Vec<E>], rows: &[usize], cols: &[usize]) -> Matrix<E> {
+    rows.iter()
+        .map(|&r| cols.iter().map(|&c| mat[r][c]).collect())
+        .collect()
+}
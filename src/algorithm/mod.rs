@@ -1 +1,4 @@
-pub mod gf256;
\ No newline at end of file
+pub mod field;
+pub mod gf256;
+pub mod gf_matrix;
+pub mod shuffle;
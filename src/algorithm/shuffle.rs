@@ -0,0 +1,22 @@
+//! Deterministic pseudo-random permutations, shared by the byte scrambler
+//! and the sampled MDS check so both reproduce from a seed.
+
+/// SplitMix64, used only to drive shuffles (and test data) deterministically.
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Fisher-Yates shuffle of `0..len` seeded by `seed`.
+pub fn permutation(len: usize, seed: u64) -> Vec<usize> {
+    let mut perm: Vec<usize> = (0..len).collect();
+    let mut state = seed;
+    for i in (1..len).rev() {
+        let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+        perm.swap(i, j);
+    }
+    perm
+}
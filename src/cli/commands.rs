@@ -1,4 +1,13 @@
-use clap::{Parser, Subcommand};
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    cli::logging::LogFormat,
+    codec::{execution::Execution, matrix::MatrixType},
+    error::RseError,
+    io::{checksum::ChecksumAlgo, compression::Compression, encryption::EncryptKey},
+};
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 #[derive(Parser, Debug, Clone)]
@@ -11,28 +20,530 @@ use std::path::PathBuf;
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Run encoding and reconstruction loops on one thread, in order. Output
+    /// is identical; useful to rule out data races when debugging.
+    #[arg(long, global = true)]
+    pub no_parallel: bool,
+
+    /// Run encoding and reconstruction loops on at most this many threads,
+    /// e.g. to leave CPU for other work alongside --rate-limit.
+    #[arg(long, global = true, value_name = "N", conflicts_with = "no_parallel")]
+    pub max_threads: Option<usize>,
+
+    /// How log lines are written; `json` emits one object per line for log
+    /// pipelines.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Pretty)]
+    pub log_format: LogFormat,
+
+    /// Most verbose level logged (error, warn, info, debug or trace).
+    /// Overrides RUST_LOG.
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<tracing::Level>,
+}
+
+impl Cli {
+    /// How the command's encoding and reconstruction loops run.
+    pub fn execution(&self) -> Result<Execution> {
+        match self.max_threads {
+            Some(0) => {
+                Err(RseError::InvalidArgument("--max-threads must be at least 1".into()).into())
+            }
+            Some(threads) => Execution::with_threads(threads),
+            None if self.no_parallel => Ok(Execution::Serial),
+            None => Ok(Execution::Parallel),
+        }
+    }
 }
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum Commands {
-    Encode {
+    // Boxed since its options outweigh every other command's.
+    Encode(Box<EncodeArgs>),
+    Decode {
         #[arg(short, long)]
         input: PathBuf,
 
+        #[arg(
+            short,
+            long,
+            required_unless_present_any = ["split_output", "verify_checksums_only"]
+        )]
+        output: Option<PathBuf>,
+
+        /// Only check every present shard against its checksum and report
+        /// which are intact, corrupt or missing; nothing is reconstructed or
+        /// written. Fails if any present shard is corrupt.
+        #[arg(long, conflicts_with_all = ["output", "split_output", "block_size"])]
+        verify_checksums_only: bool,
+
+        /// Write the recovered data shards to DIR as separate files, padded
+        /// and not concatenated, instead of reassembling the input. DIR/split.json
+        /// records the original length.
+        #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "block_size"])]
+        split_output: Option<PathBuf>,
+
+        /// With --split-output, also write the parity shards, recomputed from
+        /// the recovered data.
+        #[arg(long, requires = "split_output")]
+        split_parity: bool,
+
+        /// Fail if any present shard is not exactly the length implied by the
+        /// metadata, instead of ignoring extra bytes.
+        #[arg(long)]
+        strict: bool,
+
+        /// Number of data shards, to decode a set whose metadata is missing.
         #[arg(short, long)]
-        output: PathBuf,
+        data_shards: Option<usize>,
+
+        /// Number of parity shards, to decode a set whose metadata is missing.
+        #[arg(short, long)]
+        parity_shards: Option<usize>,
+
+        /// Key of an encrypted shard set (64 hex digits).
+        #[arg(long, value_name = "HEX_KEY")]
+        encrypt_key: Option<EncryptKey>,
+
+        /// If too few shards survive, still write the surviving data shards,
+        /// zero-filling the unrecoverable ones, and list the missing byte ranges.
+        #[arg(long)]
+        partial_ok: bool,
+
+        /// If a shard file is shorter than expected (e.g. still being copied
+        /// in), re-read it until it is complete or SECONDS have passed, then
+        /// treat it as missing.
+        #[arg(long, value_name = "SECONDS")]
+        wait_for_shards: Option<f64>,
+
+        /// Another directory holding copies of the same shards, tried in the
+        /// order given when the input's copy of a shard is absent, the wrong
+        /// size or fails its checksum. May be repeated.
+        #[arg(long = "fallback-dir", value_name = "DIR")]
+        fallback_dirs: Vec<PathBuf>,
+
+        /// How many times to retry reading a shard after an IO error before
+        /// treating it as missing. A shard that does not exist is not retried.
+        #[arg(long, value_name = "N", default_value_t = 2)]
+        read_retries: u32,
+
+        /// Delay before the first read retry; it doubles for each further one.
+        #[arg(long, value_name = "MS", default_value_t = 100)]
+        retry_backoff_ms: u64,
+
+        /// With more than k shards present, check them against each other and
+        /// drop a shard that disagrees with the rest. Finding which one needs
+        /// at least k+2 shards; meant for sets without checksums.
+        #[arg(long)]
+        locate_corruption: bool,
+
+        /// Also rebuild every present data shard from parity and fail if any
+        /// differs from the stored shard, checking that data and parity agree.
+        /// Needs at least one parity shard beyond those rebuilding lost data.
+        #[arg(long)]
+        force_reconstruct: bool,
+
+        /// Find and fix corrupted bytes in the present shards by syndrome
+        /// decoding, up to (present - k) / 2 bad shards per byte position.
+        /// Cauchy sets only (--matrix-type cauchy); meant for sets without
+        /// checksums.
+        #[arg(long)]
+        correct_errors: bool,
+
+        /// Decode BYTES of each shard at a time, writing the output as it goes,
+        /// so memory stays near (k+m) * BYTES however large the shards are.
+        /// Plain sets only: not compressed, scrambled, encrypted or rotated.
+        #[arg(long, value_name = "BYTES")]
+        block_size: Option<usize>,
+
+        /// Expected length of each shard as encoded. Fails if the metadata or
+        /// any present shard file disagrees; without metadata it is used
+        /// instead of the largest shard file's size.
+        #[arg(long, value_name = "BYTES")]
+        shard_len: Option<usize>,
+
+        /// Bytes of shard reads that may be in flight at once: fewer shards are
+        /// read concurrently when they are large. Defaults to half the
+        /// available memory. Paces the reads only; every shard read is kept
+        /// for reconstruction (see --block-size).
+        #[arg(long, value_name = "BYTES")]
+        memory_budget: Option<usize>,
+
+        /// Write a JSON summary of the run to PATH: shards read and
+        /// reconstructed, checksums, durations and the outcome. A failed run
+        /// still writes one, naming the stage it failed in.
+        #[arg(
+            long,
+            value_name = "PATH",
+            conflicts_with_all = ["split_output", "verify_checksums_only", "block_size"]
+        )]
+        report_file: Option<PathBuf>,
+    },
+    /// Report which shards of a set are present, lost, or stored elsewhere.
+    Info {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Also read N randomly chosen shards, check their checksums and
+        /// estimate the health of the whole set.
+        #[arg(long, value_name = "N")]
+        count: Option<usize>,
 
+        /// Seed for choosing the --count sample. Random (and printed) if omitted.
+        #[arg(long, requires = "count")]
+        seed: Option<u64>,
+    },
+    /// Check shard files against a manifest of sizes and hashes.
+    Verify {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Manifest to check against. Defaults to `<input>/manifest.json`.
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+
+        /// Check only N randomly chosen shards and estimate the health of the
+        /// whole set from them, instead of reading every shard.
+        #[arg(long, value_name = "N")]
+        count: Option<usize>,
+
+        /// Seed for choosing the --count sample. Random (and printed) if omitted.
+        #[arg(long, requires = "count")]
+        seed: Option<u64>,
+    },
+    /// Read every shard in full, check its size, checksum and trailer, and
+    /// report how many more losses the set can take.
+    Scrub {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Rebuild lost shards in place when fewer than MARGIN further
+        /// losses would be tolerated.
+        #[arg(long, value_name = "MARGIN")]
+        repair_below: Option<usize>,
+
+        /// Before repairing, re-encode all parity from the recovered data and
+        /// refuse to repair if a present parity shard disagrees with it.
+        #[arg(long, requires = "repair_below")]
+        verify_parity: bool,
+    },
+    /// Check a file written by `encode --self-healing` and fix damaged
+    /// regions, header and manifest copies in place.
+    Repair {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Treat INPUT as a self-healing file, its regions as shards, even if
+        /// the header at its start is damaged. Without it, INPUT must start
+        /// with a self-healing header. Shard directories are repaired with
+        /// `scrub --repair-below`.
+        #[arg(long = "self")]
+        self_healing: bool,
+    },
+    /// Compare two shard directories shard by shard.
+    Compare {
+        a: PathBuf,
+        b: PathBuf,
+    },
+    /// Print the encoding matrix, and optionally the reconstruction matrix for
+    /// a survivor set, as a hex grid.
+    DumpMatrix {
         #[arg(short, long)]
         data_shards: usize,
 
         #[arg(short, long)]
         parity_shards: usize,
+
+        #[arg(long, value_enum, default_value_t)]
+        matrix_type: MatrixType,
+
+        /// Survivor shard indices (comma-separated, exactly k of them).
+        #[arg(long, value_delimiter = ',')]
+        survivors: Option<Vec<usize>>,
     },
-    Decode {
+    /// Serve encode/decode over HTTP with one codec kept warm across requests.
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        addr: SocketAddr,
+
+        #[arg(short, long)]
+        data_shards: usize,
+
+        #[arg(short, long)]
+        parity_shards: usize,
+
+        /// Largest request body accepted, in bytes; larger requests get 413.
+        #[arg(long, value_name = "BYTES", default_value_t = 64 * 1024 * 1024)]
+        max_body_bytes: usize,
+
+        /// Most shard sets held in memory; storing one more evicts the oldest.
+        #[arg(long, value_name = "N", default_value_t = 1024)]
+        max_sets: usize,
+    },
+    /// Re-encode an existing shard set with new data/parity shard counts.
+    Reshape {
         #[arg(short, long)]
         input: PathBuf,
 
         #[arg(short, long)]
         output: PathBuf,
+
+        #[arg(short = 'd', long)]
+        new_data_shards: usize,
+
+        #[arg(short = 'p', long)]
+        new_parity_shards: usize,
+
+        /// Key of an encrypted source set; the reshaped set is encrypted with it too.
+        #[arg(long, value_name = "HEX_KEY")]
+        encrypt_key: Option<EncryptKey>,
     },
+    /// Suggest data/parity shard counts for a fault tolerance and storage budget.
+    Suggest {
+        /// Number of shards that may be lost at the same time.
+        #[arg(short, long)]
+        tolerance: usize,
+
+        /// Largest acceptable share of parity in the stored bytes, m/(k+m),
+        /// e.g. 0.3.
+        #[arg(long)]
+        max_overhead: f64,
+
+        /// Number of candidates to list.
+        #[arg(long, default_value_t = 8)]
+        limit: usize,
+    },
+}
+
+/// Split a file into data and parity shards.
+#[derive(Args, Debug, Clone)]
+pub struct EncodeArgs {
+    /// File to encode. With --parallel-files, any number of files, each
+    /// encoded into `<output>/<file name>`.
+    #[arg(short, long, num_args = 1.., required = true)]
+    pub input: Vec<PathBuf>,
+
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    #[arg(short, long)]
+    pub data_shards: usize,
+
+    #[arg(short, long)]
+    pub parity_shards: usize,
+
+    /// Skip checking the output filesystem for enough free space before encoding.
+    #[arg(long)]
+    pub no_space_check: bool,
+
+    /// Only write these shard indices to the output directory (comma-separated).
+    /// The rest are expected to be stored elsewhere.
+    #[arg(long, value_delimiter = ',')]
+    pub store_only: Option<Vec<usize>>,
+
+    /// Cap input read and shard write throughput, in MiB/s. Unlimited by default.
+    #[arg(long, value_name = "MIB_PER_SEC")]
+    pub rate_limit: Option<f64>,
+
+    /// Also write a manifest.json listing each shard file's size and SHA-256.
+    #[arg(long)]
+    pub manifest: bool,
+
+    /// Compute and write parity shards one at a time instead of holding all of
+    /// them in memory. Slower, since parity rows are no longer computed in parallel.
+    #[arg(long)]
+    pub low_memory: bool,
+
+    /// Compress the input before sharding, with zstd or gzip. Skipped if it
+    /// would not shrink the data.
+    #[arg(long, value_enum)]
+    pub compress: Option<Compression>,
+
+    /// Compress each shard file on disk after encoding. Shards that would
+    /// not shrink are stored as they are.
+    #[arg(long, value_enum)]
+    pub compress_shards: Option<Compression>,
+
+    /// Rotate shards across the output files every STRIPE_LEN bytes, RAID-style,
+    /// so parity is spread over all files.
+    #[arg(long, value_name = "STRIPE_LEN")]
+    pub rotate_stripes: Option<usize>,
+
+    /// Shuffle the input bytes with a permutation derived from SEED before
+    /// sharding, so no shard holds contiguous input. Not encryption.
+    #[arg(long, value_name = "SEED")]
+    pub scramble: Option<u64>,
+
+    /// Place each parity shard file among the data shard files it follows
+    /// (e.g. parity after every k/m data shards) instead of after all of them.
+    #[arg(long)]
+    pub interleave_parity: bool,
+
+    /// Split the input unevenly: one relative weight per data shard
+    /// (comma-separated), e.g. matching each storage node's free space.
+    #[arg(long, value_delimiter = ',')]
+    pub shard_weights: Option<Vec<usize>>,
+
+    /// Add a second Reed-Solomon dimension: DATA_ROWS rows of -d data
+    /// shards each get -p row parity shards, then PARITY_ROWS parity rows
+    /// are computed down every column.
+    #[arg(long, value_delimiter = ',', value_name = "DATA_ROWS,PARITY_ROWS")]
+    pub product_code: Option<Vec<usize>>,
+
+    /// Split the data shards into GROUPS groups and add one XOR local
+    /// parity shard per group, so a single lost shard is rebuilt from its
+    /// group alone. The -p global parity shards are kept as usual.
+    #[arg(long, value_name = "GROUPS")]
+    pub local_groups: Option<usize>,
+
+    /// Encoding matrix construction, recorded in the metadata so decode
+    /// rebuilds the same one.
+    #[arg(long, value_enum, default_value_t)]
+    pub matrix_type: MatrixType,
+
+    /// Use the parity rows in this file as the encoding matrix: one line
+    /// of two-digit hex elements per parity shard, k elements each. The
+    /// rows are stored in the metadata.
+    #[arg(long, value_name = "PATH", conflicts_with = "matrix_type")]
+    pub matrix_file: Option<PathBuf>,
+
+    /// Per-shard checksum recorded in the metadata and checked on decode.
+    #[arg(long, value_enum, default_value_t)]
+    pub checksum_algo: ChecksumAlgo,
+
+    /// Encrypt shard files at rest with this 256-bit key (64 hex digits).
+    #[arg(long, value_name = "HEX_KEY")]
+    pub encrypt_key: Option<EncryptKey>,
+
+    /// Fail unless the parity count tolerates at least T lost shards.
+    #[arg(long, value_name = "T")]
+    pub require_tolerance: Option<usize>,
+
+    /// Decode the written shard set to check it. With --require-tolerance T,
+    /// T randomly chosen shards are ignored, so recovery from them is exercised.
+    #[arg(long)]
+    pub verify_after_encode: bool,
+
+    /// Seed choosing the shards --verify-after-encode ignores, e.g. to replay
+    /// a failed verification. Random, and logged, by default.
+    #[arg(long, value_name = "SEED", requires = "verify_after_encode")]
+    pub verify_seed: Option<u64>,
+
+    /// Leave the output untouched if it already holds a complete, intact
+    /// shard set with the same k/m for this exact input.
+    #[arg(long)]
+    pub skip_existing: bool,
+
+    /// End every shard file in a magic and CRC-32 of its contents, so a
+    /// single file can be validated without the metadata.
+    #[arg(long)]
+    pub shard_trailer: bool,
+
+    /// Round the shard length up to a multiple of N bytes, zero-padding
+    /// the data shards, e.g. to match a storage block size.
+    #[arg(long, value_name = "N")]
+    pub align: Option<usize>,
+
+    /// Split every shard file into volumes of BYTES bytes, named
+    /// shard_XX.dat.000, .001, ...; the last volume of each shard is short.
+    /// Decode reassembles them.
+    #[arg(long, value_name = "BYTES")]
+    pub volume_size: Option<usize>,
+
+    /// Keep each shard's checksum in a shard_XX.meta file next to the
+    /// shard instead of in meta.json, which then only describes the set.
+    #[arg(long)]
+    pub sidecar_metadata: bool,
+
+    /// Append every shard to a single shards.log in the output directory
+    /// instead of writing a file per shard; meta.json records where each
+    /// shard lies in the log.
+    #[arg(long)]
+    pub shard_log: bool,
+
+    /// Directory for the temporary files shards are written to before
+    /// being renamed into place. Defaults to the output directory.
+    #[arg(long, value_name = "DIR")]
+    pub tmp_dir: Option<PathBuf>,
+
+    /// Flush every shard and the metadata to stable storage before
+    /// renaming it into place.
+    #[arg(long)]
+    pub fsync: bool,
+
+    /// Leave the encode time and hostname out of meta.json, so the same
+    /// input and options always give byte-identical shards and metadata,
+    /// e.g. for content-addressed storage. Not with --encrypt-key.
+    #[arg(long)]
+    pub reproducible: bool,
+
+    /// Bytes of shard writes that may be in flight at once: fewer shards
+    /// are written concurrently when they are large, more when small.
+    /// Defaults to half the available memory. Paces the writes only; every
+    /// shard is in memory before they start (see --low-memory).
+    #[arg(long, value_name = "BYTES")]
+    pub memory_budget: Option<usize>,
+
+    /// If some shard files fail to write (e.g. the disk fills up), leave
+    /// the ones that were written instead of removing them.
+    #[arg(long)]
+    pub keep_partial: bool,
+
+    /// Refuse sets of more than N shards whose shards would be smaller
+    /// than --min-shard-size, e.g. a large -d by mistake on a small file.
+    #[arg(long, value_name = "N", default_value_t = 64)]
+    pub max_shards: usize,
+
+    /// See --max-shards.
+    #[arg(long, value_name = "BYTES", default_value_t = 4096)]
+    pub min_shard_size: usize,
+
+    /// Encode even if --max-shards and --min-shard-size would refuse it.
+    #[arg(long)]
+    pub force: bool,
+
+    /// Encode only the input bytes from this offset on, e.g. one partition
+    /// of a disk image, as if they were the whole input.
+    #[arg(long, value_name = "BYTES", conflicts_with_all = ["parallel_files", "framed_objects"])]
+    pub input_offset: Option<u64>,
+
+    /// Encode only this many input bytes, starting at --input-offset.
+    #[arg(long, value_name = "BYTES", conflicts_with_all = ["parallel_files", "framed_objects"])]
+    pub input_length: Option<u64>,
+
+    /// Encode each input file into its own subdirectory of the output,
+    /// running up to JOBS files concurrently.
+    #[arg(long, value_name = "JOBS")]
+    pub parallel_files: Option<usize>,
+
+    /// Treat the input (or stdin, given as -) as a stream of objects, each
+    /// framed by an 8-byte little-endian length, and encode object N into
+    /// its own subdirectory object_NNNNNNNN of the output.
+    #[arg(long, conflicts_with = "parallel_files")]
+    pub framed_objects: bool,
+
+    /// Encode the input (or stdin, given as -) as a stream of unknown
+    /// length, reading and encoding BYTES per data shard at a time, so
+    /// only one stripe of k * BYTES input bytes is ever held in memory.
+    #[arg(
+        long,
+        value_name = "BYTES",
+        conflicts_with_all = ["parallel_files", "framed_objects", "input_offset", "input_length"]
+    )]
+    pub stream_stripe: Option<usize>,
+
+    /// Write one self-healing file to --output instead of a shard
+    /// directory: the input, then its parity and a manifest, so that
+    /// `repair --self` can find and fix damage within that one file.
+    #[arg(
+        long,
+        conflicts_with_all = ["parallel_files", "framed_objects", "stream_stripe"]
+    )]
+    pub self_healing: bool,
+
+    /// Write a JSON summary of the run to PATH: parameters, shards
+    /// written, checksums, durations and the outcome. A failed run still
+    /// writes one, naming the stage it failed in.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["parallel_files", "framed_objects"])]
+    pub report_file: Option<PathBuf>,
 }
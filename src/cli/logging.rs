@@ -0,0 +1,44 @@
+//! Construction of the global tracing subscriber from `--log-format` and
+//! `--log-level`.
+
+use clap::ValueEnum;
+use tracing::{Level, Subscriber, level_filters::LevelFilter};
+use tracing_subscriber::{EnvFilter, FmtSubscriber, fmt::MakeWriter};
+
+/// How log events are rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable, one line per event with timestamp, level and spans.
+    #[default]
+    Pretty,
+    /// Shorter lines than `pretty`, with span fields after the message.
+    Compact,
+    /// One JSON object per line, including the current span and its parents.
+    Json,
+}
+
+/// Builds a subscriber writing to `writer`. Without `level`, filtering
+/// follows `RUST_LOG` as before.
+pub fn subscriber<W>(
+    format: LogFormat,
+    level: Option<Level>,
+    writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let filter = match level {
+        Some(level) => EnvFilter::builder()
+            .with_default_directive(LevelFilter::from_level(level).into())
+            .parse_lossy(""),
+        None => EnvFilter::from_default_env(),
+    };
+    let builder = FmtSubscriber::builder()
+        .with_env_filter(filter)
+        .with_writer(writer);
+    match format {
+        LogFormat::Pretty => Box::new(builder.finish()),
+        LogFormat::Compact => Box::new(builder.compact().finish()),
+        LogFormat::Json => Box::new(builder.json().with_span_list(true).finish()),
+    }
+}
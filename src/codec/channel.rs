@@ -0,0 +1,59 @@
+use anyhow::{Context, Result, anyhow};
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+use tracing::{debug, instrument};
+
+use crate::{codec::reconstruct_shards::Codec, error::RseError};
+
+/// Collects shards as they arrive on `shards` and reconstructs the full set as
+/// soon as the arrived shards suffice, without waiting for the rest.
+///
+/// Any `k` distinct shards suffice for an MDS matrix; otherwise shards keep
+/// being received until the arrived generator rows are independent, the
+/// same check [`Codec::suggest_shards`] makes. Each message is
+/// `(shard_index, shard_data)`. Duplicate indices are ignored. Errors if an
+/// index is out of range or the channel closes before enough shards arrived.
+#[instrument(skip_all, fields(k = codec.data_shards(), n = codec.total_shards()))]
+pub async fn reconstruct_from_channel(
+    codec: Arc<Codec>,
+    mut shards: Receiver<(usize, Vec<u8>)>,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    let n = codec.total_shards();
+    let k = codec.data_shards();
+    let mut shards_opt: Vec<Option<Vec<u8>>> = vec![None; n];
+    let mut present = Vec::with_capacity(n);
+
+    while present.len() < k || !codec.suggest_shards(&present)?.is_empty() {
+        let Some((index, data)) = shards.recv().await else {
+            if present.len() >= k {
+                // Reconstruction reports which survivors are dependent.
+                break;
+            }
+            return Err(anyhow::Error::new(RseError::InsufficientShards {
+                have: present.len(),
+                need: k,
+            })
+            .context("Shard stream ended early"));
+        };
+        if index >= n {
+            return Err(anyhow!(
+                "Received shard index {} but the set only has {} shards",
+                index,
+                n
+            ));
+        }
+        if shards_opt[index].is_none() {
+            debug!(index, "received shard");
+            shards_opt[index] = Some(data);
+            present.push(index);
+        }
+    }
+    drop(shards);
+
+    tokio::task::spawn_blocking(move || {
+        codec.reconstruct(&mut shards_opt)?;
+        Ok(shards_opt)
+    })
+    .await
+    .context("Shard reconstruction task panicked")?
+}
@@ -0,0 +1,233 @@
+//! Correcting errors at unknown positions, not only erasures, by syndrome
+//! decoding.
+//!
+//! `[I; C]` with the Cauchy matrix `C[r][c] = 1 / (x_r + y_c)` is a
+//! generalized Reed-Solomon code over the points `a_i` (`y_c` for data shard
+//! `c`, `x_r` for parity shard `k + r`). Its parity checks are
+//! `sum_i w_i a_i^s r_i = 0` for `s < m`, with `w_i = 1 / Q(a_i)` for data
+//! and `1 / Q'(a_i)` for parity, where `Q(z) = prod_r (z + x_r)`. Dropping
+//! erased shards gives the same kind of code with `w_i` scaled by
+//! `prod_e (a_i + a_e)` and `m - e` checks.
+//!
+//! Every element position (byte column) is decoded on its own: the syndromes
+//! give the error locator by Berlekamp-Massey, its roots the corrupted
+//! shards, and Forney's formula the error values. Up to `(m - e) / 2`
+//! corrupted shards per column are corrected, where `e` shards are missing;
+//! different columns may have different corrupted shards.
+//!
+//! The points are those [`build_cauchy`] builds the matrix from, see
+//! [`cauchy_point`]. The Vandermonde matrix of this crate is not an
+//! evaluation code, so this only applies to Cauchy codecs; `decode
+//! --correct-errors` runs it on a Cauchy shard set.
+
+use anyhow::Result;
+
+use crate::{
+    algorithm::field::GaloisField,
+    codec::{
+        matrix::{build_cauchy, cauchy_point},
+        reconstruct_shards::Codec,
+    },
+    error::RseError,
+};
+
+/// What [`correct_errors`] changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorCorrection {
+    /// Shards with at least one corrected element, ascending.
+    pub shards: Vec<usize>,
+    /// Corrected elements across all shards.
+    pub symbols: usize,
+}
+
+/// `p(x)` for coefficients in ascending order.
+fn eval<F: GaloisField>(gf: &F, poly: &[F::Elem], x: F::Elem) -> F::Elem {
+    poly.iter()
+        .rev()
+        .fold(F::ZERO, |acc, &c| gf.add(gf.mul(acc, x), c))
+}
+
+/// Shortest connection polynomial `Λ` generating the syndromes, with
+/// `Λ(0) = 1`, and its linear complexity.
+fn berlekamp_massey<F: GaloisField>(
+    gf: &F,
+    syndromes: &[F::Elem],
+) -> Result<(Vec<F::Elem>, usize)> {
+    let mut lambda = vec![F::ONE];
+    let mut prev = vec![F::ONE];
+    let (mut len, mut shift, mut prev_discrepancy) = (0, 1, F::ONE);
+    for n in 0..syndromes.len() {
+        let discrepancy = (1..=len.min(lambda.len() - 1)).fold(syndromes[n], |acc, i| {
+            gf.add(acc, gf.mul(lambda[i], syndromes[n - i]))
+        });
+        if discrepancy == F::ZERO {
+            shift += 1;
+            continue;
+        }
+        let scale = gf.mul(discrepancy, gf.inv(prev_discrepancy)?);
+        let mut next = lambda.clone();
+        next.resize(next.len().max(prev.len() + shift), F::ZERO);
+        for (i, &b) in prev.iter().enumerate() {
+            next[i + shift] = gf.add(next[i + shift], gf.mul(scale, b));
+        }
+        if 2 * len <= n {
+            prev = std::mem::replace(&mut lambda, next);
+            len = n + 1 - len;
+            prev_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            lambda = next;
+            shift += 1;
+        }
+    }
+    Ok((lambda, len))
+}
+
+/// Finds and fixes corrupted elements in the present shards of a Cauchy
+/// codec, leaving missing shards to [`Codec::reconstruct`].
+///
+/// Fails with [`RseError::Corruption`] if some column has more errors than
+/// the remaining parity can correct. Beyond that bound a column may also be
+/// "corrected" into the wrong codeword, as with any RS decoder. On error,
+/// columns before the failing one are already corrected.
+pub fn correct_errors<F: GaloisField>(
+    codec: &Codec<F>,
+    shards: &mut [Option<Vec<F::Elem>>],
+) -> Result<ErrorCorrection> {
+    let (gf, k, m, n) = (
+        codec.field(),
+        codec.data_shards(),
+        codec.parity_shards(),
+        codec.total_shards(),
+    );
+    if *codec.encode_matrix() != build_cauchy(gf, k, m) {
+        return Err(RseError::InvalidArgument(
+            "Error correction needs a codec built with the Cauchy matrix".into(),
+        )
+        .into());
+    }
+    if shards.len() != n {
+        return Err(RseError::InvalidArgument(format!(
+            "Expected {} shards, got {}",
+            n,
+            shards.len()
+        ))
+        .into());
+    }
+    // The locator is built from inverted points, so no point may be zero:
+    // shifting every point by an unused element keeps the code the same.
+    if n == F::ORDER {
+        return Err(RseError::InvalidArgument(format!(
+            "Error correction needs k + m below {}",
+            F::ORDER
+        ))
+        .into());
+    }
+    let present: Vec<usize> = (0..n).filter(|&i| shards[i].is_some()).collect();
+    let checks = present.len().saturating_sub(k);
+    if checks < 2 {
+        return Err(RseError::InsufficientShards {
+            have: present.len(),
+            need: k + 2,
+        }
+        .into());
+    }
+    let len = shards[present[0]].as_ref().map_or(0, Vec::len);
+    if present
+        .iter()
+        .any(|&i| shards[i].as_ref().is_some_and(|s| s.len() != len))
+    {
+        return Err(
+            RseError::InvalidArgument("Shards must all have the same length".into()).into(),
+        );
+    }
+
+    let point = cauchy_point::<F>;
+    let shift = F::element(n);
+    let weights = present
+        .iter()
+        .map(|&i| {
+            let a = point(i);
+            let q = (k..n)
+                .filter(|&j| j != i)
+                .fold(F::ONE, |acc, j| gf.mul(acc, gf.add(a, point(j))));
+            let erased = (0..n)
+                .filter(|&j| shards[j].is_none())
+                .fold(F::ONE, |acc, j| gf.mul(acc, gf.add(a, point(j))));
+            Ok(gf.mul(gf.inv(q)?, erased))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let locators: Vec<F::Elem> = present.iter().map(|&i| gf.add(point(i), shift)).collect();
+
+    // Syndrome `s` of every column at once, as a combination of whole shards.
+    let mut syndromes = vec![vec![F::ZERO; len]; checks];
+    for (p, &i) in present.iter().enumerate() {
+        let shard = shards[i].as_deref().expect("present shard");
+        let mut coef = weights[p];
+        for syndrome in syndromes.iter_mut() {
+            gf.mul_acc(coef, shard, syndrome);
+            coef = gf.mul(coef, locators[p]);
+        }
+    }
+
+    let mut corrected = vec![false; n];
+    let mut symbols = 0;
+    let mut column = vec![F::ZERO; checks];
+    for col in 0..len {
+        for (value, syndrome) in column.iter_mut().zip(&syndromes) {
+            *value = syndrome[col];
+        }
+        if column.iter().all(|&s| s == F::ZERO) {
+            continue;
+        }
+        let uncorrectable = || {
+            RseError::Corruption(format!(
+                "Element {} has more corrupted shards than {} parity checks can correct",
+                col, checks
+            ))
+        };
+        let (lambda, errors) = berlekamp_massey(gf, &column)?;
+        if 2 * errors > checks {
+            return Err(uncorrectable().into());
+        }
+        // Ω = S Λ mod z^checks, and Λ' keeps the odd terms in characteristic 2.
+        let omega: Vec<F::Elem> = (0..checks)
+            .map(|d| {
+                (0..=d.min(lambda.len() - 1)).fold(F::ZERO, |acc, i| {
+                    gf.add(acc, gf.mul(lambda[i], column[d - i]))
+                })
+            })
+            .collect();
+        let derivative: Vec<F::Elem> = (1..lambda.len())
+            .map(|i| if i % 2 == 1 { lambda[i] } else { F::ZERO })
+            .collect();
+
+        let mut found = 0;
+        for (p, &i) in present.iter().enumerate() {
+            let x_inv = gf.inv(locators[p])?;
+            if eval(gf, &lambda, x_inv) != F::ZERO {
+                continue;
+            }
+            let denominator = eval(gf, &derivative, x_inv);
+            if denominator == F::ZERO {
+                return Err(uncorrectable().into());
+            }
+            let value = gf.mul(
+                gf.mul(locators[p], eval(gf, &omega, x_inv)),
+                gf.inv(gf.mul(denominator, weights[p]))?,
+            );
+            let shard = shards[i].as_mut().expect("present shard");
+            shard[col] = gf.add(shard[col], value);
+            corrected[i] = true;
+            found += 1;
+        }
+        if found != errors {
+            return Err(uncorrectable().into());
+        }
+        symbols += errors;
+    }
+    Ok(ErrorCorrection {
+        shards: (0..n).filter(|&i| corrected[i]).collect(),
+        symbols,
+    })
+}
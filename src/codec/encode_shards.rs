@@ -1,57 +1,189 @@
 use anyhow::{Result, anyhow};
-use indicatif::ProgressBar;
-use rayon::prelude::*;
 use tracing::{debug, instrument};
 
-use crate::algorithm::gf256::Gf256;
+use crate::{
+    algorithm::field::GaloisField,
+    codec::{execution::Execution, matrix::is_xor_parity},
+};
 
-#[instrument(skip_all, fields(k = data_shards.len(), m = matrix.len()))]
-pub fn shard_encoding(
-    gf: &Gf256,
-    matrix: &[Vec<u8>],
-    data_shards: &[Vec<u8>],
-    progress: &ProgressBar,
-) -> Result<Vec<Vec<u8>>> {
-    let m = matrix.len();
-    if m == 0 {
-        return Ok(vec![]);
+/// Receives a tick for every parity shard computed by [`shard_encoding`].
+/// `()` reports nothing.
+pub trait Progress: Sync {
+    fn inc(&self, delta: u64);
+}
+
+impl Progress for () {
+    fn inc(&self, _delta: u64) {}
+}
+
+#[cfg(feature = "full")]
+impl Progress for indicatif::ProgressBar {
+    fn inc(&self, delta: u64) {
+        indicatif::ProgressBar::inc(self, delta);
     }
+}
+
+/// Checks that `matrix` and `data_shards` agree and returns the common shard length.
+fn validate_encoding_inputs<E, D: AsRef<[E]>>(
+    matrix: &[Vec<E>],
+    data_shards: &[D],
+) -> Result<usize> {
     let k = matrix[0].len();
     if k != data_shards.len() {
         return Err(anyhow!(
             "Matrix columns must match the number of data shards"
         ));
     }
-    let shard_len = data_shards.get(0).map_or(0, |v| v.len());
-    if data_shards.iter().any(|s| s.len() != shard_len) {
+    let shard_len = data_shards.first().map_or(0, |v| v.as_ref().len());
+    if data_shards.iter().any(|s| s.as_ref().len() != shard_len) {
         return Err(anyhow!("All data shards must have the same length"));
     }
+    Ok(shard_len)
+}
+
+/// Accumulates one row of the encoding matrix applied to `data_shards` into `parity`.
+fn encode_parity_row<F: GaloisField, D: AsRef<[F::Elem]>>(
+    gf: &F,
+    row: &[F::Elem],
+    data_shards: &[D],
+    parity: &mut [F::Elem],
+) {
+    for (&coef, ds) in row.iter().zip(data_shards.iter()) {
+        gf.mul_acc(coef, ds.as_ref(), parity);
+    }
+}
+
+/// Sums `shards` with no multiplications: the XOR of all of them in
+/// characteristic 2. This is the parity of an all-ones row, and also
+/// recovers the one missing shard of such a set from all the others.
+pub fn xor_shards<F: GaloisField>(gf: &F, shards: &[&[F::Elem]]) -> Vec<F::Elem> {
+    let (first, rest) = shards.split_first().expect("at least one shard");
+    let mut out = first.to_vec();
+    for shard in rest {
+        gf.mul_acc(F::ONE, shard, &mut out);
+    }
+    out
+}
+
+#[instrument(skip_all, fields(k = data_shards.len(), m = matrix.len()))]
+pub fn shard_encoding<F: GaloisField>(
+    gf: &F,
+    matrix: &[Vec<F::Elem>],
+    data_shards: &[Vec<F::Elem>],
+    progress: &dyn Progress,
+    execution: &Execution,
+) -> Result<Vec<Vec<F::Elem>>> {
+    let m = matrix.len();
+    if m == 0 {
+        return Ok(vec![]);
+    }
+    let shard_len = validate_encoding_inputs(matrix, data_shards)?;
+    let data: Vec<&[F::Elem]> = data_shards.iter().map(Vec::as_slice).collect();
+    let mut parities = vec![vec![F::ZERO; shard_len]; m];
+    let mut parity_out: Vec<&mut [F::Elem]> = parities.iter_mut().map(Vec::as_mut_slice).collect();
+    shard_encoding_into(gf, matrix, &data, &mut parity_out, progress, execution)?;
+    Ok(parities)
+}
 
-    let mut parities = vec![vec![0u8; shard_len]; m];
-    debug!("Starting parallel encoding of parity shards.");
-
-    parities.par_iter_mut().enumerate().for_each(|(r, parity)| {
-        let row = &matrix[r];
-        for (c, ds) in data_shards.iter().enumerate().take(k) {
-            let coef = row[c];
-            if coef == 0 {
-                continue;
-            }
-
-            if coef == 1 {
-                for (p_byte, d_byte) in parity.iter_mut().zip(ds.iter()) {
-                    *p_byte ^= *d_byte;
-                }
-            } else {
-                let mult_table = gf.mul_table(coef);
-                for i in 0..shard_len {
-                    parity[i] ^= mult_table[ds[i] as usize];
-                }
-            }
+/// Like [`shard_encoding`], but writes the parity into the caller's buffers,
+/// one per matrix row and each as long as the data shards. Their previous
+/// contents are overwritten, so pooled buffers can be reused as they are.
+pub fn shard_encoding_into<F: GaloisField>(
+    gf: &F,
+    matrix: &[Vec<F::Elem>],
+    data_shards: &[&[F::Elem]],
+    parity_out: &mut [&mut [F::Elem]],
+    progress: &dyn Progress,
+    execution: &Execution,
+) -> Result<()> {
+    let m = matrix.len();
+    if parity_out.len() != m {
+        return Err(anyhow!(
+            "Expected {} parity buffers, got {}",
+            m,
+            parity_out.len()
+        ));
+    }
+    if m == 0 {
+        return Ok(());
+    }
+    let shard_len = validate_encoding_inputs(matrix, data_shards)?;
+    if parity_out.iter().any(|p| p.len() != shard_len) {
+        return Err(anyhow!(
+            "Parity buffers must be as long as the data shards ({} bytes)",
+            shard_len
+        ));
+    }
+    if is_xor_parity::<F>(matrix)
+        && let Some((first, rest)) = data_shards.split_first()
+    {
+        let parity = &mut *parity_out[0];
+        parity.copy_from_slice(first);
+        for shard in rest {
+            gf.mul_acc(F::ONE, shard, parity);
         }
         progress.inc(1);
+        return Ok(());
+    }
+
+    debug!("Starting encoding of parity shards.");
+    execution.for_each_mut(parity_out, |r, parity| {
+        parity.fill(F::ZERO);
+        encode_parity_row(gf, &matrix[r], data_shards, parity);
+        progress.inc(1);
     });
 
-    debug!("Finished parallel encoding.");
-    Ok(parities)
+    debug!("Finished encoding.");
+    Ok(())
+}
+
+/// Lazily computes parity shards one row at a time.
+///
+/// Only one parity shard is held at once, lowering peak memory from
+/// `m * shard_len` to `shard_len` at the cost of cross-row parallelism.
+/// Yields the same shards, in the same order, as [`shard_encoding`].
+pub struct ParityIter<'a, F: GaloisField> {
+    gf: &'a F,
+    matrix: &'a [Vec<F::Elem>],
+    data_shards: &'a [Vec<F::Elem>],
+    shard_len: usize,
+    next_row: usize,
+}
+
+impl<F: GaloisField> Iterator for ParityIter<'_, F> {
+    type Item = Vec<F::Elem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let row = self.matrix.get(self.next_row)?;
+        self.next_row += 1;
+        let mut parity = vec![F::ZERO; self.shard_len];
+        encode_parity_row(self.gf, row, self.data_shards, &mut parity);
+        Some(parity)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.matrix.len() - self.next_row;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<F: GaloisField> ExactSizeIterator for ParityIter<'_, F> {}
+
+pub fn shard_encoding_lazy<'a, F: GaloisField>(
+    gf: &'a F,
+    matrix: &'a [Vec<F::Elem>],
+    data_shards: &'a [Vec<F::Elem>],
+) -> Result<ParityIter<'a, F>> {
+    let shard_len = if matrix.is_empty() {
+        0
+    } else {
+        validate_encoding_inputs(matrix, data_shards)?
+    };
+    Ok(ParityIter {
+        gf,
+        matrix,
+        data_shards,
+        shard_len,
+        next_row: 0,
+    })
 }
@@ -0,0 +1,89 @@
+//! Serial or parallel execution of the per-shard loops in encoding and
+//! reconstruction.
+//!
+//! Parallel (Rayon's global pool) is the default. Serial mode runs the same
+//! closures in order on the calling thread and produces byte-identical output,
+//! which helps tell a data race from a logic bug, and avoids spawning threads
+//! in constrained environments. A dedicated pool caps the threads used, e.g.
+//! for background work on a shared machine. A [`Codec`](super::reconstruct_shards::Codec)
+//! and the encode and decode options each carry their own mode, so callers in
+//! one process can pick different ones.
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default)]
+pub enum Execution {
+    #[default]
+    Parallel,
+    Serial,
+    /// Parallel on a dedicated pool of limited size.
+    Pool(Arc<rayon::ThreadPool>),
+}
+
+impl Execution {
+    /// Parallel execution on at most `threads` threads; a single thread is
+    /// [`Execution::Serial`].
+    pub fn with_threads(threads: usize) -> Result<Self> {
+        if threads <= 1 {
+            return Ok(Self::Serial);
+        }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("Failed to build the thread pool")?;
+        Ok(Self::Pool(Arc::new(pool)))
+    }
+
+    /// Most threads a loop may run on.
+    pub fn threads(&self) -> usize {
+        match self {
+            Self::Parallel => rayon::current_num_threads(),
+            Self::Serial => 1,
+            Self::Pool(pool) => pool.current_num_threads(),
+        }
+    }
+
+    pub fn is_serial(&self) -> bool {
+        matches!(self, Self::Serial)
+    }
+
+    /// Calls `f(index, item)` for every item.
+    pub fn for_each_mut<T, G>(&self, items: &mut [T], f: G)
+    where
+        T: Send,
+        G: Fn(usize, &mut T) + Sync + Send,
+    {
+        match self {
+            Self::Serial => items
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, item)| f(i, item)),
+            Self::Parallel => items
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, item)| f(i, item)),
+            Self::Pool(pool) => pool.install(|| {
+                items
+                    .par_iter_mut()
+                    .enumerate()
+                    .for_each(|(i, item)| f(i, item))
+            }),
+        }
+    }
+
+    /// Maps every item through `f`, keeping the input order.
+    pub fn map<T, R, G>(&self, items: &[T], f: G) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        G: Fn(&T) -> R + Sync + Send,
+    {
+        match self {
+            Self::Serial => items.iter().map(f).collect(),
+            Self::Parallel => items.par_iter().map(f).collect(),
+            Self::Pool(pool) => pool.install(|| items.par_iter().map(f).collect()),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{algorithm::field::GaloisField, codec::execution::Execution};
+
+/// Symbols of each output shard computed per parallel task in the interleaved path.
+const INTERLEAVED_CHUNK: usize = 16 * 1024;
+
+/// Memory layout used for the recovery matrix-vector products.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShardLayout {
+    /// One pass over each survivor shard per missing shard.
+    #[default]
+    RowMajor,
+    /// Survivor symbols are interleaved column-major, so each output symbol
+    /// reads `k` contiguous symbols and every missing shard is produced in one pass.
+    ///
+    /// Currently slower than `RowMajor` on x86-64 (about 2.5x at k=200, m=20
+    /// with 256 KiB shards): the transpose and the `k` lookup tables per
+    /// output row, applied through [`GaloisField::dot_columns`], outweigh the
+    /// contiguous reads. Kept opt-in for experimentation.
+    Interleaved,
+}
+
+/// Interleaves the first `len` symbols of each shard so symbol `b` of shard
+/// `j` lands at `b * shards.len() + j`.
+pub fn interleave<E: Copy + Default>(shards: &[&[E]], len: usize) -> Vec<E> {
+    let k = shards.len();
+    let mut out = vec![E::default(); len * k];
+    for (j, shard) in shards.iter().enumerate() {
+        for (b, &symbol) in shard.iter().take(len).enumerate() {
+            out[b * k + j] = symbol;
+        }
+    }
+    out
+}
+
+/// Applies each recovery row to the survivor shards using the interleaved
+/// layout. Returns one output shard per row, identical to the row-major path.
+///
+/// `pass` is called once per row for each survivor coefficient, the
+/// interleaved counterpart of a pass over that survivor; coefficients it
+/// returns `false` for (zeros) are skipped.
+pub fn recover_interleaved<F: GaloisField>(
+    gf: &F,
+    recovery_rows: &[Vec<F::Elem>],
+    survivors: &[&[F::Elem]],
+    shard_len: usize,
+    execution: &Execution,
+    pass: impl Fn(F::Elem) -> bool,
+) -> Vec<Vec<F::Elem>> {
+    let k = survivors.len();
+    let columns = interleave(survivors, shard_len);
+    // The survivors each row reads, with their coefficients.
+    let terms: Vec<Vec<(usize, F::Elem)>> = recovery_rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .copied()
+                .enumerate()
+                .filter(|&(_, coef)| pass(coef))
+                .collect()
+        })
+        .collect();
+
+    let blocks: Vec<&[F::Elem]> = columns.chunks(INTERLEAVED_CHUNK * k).collect();
+    let chunks: Vec<Vec<Vec<F::Elem>>> = execution.map(&blocks, |block| {
+        terms
+            .iter()
+            .map(|row| {
+                let mut out = Vec::with_capacity(block.len() / k);
+                gf.dot_columns(row, block, k, &mut out);
+                out
+            })
+            .collect()
+    });
+
+    let mut outputs = vec![Vec::with_capacity(shard_len); recovery_rows.len()];
+    for chunk in chunks {
+        for (out, part) in outputs.iter_mut().zip(chunk) {
+            out.extend_from_slice(&part);
+        }
+    }
+    outputs
+}
@@ -0,0 +1,72 @@
+//! Locating a silently corrupted shard from the redundancy alone, for sets
+//! encoded without checksums.
+//!
+//! With more than `k` shards present, the extra ones must equal what any `k`
+//! of the others reconstruct; if they do not, some shard is corrupt. Leaving
+//! out each present shard in turn and re-checking the rest finds it, as long
+//! as at least `k + 1` shards remain to check against each other, i.e. `k + 2`
+//! are present. With exactly `k + 1`, corruption is detected but every
+//! exclusion leaves a consistent set, so it cannot be pinned to one shard.
+
+use anyhow::Result;
+
+use crate::{algorithm::field::GaloisField, codec::reconstruct_shards::Codec};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionCheck {
+    /// The present shards agree, or only `k` are present so there is nothing
+    /// to compare.
+    Consistent,
+    /// The rest agree once this shard, and only this one, is left out.
+    Located(usize),
+    /// The shards disagree, but no single shard explains it: only `k + 1`
+    /// are present, or more than one shard is corrupt.
+    Unlocated,
+}
+
+/// Whether the present shards, ignoring `excluded`, are all consistent with
+/// what the first `k` of them reconstruct.
+fn consistent_without<F: GaloisField>(
+    codec: &Codec<F>,
+    shards: &[Option<Vec<F::Elem>>],
+    excluded: Option<usize>,
+) -> Result<bool> {
+    let k = codec.data_shards();
+    let present: Vec<usize> = (0..shards.len())
+        .filter(|&i| shards[i].is_some() && Some(i) != excluded)
+        .collect();
+    if present.len() <= k {
+        return Ok(true);
+    }
+    let mut trial: Vec<Option<Vec<F::Elem>>> = vec![None; shards.len()];
+    for &i in &present[..k] {
+        trial[i] = shards[i].clone();
+    }
+    codec.reconstruct(&mut trial)?;
+    Ok(present[k..].iter().all(|&i| trial[i] == shards[i]))
+}
+
+/// Checks the present shards against each other and, if they disagree,
+/// looks for the one shard whose removal makes them agree.
+pub fn locate_corruption<F: GaloisField>(
+    codec: &Codec<F>,
+    shards: &[Option<Vec<F::Elem>>],
+) -> Result<CorruptionCheck> {
+    if consistent_without(codec, shards, None)? {
+        return Ok(CorruptionCheck::Consistent);
+    }
+    let present: Vec<usize> = (0..shards.len()).filter(|&i| shards[i].is_some()).collect();
+    if present.len() < codec.data_shards() + 2 {
+        return Ok(CorruptionCheck::Unlocated);
+    }
+    let mut culprits = Vec::new();
+    for &candidate in &present {
+        if consistent_without(codec, shards, Some(candidate))? {
+            culprits.push(candidate);
+        }
+    }
+    Ok(match culprits[..] {
+        [culprit] => CorruptionCheck::Located(culprit),
+        _ => CorruptionCheck::Unlocated,
+    })
+}
@@ -0,0 +1,163 @@
+//! Local reconstruction codes (LRC), as used by Azure and Facebook storage.
+//!
+//! The `k` data shards are split into contiguous groups, and each group gets
+//! one local parity shard: the XOR of its data shards. The `m` global parity
+//! shards are the usual Reed-Solomon parity over all `k` data shards, so the
+//! first `k + m` shards of an LRC set are exactly a plain `k`/`m` set.
+//!
+//! A single lost shard in a group is rebuilt from the rest of its group alone,
+//! reading `group size` shards instead of `k`. Anything the groups cannot fix
+//! falls back to the global parity; afterwards lost local parity is
+//! recomputed. Any `m` lost shards are recoverable, plus one more per group
+//! as long as it is the only loss in its group.
+//!
+//! Shards are numbered data first, then global parity, then local parity in
+//! group order.
+
+use anyhow::Result;
+use std::ops::Range;
+
+use crate::{
+    algorithm::gf256::Gf256,
+    codec::{encode_shards::xor_shards, execution::Execution, reconstruct_shards::Codec},
+    error::RseError,
+};
+
+/// Splits `k` data shards into `groups` contiguous groups whose sizes differ
+/// by at most one, larger groups first.
+pub fn local_groups(k: usize, groups: usize) -> Vec<Range<usize>> {
+    let (base, extra) = (k / groups, k % groups);
+    let mut start = 0;
+    (0..groups)
+        .map(|g| {
+            let len = base + usize::from(g < extra);
+            start += len;
+            start - len..start
+        })
+        .collect()
+}
+
+/// Which shards were rebuilt, and how.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LrcReport {
+    /// Rebuilt from their local group only, including local parity
+    /// recomputed after a global repair.
+    pub local: Vec<usize>,
+    /// Rebuilt using the global parity.
+    pub global: Vec<usize>,
+}
+
+pub struct LrcCodec {
+    k: usize,
+    m: usize,
+    groups: Vec<Range<usize>>,
+    global: Codec,
+    gf: Gf256,
+}
+
+impl LrcCodec {
+    /// `m` global parity shards and `groups` local groups over `k` data shards.
+    pub fn new(k: usize, m: usize, groups: usize) -> Result<Self> {
+        if groups == 0 || groups > k {
+            return Err(RseError::InvalidArgument(format!(
+                "Local groups must be between 1 and the {} data shards, got {}",
+                k, groups
+            ))
+            .into());
+        }
+        Ok(Self {
+            k,
+            m,
+            groups: local_groups(k, groups),
+            global: Codec::try_new(k, m)?,
+            gf: Gf256::new(),
+        })
+    }
+
+    /// Runs the global code in `execution` mode.
+    pub fn with_execution(mut self, execution: Execution) -> Self {
+        self.global = self.global.with_execution(execution);
+        self
+    }
+
+    pub fn total_shards(&self) -> usize {
+        self.k + self.m + self.groups.len()
+    }
+
+    /// Shard index of group `g`'s local parity.
+    pub fn local_parity_index(&self, g: usize) -> usize {
+        self.k + self.m + g
+    }
+
+    /// Every shard of the group that data or local parity shard `index`
+    /// belongs to, local parity last; `None` for a global parity shard.
+    pub fn group_members(&self, index: usize) -> Option<Vec<usize>> {
+        let g = if index < self.k {
+            self.groups.iter().position(|r| r.contains(&index))?
+        } else {
+            index.checked_sub(self.k + self.m)?
+        };
+        let mut members: Vec<usize> = self.groups.get(g)?.clone().collect();
+        members.push(self.local_parity_index(g));
+        Some(members)
+    }
+
+    /// Computes the global then the local parity shards for `data`, returned
+    /// in shard-index order after the data shards.
+    pub fn encode(&self, data: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+        let mut parities = self.global.encode(data)?;
+        for group in &self.groups {
+            let members: Vec<&[u8]> = data[group.clone()].iter().map(Vec::as_slice).collect();
+            parities.push(xor_shards(&self.gf, &members));
+        }
+        Ok(parities)
+    }
+
+    /// Rebuilds every shard that is the only loss in its group, reading only
+    /// that group. Returns the rebuilt indices.
+    pub fn repair_locally(&self, shards: &mut [Option<Vec<u8>>]) -> Vec<usize> {
+        assert_eq!(shards.len(), self.total_shards());
+        let mut repaired = Vec::new();
+        for g in 0..self.groups.len() {
+            let members = self.group_members(self.local_parity_index(g)).unwrap();
+            let mut missing = members.iter().filter(|&&i| shards[i].is_none());
+            let (Some(&lost), None) = (missing.next(), missing.next()) else {
+                continue;
+            };
+            let survivors: Vec<&[u8]> = members
+                .iter()
+                .filter_map(|&i| shards[i].as_deref())
+                .collect();
+            shards[lost] = Some(xor_shards(&self.gf, &survivors));
+            repaired.push(lost);
+        }
+        repaired
+    }
+
+    /// Rebuilds every lost shard: locally where possible, then from the
+    /// global parity, then any lost local parity from the recovered data.
+    pub fn reconstruct(&self, shards: &mut [Option<Vec<u8>>]) -> Result<LrcReport> {
+        let mut report = LrcReport {
+            local: self.repair_locally(shards),
+            global: Vec::new(),
+        };
+
+        let global_len = self.k + self.m;
+        report.global = (0..global_len).filter(|&i| shards[i].is_none()).collect();
+        if !report.global.is_empty() {
+            self.global.reconstruct(&mut shards[..global_len])?;
+        }
+        for g in 0..self.groups.len() {
+            let index = self.local_parity_index(g);
+            if shards[index].is_none() {
+                let members: Vec<&[u8]> = self.groups[g]
+                    .clone()
+                    .map(|i| shards[i].as_deref().unwrap())
+                    .collect();
+                shards[index] = Some(xor_shards(&self.gf, &members));
+                report.local.push(index);
+            }
+        }
+        Ok(report)
+    }
+}
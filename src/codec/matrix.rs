@@ -1,78 +1,217 @@
-use crate::algorithm::gf256::Gf256;
-use anyhow::{anyhow, Result};
-
-pub type Matrix = Vec<Vec<u8>>;
-
-pub fn mul_vec_matrix(gf: &Gf256, vec: &[u8], mat: &Matrix) -> Vec<u8> {
-    let k = mat.len();
-    assert_ne!(k, 0, "Matrix cannot be empty");
-    let cols = mat[0].len();
-    assert_eq!(vec.len(), k, "Vector length must match matrix rows");
-
-    let mut result = vec![0u8; cols];
-    for j in 0..cols {
-        let mut sum = 0;
-        for (i, &v_val) in vec.iter().enumerate() {
-            sum ^= gf.mul(v_val, mat[i][j]);
+use crate::algorithm::{field::GaloisField, shuffle::permutation};
+use crate::error::RseError;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+pub use crate::algorithm::gf_matrix::{
+    InversionScratch, Matrix, add_independent_row, identity, invert_matrix, invert_matrix_into,
+    mul_matrix_matrix, mul_matrix_vec, mul_vec_matrix, submatrix, transpose, unit_row,
+};
+
+/// Version of the matrix constructions below. Bump it whenever a builder
+/// changes the rows it gives for some `k` and `m`: shards encoded before and
+/// after the change no longer combine, and decode refuses to mix them.
+pub const MATRIX_CONSTRUCTION: u32 = 1;
+
+pub fn build_vandermonde<F: GaloisField>(gf: &F, k: usize, m: usize) -> Matrix<F::Elem> {
+    let mut matrix = vec![vec![F::ZERO; k]; m];
+    for (r, row) in matrix.iter_mut().enumerate() {
+        for (c, cell) in row.iter_mut().enumerate() {
+            // Using (r + k) as x value to ensure it's not 0 or 1,
+            // which can create degenerate matrices for some k,m values.
+            *cell = gf.exp((r + k) * c);
         }
-        result[j] = sum;
     }
-    result
+    matrix
 }
 
-pub fn invert_matrix(gf: &Gf256, mat: &[Vec<u8>]) -> Result<Matrix> {
-    let n = mat.len();
-    if n == 0 || mat.iter().any(|r| r.len() != n) {
-        return Err(anyhow!("Matrix must be square"));
-    }
-
-    let mut aug = (0..n)
-        .map(|r| {
-            let mut row = vec![0u8; 2 * n];
-            row[..n].copy_from_slice(&mat[r]);
-            row[n + r] = 1;
-            row
-        })
-        .collect::<Vec<_>>();
-
-    for col in 0..n {
-        let pivot_row = (col..n)
-            .find(|&r| aug[r][col] != 0)
-            .ok_or_else(|| anyhow!("Matrix is singular and cannot be inverted"))?;
-        aug.swap(col, pivot_row);
+/// Evaluation point of shard `i` in [`build_cauchy`]: `y_c` for data shard
+/// `c`, `x_r` for parity shard `k + r`. Error correction relies on it.
+pub fn cauchy_point<F: GaloisField>(i: usize) -> F::Elem {
+    F::element(i)
+}
 
-        let inv_pivot = gf.inv(aug[col][col])?;
-        for j in col..(2 * n) {
-            aug[col][j] = gf.mul(inv_pivot, aug[col][j]);
+/// Builds an `m x k` Cauchy matrix with `C[r][c] = 1 / (x_r + y_c)`, using
+/// the points of [`cauchy_point`]: `x_r = k + r` and `y_c = c`.
+///
+/// The two point sets are disjoint, so every square submatrix is invertible
+/// and `[I; C]` is MDS for any `k + m` up to the field order: any `k` shards
+/// recover the data.
+pub fn build_cauchy<F: GaloisField>(gf: &F, k: usize, m: usize) -> Matrix<F::Elem> {
+    assert!(k + m <= F::ORDER, "k + m must fit in the field");
+    let mut matrix = vec![vec![F::ZERO; k]; m];
+    for (r, row) in matrix.iter_mut().enumerate() {
+        let x = cauchy_point::<F>(k + r);
+        for (c, cell) in row.iter_mut().enumerate() {
+            let y = cauchy_point::<F>(c);
+            *cell = gf.inv(gf.add(x, y)).expect("Cauchy points are disjoint");
         }
+    }
+    matrix
+}
 
-        for row in 0..n {
-            if row == col {
-                continue;
-            }
-            let factor = aug[row][col];
-            if factor == 0 {
-                continue;
+/// Codes with at most this many survivor sets are checked exhaustively by
+/// [`check_mds`]; larger ones are sampled.
+const MDS_EXHAUSTIVE_LIMIT: usize = 4096;
+/// Survivor sets [`check_mds`] samples from a larger code.
+const MDS_SAMPLES: usize = 256;
+
+/// Checks that the systematic code `[I; parity]` is MDS: any `k` of the
+/// `k + m` shards recover the data. A survivor set that lost data shards `C`
+/// and kept parity rows `R` (as many as `C`) recovers iff the submatrix
+/// `parity[R][C]` is invertible.
+///
+/// Every survivor set is checked when there are at most 4096 of them,
+/// otherwise a fixed sample of 256, so a failure is reproducible. The error
+/// names the first survivor set that cannot recover the data.
+pub fn check_mds<F: GaloisField>(gf: &F, parity: &Matrix<F::Elem>, k: usize) -> Result<()> {
+    let m = parity.len();
+    let n = k + m;
+    let check_lost =
+        |lost: &[usize], scratch: &mut InversionScratch<F::Elem>, inverse: &mut Matrix<F::Elem>| {
+            let lost_data: Vec<usize> = lost.iter().copied().filter(|&i| i < k).collect();
+            if lost_data.is_empty() {
+                return Ok(());
             }
-            for j in col..(2 * n) {
-                let prod = gf.mul(factor, aug[col][j]);
-                aug[row][j] ^= prod;
+            let kept_parity = (0..m).filter(|r| !lost.contains(&(k + r)));
+            let sub: Matrix<F::Elem> = kept_parity
+                .map(|r| lost_data.iter().map(|&c| parity[r][c]).collect())
+                .collect();
+            invert_matrix_into(gf, &sub, scratch, inverse).map_err(|_| {
+                let survivors: Vec<usize> = (0..n).filter(|i| !lost.contains(i)).collect();
+                anyhow!(
+                    "Encoding matrix is not MDS: survivors {:?} cannot recover the data",
+                    survivors
+                )
+            })
+        };
+
+    let (mut scratch, mut inverse) = (InversionScratch::new(), Matrix::new());
+    if binomial(n, m) <= MDS_EXHAUSTIVE_LIMIT {
+        // Walk every m-subset of lost shards in lexicographic order.
+        let mut lost: Vec<usize> = (0..m).collect();
+        loop {
+            check_lost(&lost, &mut scratch, &mut inverse)?;
+            let Some(i) = (0..m).rev().find(|&i| lost[i] < n - m + i) else {
+                return Ok(());
+            };
+            lost[i] += 1;
+            for j in i + 1..m {
+                lost[j] = lost[j - 1] + 1;
             }
         }
     }
+    for sample in 0..MDS_SAMPLES {
+        let mut lost = permutation(n, sample as u64);
+        lost.truncate(m);
+        lost.sort_unstable();
+        check_lost(&lost, &mut scratch, &mut inverse)?;
+    }
+    Ok(())
+}
 
-    let inv = aug.into_iter().map(|row| row[n..].to_vec()).collect();
-    Ok(inv)
+/// `n` choose `r`, saturating at `usize::MAX`.
+fn binomial(n: usize, r: usize) -> usize {
+    let r = r.min(n - r);
+    (0..r)
+        .try_fold(1usize, |acc, i| acc.checked_mul(n - i).map(|v| v / (i + 1)))
+        .unwrap_or(usize::MAX)
 }
 
-pub fn build_vandermonde(gf: &Gf256, k: usize, m: usize) -> Matrix {
-    let mut matrix = vec![vec![0u8; k]; m];
-    for r in 0..m {
-        for c in 0..k {
-            // Using (r + k) as x value to ensure it's not 0 or 1,
-            // which can create degenerate matrices for some k,m values.
-            matrix[r][c] = gf.exp[((r + k) as i32 * c as i32 % 255) as usize];
+/// Whether `matrix` is a single all-ones row, so its parity is the plain XOR
+/// of the data shards.
+pub fn is_xor_parity<F: GaloisField>(matrix: &[Vec<F::Elem>]) -> bool {
+    matrix.len() == 1 && matrix[0].iter().all(|&c| c == F::ONE)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "full", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum MatrixType {
+    #[default]
+    Vandermonde,
+    Cauchy,
+    /// A single all-ones parity row: RAID5-style XOR parity. Only valid for `m == 1`.
+    Xor,
+    /// Rows supplied by the user, e.g. with `--matrix-file`, rather than built.
+    #[cfg_attr(feature = "full", value(skip))]
+    Custom,
+}
+
+impl MatrixType {
+    /// Builds the `m x k` parity rows. Fails for [`MatrixType::Custom`],
+    /// whose rows are supplied rather than built.
+    pub fn build<F: GaloisField>(self, gf: &F, k: usize, m: usize) -> Result<Matrix<F::Elem>> {
+        Ok(match self {
+            MatrixType::Vandermonde => build_vandermonde(gf, k, m),
+            MatrixType::Cauchy => build_cauchy(gf, k, m),
+            MatrixType::Xor => vec![vec![F::ONE; k]; m],
+            MatrixType::Custom => {
+                return Err(RseError::InvalidArgument(
+                    "A custom matrix cannot be built; pass its rows instead".into(),
+                )
+                .into());
+            }
+        })
+    }
+}
+
+/// Formats a matrix as a grid of hex bytes, one row per line, with row and
+/// column indices. The output is deterministic for use in bug reports.
+pub fn format_matrix_hex(matrix: &[Vec<u8>]) -> String {
+    let cols = matrix.first().map_or(0, |row| row.len());
+    let mut out = String::from("     ");
+    for c in 0..cols {
+        out.push_str(&format!(" {:>2}", c));
+    }
+    out.push('\n');
+    for (r, row) in matrix.iter().enumerate() {
+        out.push_str(&format!("{:>4}:", r));
+        for value in row {
+            out.push_str(&format!(" {:02x}", value));
         }
+        out.push('\n');
     }
+    out
+}
+
+/// Serializes a matrix as one hex string per row, two digits per element and
+/// no separators, for replicating it in another implementation.
+pub fn matrix_to_hex_rows(matrix: &[Vec<u8>]) -> Vec<String> {
     matrix
+        .iter()
+        .map(|row| row.iter().map(|value| format!("{:02x}", value)).collect())
+        .collect()
+}
+
+/// Parses rows written by [`matrix_to_hex_rows`]. Every row must have the
+/// same number of elements.
+pub fn matrix_from_hex_rows<S: AsRef<str>>(rows: &[S]) -> Result<Matrix> {
+    let matrix = rows
+        .iter()
+        .enumerate()
+        .map(|(r, row)| {
+            let row = row.as_ref();
+            if row.len() % 2 != 0 || !row.is_ascii() {
+                return Err(anyhow!("Row {} is not a sequence of hex byte pairs", r));
+            }
+            (0..row.len())
+                .step_by(2)
+                .map(|i| {
+                    u8::from_str_radix(&row[i..i + 2], 16)
+                        .map_err(|e| anyhow!("Row {}: invalid hex at column {}: {}", r, i / 2, e))
+                })
+                .collect()
+        })
+        .collect::<Result<Matrix>>()?;
+    if matrix.iter().any(|row| row.len() != matrix[0].len()) {
+        return Err(anyhow!("Matrix rows have different lengths"));
+    }
+    Ok(matrix)
+}
+
+/// Flattens a matrix into row-major bytes: element `(r, c)` is at
+/// `r * cols + c`.
+pub fn matrix_to_bytes(matrix: &[Vec<u8>]) -> Vec<u8> {
+    matrix.concat()
 }
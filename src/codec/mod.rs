@@ -1,3 +1,11 @@
-pub mod matrix;
+#[cfg(feature = "full")]
+pub mod channel;
+pub mod correct;
 pub mod encode_shards;
-pub mod reconstruct_shards; 
\ No newline at end of file
+pub mod execution;
+pub mod layout;
+pub mod locate;
+pub mod lrc;
+pub mod matrix;
+pub mod product;
+pub mod reconstruct_shards;
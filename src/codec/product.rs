@@ -0,0 +1,199 @@
+//! Two-dimensional product code built from the one-dimensional [`Codec`].
+//!
+//! Data shards are laid out in a grid of `col_data` rows by `row_data`
+//! columns. Every row gets `row_parity` parity shards, then every column
+//! (including the row-parity columns) gets `col_parity` parity rows. Each row
+//! and each column of the full grid is then a codeword of its 1D code, so
+//! decoding alternates row and column reconstruction until nothing changes.
+//!
+//! This tolerates burst patterns a single code of the same row parameters
+//! cannot, such as a whole row of shards lost at once. The guarantee is
+//! weaker than an MDS code with the same total parity: any
+//! `(row_parity + 1) * (col_parity + 1) - 1` losses are recoverable.
+//!
+//! Shards are numbered so the `row_data * col_data` data shards come first in
+//! input order, followed by the parity shards in grid row-major order.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{execution::Execution, reconstruct_shards::Codec};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProductGeometry {
+    /// Data shards per row.
+    pub row_data: usize,
+    /// Parity shards per row.
+    pub row_parity: usize,
+    /// Data rows.
+    pub col_data: usize,
+    /// Parity rows.
+    pub col_parity: usize,
+}
+
+impl ProductGeometry {
+    /// Shards per row of the full grid.
+    pub fn row_len(&self) -> usize {
+        self.row_data + self.row_parity
+    }
+
+    /// Rows of the full grid.
+    pub fn col_len(&self) -> usize {
+        self.col_data + self.col_parity
+    }
+
+    pub fn data_shards(&self) -> usize {
+        self.row_data * self.col_data
+    }
+
+    pub fn total_shards(&self) -> usize {
+        self.row_len() * self.col_len()
+    }
+
+    /// Number of lost shards that is always recoverable, whatever the pattern.
+    pub fn guaranteed_tolerance(&self) -> usize {
+        (self.row_parity + 1) * (self.col_parity + 1) - 1
+    }
+
+    /// Grid `(row, column)` of shard `index`.
+    pub fn cell(&self, index: usize) -> (usize, usize) {
+        let k = self.data_shards();
+        if index < k {
+            return (index / self.row_data, index % self.row_data);
+        }
+        let p = index - k;
+        let row_parity_cells = self.col_data * self.row_parity;
+        if p < row_parity_cells {
+            (p / self.row_parity, self.row_data + p % self.row_parity)
+        } else {
+            let q = p - row_parity_cells;
+            (self.col_data + q / self.row_len(), q % self.row_len())
+        }
+    }
+
+    /// Shard index of grid cell `(row, column)`; the inverse of [`ProductGeometry::cell`].
+    pub fn index(&self, row: usize, col: usize) -> usize {
+        if row < self.col_data && col < self.row_data {
+            row * self.row_data + col
+        } else if row < self.col_data {
+            self.data_shards() + row * self.row_parity + (col - self.row_data)
+        } else {
+            self.data_shards()
+                + self.col_data * self.row_parity
+                + (row - self.col_data) * self.row_len()
+                + col
+        }
+    }
+}
+
+pub struct ProductCodec {
+    geometry: ProductGeometry,
+    rows: Codec,
+    cols: Codec,
+}
+
+impl ProductCodec {
+    pub fn new(geometry: ProductGeometry) -> Result<Self> {
+        Ok(Self {
+            geometry,
+            rows: Codec::try_new(geometry.row_data, geometry.row_parity)?,
+            cols: Codec::try_new(geometry.col_data, geometry.col_parity)?,
+        })
+    }
+
+    /// Runs the row and column codes, and the loops over them, in `execution` mode.
+    pub fn with_execution(mut self, execution: Execution) -> Self {
+        self.rows = self.rows.with_execution(execution.clone());
+        self.cols = self.cols.with_execution(execution);
+        self
+    }
+
+    pub fn geometry(&self) -> ProductGeometry {
+        self.geometry
+    }
+
+    /// Computes every parity shard for `data` (the data shards in input
+    /// order, all the same length), returned in shard-index order after the
+    /// data shards.
+    pub fn encode(&self, data: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+        let g = self.geometry;
+        let rows: Vec<&[Vec<u8>]> = data.chunks(g.row_data).collect();
+        let execution = self.rows.execution();
+        let row_parities: Vec<Vec<Vec<u8>>> = execution
+            .map(&rows, |row| self.rows.encode(row))
+            .into_iter()
+            .collect::<Result<_>>()?;
+
+        // Column codewords run down every column of the data rows, including
+        // the row-parity columns just computed.
+        let cols: Vec<usize> = (0..g.row_len()).collect();
+        let col_parities: Vec<Vec<Vec<u8>>> = execution
+            .map(&cols, |&col| {
+                let column: Vec<Vec<u8>> = (0..g.col_data)
+                    .map(|row| {
+                        if col < g.row_data {
+                            data[row * g.row_data + col].clone()
+                        } else {
+                            row_parities[row][col - g.row_data].clone()
+                        }
+                    })
+                    .collect();
+                self.cols.encode(&column)
+            })
+            .into_iter()
+            .collect::<Result<_>>()?;
+
+        let mut parities = Vec::with_capacity(g.total_shards() - g.data_shards());
+        parities.extend(row_parities.into_iter().flatten());
+        for parity_row in 0..g.col_parity {
+            for column in &col_parities {
+                parities.push(column[parity_row].clone());
+            }
+        }
+        Ok(parities)
+    }
+
+    /// Alternates row and column reconstruction until a full sweep recovers
+    /// nothing more. Shards that cannot be recovered are left as `None`.
+    /// Returns the number of sweeps that recovered at least one shard.
+    pub fn reconstruct(&self, shards: &mut [Option<Vec<u8>>]) -> Result<usize> {
+        let g = self.geometry;
+        assert_eq!(shards.len(), g.total_shards());
+        let mut passes = 0;
+        loop {
+            let mut progress = false;
+            for row in 0..g.col_len() {
+                let cells: Vec<usize> = (0..g.row_len()).map(|col| g.index(row, col)).collect();
+                progress |= Self::reconstruct_line(&self.rows, shards, &cells)?;
+            }
+            for col in 0..g.row_len() {
+                let cells: Vec<usize> = (0..g.col_len()).map(|row| g.index(row, col)).collect();
+                progress |= Self::reconstruct_line(&self.cols, shards, &cells)?;
+            }
+            if !progress {
+                return Ok(passes);
+            }
+            passes += 1;
+        }
+    }
+
+    /// Reconstructs one row or column (the shards at `cells`, in codeword
+    /// order) if it has losses and enough survivors. Returns whether it did.
+    fn reconstruct_line(
+        codec: &Codec,
+        shards: &mut [Option<Vec<u8>>],
+        cells: &[usize],
+    ) -> Result<bool> {
+        let present = cells.iter().filter(|&&i| shards[i].is_some()).count();
+        if present == cells.len() || present < codec.data_shards() {
+            return Ok(false);
+        }
+        let mut line: Vec<Option<Vec<u8>>> = cells.iter().map(|&i| shards[i].take()).collect();
+        let result = codec.reconstruct(&mut line);
+        for (&i, shard) in cells.iter().zip(line) {
+            shards[i] = shard;
+        }
+        result?;
+        Ok(true)
+    }
+}
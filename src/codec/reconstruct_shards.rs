@@ -1,142 +1,1372 @@
 use crate::{
-    algorithm::gf256::Gf256,
-    codec::matrix::{Matrix, build_vandermonde, invert_matrix, mul_vec_matrix},
+    algorithm::{field::GaloisField, gf256::Gf256},
+    codec::{
+        encode_shards::{shard_encoding, shard_encoding_into},
+        execution::Execution,
+        layout::{ShardLayout, recover_interleaved},
+        matrix::{
+            Matrix, MatrixType, add_independent_row, identity, invert_matrix, matrix_from_hex_rows,
+            mul_matrix_matrix, unit_row,
+        },
+    },
+    error::RseError,
 };
 use anyhow::{Context, Result, anyhow};
 use dashmap::DashMap;
-use rayon::prelude::*;
+use std::{
+    collections::BTreeSet,
+    fmt,
+    ops::{Deref, Range},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 use tracing::{info_span, instrument};
 
-pub struct Codec {
+/// Recovered shards paired with their indices.
+type Recovered<E> = Vec<(usize, Vec<E>)>;
+
+/// What a single reconstruction did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconstructReport {
+    /// Indices of the shards that were recovered, ascending.
+    pub recovered: Vec<usize>,
+    /// Indices of the `k` shards they were recovered from. Empty if nothing
+    /// needed recovering.
+    pub survivors: Vec<usize>,
+    /// Whether the inverse for `survivors` came from the codec's cache.
+    pub cache_hit: bool,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for ReconstructReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.recovered.is_empty() {
+            return write!(f, "nothing to recover");
+        }
+        write!(
+            f,
+            "recovered shards {:?} from survivors {:?} (inverse {}) in {:.2?}",
+            self.recovered,
+            self.survivors,
+            if self.cache_hit { "cached" } else { "computed" },
+            self.elapsed
+        )
+    }
+}
+
+/// The length every one of the `present` shards shares. Data and parity
+/// rows are combined element by element, so a shard of another length would
+/// silently cut the computation short. The most common length is taken as
+/// the right one, and the first shard of a different length is named.
+fn common_shard_len<E>(present: &[(usize, &[E])]) -> Result<usize> {
+    let Some(&(_, first)) = present.first() else {
+        return Ok(0);
+    };
+    if present.iter().all(|(_, s)| s.len() == first.len()) {
+        return Ok(first.len());
+    }
+    let count = |len: usize| present.iter().filter(|(_, s)| s.len() == len).count();
+    let expected = present
+        .iter()
+        .map(|(_, s)| s.len())
+        .fold(first.len(), |best, len| {
+            if count(len) > count(best) { len } else { best }
+        });
+    match present.iter().find(|(_, s)| s.len() != expected) {
+        Some(&(i, shard)) => Err(RseError::Corruption(format!(
+            "Shard {} has {} elements, but the other present shards have {}; all present \
+             shards must share one length",
+            i,
+            shard.len(),
+            expected
+        ))
+        .into()),
+        None => Ok(expected),
+    }
+}
+
+fn nonzero_count<F: GaloisField>(row: &[F::Elem]) -> usize {
+    row.iter().filter(|&&c| c != F::ZERO).count()
+}
+
+/// How a [`Codec`] picks the `k` survivors to reconstruct from when more
+/// than `k` shards are present.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SurvivorSelection {
+    /// The `k` lowest indices: every present data shard, then parity in order.
+    #[default]
+    FirstK,
+    /// Every present data shard, then the sparsest useful parity rows; see
+    /// [`sparsest_survivors`].
+    Sparsest,
+}
+
+/// Picks `k` of the `present` shard indices (ascending) so the recovery
+/// matrix tends to be sparse. Present data shards are always used, since
+/// their identity rows cost nothing. The remaining slots go to parity rows
+/// that involve at least one missing data shard, preferring rows with more
+/// zero coefficients (no work) and then more one coefficients (a plain XOR);
+/// ties keep index order. Rows that involve no missing data shard go last.
+///
+/// This is a heuristic: the chosen rows may still be dependent for a
+/// matrix that is not MDS, in which case the caller should fall back.
+pub fn sparsest_survivors<F: GaloisField>(
+    k: usize,
+    encode_matrix: &[Vec<F::Elem>],
+    present: &[usize],
+) -> Vec<usize> {
+    let (mut survivors, mut parity): (Vec<usize>, Vec<usize>) =
+        present.iter().partition(|&&i| i < k);
+    let missing_data: Vec<usize> = (0..k).filter(|i| !survivors.contains(i)).collect();
+    parity.sort_by_key(|&i| {
+        let row = &encode_matrix[i - k];
+        let useless = missing_data.iter().all(|&c| row[c] == F::ZERO);
+        let nonzero = row.iter().filter(|&&c| c != F::ZERO).count();
+        let non_one = row.iter().filter(|&&c| c != F::ZERO && c != F::ONE).count();
+        (useless, nonzero, non_one)
+    });
+    survivors.extend(parity.into_iter().take(k.saturating_sub(survivors.len())));
+    survivors.sort_unstable();
+    survivors
+}
+
+/// Erasure codec over the field `F`; shards are sequences of `F::Elem`.
+///
+/// The field tables, matrix, inverse cache and counters are reference
+/// counted, so [`Codec::handle`] can hand out cheap views that share them.
+pub struct Codec<F: GaloisField = Gf256> {
     k: usize,
     m: usize,
     n: usize,
-    gf: Gf256,
+    gf: Arc<F>,
     /// Encoding matrix, also used for reconstruction.
-    /// This is a Vandermonde matrix of size m x k.
-    encode_matrix: Matrix,
+    /// This is a Vandermonde or Cauchy matrix of size m x k.
+    encode_matrix: Arc<Matrix<F::Elem>>,
     /// Cache for inverted matrices, keyed by the sorted indices of survivor shards.
-    inverse_matrix_cache: DashMap<Vec<usize>, Matrix>,
+    inverse_matrix_cache: Arc<DashMap<Vec<usize>, Matrix<F::Elem>>>,
+    /// Multiply-accumulate passes over survivor shards done by reconstruction.
+    mac_passes: Arc<AtomicUsize>,
+    /// The subset of `mac_passes` with a nonzero coefficient, i.e. actual work.
+    nonzero_mac_passes: Arc<AtomicUsize>,
+    survivor_selection: SurvivorSelection,
+    execution: Execution,
+}
+
+/// A cheap, cloneable view of a [`Codec`], from [`Codec::handle`].
+///
+/// Every handle, and every clone of one, shares the field tables, encoding
+/// matrix, inverse cache and MAC counters of the codec it came from: an
+/// inverse computed through one is a cache hit for all the others and for
+/// the codec itself, and nothing is copied but a few reference counts. The
+/// survivor selection and execution mode are copied when the handle is made. A handle derefs to
+/// [`Codec`], so it encodes and reconstructs exactly as the codec does; it
+/// is meant to be moved into per-request tasks or threads of a server.
+pub struct CodecHandle<F: GaloisField = Gf256> {
+    codec: Codec<F>,
+}
+
+impl<F: GaloisField> Clone for CodecHandle<F> {
+    fn clone(&self) -> Self {
+        self.codec.handle()
+    }
+}
+
+impl<F: GaloisField> Deref for CodecHandle<F> {
+    type Target = Codec<F>;
+
+    fn deref(&self) -> &Codec<F> {
+        &self.codec
+    }
 }
 
 impl Codec {
+    /// Creates a codec for `k` data and `m` parity shards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the parameters are invalid; see [`Codec::try_new`].
     pub fn new(k: usize, m: usize) -> Self {
-        let gf = Gf256::new();
-        let encode_matrix = build_vandermonde(&gf, k, m);
-        Self {
+        Self::try_new(k, m).expect("invalid codec parameters")
+    }
+
+    /// Creates a codec for `k` data and `m` parity shards, rejecting parameters
+    /// the field cannot support.
+    pub fn try_new(k: usize, m: usize) -> Result<Self> {
+        Self::try_with_matrix_type(k, m, MatrixType::Vandermonde)
+    }
+
+    /// Like [`Codec::try_new`], but builds the given kind of encoding matrix.
+    pub fn try_with_matrix_type(k: usize, m: usize, matrix_type: MatrixType) -> Result<Self> {
+        Codec::with_field(Gf256::new(), k, m, matrix_type)
+    }
+
+    /// Creates a codec with caller-supplied `m x k` parity rows, e.g. the
+    /// matrix of another implementation. See [`Codec::with_field_matrix`].
+    pub fn with_matrix(k: usize, m: usize, encode_matrix: Matrix) -> Result<Self> {
+        Codec::with_field_matrix(Gf256::new(), k, m, encode_matrix)
+    }
+
+    /// The codec a shard set's metadata records: its hex `custom_matrix`
+    /// rows if any, otherwise its `matrix_type` (Vandermonde if unset).
+    pub fn from_recorded(
+        k: usize,
+        m: usize,
+        matrix_type: Option<MatrixType>,
+        custom_matrix: Option<&[String]>,
+    ) -> Result<Self> {
+        match custom_matrix {
+            Some(rows) => Codec::with_matrix(k, m, matrix_from_hex_rows(rows)?),
+            None => Codec::try_with_matrix_type(k, m, matrix_type.unwrap_or_default()),
+        }
+    }
+
+    /// Checks that `k > 0`, `m > 0` and `k + m` fits in GF(2^8).
+    pub fn validate_params(k: usize, m: usize) -> Result<()> {
+        Codec::<Gf256>::validate_params_for_field(k, m)
+    }
+}
+
+impl<F: GaloisField> Codec<F> {
+    /// Creates a codec over an arbitrary field implementation.
+    pub fn with_field(gf: F, k: usize, m: usize, matrix_type: MatrixType) -> Result<Self> {
+        Self::validate_params_for_field(k, m)?;
+        if matrix_type == MatrixType::Xor && m != 1 {
+            return Err(RseError::InvalidArgument(format!(
+                "XOR parity needs exactly one parity shard, got {}",
+                m
+            ))
+            .into());
+        }
+        let encode_matrix = matrix_type.build(&gf, k, m)?;
+        // Warn rather than fail: the Vandermonde construction is not MDS for
+        // every k/m, and debug builds must accept what release builds do.
+        #[cfg(debug_assertions)]
+        if let Err(e) = crate::codec::matrix::check_mds(&gf, &encode_matrix, k) {
+            tracing::warn!("{:?} k={} m={}: {:#}", matrix_type, k, m, e);
+        }
+        Self::with_field_matrix(gf, k, m, encode_matrix)
+    }
+
+    /// Creates a codec over an arbitrary field with the given `m x k` parity
+    /// rows. The matrix is not checked to be MDS; see
+    /// [`crate::codec::matrix::check_mds`].
+    pub fn with_field_matrix(
+        gf: F,
+        k: usize,
+        m: usize,
+        encode_matrix: Matrix<F::Elem>,
+    ) -> Result<Self> {
+        Self::validate_params_for_field(k, m)?;
+        let codec = Self {
             k,
             m,
             n: k + m,
-            gf,
-            encode_matrix,
-            inverse_matrix_cache: DashMap::new(),
+            gf: Arc::new(gf),
+            encode_matrix: Arc::new(encode_matrix),
+            inverse_matrix_cache: Arc::default(),
+            mac_passes: Arc::default(),
+            nonzero_mac_passes: Arc::default(),
+            survivor_selection: SurvivorSelection::default(),
+            execution: Execution::default(),
+        };
+        codec
+            .validate_matrix(&codec.encode_matrix)
+            .map_err(|e| RseError::InvalidArgument(e.to_string()))?;
+        Ok(codec)
+    }
+
+    /// Sets how survivors are chosen when more than `k` shards are present.
+    pub fn with_survivor_selection(mut self, selection: SurvivorSelection) -> Self {
+        self.survivor_selection = selection;
+        self
+    }
+
+    /// Sets whether encoding and reconstruction loop over shards serially or
+    /// in parallel.
+    pub fn with_execution(mut self, execution: Execution) -> Self {
+        self.execution = execution;
+        self
+    }
+
+    pub fn execution(&self) -> &Execution {
+        &self.execution
+    }
+
+    /// A view of this codec that shares its tables and inverse cache and is
+    /// cheap to clone; see [`CodecHandle`].
+    pub fn handle(&self) -> CodecHandle<F> {
+        CodecHandle {
+            codec: Self {
+                k: self.k,
+                m: self.m,
+                n: self.n,
+                gf: Arc::clone(&self.gf),
+                encode_matrix: Arc::clone(&self.encode_matrix),
+                inverse_matrix_cache: Arc::clone(&self.inverse_matrix_cache),
+                mac_passes: Arc::clone(&self.mac_passes),
+                nonzero_mac_passes: Arc::clone(&self.nonzero_mac_passes),
+                survivor_selection: self.survivor_selection,
+                execution: self.execution.clone(),
+            },
+        }
+    }
+
+    /// The `m x k` parity rows of the encoding matrix.
+    pub fn encode_matrix(&self) -> &Matrix<F::Elem> {
+        &self.encode_matrix
+    }
+
+    /// The field the codec computes in.
+    pub fn field(&self) -> &F {
+        &self.gf
+    }
+
+    pub fn data_shards(&self) -> usize {
+        self.k
+    }
+
+    pub fn parity_shards(&self) -> usize {
+        self.m
+    }
+
+    pub fn total_shards(&self) -> usize {
+        self.n
+    }
+
+    /// Checks that `k > 0`, `m > 0` and `k + m` fits in the field.
+    pub fn validate_params_for_field(k: usize, m: usize) -> Result<()> {
+        if k == 0 || m == 0 || k + m > F::ORDER {
+            return Err(RseError::InvalidArgument(format!(
+                "Invalid k/m values ({}, {}). Must be > 0 and k+m <= {}",
+                k,
+                m,
+                F::ORDER
+            ))
+            .into());
         }
+        Ok(())
     }
 
-    fn get_or_compute_inverse_matrix(&self, survivors: &[usize]) -> Result<Matrix> {
+    /// Checks that a caller-supplied coefficient matrix is `m x k`, matching
+    /// the shard layout this codec was built for.
+    fn validate_matrix(&self, matrix: &[Vec<F::Elem>]) -> Result<()> {
+        if matrix.len() != self.m {
+            return Err(anyhow!(
+                "Custom matrix must have {} rows (one per parity shard), got {}",
+                self.m,
+                matrix.len()
+            ));
+        }
+        if let Some((r, row)) = matrix
+            .iter()
+            .enumerate()
+            .find(|(_, row)| row.len() != self.k)
+        {
+            return Err(anyhow!(
+                "Custom matrix row {} must have {} columns (one per data shard), got {}",
+                r,
+                self.k,
+                row.len()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Builds the k x k matrix `A` from the survivor shards.
+    fn survivor_matrix(
+        &self,
+        encode_matrix: &[Vec<F::Elem>],
+        survivors: &[usize],
+    ) -> Matrix<F::Elem> {
+        // The rows of `A` are the survivors' rows of the generator.
+        survivors
+            .iter()
+            .map(|&i| self.generator_row(encode_matrix, i))
+            .collect()
+    }
+
+    fn compute_inverse_matrix(
+        &self,
+        encode_matrix: &[Vec<F::Elem>],
+        survivors: &[usize],
+    ) -> Result<Matrix<F::Elem>> {
+        let a = self.survivor_matrix(encode_matrix, survivors);
+        invert_matrix(&*self.gf, &a)
+            .with_context(|| format!("Failed to invert matrix for survivors: {:?}", survivors))
+    }
+
+    /// Returns the `k x k` matrix that recovers the data shards from the given
+    /// survivor shards, using the inverse cache. Column `j` of the result
+    /// applies to the `j`-th survivor in ascending index order.
+    pub fn inverse_matrix(&self, survivors: &[usize]) -> Result<Matrix<F::Elem>> {
+        if survivors.len() != self.k {
+            return Err(anyhow!(
+                "Need exactly {} survivors, got {}",
+                self.k,
+                survivors.len()
+            ));
+        }
+        if let Some(&bad) = survivors.iter().find(|&&i| i >= self.n) {
+            return Err(anyhow!("Survivor index {} is out of range", bad));
+        }
+        let mut sorted = survivors.to_vec();
+        sorted.sort_unstable();
+        self.get_or_compute_inverse_matrix(&sorted)
+            .map(|(inverse, _)| inverse)
+    }
+
+    /// Returns the inverse for `survivors` and whether it was already cached.
+    fn get_or_compute_inverse_matrix(
+        &self,
+        survivors: &[usize],
+    ) -> Result<(Matrix<F::Elem>, bool)> {
         let mut key = survivors.to_vec();
         key.sort_unstable();
 
         if let Some(cached_inv) = self.inverse_matrix_cache.get(&key) {
-            return Ok(cached_inv.value().clone());
+            return Ok((cached_inv.value().clone(), true));
         }
 
-        // Build the k x k matrix `A` from the survivor shards.
-        // The rows of `A` are the rows of the original encoding matrix.
-        // If the survivor is a data shard i < k, the row is an identity row.
-        // If the survivor is a parity shard i >= k, the row is from the Vandermonde matrix.
-        let mut a = vec![vec![0u8; self.k]; self.k];
-        for (row_idx, &global_row_idx) in survivors.iter().enumerate() {
-            if global_row_idx < self.k {
-                a[row_idx][global_row_idx] = 1;
-            } else {
-                a[row_idx].copy_from_slice(&self.encode_matrix[global_row_idx - self.k]);
+        let inverted = self.compute_inverse_matrix(&self.encode_matrix, survivors)?;
+
+        self.inverse_matrix_cache.insert(key, inverted.clone());
+        Ok((inverted, false))
+    }
+
+    /// Like [`Codec::get_or_compute_inverse_matrix`], but leaves the cache
+    /// as it is.
+    fn peek_or_compute_inverse_matrix(
+        &self,
+        survivors: &[usize],
+    ) -> Result<(Matrix<F::Elem>, bool)> {
+        let mut key = survivors.to_vec();
+        key.sort_unstable();
+        match self.inverse_matrix_cache.get(&key) {
+            Some(cached_inv) => Ok((cached_inv.value().clone(), true)),
+            None => Ok((
+                self.compute_inverse_matrix(&self.encode_matrix, survivors)?,
+                false,
+            )),
+        }
+    }
+
+    /// Number of survivor sets whose inverse is cached.
+    pub fn cached_inverses(&self) -> usize {
+        self.inverse_matrix_cache.len()
+    }
+
+    /// Returns a snapshot of every cached inverse, keyed by sorted survivor
+    /// indices, so it can be persisted and later passed to [`Codec::import_cache`].
+    pub fn export_cache(&self) -> Vec<(Vec<usize>, Matrix<F::Elem>)> {
+        let mut entries: Vec<(Vec<usize>, Matrix<F::Elem>)> = self
+            .inverse_matrix_cache
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    /// Warm-starts the inverse cache with entries from [`Codec::export_cache`].
+    ///
+    /// Each entry is checked against this codec's encoding matrix before it is
+    /// trusted: the survivor set must be valid and the matrix must actually
+    /// invert the corresponding survivor submatrix. Nothing is imported if any
+    /// entry is rejected. Returns the number of entries imported.
+    pub fn import_cache(&self, entries: Vec<(Vec<usize>, Matrix<F::Elem>)>) -> Result<usize> {
+        let identity = identity::<F>(self.k);
+        for (survivors, inverse) in &entries {
+            if survivors.len() != self.k
+                || survivors.windows(2).any(|w| w[0] >= w[1])
+                || survivors.iter().any(|&i| i >= self.n)
+            {
+                return Err(anyhow!(
+                    "Cached survivor set {:?} is not {} sorted, distinct indices below {}",
+                    survivors,
+                    self.k,
+                    self.n
+                ));
             }
+            if inverse.len() != self.k || inverse.iter().any(|row| row.len() != self.k) {
+                return Err(anyhow!(
+                    "Cached inverse for survivors {:?} is not {}x{}",
+                    survivors,
+                    self.k,
+                    self.k
+                ));
+            }
+            let a = self.survivor_matrix(&self.encode_matrix, survivors);
+            if mul_matrix_matrix(&*self.gf, inverse, &a) != identity {
+                return Err(anyhow!(
+                    "Cached inverse for survivors {:?} does not invert the survivor matrix",
+                    survivors
+                ));
+            }
+        }
+
+        let count = entries.len();
+        for (survivors, inverse) in entries {
+            self.inverse_matrix_cache.insert(survivors, inverse);
         }
+        Ok(count)
+    }
 
-        let inverted = invert_matrix(&self.gf, &a)
-            .with_context(|| format!("Failed to invert matrix for survivors: {:?}", survivors))?;
+    /// Computes the `m` parity shards for `data_shards`.
+    pub fn encode(&self, data_shards: &[Vec<F::Elem>]) -> Result<Vec<Vec<F::Elem>>> {
+        self.encode_with_matrix(data_shards, &self.encode_matrix)
+    }
 
-        self.inverse_matrix_cache.insert(key, inverted.clone());
-        Ok(inverted)
+    /// Like [`Codec::encode`], but writes the parity into `parity_out`, `m`
+    /// caller-owned buffers as long as the data shards, instead of
+    /// allocating. Lets a server reuse pooled buffers across encodes.
+    pub fn encode_into(
+        &self,
+        data_shards: &[&[F::Elem]],
+        parity_out: &mut [&mut [F::Elem]],
+    ) -> Result<()> {
+        if data_shards.len() != self.k {
+            return Err(anyhow!(
+                "Expected {} data shards, got {}",
+                self.k,
+                data_shards.len()
+            ));
+        }
+        shard_encoding_into(
+            &*self.gf,
+            &self.encode_matrix,
+            data_shards,
+            parity_out,
+            &(),
+            &self.execution,
+        )
+    }
+
+    /// Computes the `m` parity shards for `data_shards` using a caller-supplied
+    /// `m x k` coefficient matrix instead of the built-in Vandermonde matrix.
+    ///
+    /// This allows unequal erasure protection, e.g. parity rows that only
+    /// cover the most important data shards.
+    pub fn encode_with_matrix(
+        &self,
+        data_shards: &[Vec<F::Elem>],
+        matrix: &[Vec<F::Elem>],
+    ) -> Result<Vec<Vec<F::Elem>>> {
+        self.validate_matrix(matrix)?;
+        if data_shards.len() != self.k {
+            return Err(anyhow!(
+                "Expected {} data shards, got {}",
+                self.k,
+                data_shards.len()
+            ));
+        }
+        shard_encoding(&*self.gf, matrix, data_shards, &(), &self.execution)
+    }
+
+    /// Returns the sorted data shard indices covering the given byte ranges of
+    /// the original input, for use with [`Codec::update_parity_ranges`].
+    pub fn shards_for_ranges(&self, ranges: &[Range<usize>], shard_len: usize) -> Vec<usize> {
+        if shard_len == 0 {
+            return vec![];
+        }
+        let mut touched = BTreeSet::new();
+        for range in ranges.iter().filter(|r| !r.is_empty()) {
+            let first = range.start / shard_len;
+            let last = ((range.end - 1) / shard_len).min(self.k - 1);
+            touched.extend(first..=last);
+        }
+        touched.into_iter().filter(|&i| i < self.k).collect()
+    }
+
+    /// Updates `parities` in place after the data shards at
+    /// `changed_shard_indices` went from `old_data` to `new_data`.
+    ///
+    /// Each changed shard contributes `M[r][j] * (old + new)` to parity row
+    /// `r`, so only the changed columns of the encoding matrix are applied
+    /// instead of re-encoding every data shard.
+    pub fn update_parity_ranges(
+        &self,
+        old_data: &[Vec<F::Elem>],
+        new_data: &[Vec<F::Elem>],
+        changed_shard_indices: &[usize],
+        parities: &mut [Vec<F::Elem>],
+    ) -> Result<()> {
+        if old_data.len() != self.k || new_data.len() != self.k {
+            return Err(anyhow!(
+                "Expected {} old and new data shards, got {} and {}",
+                self.k,
+                old_data.len(),
+                new_data.len()
+            ));
+        }
+        if parities.len() != self.m {
+            return Err(anyhow!(
+                "Expected {} parity shards, got {}",
+                self.m,
+                parities.len()
+            ));
+        }
+        let shard_len = parities[0].len();
+        if old_data
+            .iter()
+            .chain(new_data)
+            .chain(parities.iter())
+            .any(|s| s.len() != shard_len)
+        {
+            return Err(anyhow!("All shards must have the same length"));
+        }
+        if let Some(&bad) = changed_shard_indices.iter().find(|&&j| j >= self.k) {
+            return Err(anyhow!("Changed shard index {} is not a data shard", bad));
+        }
+
+        let mut changed = changed_shard_indices.to_vec();
+        changed.sort_unstable();
+        changed.dedup();
+        let deltas: Vec<(usize, Vec<F::Elem>)> = changed
+            .into_iter()
+            .map(|j| {
+                let delta = old_data[j]
+                    .iter()
+                    .zip(&new_data[j])
+                    .map(|(&a, &b)| self.gf.add(a, b))
+                    .collect();
+                (j, delta)
+            })
+            .collect();
+
+        self.execution.for_each_mut(parities, |r, parity| {
+            for (j, delta) in &deltas {
+                self.gf.mul_acc(self.encode_matrix[r][*j], delta, parity);
+            }
+        });
+        Ok(())
     }
 
+    /// Reconstructs missing shards of a set produced by [`Codec::encode_with_matrix`].
+    ///
+    /// The same matrix used for encoding must be supplied. Inverses computed
+    /// for a custom matrix are not cached, since the cache is keyed by survivor
+    /// indices only.
+    pub fn reconstruct_with_matrix(
+        &self,
+        shards_opt: &mut [Option<Vec<F::Elem>>],
+        matrix: &[Vec<F::Elem>],
+    ) -> Result<()> {
+        self.validate_matrix(matrix)?;
+        self.reconstruct_using(
+            shards_opt,
+            matrix,
+            0..self.n,
+            ShardLayout::RowMajor,
+            |survivors| Ok((self.compute_inverse_matrix(matrix, survivors)?, false)),
+        )
+        .map(|_| ())
+    }
+
+    /// Recovers the data of a non-systematic shard set, in which every shard
+    /// (including those below index `k`) is a combination of the data:
+    /// shard `i` is `generator[i]` applied to the `k` data shards. Returns the
+    /// set re-encoded as this codec's systematic set, i.e. the `k` data shards
+    /// followed by the `m` parity shards.
+    ///
+    /// `generator` is the producing tool's `n x k` generator matrix. Any `k`
+    /// present shards with independent generator rows suffice.
+    pub fn to_systematic(
+        &self,
+        shards_opt: &[Option<Vec<F::Elem>>],
+        generator: &[Vec<F::Elem>],
+    ) -> Result<Vec<Vec<F::Elem>>> {
+        assert_eq!(self.n, shards_opt.len());
+        if generator.len() != self.n || generator.iter().any(|row| row.len() != self.k) {
+            return Err(anyhow!(
+                "Generator matrix must be {} x {} (one row per shard, one column per data shard)",
+                self.n,
+                self.k
+            ));
+        }
+        let present: Vec<usize> = (0..self.n).filter(|&i| shards_opt[i].is_some()).collect();
+        if present.len() < self.k {
+            return Err(RseError::InsufficientShards {
+                have: present.len(),
+                need: self.k,
+            }
+            .into());
+        }
+
+        // No row is assumed to be an identity row: `A` takes every survivor's
+        // row from the generator.
+        let survivors = &present[..self.k];
+        let a: Matrix<F::Elem> = survivors.iter().map(|&i| generator[i].clone()).collect();
+        let a_inv = invert_matrix(&*self.gf, &a).with_context(|| {
+            format!(
+                "Generator rows of survivors {:?} are not independent",
+                survivors
+            )
+        })?;
+        let survivor_data: Vec<Vec<F::Elem>> = survivors
+            .iter()
+            .map(|&i| shards_opt[i].clone().unwrap())
+            .collect();
+        let mut data = shard_encoding(&*self.gf, &a_inv, &survivor_data, &(), &self.execution)?;
+        let parities = shard_encoding(&*self.gf, &self.encode_matrix, &data, &(), &self.execution)?;
+        data.extend(parities);
+        Ok(data)
+    }
+
+    #[instrument(skip_all, fields(k = self.k, m = self.m, total_shards = shards_opt.len()))]
+    pub fn reconstruct(&self, shards_opt: &mut [Option<Vec<F::Elem>>]) -> Result<()> {
+        self.reconstruct_with_report(shards_opt).map(|_| ())
+    }
+
+    /// Like [`Codec::reconstruct`], but reports which shards were recovered
+    /// and how.
+    #[instrument(skip_all, fields(k = self.k, m = self.m, total_shards = shards_opt.len()))]
+    pub fn reconstruct_with_report(
+        &self,
+        shards_opt: &mut [Option<Vec<F::Elem>>],
+    ) -> Result<ReconstructReport> {
+        self.reconstruct_using(
+            shards_opt,
+            &self.encode_matrix,
+            0..self.n,
+            ShardLayout::RowMajor,
+            |survivors| self.get_or_compute_inverse_matrix(survivors),
+        )
+    }
+
+    /// Like [`Codec::reconstruct_with_report`], then re-encodes all parity
+    /// from the completed data shards and fails if a parity shard that was
+    /// present differs from it. Parity used to rebuild missing shards always
+    /// agrees with the result, so this catches a silently wrong parity shard
+    /// among those beyond the `k` survivors.
+    pub fn reconstruct_checked(
+        &self,
+        shards_opt: &mut [Option<Vec<F::Elem>>],
+    ) -> Result<ReconstructReport> {
+        let present: Vec<bool> = shards_opt.iter().map(Option::is_some).collect();
+        let report = self.reconstruct_with_report(shards_opt)?;
+        let data: Vec<Vec<F::Elem>> = shards_opt[..self.k]
+            .iter()
+            .map(|s| s.clone().expect("every shard is reconstructed"))
+            .collect();
+        let parities = self.encode(&data)?;
+        let mismatched: Vec<usize> = (self.k..self.n)
+            .filter(|&i| present[i] && shards_opt[i].as_ref() != Some(&parities[i - self.k]))
+            .collect();
+        if !mismatched.is_empty() {
+            return Err(RseError::Corruption(format!(
+                "Parity shards {:?} differ from the parity re-encoded from the reconstructed \
+                 data; they were present but wrong",
+                mismatched
+            ))
+            .into());
+        }
+        Ok(report)
+    }
+
+    /// Like [`Codec::reconstruct_with_report`], but only recovers missing data
+    /// shards. Missing parity shards are left as `None`, which is all a decode
+    /// needs.
     #[instrument(skip_all, fields(k = self.k, m = self.m, total_shards = shards_opt.len()))]
-    pub fn reconstruct(&self, shards_opt: &mut [Option<Vec<u8>>]) -> Result<()> {
+    pub fn reconstruct_data(
+        &self,
+        shards_opt: &mut [Option<Vec<F::Elem>>],
+    ) -> Result<ReconstructReport> {
+        self.reconstruct_using(
+            shards_opt,
+            &self.encode_matrix,
+            0..self.k,
+            ShardLayout::RowMajor,
+            |survivors| self.get_or_compute_inverse_matrix(survivors),
+        )
+    }
+
+    /// Rebuilds present data shards from parity instead of trusting them,
+    /// and returns the indices of those whose rebuilt contents differ from
+    /// the stored ones. Data shards are hidden a batch at a time, as many per
+    /// batch as there are present parity shards not already needed for
+    /// missing data, so every present parity shard takes part.
+    ///
+    /// A mismatch means the stored data and parity are inconsistent, but not
+    /// which side is wrong: a corrupt data shard also skews the shards rebuilt
+    /// while it is visible.
+    pub fn cross_check_data(&self, shards_opt: &[Option<Vec<F::Elem>>]) -> Result<Vec<usize>> {
         assert_eq!(self.n, shards_opt.len());
+        let present_data: Vec<usize> = (0..self.k).filter(|&i| shards_opt[i].is_some()).collect();
+        let present_parity = (self.k..self.n)
+            .filter(|&i| shards_opt[i].is_some())
+            .count();
+        let spare = (present_parity + present_data.len()).saturating_sub(self.k);
+        if present_data.is_empty() || spare == 0 {
+            return Err(RseError::InsufficientShards {
+                have: present_data.len() + present_parity,
+                need: self.k + 1,
+            })
+            .context("checking data shards against parity needs a present data shard and a spare parity shard");
+        }
+
+        let mut mismatched = Vec::new();
+        for hidden in present_data.chunks(spare) {
+            let mut shards = shards_opt.to_vec();
+            for &i in hidden {
+                shards[i] = None;
+            }
+            self.reconstruct_data(&mut shards)?;
+            mismatched.extend(
+                hidden
+                    .iter()
+                    .copied()
+                    .filter(|&i| shards[i] != shards_opt[i]),
+            );
+        }
+        Ok(mismatched)
+    }
+
+    /// Total multiply-accumulate passes (one recovery coefficient applied to
+    /// one survivor shard) performed by this codec's reconstructions. Each
+    /// recovered shard costs exactly `k` passes, so this grows with the number
+    /// of shards recovered, never with the total shard count.
+    pub fn mac_passes(&self) -> usize {
+        self.mac_passes.load(Ordering::Relaxed)
+    }
 
-        let shard_len = shards_opt
+    /// The passes counted by [`Codec::mac_passes`] whose coefficient was
+    /// nonzero. Zero coefficients are skipped, so this is the work actually
+    /// done, and what [`Codec::reconstruction_mac_count`] predicts.
+    pub fn nonzero_mac_passes(&self) -> usize {
+        self.nonzero_mac_passes.load(Ordering::Relaxed)
+    }
+
+    /// Predicts the nonzero multiply-accumulate passes [`Codec::reconstruct`]
+    /// performs to recover `missing` when every other shard is present,
+    /// without touching any shard data: the nonzero coefficients across the
+    /// recovery rows for the survivors this codec would choose. The inverse is
+    /// computed if needed but not cached.
+    pub fn reconstruction_mac_count(&self, missing: &[usize]) -> Result<usize> {
+        let mut seen = BTreeSet::new();
+        if let Some(&bad) = missing.iter().find(|&&i| i >= self.n || !seen.insert(i)) {
+            return Err(anyhow!(
+                "Missing index {} must be below {} and listed once",
+                bad,
+                self.n
+            ));
+        }
+        let present: Vec<usize> = (0..self.n).filter(|i| !seen.contains(i)).collect();
+        if present.len() < self.k {
+            return Err(RseError::InsufficientShards {
+                have: present.len(),
+                need: self.k,
+            }
+            .into());
+        }
+        if missing.is_empty() {
+            return Ok(0);
+        }
+        if self.encode_matrix.len() == 1 {
+            return Ok(self.single_parity_passes(&present, &self.encode_matrix[0]));
+        }
+        let (_, a_inv, _) = self.select_survivors(&present, &self.encode_matrix, |survivors| {
+            self.peek_or_compute_inverse_matrix(survivors)
+        })?;
+        Ok(self
+            .recovery_rows(&a_inv, &self.encode_matrix, missing)
             .iter()
-            .find_map(|s| s.as_ref().map(|v| v.len()))
-            .ok_or_else(|| anyhow!("No shards available to determine length"))?;
+            .map(|row| nonzero_count::<F>(row))
+            .sum())
+    }
 
-        let present_indices: Vec<usize> =
-            (0..self.n).filter(|&i| shards_opt[i].is_some()).collect();
-        if present_indices.len() < self.k {
+    /// How many more shards [`Codec::reconstruct`] needs besides `present`:
+    /// `k` minus the distinct valid indices in `present`, or 0 once there are
+    /// `k`. Enough for an MDS matrix; see [`Codec::suggest_shards`] for which
+    /// ones to fetch in general.
+    pub fn shards_needed(&self, present: &[usize]) -> usize {
+        let distinct: BTreeSet<usize> = present.iter().copied().filter(|&i| i < self.n).collect();
+        self.k.saturating_sub(distinct.len())
+    }
+
+    /// Missing shards to fetch, ascending, so that they and `present` include
+    /// `k` shards with independent rows, i.e. the data becomes recoverable.
+    /// Missing data shards are preferred, lowest first, then parity. Empty if
+    /// `present` already suffices.
+    ///
+    /// For an MDS matrix this is the [`Codec::shards_needed`] lowest missing
+    /// indices. Otherwise present shards whose rows depend on the others do
+    /// not count, so more may be suggested.
+    pub fn suggest_shards(&self, present: &[usize]) -> Result<Vec<usize>> {
+        let mut seen = BTreeSet::new();
+        if let Some(&bad) = present.iter().find(|&&i| i >= self.n || !seen.insert(i)) {
             return Err(anyhow!(
-                "Not enough shards to reconstruct: have {}, need {}",
-                present_indices.len(),
-                self.k
+                "Present index {} must be below {} and listed once",
+                bad,
+                self.n
             ));
         }
+        let row = |i: usize| self.generator_row(&self.encode_matrix, i);
+        let mut basis = Vec::with_capacity(self.k);
+        for &i in &seen {
+            add_independent_row(&*self.gf, &mut basis, row(i))?;
+        }
+        let mut suggested = Vec::new();
+        for i in (0..self.n).filter(|i| !seen.contains(i)) {
+            if basis.len() == self.k {
+                break;
+            }
+            if add_independent_row(&*self.gf, &mut basis, row(i))? {
+                suggested.push(i);
+            }
+        }
+        if basis.len() < self.k {
+            return Err(anyhow!(
+                "The encoding matrix cannot recover the data from any set of shards"
+            ));
+        }
+        Ok(suggested)
+    }
 
-        let survivors = &present_indices[0..self.k];
-        let a_inv = self.get_or_compute_inverse_matrix(survivors)?;
+    /// Like [`Codec::reconstruct`], but computes the recovery products using
+    /// the given memory layout. The result is identical for every layout.
+    pub fn reconstruct_with_layout(
+        &self,
+        shards_opt: &mut [Option<Vec<F::Elem>>],
+        layout: ShardLayout,
+    ) -> Result<()> {
+        self.reconstruct_using(
+            shards_opt,
+            &self.encode_matrix,
+            0..self.n,
+            layout,
+            |survivors| self.get_or_compute_inverse_matrix(survivors),
+        )
+        .map(|_| ())
+    }
+
+    /// Recovers the `missing` shards from borrowed survivor data, e.g. slices
+    /// of one read-only buffer, without building the `n`-length `Option`
+    /// vector [`Codec::reconstruct`] works on. `survivors` pairs shard indices
+    /// with their data, in any order, and needs at least `k` entries.
+    ///
+    /// Returns only the recovered shards, in the order of `missing`.
+    pub fn reconstruct_borrowed(
+        &self,
+        survivors: &[(usize, &[F::Elem])],
+        missing: &[usize],
+    ) -> Result<Vec<(usize, Vec<F::Elem>)>> {
+        let mut present = survivors.to_vec();
+        present.sort_unstable_by_key(|&(i, _)| i);
+        if let Some(&(i, _)) = present.iter().find(|&&(i, _)| i >= self.n) {
+            return Err(anyhow!(
+                "Survivor index {} is out of range for {} shards",
+                i,
+                self.n
+            ));
+        }
+        if present.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err(anyhow!("Survivor indices must be distinct"));
+        }
+        if present.iter().any(|(_, s)| s.len() != present[0].1.len()) {
+            return Err(anyhow!("All survivor shards must have the same length"));
+        }
+        let mut seen = BTreeSet::new();
+        for &i in missing {
+            if i >= self.n
+                || !seen.insert(i)
+                || present.binary_search_by_key(&i, |&(j, _)| j).is_ok()
+            {
+                return Err(anyhow!(
+                    "Missing index {} must be below {}, distinct and not a survivor",
+                    i,
+                    self.n
+                ));
+            }
+        }
+        self.recover_using(
+            &present,
+            missing,
+            &self.encode_matrix,
+            ShardLayout::RowMajor,
+            |survivors| self.get_or_compute_inverse_matrix(survivors),
+        )
+        .map(|(recovered, _)| recovered)
+    }
 
-        let survivor_data: Vec<&[u8]> = survivors
+    /// Recovers the `missing` shards from exactly the given `k` survivors
+    /// instead of letting the codec choose among the present shards, e.g. to
+    /// exercise a particular recovery path or to use the shards that are
+    /// cheapest to fetch. `survivor_data[j]` is the data of shard
+    /// `survivors[j]`.
+    ///
+    /// Fails if the survivors' rows of the encoding matrix are not
+    /// independent. Returns the recovered shards in the order of `missing`.
+    pub fn reconstruct_with_survivors(
+        &self,
+        survivors: &[usize],
+        survivor_data: &[&[F::Elem]],
+        missing: &[usize],
+    ) -> Result<Vec<Vec<F::Elem>>> {
+        if survivors.len() != self.k {
+            return Err(anyhow!(
+                "Need exactly {} survivors, got {}",
+                self.k,
+                survivors.len()
+            ));
+        }
+        if survivor_data.len() != survivors.len() {
+            return Err(anyhow!(
+                "Got data for {} shards but {} survivor indices",
+                survivor_data.len(),
+                survivors.len()
+            ));
+        }
+        let pairs: Vec<(usize, &[F::Elem])> = survivors
             .iter()
-            .map(|&idx| shards_opt[idx].as_ref().unwrap().as_slice())
+            .copied()
+            .zip(survivor_data.iter().copied())
             .collect();
+        let recovered = self
+            .reconstruct_borrowed(&pairs, missing)
+            .with_context(|| format!("Cannot reconstruct from survivors {:?}", survivors))?;
+        Ok(recovered.into_iter().map(|(_, shard)| shard).collect())
+    }
 
-        let missing_indices: Vec<usize> =
-            (0..self.n).filter(|&i| shards_opt[i].is_none()).collect();
-        if missing_indices.is_empty() {
-            return Ok(());
+    /// Recovers the one missing shard of an `m == 1` set with parity row
+    /// `row` from the `present` shards: the survivors summed with their
+    /// coefficients and, for a data shard, scaled by the inverse of its own
+    /// coefficient. For an all-ones row this is the XOR of every present
+    /// shard.
+    fn recover_single_parity(
+        &self,
+        present: &[(usize, &[F::Elem])],
+        row: &[F::Elem],
+        missing_idx: usize,
+    ) -> Result<Vec<F::Elem>> {
+        // Scaling every coefficient up front keeps this to one pass per
+        // survivor.
+        let scale = if missing_idx == self.k {
+            F::ONE
+        } else {
+            self.gf.inv(row[missing_idx]).with_context(|| {
+                format!(
+                    "Data shard {} has a zero parity coefficient and cannot be recovered",
+                    missing_idx
+                )
+            })?
+        };
+        let mut out = vec![F::ZERO; present[0].1.len()];
+        for &(i, shard) in present {
+            let coef = if i < self.k { row[i] } else { F::ONE };
+            self.mac(self.gf.mul(coef, scale), shard, &mut out);
         }
+        Ok(out)
+    }
 
-        let recovered_shards: Vec<(usize, Vec<u8>)> = missing_indices
-            .par_iter()
-            .map(|&missing_idx| {
-                let _span = info_span!("reconstruct_shard", index = missing_idx).entered();
-                let mut out_shard = vec![0u8; shard_len];
+    /// The nonzero passes [`Codec::recover_single_parity`] makes over the
+    /// `present` shards: one per survivor whose coefficient is nonzero, since
+    /// scaling by a nonzero inverse keeps zeros and nonzeros apart.
+    fn single_parity_passes(&self, present: &[usize], row: &[F::Elem]) -> usize {
+        present
+            .iter()
+            .filter(|&&i| i >= self.k || row[i] != F::ZERO)
+            .count()
+    }
+
+    /// Counts one multiply-accumulate pass of `coef` over a survivor shard in
+    /// [`Codec::mac_passes`], and in [`Codec::nonzero_mac_passes`] unless
+    /// `coef` is zero. Returns whether the pass has any work to do.
+    fn count_pass(&self, coef: F::Elem) -> bool {
+        self.mac_passes.fetch_add(1, Ordering::Relaxed);
+        let nonzero = coef != F::ZERO;
+        if nonzero {
+            self.nonzero_mac_passes.fetch_add(1, Ordering::Relaxed);
+        }
+        nonzero
+    }
+
+    /// Adds `coef` times survivor `src` to `dst`, skipping a zero `coef`.
+    fn mac(&self, coef: F::Elem, src: &[F::Elem], dst: &mut [F::Elem]) {
+        if self.count_pass(coef) {
+            self.gf.mul_acc(coef, src, dst);
+        }
+    }
+
+    /// Recovers `missing_indices` of a `k == 1` set from the first present
+    /// shard with a nonzero coefficient, without a matrix inversion. Every
+    /// shard is the data shard times its coefficient, so the survivor is
+    /// scaled back to the data and from there to each missing shard. With the
+    /// Vandermonde matrix every coefficient is one and the shards are plain
+    /// copies. Returns the survivor used along with the recovered shards.
+    fn recover_replicated(
+        &self,
+        present: &[(usize, &[F::Elem])],
+        missing_indices: &[usize],
+        encode_matrix: &[Vec<F::Elem>],
+    ) -> Result<(usize, Recovered<F::Elem>)> {
+        let coef = |i: usize| {
+            if i == 0 {
+                F::ONE
+            } else {
+                encode_matrix[i - 1][0]
+            }
+        };
+        let &(survivor, survivor_data) = present
+            .iter()
+            .find(|&&(i, _)| coef(i) != F::ZERO)
+            .ok_or_else(|| anyhow!("Every present shard has a zero coefficient"))?;
+        let to_data = self.gf.inv(coef(survivor))?;
+
+        let scales: Vec<F::Elem> = missing_indices
+            .iter()
+            .map(|&i| self.gf.mul(coef(i), to_data))
+            .collect();
+        let recovered = missing_indices
+            .iter()
+            .zip(scales)
+            .map(|(&i, scale)| {
+                let mut shard = vec![F::ZERO; survivor_data.len()];
+                self.mac(scale, survivor_data, &mut shard);
+                (i, shard)
+            })
+            .collect();
+        Ok((survivor, recovered))
+    }
+
+    /// Picks the `k` survivors to recover from among `present_indices`
+    /// according to the survivor selection, with their inverse and whether it
+    /// was cached. If that pick is singular, the lowest independent rows are
+    /// used instead.
+    fn select_survivors<I>(
+        &self,
+        present_indices: &[usize],
+        encode_matrix: &[Vec<F::Elem>],
+        inverse_for: I,
+    ) -> Result<(Vec<usize>, Matrix<F::Elem>, bool)>
+    where
+        I: Fn(&[usize]) -> Result<(Matrix<F::Elem>, bool)>,
+    {
+        let first_k = &present_indices[0..self.k];
+        let preferred = match self.survivor_selection {
+            SurvivorSelection::FirstK => first_k.to_vec(),
+            SurvivorSelection::Sparsest => {
+                sparsest_survivors::<F>(self.k, encode_matrix, present_indices)
+            }
+        };
+        let error = match inverse_for(&preferred) {
+            Ok((a_inv, cache_hit)) => return Ok((preferred, a_inv, cache_hit)),
+            Err(e) => e,
+        };
+        // For a matrix that is not MDS, the heuristic's pick or even the
+        // first `k` present rows can be singular while other present rows
+        // still span the data.
+        match self.independent_survivors(present_indices, encode_matrix)? {
+            Some(survivors) if survivors != preferred => {
+                let (a_inv, cache_hit) = inverse_for(&survivors)?;
+                Ok((survivors, a_inv, cache_hit))
+            }
+            _ => Err(error),
+        }
+    }
+
+    /// The lowest `k` of `present_indices` whose rows are independent, each
+    /// skipped if it depends on the ones before it, or `None` if the present
+    /// rows cannot recover the data. Equal to the first `k` whenever those
+    /// are independent.
+    fn independent_survivors(
+        &self,
+        present_indices: &[usize],
+        encode_matrix: &[Vec<F::Elem>],
+    ) -> Result<Option<Vec<usize>>> {
+        let mut basis = Vec::with_capacity(self.k);
+        let mut survivors = Vec::with_capacity(self.k);
+        for &i in present_indices {
+            if survivors.len() == self.k {
+                break;
+            }
+            if add_independent_row(&*self.gf, &mut basis, self.generator_row(encode_matrix, i))? {
+                survivors.push(i);
+            }
+        }
+        Ok((survivors.len() == self.k).then_some(survivors))
+    }
 
-                let recovery_row = if missing_idx < self.k {
-                    // If we're recovering a data shard, the recovery row is simply
-                    // the corresponding row from the inverted matrix.
-                    a_inv[missing_idx].clone()
+    /// Row `i` of the systematic generator `[I; encode_matrix]`: a unit row
+    /// for a data shard, the shard's parity row otherwise.
+    fn generator_row(&self, encode_matrix: &[Vec<F::Elem>], i: usize) -> Vec<F::Elem> {
+        if i < self.k {
+            unit_row::<F>(self.k, i)
+        } else {
+            encode_matrix[i - self.k].clone()
+        }
+    }
+
+    /// Coefficients applied to the survivors to recover each shard in
+    /// `missing_indices`, in the same order.
+    fn recovery_rows(
+        &self,
+        a_inv: &Matrix<F::Elem>,
+        encode_matrix: &[Vec<F::Elem>],
+        missing_indices: &[usize],
+    ) -> Matrix<F::Elem> {
+        // A missing parity shard needs its row of the encoding matrix
+        // multiplied by the inverse. Doing all of them in one matrix product
+        // reads the inverse once instead of once per shard.
+        let parity_rows: Matrix<F::Elem> = missing_indices
+            .iter()
+            .filter(|&&i| i >= self.k)
+            .map(|&i| encode_matrix[i - self.k].clone())
+            .collect();
+        let mut parity_recovery = if parity_rows.is_empty() {
+            Vec::new()
+        } else {
+            mul_matrix_matrix(&*self.gf, &parity_rows, a_inv)
+        }
+        .into_iter();
+        missing_indices
+            .iter()
+            .map(|&i| {
+                if i < self.k {
+                    // A data shard's row is simply its row of the inverse.
+                    a_inv[i].clone()
                 } else {
-                    // If we're recovering a parity shard, we need to multiply its
-                    // corresponding row from the original encoding matrix by the
-                    // inverted matrix.
-                    let encode_row = &self.encode_matrix[missing_idx - self.k];
-                    mul_vec_matrix(&self.gf, encode_row, &a_inv)
-                };
-
-                for (j, sdata) in survivor_data.iter().enumerate() {
-                    let coef = recovery_row[j];
-                    if coef == 0 {
-                        continue;
-                    }
-
-                    if coef == 1 {
-                        for (out_byte, &in_byte) in out_shard.iter_mut().zip(sdata.iter()) {
-                            *out_byte ^= in_byte;
-                        }
-                    } else {
-                        let mult_table = self.gf.mul_table(coef);
-                        for (out_byte, &in_byte) in out_shard.iter_mut().zip(sdata.iter()) {
-                            *out_byte ^= mult_table[in_byte as usize];
-                        }
-                    }
+                    parity_recovery
+                        .next()
+                        .expect("one product row per missing parity shard")
                 }
-                (missing_idx, out_shard)
             })
-            .collect();
+            .collect()
+    }
 
-        for (idx, shard_data) in recovered_shards {
+    fn reconstruct_using<I>(
+        &self,
+        shards_opt: &mut [Option<Vec<F::Elem>>],
+        encode_matrix: &[Vec<F::Elem>],
+        targets: Range<usize>,
+        layout: ShardLayout,
+        inverse_for: I,
+    ) -> Result<ReconstructReport>
+    where
+        I: Fn(&[usize]) -> Result<(Matrix<F::Elem>, bool)>,
+    {
+        assert_eq!(self.n, shards_opt.len());
+        // Only shards in `targets` are recovered; present shards are never touched.
+        let missing: Vec<usize> = targets.filter(|&i| shards_opt[i].is_none()).collect();
+        let present: Vec<(usize, &[F::Elem])> = shards_opt
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_deref().map(|s| (i, s)))
+            .collect();
+        let (recovered, report) =
+            self.recover_using(&present, &missing, encode_matrix, layout, inverse_for)?;
+        for (idx, shard_data) in recovered {
             shards_opt[idx] = Some(shard_data);
         }
+        Ok(report)
+    }
+
+    /// Recovers `missing_indices` from `present`, which is sorted by shard
+    /// index. The recovered shards come back in the order of `missing_indices`.
+    fn recover_using<I>(
+        &self,
+        present: &[(usize, &[F::Elem])],
+        missing_indices: &[usize],
+        encode_matrix: &[Vec<F::Elem>],
+        layout: ShardLayout,
+        inverse_for: I,
+    ) -> Result<(Recovered<F::Elem>, ReconstructReport)>
+    where
+        I: Fn(&[usize]) -> Result<(Matrix<F::Elem>, bool)>,
+    {
+        let started = Instant::now();
+
+        let present_indices: Vec<usize> = present.iter().map(|&(i, _)| i).collect();
+        if present_indices.len() < self.k {
+            return Err(RseError::InsufficientShards {
+                have: present_indices.len(),
+                need: self.k,
+            }
+            .into());
+        }
+        let shard_len = common_shard_len(present)?;
 
-        Ok(())
+        if missing_indices.is_empty() {
+            return Ok((vec![], ReconstructReport::default()));
+        }
+
+        if encode_matrix.len() == 1 {
+            // Single parity: one shard is missing and every other shard is
+            // needed, so it is solved for directly without any inversion.
+            let survivors = present_indices[..self.k].to_vec();
+            let missing_idx = missing_indices[0];
+            let shard_data = self.recover_single_parity(present, &encode_matrix[0], missing_idx)?;
+            let report = ReconstructReport {
+                recovered: missing_indices.to_vec(),
+                survivors,
+                cache_hit: false,
+                elapsed: started.elapsed(),
+            };
+            return Ok((vec![(missing_idx, shard_data)], report));
+        }
+
+        if self.k == 1 {
+            let (survivor, recovered) =
+                self.recover_replicated(present, missing_indices, encode_matrix)?;
+            let report = ReconstructReport {
+                recovered: missing_indices.to_vec(),
+                survivors: vec![survivor],
+                cache_hit: false,
+                elapsed: started.elapsed(),
+            };
+            return Ok((recovered, report));
+        }
+
+        let (survivors, a_inv, cache_hit) =
+            self.select_survivors(&present_indices, encode_matrix, inverse_for)?;
+        let mut report = ReconstructReport {
+            recovered: missing_indices.to_vec(),
+            survivors: survivors.to_vec(),
+            cache_hit,
+            elapsed: Duration::ZERO,
+        };
+
+        let survivor_data: Vec<&[F::Elem]> = survivors
+            .iter()
+            .map(|&idx| present[present_indices.binary_search(&idx).unwrap()].1)
+            .collect();
+        let recovery_rows = self.recovery_rows(&a_inv, encode_matrix, missing_indices);
+
+        if layout == ShardLayout::Interleaved {
+            let recovered = recover_interleaved(
+                &*self.gf,
+                &recovery_rows,
+                &survivor_data,
+                shard_len,
+                &self.execution,
+                |coef| self.count_pass(coef),
+            );
+            report.elapsed = started.elapsed();
+            return Ok((
+                missing_indices.iter().copied().zip(recovered).collect(),
+                report,
+            ));
+        }
+
+        let jobs: Vec<(usize, &[F::Elem])> = missing_indices
+            .iter()
+            .copied()
+            .zip(recovery_rows.iter().map(Vec::as_slice))
+            .collect();
+        let recovered_shards: Vec<(usize, Vec<F::Elem>)> =
+            self.execution.map(&jobs, |&(missing_idx, recovery_row)| {
+                let _span = info_span!("reconstruct_shard", index = missing_idx).entered();
+                let mut out_shard = vec![F::ZERO; shard_len];
+
+                for (&coef, sdata) in recovery_row.iter().zip(&survivor_data) {
+                    self.mac(coef, sdata, &mut out_shard);
+                }
+                (missing_idx, out_shard)
+            });
+
+        report.elapsed = started.elapsed();
+        Ok((recovered_shards, report))
     }
 }
@@ -0,0 +1,41 @@
+//! Error kinds that callers need to tell apart, and the process exit codes
+//! they map to. Everything else stays a plain `anyhow` error and exits with 1.
+
+use thiserror::Error;
+
+/// Any failure not covered below, e.g. IO errors.
+pub const EXIT_FAILURE: u8 = 1;
+/// Too few shards survive to reconstruct the data; fetching more may help.
+pub const EXIT_UNRECOVERABLE: u8 = 2;
+/// Shards or metadata are present but fail an integrity check.
+pub const EXIT_CORRUPTION: u8 = 3;
+/// The command line or codec parameters are invalid.
+pub const EXIT_INVALID_ARGS: u8 = 4;
+
+#[derive(Debug, Error)]
+pub enum RseError {
+    #[error("Not enough shards to reconstruct: have {have}, need {need}")]
+    InsufficientShards { have: usize, need: usize },
+    #[error("{0}")]
+    Corruption(String),
+    #[error("{0}")]
+    InvalidArgument(String),
+}
+
+impl RseError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            RseError::InsufficientShards { .. } => EXIT_UNRECOVERABLE,
+            RseError::Corruption(_) => EXIT_CORRUPTION,
+            RseError::InvalidArgument(_) => EXIT_INVALID_ARGS,
+        }
+    }
+}
+
+/// Exit code for `err`, taken from the first [`RseError`] in its chain so
+/// added context does not hide the kind.
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<RseError>())
+        .map_or(EXIT_FAILURE, RseError::exit_code)
+}
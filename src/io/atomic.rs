@@ -0,0 +1,171 @@
+//! Crash-safe file writes.
+//!
+//! Data is written to a temporary file first and renamed over the final path
+//! only once complete, so a reader sees either no file (or the previous one)
+//! or the whole new file, never a partial write. An interrupted write leaves
+//! at most a stray `.*.tmp` file behind.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::{fs, fs::File, io::AsyncWriteExt};
+
+use crate::io::{
+    files::{parent_dir, temp_path},
+    throttle::{RateLimiter, write_throttled},
+};
+
+/// Where temporary files go and whether writes are flushed to stable storage.
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    /// Directory for temporary files; the destination's directory if `None`.
+    /// On a different filesystem the finished file is copied next to the
+    /// destination and renamed from there.
+    pub tmp_dir: Option<PathBuf>,
+    /// `fsync` each file before renaming it, and the directory after.
+    pub fsync: bool,
+}
+
+/// A fully written temporary file that has not been moved into place yet.
+#[derive(Debug)]
+pub struct StagedFile {
+    tmp: PathBuf,
+    dest: PathBuf,
+}
+
+async fn write_file(
+    path: &Path,
+    data: &[u8],
+    fsync: bool,
+    limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let mut file = File::create(path)
+        .await
+        .with_context(|| format!("Failed to create {:?}", path))?;
+    match limiter {
+        Some(limiter) => write_throttled(&mut file, data, limiter).await?,
+        None => file.write_all(data).await?,
+    }
+    file.flush().await?;
+    if fsync {
+        file.sync_all()
+            .await
+            .with_context(|| format!("Failed to sync {:?}", path))?;
+    }
+    Ok(())
+}
+
+impl StagedFile {
+    /// Writes `data` to a temporary file for `dest`. Nothing is visible at
+    /// `dest` until [`StagedFile::commit`].
+    pub async fn write(
+        dest: &Path,
+        data: &[u8],
+        opts: &WriteOptions,
+        limiter: Option<&RateLimiter>,
+    ) -> Result<Self> {
+        let dir = opts.tmp_dir.as_deref().unwrap_or_else(|| parent_dir(dest));
+        let tmp = temp_path(dir, dest);
+        if let Err(e) = write_file(&tmp, data, opts.fsync, limiter).await {
+            let _ = fs::remove_file(&tmp).await;
+            return Err(e.context(format!("Failed to write {:?}", dest)));
+        }
+        Ok(Self {
+            tmp,
+            dest: dest.to_path_buf(),
+        })
+    }
+
+    /// Creates an empty temporary file for `dest`, to be written through the
+    /// returned handle and then committed. The caller flushes (and, with
+    /// `opts.fsync`, syncs) the file before [`StagedFile::commit`].
+    pub async fn create(dest: &Path, opts: &WriteOptions) -> Result<(File, Self)> {
+        let dir = opts.tmp_dir.as_deref().unwrap_or_else(|| parent_dir(dest));
+        let tmp = temp_path(dir, dest);
+        let file = File::create(&tmp)
+            .await
+            .with_context(|| format!("Failed to create {:?}", tmp))?;
+        Ok((
+            file,
+            Self {
+                tmp,
+                dest: dest.to_path_buf(),
+            },
+        ))
+    }
+
+    pub fn temp_path(&self) -> &Path {
+        &self.tmp
+    }
+
+    /// Moves the temporary file over the destination.
+    pub async fn commit(self, opts: &WriteOptions) -> Result<()> {
+        let dest_dir = parent_dir(&self.dest);
+        if let Err(e) = fs::rename(&self.tmp, &self.dest).await {
+            if e.kind() != std::io::ErrorKind::CrossesDevices {
+                let _ = fs::remove_file(&self.tmp).await;
+                return Err(e).with_context(|| {
+                    format!("Failed to rename {:?} to {:?}", self.tmp, self.dest)
+                });
+            }
+            // A rename cannot cross filesystems: copy next to the
+            // destination first so the final step is still a rename.
+            let local = temp_path(dest_dir, &self.dest);
+            let data = fs::read(&self.tmp).await?;
+            write_file(&local, &data, opts.fsync, None).await?;
+            fs::rename(&local, &self.dest)
+                .await
+                .with_context(|| format!("Failed to rename {:?} to {:?}", local, self.dest))?;
+            fs::remove_file(&self.tmp).await?;
+        }
+        #[cfg(unix)]
+        if opts.fsync {
+            File::open(dest_dir)
+                .await?
+                .sync_all()
+                .await
+                .with_context(|| format!("Failed to sync directory {:?}", dest_dir))?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes temporary files left next to `dest` by interrupted writes of it,
+/// or of its volumes, returning how many there were. Only the destination's
+/// own directory is searched, not a `--tmp-dir`.
+pub async fn remove_stale_temps(dest: &Path) -> Result<usize> {
+    let dir = parent_dir(dest);
+    let prefix = format!(
+        ".{}.",
+        dest.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e).with_context(|| format!("Failed to list {:?}", dir)),
+    };
+    let mut removed = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with(&prefix) && name.ends_with(".tmp") {
+            fs::remove_file(entry.path())
+                .await
+                .with_context(|| format!("Failed to remove {:?}", entry.path()))?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Writes `data` to `dest` through a temporary file and a rename.
+pub async fn write_atomic(
+    dest: &Path,
+    data: &[u8],
+    opts: &WriteOptions,
+    limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    StagedFile::write(dest, data, opts, limiter)
+        .await?
+        .commit(opts)
+        .await
+}
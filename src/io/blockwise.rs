@@ -0,0 +1,310 @@
+//! Decoding in fixed-size blocks, for shards too large to hold in memory.
+//!
+//! Each output byte only depends on the bytes at the same offset of the
+//! survivors, so the set is decoded one byte range at a time: block `b` of
+//! `k` survivors is read, block `b` of the missing data shards is rebuilt and
+//! written to its place in the output, then block `b + 1` follows. Memory is
+//! bounded by `n * block_size` instead of `n * shard_len`.
+//!
+//! Shard files are streamed twice: once to check their size and checksum,
+//! then for the blocks. The output is written to a temporary file and only
+//! renamed into place once it matches the recorded input SHA-256. Only sets whose data shards hold the input bytes
+//! as-is are supported; compressed, scrambled, encrypted, rotated, uneven,
+//! product-code and local-group sets, and sets with compressed shard files,
+//! need the whole-shard path.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::{
+    codec::reconstruct_shards::Codec,
+    error::RseError,
+    io::{
+        decoding::{DecodeOptions, misplaced_shards_error, read_decode_metadata},
+        files::{parent_dir, temp_path},
+        metadata::ShardMetadata,
+    },
+};
+
+/// Decodes the set in `shard_dir` into `output` reading `block_size` bytes
+/// of each shard at a time. Returns the number of bytes written.
+pub async fn decode_blockwise(
+    shard_dir: &Path,
+    opts: &DecodeOptions,
+    block_size: usize,
+    output: &Path,
+) -> Result<usize> {
+    if block_size == 0 {
+        return Err(RseError::InvalidArgument("--block-size must be at least 1".into()).into());
+    }
+    if opts.partial_ok
+        || opts.locate_corruption
+        || opts.force_reconstruct
+        || opts.correct_errors
+        || opts.wait_for_shards.is_some()
+    {
+        return Err(RseError::InvalidArgument(
+            "--block-size cannot be combined with --partial-ok, --locate-corruption, \
+             --force-reconstruct, --correct-errors or --wait-for-shards"
+                .into(),
+        )
+        .into());
+    }
+    let meta = read_decode_metadata(shard_dir, opts).await?;
+    if meta.compression.is_some()
+        || meta.scramble_seed.is_some()
+        || meta.encryption.is_some()
+        || meta.shard_compression.is_some()
+        || meta.stripe_rotation.is_some()
+        || meta.data_shard_lens.is_some()
+        || meta.stream_stripe.is_some()
+        || meta.product_code.is_some()
+        || meta.local_groups.is_some()
+        || meta.volume_size.is_some()
+        || meta.shard_log.is_some()
+    {
+        return Err(RseError::InvalidArgument(
+            "--block-size only supports plain shard sets: not compressed, scrambled, \
+             encrypted, stripe-rotated, uneven, streamed, product-code, local-group, split into \
+             volumes or in a shard log"
+                .into(),
+        )
+        .into());
+    }
+    let codec = meta.codec()?.with_execution(opts.execution.clone());
+
+    let dirs: Vec<PathBuf> = std::iter::once(shard_dir.to_path_buf())
+        .chain(opts.fallback_dirs.iter().cloned())
+        .collect();
+    let (ignore, strict) = (opts.ignore_shards.clone(), opts.strict);
+    let output = output.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut sources = Vec::with_capacity(meta.total_shards());
+        let mut foreign = Vec::new();
+        for i in 0..meta.total_shards() {
+            let source = if ignore.contains(&i) {
+                info!("Ignoring shard {} as requested", i);
+                None
+            } else {
+                open_valid_copy(&meta, &dirs, i, block_size, strict, &mut foreign)?
+            };
+            sources.push(source);
+        }
+        // A copy holding another shard's contents is a reordered file only if
+        // that shard has no valid copy, as in a swap.
+        if let Some(checksums) = &meta.checksums {
+            let misplaced: Vec<(usize, usize)> = foreign
+                .iter()
+                .filter_map(|(i, digest)| {
+                    let belongs_at = checksums
+                        .vacant_index_of(digest, |j| sources.get(j).is_none_or(Option::is_none))?;
+                    Some((*i, belongs_at))
+                })
+                .collect();
+            if !misplaced.is_empty() {
+                return Err(misplaced_shards_error(&meta, &misplaced));
+            }
+        }
+        let tmp = temp_path(parent_dir(&output), &output);
+        let result =
+            reconstruct_blocks(&codec, &meta, sources, block_size, &tmp).and_then(|written| {
+                if let Some(expected) = &meta.input_sha256
+                    && sha256_file(&tmp, block_size)? != *expected
+                {
+                    return Err(RseError::Corruption(
+                        "Decoded output does not match the recorded input SHA-256".into(),
+                    )
+                    .into());
+                }
+                fs::rename(&tmp, &output)
+                    .with_context(|| format!("Failed to rename {:?} to {:?}", tmp, output))?;
+                Ok(written)
+            });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        result
+    })
+    .await
+    .context("Block-wise decode task panicked")?
+}
+
+/// Opens the first copy of shard `index` with the expected size and
+/// checksum, positioned at its start. A copy failing either check is
+/// skipped, or is an error with `strict`. Skipped copies that match another
+/// shard's checksum are added to `foreign` as `(index, digest)`.
+fn open_valid_copy(
+    meta: &ShardMetadata,
+    dirs: &[PathBuf],
+    index: usize,
+    block_size: usize,
+    strict: bool,
+    foreign: &mut Vec<(usize, String)>,
+) -> Result<Option<File>> {
+    let expected_len = meta.stored_len(index) as u64;
+    for dir in dirs {
+        let path = meta.shard_path(dir, index);
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                warn!("Cannot open shard {:?} ({}); skipping it", path, e);
+                continue;
+            }
+        };
+        let len = file.metadata()?.len();
+        if len != expected_len {
+            if strict {
+                return Err(RseError::Corruption(format!(
+                    "Shard {:?} is {} bytes, expected {}",
+                    path, len, expected_len
+                ))
+                .into());
+            }
+            warn!(
+                "Shard {:?} is {} bytes, expected {}; skipping it",
+                path, len, expected_len
+            );
+            continue;
+        }
+        if let Some(checksums) = &meta.checksums
+            && let Some(mut hasher) = checksums.algorithm.hasher()
+        {
+            let mut buf = vec![0u8; block_size];
+            loop {
+                let read = file.read(&mut buf)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buf[..read]);
+            }
+            let digest = hasher.finish();
+            if checksums.shards.get(index) != Some(&digest) {
+                warn!("Shard {:?} failed its checksum; skipping it", path);
+                if checksums.shards.contains(&digest) {
+                    foreign.push((index, digest));
+                }
+                continue;
+            }
+            file.seek(SeekFrom::Start(0))?;
+        }
+        return Ok(Some(file));
+    }
+    Ok(None)
+}
+
+/// Streams `k` present shards chosen by [`choose_survivors`] block by block,
+/// rebuilding missing data shards and writing every data block to its offset
+/// in `output`.
+fn reconstruct_blocks(
+    codec: &Codec,
+    meta: &ShardMetadata,
+    mut sources: Vec<Option<File>>,
+    block_size: usize,
+    output: &Path,
+) -> Result<usize> {
+    let (k, orig_len, shard_len) = (meta.data_shards, meta.orig_len, meta.shard_len());
+    let present: Vec<usize> = (0..sources.len())
+        .filter(|&i| sources[i].is_some())
+        .collect();
+    let survivors = choose_survivors(codec, k, &present)?;
+    // Shards other than the survivors are never read.
+    for &i in &present {
+        if !survivors.contains(&i) {
+            sources[i] = None;
+        }
+    }
+    let missing: Vec<usize> = (0..k).filter(|&i| sources[i].is_none()).collect();
+    info!(
+        "Decoding in {}-byte blocks from shards {:?}; rebuilding data shards {:?}",
+        block_size, survivors, missing
+    );
+
+    let mut out = File::create(output).with_context(|| format!("Failed to create {:?}", output))?;
+    out.set_len(orig_len as u64)?;
+    for start in (0..shard_len).step_by(block_size) {
+        let len = block_size.min(shard_len - start);
+        let mut shards = sources
+            .iter_mut()
+            .map(|source| {
+                source
+                    .as_mut()
+                    .map(|file| {
+                        let mut block = vec![0u8; len];
+                        file.read_exact(&mut block).map(|_| block)
+                    })
+                    .transpose()
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if !missing.is_empty() {
+            codec.reconstruct_data(&mut shards)?;
+        }
+        for (i, block) in shards[..k].iter().enumerate() {
+            let offset = i * shard_len + start;
+            if offset >= orig_len {
+                break;
+            }
+            let block = block.as_deref().expect("data shards are reconstructed");
+            out.seek(SeekFrom::Start(offset as u64))?;
+            out.write_all(&block[..len.min(orig_len - offset)])?;
+        }
+    }
+    out.flush()?;
+    Ok(orig_len)
+}
+
+/// The lowest `k` of the `present` shards (ascending) whose rows are
+/// independent. That is the first `k` for an MDS matrix; otherwise a shard is
+/// skipped when [`Codec::suggest_shards`] still asks for as many shards with
+/// it as without it.
+fn choose_survivors(codec: &Codec, k: usize, present: &[usize]) -> Result<Vec<usize>> {
+    if present.len() < k {
+        return Err(RseError::InsufficientShards {
+            have: present.len(),
+            need: k,
+        }
+        .into());
+    }
+    if codec.suggest_shards(&present[..k])?.is_empty() {
+        return Ok(present[..k].to_vec());
+    }
+    let mut survivors = Vec::with_capacity(k);
+    let mut needed = k;
+    for (j, &i) in present.iter().enumerate() {
+        let still_needed = codec.suggest_shards(&present[..=j])?.len();
+        if still_needed < needed {
+            survivors.push(i);
+            needed = still_needed;
+        }
+        if needed == 0 {
+            return Ok(survivors);
+        }
+    }
+    Err(RseError::InsufficientShards {
+        have: survivors.len(),
+        need: k,
+    }
+    .into())
+}
+
+fn sha256_file(path: &Path, block_size: usize) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; block_size];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
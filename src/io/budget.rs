@@ -0,0 +1,122 @@
+//! How many shards to read or write at once.
+//!
+//! With a memory budget, at most `min(budget / shard_len, n)` shard IO tasks
+//! run at a time, and never fewer than one: fewer for large shards, more for
+//! small ones. The CLI takes the budget from `--memory-budget`, or else half
+//! the memory the OS reports available.
+//!
+//! This paces the IO, not the memory held: encode has every shard in memory
+//! before it writes any, and decode keeps each shard it reads for the
+//! reconstruction. `--low-memory` encodes and `--block-size` decodes are what
+//! bound that.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::info;
+
+/// Memory the OS reports available, where it can be determined (Linux).
+pub fn available_memory() -> Option<usize> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kib: usize = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+/// Budget for shard buffers in flight when none is given: half the
+/// available memory, leaving the rest for the shards held for coding.
+pub fn default_memory_budget() -> Option<usize> {
+    available_memory().map(|bytes| bytes / 2)
+}
+
+/// Shard IO tasks of `shard_len` bytes each that fit in `budget`, between 1
+/// and the `n` shards there are. All `n` without a budget.
+pub fn shard_concurrency(budget: Option<usize>, shard_len: usize, n: usize) -> usize {
+    let n = n.max(1);
+    match budget {
+        Some(budget) => (budget / shard_len.max(1)).clamp(1, n),
+        None => n,
+    }
+}
+
+/// Caps the shard IO tasks running at once and records the most that ever
+/// did.
+#[derive(Clone)]
+pub struct ShardIoLimit {
+    permits: Arc<Semaphore>,
+    limit: usize,
+    n: usize,
+    in_flight: Arc<AtomicUsize>,
+    peak: Arc<AtomicUsize>,
+}
+
+/// Held by a shard IO task while it runs.
+pub struct ShardIoPermit {
+    _permit: OwnedSemaphorePermit,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for ShardIoPermit {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ShardIoLimit {
+    /// Limit for IO on `n` shards of up to `shard_len` bytes within `budget`.
+    pub fn new(budget: Option<usize>, shard_len: usize, n: usize) -> Self {
+        let limit = shard_concurrency(budget, shard_len, n);
+        Self {
+            permits: Arc::new(Semaphore::new(limit)),
+            limit,
+            n,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            peak: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Waits until another shard IO task may start.
+    pub async fn acquire(&self) -> ShardIoPermit {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+        ShardIoPermit {
+            _permit: permit,
+            in_flight: self.in_flight.clone(),
+        }
+    }
+
+    /// Most shard IO tasks that ran at once so far.
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::SeqCst)
+    }
+
+    /// Logs the peak if the budget held the tasks below one per shard.
+    pub fn log_peak(&self, what: &str) {
+        if self.limit < self.n {
+            info!(
+                shard_buffers_peak = self.peak(),
+                shard_buffers_limit = self.limit,
+                "{} at most {} shards at once within the memory budget (limit {})",
+                what,
+                self.peak(),
+                self.limit
+            );
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Per-shard integrity check recorded in the metadata at encode time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgo {
+    /// Fast; catches accidental corruption only.
+    Crc32,
+    /// Fast with strong mixing; not cryptographic.
+    #[default]
+    Xxh3,
+    /// Cryptographic; also resists deliberate tampering.
+    Blake3,
+    /// No per-shard checksums.
+    None,
+}
+
+impl ChecksumAlgo {
+    /// Hex digest of `data`, or `None` for [`ChecksumAlgo::None`].
+    pub fn digest(self, data: &[u8]) -> Option<String> {
+        match self {
+            ChecksumAlgo::Crc32 => Some(format!("{:08x}", crc32fast::hash(data))),
+            ChecksumAlgo::Xxh3 => Some(format!("{:016x}", xxhash_rust::xxh3::xxh3_64(data))),
+            ChecksumAlgo::Blake3 => Some(blake3::hash(data).to_hex().to_string()),
+            ChecksumAlgo::None => None,
+        }
+    }
+
+    /// Incremental form of [`ChecksumAlgo::digest`], for shards read in
+    /// pieces. `None` for [`ChecksumAlgo::None`].
+    pub fn hasher(self) -> Option<ChecksumHasher> {
+        match self {
+            ChecksumAlgo::Crc32 => Some(ChecksumHasher::Crc32(crc32fast::Hasher::new())),
+            ChecksumAlgo::Xxh3 => Some(ChecksumHasher::Xxh3(Box::default())),
+            ChecksumAlgo::Blake3 => Some(ChecksumHasher::Blake3(Box::default())),
+            ChecksumAlgo::None => None,
+        }
+    }
+}
+
+pub enum ChecksumHasher {
+    Crc32(crc32fast::Hasher),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl ChecksumHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ChecksumHasher::Crc32(h) => h.update(data),
+            ChecksumHasher::Xxh3(h) => h.update(data),
+            ChecksumHasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    /// Hex digest, formatted like [`ChecksumAlgo::digest`].
+    pub fn finish(self) -> String {
+        match self {
+            ChecksumHasher::Crc32(h) => format!("{:08x}", h.finalize()),
+            ChecksumHasher::Xxh3(h) => format!("{:016x}", h.digest()),
+            ChecksumHasher::Blake3(h) => h.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// Checksums of every shard file in a set, indexed by shard.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardChecksums {
+    pub algorithm: ChecksumAlgo,
+    pub shards: Vec<String>,
+}
+
+impl ShardChecksums {
+    pub fn matches(&self, index: usize, data: &[u8]) -> bool {
+        self.shards
+            .get(index)
+            .is_some_and(|expected| self.algorithm.digest(data).as_ref() == Some(expected))
+    }
+
+    /// Lowest index whose checksum is `digest` and for which `vacant` holds:
+    /// where a shard with those contents belongs if that shard is absent or
+    /// damaged.
+    pub fn vacant_index_of(&self, digest: &str, vacant: impl Fn(usize) -> bool) -> Option<usize> {
+        (0..self.shards.len()).find(|&j| self.shards[j] == digest && vacant(j))
+    }
+
+    /// Present shards that fail their own checksum but match another
+    /// shard's, as `(index, index it belongs at)`: files that were renamed or
+    /// reordered rather than damaged. Only a shard whose contents belong at an
+    /// index that is absent or itself mismatched counts, as in a swap; a copy
+    /// of a shard that is present and intact is just corrupt. A shard whose
+    /// own checksum is unknown (empty, e.g. from a lost sidecar) is never
+    /// counted as misplaced.
+    pub fn misplaced(&self, shards: &[Option<Vec<u8>>]) -> Vec<(usize, usize)> {
+        let digests: Vec<Option<String>> = shards
+            .iter()
+            .map(|s| self.algorithm.digest(s.as_ref()?))
+            .collect();
+        let vacant = |j: usize| {
+            digests
+                .get(j)
+                .is_none_or(|d| d.as_ref() != self.shards.get(j))
+        };
+        digests
+            .iter()
+            .enumerate()
+            .filter_map(|(i, digest)| {
+                let own = self.shards.get(i).filter(|own| !own.is_empty())?;
+                let digest = digest.as_ref()?;
+                if own == digest {
+                    return None;
+                }
+                Some((i, self.vacant_index_of(digest, vacant)?))
+            })
+            .collect()
+    }
+
+    /// Indices of present shards whose contents do not match.
+    pub fn corrupted(&self, shards: &[Option<Vec<u8>>]) -> Vec<usize> {
+        shards
+            .iter()
+            .enumerate()
+            .filter_map(|(i, s)| s.as_ref().filter(|data| !self.matches(i, data)).map(|_| i))
+            .collect()
+    }
+}
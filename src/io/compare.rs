@@ -0,0 +1,109 @@
+use anyhow::{Result, anyhow};
+use std::path::Path;
+use tracing::{info, instrument};
+
+use crate::{cli::commands::Commands, io::metadata::ShardMetadata};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardComparison {
+    Identical,
+    Differs,
+    OnlyInA,
+    OnlyInB,
+    MissingInBoth,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompareReport {
+    /// Comparison result for each shard index.
+    pub shards: Vec<ShardComparison>,
+}
+
+impl CompareReport {
+    pub fn differences(&self) -> impl Iterator<Item = (usize, ShardComparison)> + '_ {
+        self.shards
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, c)| *c != ShardComparison::Identical)
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.differences().next().is_none()
+    }
+}
+
+async fn read_if_exists(meta: &ShardMetadata, dir: &Path, index: usize) -> Result<Option<Vec<u8>>> {
+    if meta.shard_exists(dir, index).await? {
+        Ok(Some(meta.read_shard(dir, index).await?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Compares two shard directories shard by shard. Errors if their metadata
+/// describes different shard sets, since the shards would not be comparable.
+pub async fn compare_dirs(a: &Path, b: &Path) -> Result<CompareReport> {
+    let meta_a = ShardMetadata::read(a).await?;
+    let meta_b = ShardMetadata::read(b).await?;
+    if (meta_a.orig_len, meta_a.data_shards, meta_a.parity_shards)
+        != (meta_b.orig_len, meta_b.data_shards, meta_b.parity_shards)
+    {
+        return Err(anyhow!(
+            "Metadata differs: {:?} has len={} k={} m={}, {:?} has len={} k={} m={}",
+            a,
+            meta_a.orig_len,
+            meta_a.data_shards,
+            meta_a.parity_shards,
+            b,
+            meta_b.orig_len,
+            meta_b.data_shards,
+            meta_b.parity_shards
+        ));
+    }
+
+    let mut shards = Vec::with_capacity(meta_a.total_shards());
+    for i in 0..meta_a.total_shards() {
+        let shard_a = read_if_exists(&meta_a, a, i).await?;
+        let shard_b = read_if_exists(&meta_b, b, i).await?;
+        shards.push(match (shard_a, shard_b) {
+            (Some(x), Some(y)) if x == y => ShardComparison::Identical,
+            (Some(_), Some(_)) => ShardComparison::Differs,
+            (Some(_), None) => ShardComparison::OnlyInA,
+            (None, Some(_)) => ShardComparison::OnlyInB,
+            (None, None) => ShardComparison::MissingInBoth,
+        });
+    }
+    Ok(CompareReport { shards })
+}
+
+#[instrument(skip(args))]
+pub async fn handle_compare(args: Commands) -> Result<()> {
+    let Commands::Compare { a, b } = args else {
+        unreachable!()
+    };
+
+    info!("Comparing {:?} with {:?}", a, b);
+    let report = compare_dirs(&a, &b).await?;
+    for (i, comparison) in report.differences() {
+        let description = match comparison {
+            ShardComparison::Identical => continue,
+            ShardComparison::Differs => "contents differ",
+            ShardComparison::OnlyInA => "missing in B",
+            ShardComparison::OnlyInB => "missing in A",
+            ShardComparison::MissingInBoth => "missing in both",
+        };
+        println!("shard {:02}: {}", i, description);
+    }
+
+    let different = report.differences().count();
+    if different > 0 {
+        return Err(anyhow!(
+            "{} of {} shards differ",
+            different,
+            report.shards.len()
+        ));
+    }
+    println!("All {} shards are identical", report.shards.len());
+    Ok(())
+}
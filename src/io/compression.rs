@@ -0,0 +1,112 @@
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+use crate::codec::execution::Execution;
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
+    Gzip,
+}
+
+/// Compresses `data`, returning `None` if the result would not be smaller
+/// than the input so incompressible data is never expanded.
+pub fn compress(algorithm: Compression, data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let compressed = match algorithm {
+        Compression::Zstd => {
+            zstd::bulk::compress(data, ZSTD_LEVEL).context("zstd compression failed")?
+        }
+        Compression::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data)?;
+            encoder.finish().context("gzip compression failed")?
+        }
+    };
+    Ok((compressed.len() < data.len()).then_some(compressed))
+}
+
+pub fn decompress(algorithm: Compression, data: &[u8], expected_len: usize) -> Result<Vec<u8>> {
+    let out = match algorithm {
+        Compression::Zstd => {
+            zstd::bulk::decompress(data, expected_len).context("zstd decompression failed")?
+        }
+        Compression::Gzip => {
+            let mut out = Vec::with_capacity(expected_len);
+            flate2::read::GzDecoder::new(data)
+                .take(expected_len as u64 + 1)
+                .read_to_end(&mut out)
+                .context("gzip decompression failed")?;
+            out
+        }
+    };
+    if out.len() != expected_len {
+        return Err(anyhow!(
+            "Decompressed length {} does not match recorded length {}",
+            out.len(),
+            expected_len
+        ));
+    }
+    Ok(out)
+}
+
+/// Compression of each shard file on disk, separate from compressing the
+/// input. The Reed-Solomon math always sees the uncompressed shards.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardCompression {
+    pub algorithm: Compression,
+    /// Compressed size of each shard file, or `None` for a shard stored as
+    /// is because compressing did not make it smaller.
+    pub sizes: Vec<Option<usize>>,
+}
+
+impl ShardCompression {
+    pub fn new(algorithm: Compression) -> Self {
+        Self {
+            algorithm,
+            sizes: Vec::new(),
+        }
+    }
+
+    /// Compresses the next shard in index order, recording its size.
+    pub fn push(&mut self, shard: Vec<u8>) -> Result<Vec<u8>> {
+        let compressed = compress(self.algorithm, &shard)?;
+        self.sizes.push(compressed.as_ref().map(Vec::len));
+        Ok(compressed.unwrap_or(shard))
+    }
+
+    /// [`ShardCompression::push`] for several shards, compressed in
+    /// `execution` mode.
+    pub fn push_all(
+        &mut self,
+        shards: Vec<Vec<u8>>,
+        execution: &Execution,
+    ) -> Result<Vec<Vec<u8>>> {
+        let algorithm = self.algorithm;
+        let compressed: Vec<Option<Vec<u8>>> = execution
+            .map(&shards, |shard| compress(algorithm, shard))
+            .into_iter()
+            .collect::<Result<_>>()?;
+        Ok(shards
+            .into_iter()
+            .zip(compressed)
+            .map(|(shard, compressed)| {
+                self.sizes.push(compressed.as_ref().map(Vec::len));
+                compressed.unwrap_or(shard)
+            })
+            .collect())
+    }
+
+    /// Restores shard `index` to its `plain_len` bytes.
+    pub fn decompress(&self, index: usize, file: Vec<u8>, plain_len: usize) -> Result<Vec<u8>> {
+        match self.sizes.get(index) {
+            Some(Some(_)) => decompress(self.algorithm, &file, plain_len),
+            _ => Ok(file),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Cross-checks of a shard directory against its metadata, so an interrupted
+//! encode or a failed shard write is reported as such instead of surfacing as
+//! an unrelated reconstruction or hash error.
+
+use anyhow::{Context, Result, anyhow};
+use std::path::Path;
+use tokio::fs;
+use tracing::warn;
+
+use crate::{codec::reconstruct_shards::Codec, error::RseError, io::metadata::ShardMetadata};
+
+/// How the present shard files compare with the sizes the metadata implies.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ShardSizeReport {
+    /// Expected here but absent.
+    pub missing: Vec<usize>,
+    /// Shorter than expected, e.g. a write that did not finish.
+    pub truncated: Vec<usize>,
+    /// Longer than expected, e.g. appended garbage.
+    pub oversized: Vec<usize>,
+}
+
+impl ShardSizeReport {
+    pub fn check(meta: &ShardMetadata, shards: &[Option<Vec<u8>>]) -> Self {
+        let mut report = Self::default();
+        for (i, shard) in shards.iter().enumerate() {
+            match shard {
+                None if meta.is_stored_here(i) => report.missing.push(i),
+                None => {}
+                Some(shard) if shard.len() < meta.stored_len(i) => report.truncated.push(i),
+                Some(shard) if shard.len() > meta.stored_len(i) => report.oversized.push(i),
+                Some(_) => {}
+            }
+        }
+        report
+    }
+
+    /// A one-line summary, or `None` if every expected shard has the right size.
+    pub fn diagnosis(&self) -> Option<String> {
+        let parts: Vec<String> = [
+            ("truncated", &self.truncated),
+            ("oversized", &self.oversized),
+            ("missing", &self.missing),
+        ]
+        .into_iter()
+        .filter(|(_, indices)| !indices.is_empty())
+        .map(|(what, indices)| {
+            let files = if indices.len() == 1 { "file" } else { "files" };
+            format!("{} shard {} {} {:?}", indices.len(), files, what, indices)
+        })
+        .collect();
+        if parts.is_empty() {
+            None
+        } else {
+            Some(format!("metadata present, {}", parts.join(", ")))
+        }
+    }
+}
+
+/// Indices and sizes of the `shard_NN.dat` files in `dir`, sorted by index.
+pub async fn scan_shard_files(dir: &Path) -> Result<Vec<(usize, u64)>> {
+    let mut files = Vec::new();
+    let mut entries = fs::read_dir(dir)
+        .await
+        .with_context(|| format!("Failed to read shard directory {:?}", dir))?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let index = name
+            .to_str()
+            .and_then(|n| n.strip_prefix("shard_"))
+            .and_then(|n| n.strip_suffix(".dat"))
+            .and_then(|n| n.parse::<usize>().ok());
+        if let Some(index) = index {
+            files.push((index, entry.metadata().await?.len()));
+        }
+    }
+    files.sort_unstable();
+    Ok(files)
+}
+
+/// The error for a directory without metadata: distinguishes a wrong path
+/// from an encode that was interrupted before the metadata was written.
+pub async fn missing_metadata_error(dir: &Path) -> anyhow::Error {
+    match scan_shard_files(dir).await {
+        Ok(files) if !files.is_empty() => anyhow!(
+            "metadata missing, {} shard files present (indices {}..={}) in {:?}; the encode was \
+             probably interrupted before writing metadata. Supply --data-shards and \
+             --parity-shards to decode without it",
+            files.len(),
+            files[0].0,
+            files[files.len() - 1].0,
+            dir
+        ),
+        _ => anyhow!(
+            "No metadata and no shard files in {:?}. Is the shard directory correct?",
+            dir
+        ),
+    }
+}
+
+/// Stand-in metadata for a directory whose metadata was never written, from
+/// the caller-supplied `k`/`m` and `shard_len`, or else the largest shard
+/// file. The original length is unknown, so the decoded output keeps the
+/// final shard's zero padding.
+pub async fn metadata_from_shard_files(
+    dir: &Path,
+    k: usize,
+    m: usize,
+    shard_len: Option<usize>,
+) -> Result<ShardMetadata> {
+    Codec::validate_params(k, m)?;
+    let files = scan_shard_files(dir).await?;
+    let Some(&(max_index, _)) = files.last() else {
+        return Err(missing_metadata_error(dir).await);
+    };
+    if max_index >= k + m {
+        return Err(RseError::InvalidArgument(format!(
+            "Found shard index {} but --data-shards {} --parity-shards {} allow at most {}",
+            max_index,
+            k,
+            m,
+            k + m - 1
+        ))
+        .into());
+    }
+    let shard_len =
+        shard_len.unwrap_or_else(|| files.iter().map(|&(_, len)| len).max().unwrap_or(0) as usize);
+    warn!(
+        "Decoding without metadata as k={} m={} with {}-byte shards: trailing padding is kept, \
+         and compression, scrambling or uneven shards from the original encode cannot be undone",
+        k, m, shard_len
+    );
+    Ok(ShardMetadata::new(k * shard_len, k, m))
+}
@@ -1,48 +1,499 @@
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result};
 use futures_util::future::join_all;
 use indicatif::{ProgressBar, ProgressStyle};
-use std::sync::Arc;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs;
-use tracing::{info, instrument};
+use tokio::time::Instant;
+use tracing::{info, instrument, warn};
 
-use crate::{cli::commands::Commands, codec::reconstruct_shards::Codec};
+use crate::{
+    cli::commands::Commands,
+    codec::{
+        correct::correct_errors,
+        execution::Execution,
+        locate::{CorruptionCheck, locate_corruption},
+        lrc::LrcCodec,
+        product::ProductCodec,
+        reconstruct_shards::Codec,
+    },
+    error::RseError,
+    io::{
+        blockwise::decode_blockwise,
+        budget::{ShardIoLimit, default_memory_budget},
+        checksum::ShardChecksums,
+        compression::decompress,
+        consistency::{ShardSizeReport, metadata_from_shard_files, missing_metadata_error},
+        encryption::{EncryptKey, ShardEncryption},
+        manifest::sha256_hex,
+        metadata::ShardMetadata,
+        report::{OperationReport, write_report},
+        retry::RetryPolicy,
+        rotation::reconstruct_rotated,
+        scramble::unscramble,
+        shard_log::{ShardLogEntry, read_log_shard, shard_log_path},
+        sidecar::read_sidecar_checksum,
+        split::decode_split,
+        stats::{PhaseTimes, log_throughput},
+        stream::assemble_streamed_data_shards,
+        trailer::{check_trailer, strip_trailer},
+        verify::{ChecksumScan, stored_indices},
+        volumes::read_shard_file,
+        wipe::Wiping,
+    },
+};
 
 #[instrument(skip(args))]
-pub async fn handle_decode(args: Commands) -> Result<()> {
-    let (shard_dir, output_path) = match args {
-        Commands::Decode { input, output } => (input, output),
+pub async fn handle_decode(args: Commands, execution: &Execution) -> Result<()> {
+    let Commands::Decode { report_file, .. } = &args else {
+        unreachable!()
+    };
+    let report_file = report_file.clone();
+    let mut report = OperationReport::new("decode");
+    let result = decode_command(args, execution, &mut report).await;
+    write_report(report_file.as_deref(), report, result).await
+}
+
+/// Runs `decode` as `args` asks, recording what it did in `report`.
+async fn decode_command(
+    args: Commands,
+    execution: &Execution,
+    report: &mut OperationReport,
+) -> Result<()> {
+    let (shard_dir, output_path, opts, block_size, split, checksums_only) = match args {
+        Commands::Decode {
+            input,
+            output,
+            split_output,
+            split_parity,
+            verify_checksums_only,
+            strict,
+            data_shards,
+            parity_shards,
+            encrypt_key,
+            partial_ok,
+            wait_for_shards,
+            fallback_dirs,
+            read_retries,
+            retry_backoff_ms,
+            locate_corruption,
+            force_reconstruct,
+            correct_errors,
+            block_size,
+            shard_len,
+            memory_budget,
+            report_file: _,
+        } => (
+            input,
+            output,
+            DecodeOptions {
+                strict,
+                data_shards,
+                parity_shards,
+                partial_ok,
+                encrypt_key,
+                fallback_dirs,
+                read_retry: RetryPolicy {
+                    retries: read_retries,
+                    backoff: Duration::from_millis(retry_backoff_ms),
+                },
+                locate_corruption,
+                force_reconstruct,
+                correct_errors,
+                shard_len,
+                memory_budget: memory_budget.or_else(default_memory_budget),
+                execution: execution.clone(),
+                wait_for_shards: wait_for_shards
+                    .map(Duration::try_from_secs_f64)
+                    .transpose()
+                    .map_err(|_| {
+                        RseError::InvalidArgument(
+                            "--wait-for-shards must be a non-negative number of seconds".into(),
+                        )
+                    })?,
+                ..Default::default()
+            },
+            block_size,
+            split_output.map(|dir| (dir, split_parity)),
+            verify_checksums_only,
+        ),
         _ => unreachable!(),
     };
 
-    info!("Reading metadata from: {:?}", shard_dir);
-    let meta_raw = fs::read_to_string(shard_dir.join("meta.txt"))
+    if checksums_only {
+        return check_checksums_only(&shard_dir, &opts).await;
+    }
+    if let Some((split_dir, with_parity)) = split {
+        let split = decode_split(&shard_dir, &opts, &split_dir, with_parity).await?;
+        info!(
+            "✅ Wrote {} data and {} parity shards of {} bytes to '{}' (original length {})",
+            split.data_shards,
+            split.parity_shards,
+            split.shard_len,
+            split_dir.display(),
+            split.orig_len
+        );
+        return Ok(());
+    }
+    let output_path = output_path.expect("clap requires --output without --split-output");
+
+    if let Some(block_size) = block_size {
+        // Blocks are read, rebuilt and written in turn, so only the overall
+        // rate is meaningful.
+        let start = Instant::now();
+        let written = decode_blockwise(&shard_dir, &opts, block_size, &output_path).await?;
+        log_throughput(
+            &format!("✅ Successfully reconstructed '{}'", output_path.display()),
+            written,
+            start.elapsed(),
+            &PhaseTimes::default(),
+        );
+        return Ok(());
+    }
+
+    report.inputs = std::iter::once(shard_dir.clone())
+        .chain(opts.fallback_dirs.iter().cloned())
+        .collect();
+    report.output = output_path.clone();
+    report.stage("decoding");
+    let start = Instant::now();
+    let mut decoded = decode_dir_with_gaps(&shard_dir, &opts).await?;
+    report.stage("writing output");
+    let write_start = Instant::now();
+    fs::write(&output_path, &decoded.data).await?;
+    decoded.times.write = write_start.elapsed();
+    record_decoded(report, &shard_dir, &opts, &decoded).await?;
+    let DecodedOutput {
+        data: out_buf,
+        missing_ranges,
+        times,
+        ..
+    } = decoded;
+    let out_buf = Wiping::new(out_buf);
+
+    if !missing_ranges.is_empty() {
+        let missing_bytes: usize = missing_ranges.iter().map(|r| r.len()).sum();
+        for range in &missing_ranges {
+            println!("MISSING   bytes {}..{}", range.start, range.end);
+        }
+        warn!(
+            "Wrote partial output '{}': {} of {} bytes are zero-filled",
+            output_path.display(),
+            missing_bytes,
+            out_buf.len()
+        );
+        return Ok(());
+    }
+    log_throughput(
+        &format!("✅ Successfully reconstructed '{}'", output_path.display()),
+        out_buf.len(),
+        start.elapsed(),
+        &times,
+    );
+    Ok(())
+}
+
+/// Records in `report` what decoding the set in `shard_dir` gave.
+async fn record_decoded(
+    report: &mut OperationReport,
+    shard_dir: &Path,
+    opts: &DecodeOptions,
+    decoded: &DecodedOutput,
+) -> Result<()> {
+    report.stage("reading back metadata");
+    if ShardMetadata::exists(shard_dir).await {
+        report.record_set(&ShardMetadata::read(shard_dir).await?);
+    } else {
+        report.data_shards = opts.data_shards;
+        report.parity_shards = opts.parity_shards;
+    }
+    let (k, m) = (
+        report.data_shards.unwrap_or(0),
+        report.parity_shards.unwrap_or(0),
+    );
+    let lost = &decoded.lost_shards;
+    report.shards_read = (0..k + m).filter(|i| !lost.contains(i)).collect();
+    // A partial output zero-fills the lost data shards instead.
+    if decoded.missing_ranges.is_empty() {
+        report.shards_reconstructed = lost.iter().copied().filter(|&i| i < k).collect();
+    }
+    report.bytes = Some(decoded.data.len());
+    report.set_times(&decoded.times);
+    Ok(())
+}
+
+/// Checks every shard stored in `shard_dir` against its checksum and prints
+/// a summary, without reconstructing or assembling anything.
+async fn check_checksums_only(shard_dir: &Path, opts: &DecodeOptions) -> Result<()> {
+    let meta = read_decode_metadata(shard_dir, opts).await?;
+    let Some(scan) = ChecksumScan::run(shard_dir, &meta, &stored_indices(&meta), true).await?
+    else {
+        return Err(RseError::InvalidArgument(format!(
+            "{:?} was encoded without checksums; there is nothing to check",
+            shard_dir
+        ))
+        .into());
+    };
+    println!(
+        "Summary: {} intact, {} corrupt, {} missing (k={})",
+        scan.intact.len(),
+        scan.corrupt.len(),
+        scan.missing.len(),
+        meta.data_shards
+    );
+    if !scan.corrupt.is_empty() {
+        return Err(RseError::Corruption(format!(
+            "Shards {:?} fail their checksums",
+            scan.corrupt
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Options for [`decode_dir`].
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Reject shards whose length differs from the metadata.
+    pub strict: bool,
+    /// Shard counts for a set whose metadata is missing. If metadata exists,
+    /// they must agree with it.
+    pub data_shards: Option<usize>,
+    pub parity_shards: Option<usize>,
+    /// How long to wait for short shard files to finish growing.
+    pub wait_for_shards: Option<Duration>,
+    /// Write what survives, zero-filled, instead of failing when too few
+    /// shards remain.
+    pub partial_ok: bool,
+    /// Key for an encrypted shard set.
+    pub encrypt_key: Option<EncryptKey>,
+    /// Shards to treat as missing even if present, e.g. to rehearse losing them.
+    pub ignore_shards: Vec<usize>,
+    /// Directories with further copies of the shards, in order of preference
+    /// after the shard directory itself. Their metadata is not read.
+    pub fallback_dirs: Vec<PathBuf>,
+    /// Retries for shard reads that fail with an IO error.
+    pub read_retry: RetryPolicy,
+    /// Cross-check the present shards and drop one that disagrees.
+    pub locate_corruption: bool,
+    /// Rebuild every present data shard from parity as well and fail if any
+    /// rebuilt shard differs from the stored one.
+    pub force_reconstruct: bool,
+    /// Correct corrupted elements in the present shards of a Cauchy set with
+    /// [`correct_errors`] before reconstructing.
+    pub correct_errors: bool,
+    /// Expected length of every shard as encoded. Every present shard file
+    /// must match it; without metadata it replaces the inferred length.
+    pub shard_len: Option<usize>,
+    /// Bytes of shard reads that may be in flight at once; bounds how many
+    /// shards are read concurrently, not the memory they take. Unbounded if
+    /// `None`.
+    pub memory_budget: Option<usize>,
+    /// Serial or parallel reconstruction and decryption.
+    pub execution: Execution,
+}
+
+/// Result of [`decode_dir_with_gaps`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedOutput {
+    pub data: Vec<u8>,
+    /// Byte ranges of `data` that could not be recovered and are zero-filled.
+    /// Only ever non-empty with [`DecodeOptions::partial_ok`].
+    pub missing_ranges: Vec<Range<usize>>,
+    /// Time spent reading the shards and rebuilding the output. Writing it
+    /// is up to the caller.
+    pub times: PhaseTimes,
+    /// Shards that were missing or failed their checks. Those among the data
+    /// shards were rebuilt, unless the output is partial.
+    pub lost_shards: Vec<usize>,
+}
+
+/// Reads the shard set in `shard_dir`, reconstructs what is missing and
+/// returns the original input, checked against the recorded hash if any.
+pub async fn decode_dir(shard_dir: &Path, opts: &DecodeOptions) -> Result<Vec<u8>> {
+    decode_dir_with_gaps(shard_dir, opts)
         .await
-        .context("Failed to read meta.txt. Is the shard directory correct?")?;
-    let mut lines = meta_raw.lines();
-    let orig_len: usize = lines
-        .next()
-        .ok_or(anyhow!("Invalid meta.txt: missing length"))?
-        .trim()
-        .parse()?;
-    let km_line = lines
-        .next()
-        .ok_or(anyhow!("Invalid meta.txt: missing k/m"))?;
-    let mut parts = km_line.split_whitespace();
-    let k: usize = parts
-        .next()
-        .ok_or(anyhow!("Invalid meta.txt: missing k"))?
-        .parse()?;
-    let m: usize = parts
-        .next()
-        .ok_or(anyhow!("Invalid meta.txt: missing m"))?
-        .parse()?;
-
-    let codec = Arc::new(Codec::new(k, m));
+        .map(|output| output.data)
+}
+
+/// Reads the set's metadata, or rebuilds it from the shard files and the
+/// shard counts in `opts` when it is missing. The checksums of a set with
+/// sidecars are loaded from every sidecar up front.
+pub(crate) async fn read_decode_metadata(
+    shard_dir: &Path,
+    opts: &DecodeOptions,
+) -> Result<ShardMetadata> {
+    let mut meta = read_decode_descriptor(shard_dir, opts).await?;
+    meta.load_sidecars(shard_dir).await?;
+    Ok(meta)
+}
+
+/// Like [`read_decode_metadata`], but leaves the checksums of a set with
+/// sidecars to be read along with each shard.
+async fn read_decode_descriptor(shard_dir: &Path, opts: &DecodeOptions) -> Result<ShardMetadata> {
+    info!("Reading metadata from: {:?}", shard_dir);
+    let meta = if ShardMetadata::exists(shard_dir).await {
+        let meta = ShardMetadata::read_descriptor(shard_dir).await?;
+        if opts.data_shards.is_some_and(|k| k != meta.data_shards)
+            || opts.parity_shards.is_some_and(|m| m != meta.parity_shards)
+        {
+            return Err(RseError::InvalidArgument(format!(
+                "--data-shards/--parity-shards disagree with the metadata (k={}, m={})",
+                meta.data_shards, meta.parity_shards
+            ))
+            .into());
+        }
+        meta
+    } else {
+        match (opts.data_shards, opts.parity_shards) {
+            (Some(k), Some(m)) => {
+                metadata_from_shard_files(shard_dir, k, m, opts.shard_len).await?
+            }
+            _ => return Err(missing_metadata_error(shard_dir).await),
+        }
+    };
+    if let Some(shard_len) = opts.shard_len {
+        check_shard_len(&meta, shard_dir, opts, shard_len).await?;
+    }
+    check_fallback_formats(&meta, shard_dir, opts).await?;
+    Ok(meta)
+}
+
+/// Fails if a fallback directory's metadata gives another encoding than
+/// `meta`, the set in `shard_dir`: rebuilding from a mix of their shards would
+/// silently corrupt the output. Directories without metadata are not checked.
+async fn check_fallback_formats(
+    meta: &ShardMetadata,
+    shard_dir: &Path,
+    opts: &DecodeOptions,
+) -> Result<()> {
+    let format = meta.fingerprint();
+    info!("Shard format: {}", format);
+    for dir in &opts.fallback_dirs {
+        if !ShardMetadata::exists(dir).await {
+            continue;
+        }
+        let fallback = ShardMetadata::read_descriptor(dir)
+            .await
+            .with_context(|| format!("Failed to read the metadata of fallback {:?}", dir))?
+            .fingerprint();
+        if fallback != format {
+            return Err(RseError::Corruption(format!(
+                "Fallback {:?} was encoded with a {}, but {:?} with a {}; shards from \
+                 different encodings cannot be combined",
+                dir, fallback, shard_dir, format
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Checks `--shard-len` against the metadata and every present copy of every
+/// shard, naming each file whose size disagrees.
+async fn check_shard_len(
+    meta: &ShardMetadata,
+    shard_dir: &Path,
+    opts: &DecodeOptions,
+    shard_len: usize,
+) -> Result<()> {
+    if meta.shard_len() != shard_len {
+        return Err(RseError::InvalidArgument(format!(
+            "--shard-len {} disagrees with the metadata, which gives {}-byte shards",
+            shard_len,
+            meta.shard_len()
+        ))
+        .into());
+    }
+    let mut mismatched = Vec::new();
+    for dir in std::iter::once(shard_dir).chain(opts.fallback_dirs.iter().map(PathBuf::as_path)) {
+        for i in 0..meta.total_shards() {
+            let Ok(len) = meta.shard_file_len(dir, i).await else {
+                continue;
+            };
+            let expected = meta.stored_len(i) as u64;
+            if len != expected {
+                mismatched.push(format!(
+                    "{:?} ({} bytes, expected {})",
+                    meta.shard_path(dir, i),
+                    len,
+                    expected
+                ));
+            }
+        }
+    }
+    if !mismatched.is_empty() {
+        return Err(RseError::Corruption(format!(
+            "Shard files disagree with --shard-len {}: {}",
+            shard_len,
+            mismatched.join(", ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// The error for shard files holding another shard's contents, as
+/// `(index, index it belongs at)`. Rebuilding them from parity would hide
+/// that the directory was reordered, so decode stops instead.
+pub(crate) fn misplaced_shards_error(
+    meta: &ShardMetadata,
+    misplaced: &[(usize, usize)],
+) -> anyhow::Error {
+    let files: Vec<String> = misplaced
+        .iter()
+        .map(|&(i, j)| {
+            format!(
+                "{} holds the contents of {}",
+                meta.shard_file_name(i),
+                meta.shard_file_name(j)
+            )
+        })
+        .collect();
+    RseError::Corruption(format!(
+        "Shard files are out of order ({}); restore their names before decoding",
+        files.join(", ")
+    ))
+    .into()
+}
+
+/// Outcome of reading a set and rebuilding its missing data shards.
+pub(crate) enum Recovery {
+    /// The metadata and every shard, with all `k` data shards present.
+    /// Parity shards are present where they were read intact. Also the time
+    /// spent reading the shards and the shards that were lost.
+    Shards(
+        Box<ShardMetadata>,
+        Vec<Option<Vec<u8>>>,
+        Duration,
+        Vec<usize>,
+    ),
+    /// Too few shards survived; the best effort of [`DecodeOptions::partial_ok`].
+    Partial(DecodedOutput),
+}
+
+/// Reads the set in `shard_dir` and rebuilds its missing data shards,
+/// stopping short of assembling the output.
+pub(crate) async fn recover_data_shards(
+    shard_dir: &Path,
+    opts: &DecodeOptions,
+) -> Result<Recovery> {
+    let mut meta = read_decode_descriptor(shard_dir, opts).await?;
+    let (k, m) = (meta.data_shards, meta.parity_shards);
+
+    // A product code may have more shards than one field allows; its row and
+    // column codecs are built in the reconstruction branch instead, as is the
+    // global codec of a local reconstruction code.
+    if meta.product_code.is_none() && meta.local_groups.is_none() {
+        Codec::validate_params(k, m)?;
+    }
 
     let n = k + m;
     let mut shards_opt: Vec<Option<Vec<u8>>> = vec![None; n];
 
     info!("Reading available shards...");
+    let read_start = Instant::now();
     let pb = ProgressBar::new(n as u64);
     pb.set_style(
         ProgressStyle::with_template(
@@ -52,34 +503,295 @@ pub async fn handle_decode(args: Commands) -> Result<()> {
         .progress_chars("=> "),
     );
 
+    let io_limit = ShardIoLimit::new(
+        opts.memory_budget,
+        (0..n).map(|i| meta.stored_len(i)).max().unwrap_or(0),
+        n,
+    );
     let mut read_handles = Vec::with_capacity(n);
     for i in 0..n {
-        let path = shard_dir.join(format!("shard_{:02}.dat", i));
+        let log_entry = meta.shard_log_entry(i);
+        let candidates: Vec<PathBuf> = std::iter::once(shard_dir)
+            .chain(opts.fallback_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| match log_entry {
+                Some(_) => shard_log_path(dir),
+                None => meta.shard_path(dir, i),
+            })
+            .collect();
+        let expected_len = meta.stored_len(i);
+        let volumes = meta.volumes(i);
+        let logged = meta.shard_log.is_some();
+        let checksum = meta
+            .checksums
+            .as_ref()
+            .map(|c| (c.algorithm, c.shards[i].clone()));
+        let sidecar_algo = meta.sidecar_checksums;
+        let format = meta.fingerprint();
+        let trailer = meta.shard_trailer.is_some();
+        let retry = opts.read_retry;
         let pb_clone = pb.clone();
+        let permit = io_limit.acquire().await;
         read_handles.push(tokio::spawn(async move {
-            let data = if path.exists() {
-                Some(fs::read(&path).await?)
-            } else {
-                None
+            let _permit = permit;
+            // A sidecar is read along with its shard. Every copy of a shard
+            // has the same checksum, so any copy's sidecar will do; without
+            // one the checksum is empty and no copy passes.
+            let checksum = match sidecar_algo {
+                Some(algorithm) => Some((
+                    algorithm,
+                    read_sidecar_checksum(&candidates, i, expected_len, &format)
+                        .await?
+                        .unwrap_or_default(),
+                )),
+                None => checksum,
             };
+            // A shard the log has no entry for was not stored here.
+            if logged && log_entry.is_none() {
+                pb_clone.inc(1);
+                return Ok((None, checksum));
+            }
+            let data = read_first_valid(&candidates, volumes, log_entry, &retry, |data| {
+                data.len() == expected_len
+                    && checksum.as_ref().is_none_or(|(algorithm, expected)| {
+                        algorithm.digest(data).as_ref() == Some(expected)
+                    })
+                    && (!trailer || check_trailer(data).is_some())
+            })
+            .await?;
             pb_clone.inc(1);
-            Ok::<Option<Vec<u8>>, anyhow::Error>(data)
+            Ok::<_, anyhow::Error>((data, checksum))
         }));
     }
 
     let results = join_all(read_handles).await;
+    let mut sidecar_checksums = Vec::with_capacity(n);
     for (i, result) in results.into_iter().enumerate() {
-        let shard = result.context("Join error in shard read task")??;
+        let (shard, checksum) = result.context("Join error in shard read task")??;
         shards_opt[i] = shard;
+        if let Some((_, checksum)) = checksum {
+            sidecar_checksums.push(checksum);
+        }
     }
     pb.finish_with_message("Shards read!");
+    io_limit.log_peak("Read");
+    let read = read_start.elapsed();
+    if let Some(algorithm) = meta.sidecar_checksums {
+        for i in (0..n).filter(|&i| shards_opt[i].is_some() && sidecar_checksums[i].is_empty()) {
+            warn!("No sidecar for {}", meta.shard_file_name(i));
+        }
+        meta.checksums = Some(ShardChecksums {
+            algorithm,
+            shards: sidecar_checksums,
+        });
+    }
+
+    if let Some(timeout) = opts.wait_for_shards {
+        wait_for_growing_shards(shard_dir, &meta, &mut shards_opt, timeout).await?;
+    }
+    recover_read_shards(meta, shards_opt, opts, read).await
+}
+
+/// Checks the shards read for `meta`, taking `read` to read, and rebuilds the
+/// missing data shards. Where the shards came from does not matter here.
+pub(crate) async fn recover_read_shards(
+    meta: ShardMetadata,
+    mut shards_opt: Vec<Option<Vec<u8>>>,
+    opts: &DecodeOptions,
+    read: Duration,
+) -> Result<Recovery> {
+    let (k, m) = (meta.data_shards, meta.parity_shards);
+    let n = k + m;
+    let report = ShardSizeReport::check(&meta, &shards_opt);
+    let diagnosis = report.diagnosis();
+    if let Some(diagnosis) = &diagnosis {
+        warn!("Shard files disagree with the metadata: {}", diagnosis);
+    }
+    if opts.strict {
+        check_shard_lengths(&shards_opt, |i| meta.stored_len(i))?;
+    }
+    // A short shard lost data in a failed write; rebuild it instead.
+    for &i in &report.truncated {
+        shards_opt[i] = None;
+    }
+    for &i in &opts.ignore_shards {
+        if let Some(shard) = shards_opt.get_mut(i) {
+            *shard = None;
+        }
+    }
+    let usable = shards_opt.iter().filter(|s| s.is_some()).count();
+    if usable < k
+        && !opts.partial_ok
+        && let Some(diagnosis) = diagnosis
+    {
+        return Err(RseError::InsufficientShards {
+            have: usable,
+            need: k,
+        })
+        .context(diagnosis);
+    }
+
+    if let Some(checksums) = &meta.checksums {
+        let misplaced = checksums.misplaced(&shards_opt);
+        if !misplaced.is_empty() {
+            return Err(misplaced_shards_error(&meta, &misplaced));
+        }
+        // A shard that fails its checksum is treated as lost and rebuilt.
+        for i in checksums.corrupted(&shards_opt) {
+            warn!(
+                "Shard {} failed its {:?} checksum; treating it as missing",
+                i, checksums.algorithm
+            );
+            shards_opt[i] = None;
+        }
+    }
+
+    if meta.shard_trailer.is_some() {
+        for (i, shard) in shards_opt.iter_mut().enumerate() {
+            let Some(file) = shard.take() else { continue };
+            *shard = strip_trailer(file);
+            if shard.is_none() {
+                warn!("Shard {} has a bad trailer; treating it as missing", i);
+            }
+        }
+    }
+
+    if let Some(encryption) = &meta.encryption {
+        decrypt_shards(
+            encryption,
+            opts.encrypt_key.as_ref(),
+            &mut shards_opt,
+            &opts.execution,
+        )?;
+    } else if opts.encrypt_key.is_some() {
+        warn!("The shard set is not encrypted; ignoring --encrypt-key");
+    }
+
+    if let Some(compression) = &meta.shard_compression {
+        for (i, shard) in shards_opt.iter_mut().enumerate() {
+            let Some(file) = shard.take() else { continue };
+            match compression.decompress(i, file, meta.plain_len(i)) {
+                Ok(data) => *shard = Some(data),
+                Err(e) => warn!(
+                    "Shard {} does not decompress ({}); treating it as missing",
+                    i, e
+                ),
+            }
+        }
+    }
+
+    if let Some(lens) = &meta.data_shard_lens {
+        // Uneven data shards are stored without their zero padding.
+        let shard_len = meta.shard_len();
+        for (shard, &len) in shards_opt[..k].iter_mut().zip(lens) {
+            if let Some(shard) = shard
+                && shard.len() == len
+            {
+                shard.resize(shard_len, 0);
+            }
+        }
+    }
+
+    if opts.locate_corruption {
+        shards_opt = drop_located_corruption(&meta, shards_opt, &opts.execution).await?;
+    }
+    if opts.correct_errors {
+        shards_opt = correct_shard_errors(&meta, shards_opt).await?;
+    }
 
     let missing_count = shards_opt.iter().filter(|s| s.is_none()).count();
+    if opts.partial_ok && n - missing_count < k {
+        return assemble_partial(&meta, &shards_opt, n - missing_count).map(Recovery::Partial);
+    }
+    if opts.force_reconstruct {
+        shards_opt = cross_check_parity(&meta, shards_opt, &opts.execution).await?;
+    }
+    let lost: Vec<usize> = (0..n).filter(|&i| shards_opt[i].is_none()).collect();
 
-    if missing_count > 0 {
-        info!("Found {} missing shards. Reconstructing...", missing_count);
+    if let Some(stripe_len) = meta.stripe_rotation {
+        info!(
+            "Undoing stripe rotation ({} bytes per stripe, {} device files missing)",
+            stripe_len, missing_count
+        );
+        let codec = meta.codec()?.with_execution(opts.execution.clone());
+        shards_opt = tokio::task::spawn_blocking(move || {
+            reconstruct_rotated(&codec, &shards_opt, stripe_len)
+        })
+        .await
+        .context("Shard reconstruction task panicked")??;
+    } else if let Some(geometry) = meta.product_code
+        && shards_opt[..k].iter().any(|s| s.is_none())
+    {
+        info!(
+            "Found {} missing shards. Reconstructing across rows and columns...",
+            missing_count
+        );
+        let product = ProductCodec::new(geometry)?.with_execution(opts.execution.clone());
+        let (shards, passes) = tokio::task::spawn_blocking(move || {
+            let mut shards = shards_opt;
+            let passes = product.reconstruct(&mut shards)?;
+            Ok::<_, anyhow::Error>((shards, passes))
+        })
+        .await
+        .context("Shard reconstruction task panicked")??;
+        shards_opt = shards;
+        let unrecovered: Vec<usize> = (0..k).filter(|&i| shards_opt[i].is_none()).collect();
+        if !unrecovered.is_empty() {
+            if opts.partial_ok {
+                return assemble_partial(&meta, &shards_opt, n - missing_count)
+                    .map(Recovery::Partial);
+            }
+            return Err(RseError::InsufficientShards {
+                have: n - missing_count,
+                need: k,
+            })
+            .context(format!(
+                "the lost shards form a pattern the product code cannot recover; data shards \
+                 {:?} are still missing after {} passes",
+                unrecovered, passes
+            ));
+        }
+        info!("Reconstruction converged after {} passes", passes);
+    } else if let Some(groups) = meta.local_groups
+        && shards_opt[..k].iter().any(|s| s.is_none())
+    {
+        info!(
+            "Found {} missing shards. Repairing within local groups first...",
+            missing_count
+        );
+        let lrc = LrcCodec::new(k, m - groups, groups)?.with_execution(opts.execution.clone());
+        let (shards, result) = tokio::task::spawn_blocking(move || {
+            let mut shards = shards_opt;
+            let result = lrc.reconstruct(&mut shards);
+            (shards, result)
+        })
+        .await
+        .context("Shard reconstruction task panicked")?;
+        shards_opt = shards;
+        match result {
+            Ok(report) => info!(
+                "Rebuilt {:?} from their local groups and {:?} from global parity",
+                report.local, report.global
+            ),
+            Err(_) if opts.partial_ok => {
+                return assemble_partial(&meta, &shards_opt, n - missing_count)
+                    .map(Recovery::Partial);
+            }
+            Err(e) => {
+                return Err(e.context(
+                    "the lost shards are beyond what the local groups and global parity \
+                     can recover",
+                ));
+            }
+        }
+    } else if shards_opt[..k].iter().any(|s| s.is_none()) {
+        // Only data shards are needed for the output; lost parity is left alone.
+        let missing_data = shards_opt[..k].iter().filter(|s| s.is_none()).count();
+        info!(
+            "Found {} missing shards ({} data). Reconstructing data shards...",
+            missing_count, missing_data
+        );
 
-        let pb_recon = ProgressBar::new(missing_count as u64);
+        let pb_recon = ProgressBar::new(missing_data as u64);
         pb_recon.set_style(
             ProgressStyle::with_template(
                 "[{elapsed_precise}] [{bar:40.yellow/black}] Reconstructing {pos}/{len}",
@@ -88,21 +800,52 @@ pub async fn handle_decode(args: Commands) -> Result<()> {
             .progress_chars("=> "),
         );
 
-        let codec_clone = codec.clone();
+        let codec = meta.codec()?.with_execution(opts.execution.clone());
         shards_opt =
             tokio::task::spawn_blocking(move || -> Result<Vec<Option<Vec<u8>>>, anyhow::Error> {
                 let mut shards_to_reconstruct = shards_opt;
-                codec_clone.reconstruct(&mut shards_to_reconstruct)?;
+                let report = codec.reconstruct_data(&mut shards_to_reconstruct)?;
                 pb_recon.finish_with_message("Reconstruction complete!");
+                info!("Reconstruction {}", report);
                 Ok(shards_to_reconstruct)
             })
             .await
             .context("Shard reconstruction task panicked")??;
     } else {
-        info!("All shards present, no reconstruction needed.");
+        info!(
+            "All data shards present ({} parity missing), no reconstruction needed.",
+            missing_count
+        );
     };
+    Ok(Recovery::Shards(Box::new(meta), shards_opt, read, lost))
+}
 
-    info!("Assembling final file: {:?}", output_path);
+/// Like [`decode_dir`], but also reports the ranges a partial decode could
+/// not recover.
+pub async fn decode_dir_with_gaps(shard_dir: &Path, opts: &DecodeOptions) -> Result<DecodedOutput> {
+    let start = Instant::now();
+    let (meta, shards_opt, read, lost) = match recover_data_shards(shard_dir, opts).await? {
+        Recovery::Shards(meta, shards_opt, read, lost) => (*meta, shards_opt, read, lost),
+        Recovery::Partial(output) => return Ok(output),
+    };
+    assemble_output(meta, shards_opt, read, lost, start)
+}
+
+/// Assembles the original input from a set's recovered data shards, undoing
+/// scrambling and compression, and checks it against the recorded hash.
+/// `start` is when decoding began, `read` how long reading took and `lost`
+/// the shards that were not read intact.
+pub(crate) fn assemble_output(
+    meta: ShardMetadata,
+    shards_opt: Vec<Option<Vec<u8>>>,
+    read: Duration,
+    lost: Vec<usize>,
+    start: Instant,
+) -> Result<DecodedOutput> {
+    let (orig_len, k) = (meta.orig_len, meta.data_shards);
+    let shards_opt = Wiping::new(shards_opt);
+
+    info!("Assembling data shards...");
     let pb_write = ProgressBar::new(orig_len as u64);
     pb_write.set_style(
         ProgressStyle::with_template(
@@ -112,25 +855,491 @@ pub async fn handle_decode(args: Commands) -> Result<()> {
         .progress_chars("=> "),
     );
 
+    let mut out_buf = Wiping::new(match (&meta.data_shard_lens, meta.stream_stripe) {
+        (Some(lens), _) => assemble_uneven_data_shards(&shards_opt[..k], lens, &pb_write)?,
+        (None, Some(stripe)) => {
+            assemble_streamed_data_shards(&shards_opt[..k], orig_len, stripe, &pb_write)?
+        }
+        (None, None) => {
+            assemble_data_shards(&shards_opt[..k], orig_len, meta.shard_len(), &pb_write)?
+        }
+    });
+    pb_write.finish_with_message("File assembled!");
+
+    if let Some(seed) = meta.scramble_seed {
+        out_buf = Wiping::new(unscramble(&out_buf, seed));
+    }
+    if let (Some(algorithm), Some(uncompressed_len)) = (meta.compression, meta.uncompressed_len) {
+        info!("Decompressing {} bytes ({:?})", out_buf.len(), algorithm);
+        out_buf = Wiping::new(decompress(algorithm, &out_buf, uncompressed_len)?);
+    }
+    if let Some(expected) = &meta.input_sha256
+        && sha256_hex(&out_buf) != *expected
+    {
+        return Err(RseError::Corruption(
+            "Decoded output does not match the recorded input SHA-256".into(),
+        )
+        .into());
+    }
+    Ok(DecodedOutput {
+        data: out_buf.into_inner(),
+        missing_ranges: Vec::new(),
+        times: PhaseTimes {
+            read,
+            compute: start.elapsed().saturating_sub(read),
+            ..Default::default()
+        },
+        lost_shards: lost,
+    })
+}
+
+/// Reads the first of `candidates` (copies of one shard, most preferred
+/// first) whose contents pass `is_valid`. If none does, the first copy that
+/// exists is returned anyway, so the usual size and checksum handling reports
+/// and discards it; `None` means no copy could be read at all. A copy that
+/// still fails to read after `retry` is skipped like an absent one. Split
+/// copies are reassembled from `volumes` volumes. With a `log_entry`, the
+/// candidates are shard logs and the shard is read from that place in each.
+async fn read_first_valid(
+    candidates: &[PathBuf],
+    volumes: Option<usize>,
+    log_entry: Option<ShardLogEntry>,
+    retry: &RetryPolicy,
+    is_valid: impl Fn(&[u8]) -> bool,
+) -> Result<Option<Vec<u8>>> {
+    let mut first_existing = None;
+    for (rank, path) in candidates.iter().enumerate() {
+        let what = format!("{:?}", path);
+        let read = || async {
+            match &log_entry {
+                Some(entry) => read_log_shard(path, entry).await,
+                None => read_shard_file(path, volumes).await,
+            }
+        };
+        let data = match retry.read(&what, read).await {
+            Ok(Some(data)) => data,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!(
+                    "Giving up on shard {:?} after {} attempts ({}); treating it as missing",
+                    path,
+                    retry.retries + 1,
+                    e
+                );
+                continue;
+            }
+        };
+        if is_valid(&data) {
+            if rank > 0 {
+                info!("Using fallback copy {:?}", path);
+            }
+            return Ok(Some(data));
+        }
+        if candidates.len() > 1 {
+            warn!("Shard copy {:?} is damaged; trying the next copy", path);
+        }
+        first_existing.get_or_insert(data);
+    }
+    Ok(first_existing)
+}
+
+/// Best-effort output when fewer than `k` shards survive: each present data
+/// shard is copied to its place in the input and the others are zero-filled.
+/// Only possible when data shards hold plain contiguous input, i.e. without
+/// compression, scrambling or stripe rotation.
+fn assemble_partial(
+    meta: &ShardMetadata,
+    shards: &[Option<Vec<u8>>],
+    usable: usize,
+) -> Result<DecodedOutput> {
+    let transform = if meta.compression.is_some() {
+        Some("compressed")
+    } else if meta.scramble_seed.is_some() {
+        Some("scrambled")
+    } else if meta.stripe_rotation.is_some() {
+        Some("stripe-rotated")
+    } else if meta.stream_stripe.is_some() {
+        Some("encoded as a stream")
+    } else {
+        None
+    };
+    if let Some(transform) = transform {
+        return Err(RseError::InsufficientShards {
+            have: usable,
+            need: meta.data_shards,
+        })
+        .context(format!(
+            "--partial-ok cannot help: the set is {}, so surviving shards do not map to input bytes",
+            transform
+        ));
+    }
+
+    let data_shards = &shards[..meta.data_shards];
+    let shard_len = meta.shard_len();
+    let mut data = Vec::with_capacity(meta.orig_len);
+    let mut missing_ranges: Vec<Range<usize>> = Vec::new();
+    for (i, shard) in data_shards.iter().enumerate() {
+        let len = match &meta.data_shard_lens {
+            Some(lens) => lens[i],
+            None => shard_len.min(meta.orig_len.saturating_sub(i * shard_len)),
+        };
+        let start = data.len();
+        match shard {
+            Some(shard) => data.extend_from_slice(&shard[..len]),
+            None => {
+                data.resize(start + len, 0);
+                match missing_ranges.last_mut() {
+                    Some(last) if last.end == start => last.end = start + len,
+                    _ if len > 0 => missing_ranges.push(start..start + len),
+                    _ => {}
+                }
+            }
+        }
+    }
+    warn!(
+        "Only {} of {} needed shards survive; missing byte ranges {:?} are zero-filled",
+        usable, meta.data_shards, missing_ranges
+    );
+    Ok(DecodedOutput {
+        data,
+        missing_ranges,
+        times: PhaseTimes::default(),
+        lost_shards: (0..shards.len()).filter(|&i| shards[i].is_none()).collect(),
+    })
+}
+
+/// Cross-checks the present shards with [`locate_corruption`] and drops the
+/// one that disagrees with the rest, so it is rebuilt like a missing shard.
+async fn drop_located_corruption(
+    meta: &ShardMetadata,
+    mut shards_opt: Vec<Option<Vec<u8>>>,
+    execution: &Execution,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    if meta.stripe_rotation.is_some() || meta.product_code.is_some() || meta.local_groups.is_some()
+    {
+        return Err(RseError::InvalidArgument(
+            "--locate-corruption does not support stripe-rotated, product-code or \
+             local-group sets"
+                .into(),
+        )
+        .into());
+    }
+    let codec = meta.codec()?.with_execution(execution.clone());
+    let (shards, check) = tokio::task::spawn_blocking(move || {
+        let check = locate_corruption(&codec, &shards_opt);
+        (shards_opt, check)
+    })
+    .await
+    .context("Corruption check task panicked")?;
+    shards_opt = shards;
+
+    let present = shards_opt.iter().filter(|s| s.is_some()).count();
+    match check? {
+        CorruptionCheck::Consistent => {
+            info!("All {} present shards are consistent", present);
+        }
+        CorruptionCheck::Located(i) => {
+            println!("CORRUPT   {}", meta.shard_file_name(i));
+            warn!(
+                "Shard {} disagrees with the other {} shards; treating it as missing",
+                i,
+                present - 1
+            );
+            shards_opt[i] = None;
+        }
+        CorruptionCheck::Unlocated => {
+            return Err(RseError::Corruption(format!(
+                "The {} present shards are inconsistent, but no single corrupt shard could be \
+                 identified (this needs at least k+2 = {} shards and one bad shard)",
+                present,
+                meta.data_shards + 2
+            ))
+            .into());
+        }
+    }
+    Ok(shards_opt)
+}
+
+/// Fixes corrupted elements of the present shards with [`correct_errors`],
+/// printing a line for each shard it changed.
+async fn correct_shard_errors(
+    meta: &ShardMetadata,
+    shards_opt: Vec<Option<Vec<u8>>>,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    if meta.stripe_rotation.is_some() || meta.product_code.is_some() || meta.local_groups.is_some()
+    {
+        return Err(RseError::InvalidArgument(
+            "--correct-errors does not support stripe-rotated, product-code or local-group sets"
+                .into(),
+        )
+        .into());
+    }
+    let codec = meta.codec()?;
+    let (shards, correction) = tokio::task::spawn_blocking(move || {
+        let mut shards = shards_opt;
+        let correction = correct_errors(&codec, &mut shards);
+        (shards, correction)
+    })
+    .await
+    .context("Error correction task panicked")?;
+    let correction = correction?;
+    for &i in &correction.shards {
+        println!("CORRECTED {}", meta.shard_file_name(i));
+    }
+    if correction.symbols > 0 {
+        warn!(
+            "Corrected {} bytes in {} shards",
+            correction.symbols,
+            correction.shards.len()
+        );
+    }
+    Ok(shards)
+}
+
+/// Rebuilds the present data shards from parity with
+/// [`Codec::cross_check_data`] and fails if any comes out different from the
+/// stored shard, printing a line for each.
+async fn cross_check_parity(
+    meta: &ShardMetadata,
+    shards_opt: Vec<Option<Vec<u8>>>,
+    execution: &Execution,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    if meta.stripe_rotation.is_some() || meta.product_code.is_some() || meta.local_groups.is_some()
+    {
+        return Err(RseError::InvalidArgument(
+            "--force-reconstruct does not support stripe-rotated, product-code or local-group \
+             sets"
+                .into(),
+        )
+        .into());
+    }
+    let codec = meta.codec()?.with_execution(execution.clone());
+    let (shards_opt, mismatched) = tokio::task::spawn_blocking(move || {
+        let mismatched = codec.cross_check_data(&shards_opt);
+        (shards_opt, mismatched)
+    })
+    .await
+    .context("Parity cross-check task panicked")?;
+    let mismatched = mismatched?;
+
+    let checked = shards_opt[..meta.data_shards]
+        .iter()
+        .filter(|s| s.is_some())
+        .count();
+    if mismatched.is_empty() {
+        info!(
+            "All {} present data shards match their reconstruction from parity",
+            checked
+        );
+        return Ok(shards_opt);
+    }
+    for &i in &mismatched {
+        println!("MISMATCH  {}", meta.shard_file_name(i));
+    }
+    Err(RseError::Corruption(format!(
+        "{} of {} data shards differ when rebuilt from parity ({:?}); the stored data and \
+         parity are inconsistent (try --locate-corruption to find the bad shard)",
+        mismatched.len(),
+        checked,
+        mismatched
+    ))
+    .into())
+}
+
+/// Decrypts every present shard in place. A shard that fails authentication
+/// was modified and is treated as missing; if none decrypts, the key is wrong.
+fn decrypt_shards(
+    encryption: &ShardEncryption,
+    key: Option<&EncryptKey>,
+    shards: &mut [Option<Vec<u8>>],
+    execution: &Execution,
+) -> Result<()> {
+    let key = key.ok_or_else(|| {
+        RseError::InvalidArgument("The shard set is encrypted; supply --encrypt-key".into())
+    })?;
+    let mut slots: Vec<(&mut Option<Vec<u8>>, bool)> =
+        shards.iter_mut().map(|shard| (shard, false)).collect();
+    execution.for_each_mut(&mut slots, |i, (shard, failed)| {
+        let Some(file) = shard.as_ref() else { return };
+        match encryption.decrypt(key, i, file) {
+            Ok(plaintext) => **shard = Some(plaintext),
+            Err(_) => {
+                **shard = None;
+                *failed = true;
+            }
+        }
+    });
+    let failed: Vec<usize> = (0..slots.len()).filter(|&i| slots[i].1).collect();
+    if !failed.is_empty() && shards.iter().all(|s| s.is_none()) {
+        return Err(RseError::InvalidArgument(
+            "No shard could be decrypted; is --encrypt-key the key used to encode?".into(),
+        )
+        .into());
+    }
+    if !failed.is_empty() {
+        warn!(
+            "Shards {:?} failed authentication; treating them as missing",
+            failed
+        );
+    }
+    Ok(())
+}
+
+/// How often [`wait_for_growing_shards`] re-checks short shard files.
+const SHARD_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Re-reads shards shorter than the metadata expects, e.g. files still being
+/// replicated in, until they reach full size or `timeout` passes. Shards that
+/// are still short are left as read and later treated as missing. Absent
+/// files are not waited for.
+async fn wait_for_growing_shards(
+    shard_dir: &Path,
+    meta: &ShardMetadata,
+    shards: &mut [Option<Vec<u8>>],
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut announced = false;
+    loop {
+        let mut waiting = Vec::new();
+        for (i, shard) in shards.iter_mut().enumerate() {
+            let Some(data) = shard else { continue };
+            let expected = meta.stored_len(i);
+            if data.len() >= expected {
+                continue;
+            }
+            let len = meta
+                .shard_file_len(shard_dir, i)
+                .await
+                .map_or(0, |len| len as usize);
+            if len > data.len() {
+                *data = meta.read_shard(shard_dir, i).await.with_context(|| {
+                    format!(
+                        "Failed to re-read shard {:?}",
+                        meta.shard_path(shard_dir, i)
+                    )
+                })?;
+            }
+            if data.len() < expected {
+                waiting.push(i);
+            }
+        }
+
+        let now = Instant::now();
+        if waiting.is_empty() {
+            return Ok(());
+        }
+        if now >= deadline {
+            warn!(
+                "Shards {:?} are still incomplete after waiting {:.2?}",
+                waiting, timeout
+            );
+            return Ok(());
+        }
+        if !announced {
+            info!(
+                "Waiting up to {:.2?} for shards {:?} to finish growing",
+                timeout, waiting
+            );
+            announced = true;
+        }
+        tokio::time::sleep(SHARD_POLL_INTERVAL.min(deadline - now)).await;
+    }
+}
+
+/// Errors if any present shard is not exactly `expected_len(index)` bytes.
+/// Without this, extra bytes from a bad write or appended garbage are silently
+/// ignored.
+pub fn check_shard_lengths(
+    shards: &[Option<Vec<u8>>],
+    expected_len: impl Fn(usize) -> usize,
+) -> Result<()> {
+    for (i, shard) in shards.iter().enumerate() {
+        let shard_len = expected_len(i);
+        if let Some(shard) = shard
+            && shard.len() != shard_len
+        {
+            return Err(RseError::Corruption(format!(
+                "Shard {} is {} bytes, expected {} bytes from metadata",
+                i,
+                shard.len(),
+                shard_len
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Concatenates the data shards and truncates the padding to `orig_len`.
+///
+/// Every data shard must be `shard_len` bytes, the length the metadata
+/// records (`ceil(orig_len / k)` unless the set is aligned). Any other length
+/// means the recorded length and the shards disagree (e.g. a corrupted
+/// metadata file); that is reported as an error instead of silently
+/// producing a truncated or padded file.
+pub fn assemble_data_shards(
+    data_shards: &[Option<Vec<u8>>],
+    orig_len: usize,
+    shard_len: usize,
+    progress: &ProgressBar,
+) -> Result<Vec<u8>> {
+    let k = data_shards.len();
+    if orig_len > k * shard_len {
+        return Err(RseError::Corruption(format!(
+            "The recorded length of {} bytes exceeds {} data shards of {} bytes; metadata and \
+             shards are inconsistent",
+            orig_len, k, shard_len
+        ))
+        .into());
+    }
+
     let mut out_buf = Vec::with_capacity(orig_len);
-    let mut bytes_written = 0;
-    for i in 0..k {
-        let shard = shards_opt[i]
+    for (i, shard) in data_shards.iter().enumerate() {
+        let shard = shard
             .as_ref()
             .context("Reconstructed data shard is missing unexpectedly")?;
-        let to_write = std::cmp::min(shard.len(), orig_len - bytes_written);
+        if shard.len() != shard_len {
+            return Err(RseError::Corruption(format!(
+                "Data shard {} is {} bytes, but the metadata gives {}-byte shards for its {} \
+                 bytes; metadata and shards are inconsistent",
+                i,
+                shard.len(),
+                shard_len,
+                orig_len
+            ))
+            .into());
+        }
+        let to_write = shard.len().min(orig_len.saturating_sub(out_buf.len()));
         out_buf.extend_from_slice(&shard[..to_write]);
-        bytes_written += to_write;
-        pb_write.inc(to_write as u64);
+        progress.inc(to_write as u64);
     }
-    pb_write.finish_with_message("File assembled!");
-
-    fs::write(&output_path, &out_buf).await?;
+    Ok(out_buf)
+}
 
-    info!(
-        "✅ Successfully reconstructed '{}' ({} bytes)",
-        output_path.display(),
-        orig_len
-    );
-    Ok(())
+/// Concatenates the first `lens[i]` bytes of each zero-padded data shard, for
+/// shard sets split with `--shard-weights`.
+pub fn assemble_uneven_data_shards(
+    data_shards: &[Option<Vec<u8>>],
+    lens: &[usize],
+    progress: &ProgressBar,
+) -> Result<Vec<u8>> {
+    let mut out_buf = Vec::with_capacity(lens.iter().sum());
+    for (i, (shard, &len)) in data_shards.iter().zip(lens).enumerate() {
+        let shard = shard
+            .as_ref()
+            .context("Reconstructed data shard is missing unexpectedly")?;
+        if shard.len() < len {
+            return Err(RseError::Corruption(format!(
+                "Data shard {} is {} bytes, but metadata records {} logical bytes",
+                i,
+                shard.len(),
+                len
+            ))
+            .into());
+        }
+        out_buf.extend_from_slice(&shard[..len]);
+        progress.inc(len as u64);
+    }
+    Ok(out_buf)
 }
@@ -0,0 +1,40 @@
+use anyhow::Result;
+use tracing::instrument;
+
+use crate::{
+    cli::commands::Commands,
+    codec::{matrix::format_matrix_hex, reconstruct_shards::Codec},
+};
+
+#[instrument(skip(args))]
+pub async fn handle_dump_matrix(args: Commands) -> Result<()> {
+    let Commands::DumpMatrix {
+        data_shards: k,
+        parity_shards: m,
+        matrix_type,
+        survivors,
+    } = args
+    else {
+        unreachable!()
+    };
+
+    let codec = Codec::try_with_matrix_type(k, m, matrix_type)?;
+    println!(
+        "Encoding matrix ({:?}, k={}, m={}), {} x {}:",
+        matrix_type, k, m, m, k
+    );
+    print!("{}", format_matrix_hex(codec.encode_matrix()));
+
+    if let Some(survivors) = survivors {
+        let mut sorted = survivors.clone();
+        sorted.sort_unstable();
+        let inverse = codec.inverse_matrix(&sorted)?;
+        println!();
+        println!(
+            "Reconstruction matrix for survivors {:?}, {} x {}:",
+            sorted, k, k
+        );
+        print!("{}", format_matrix_hex(&inverse));
+    }
+    Ok(())
+}
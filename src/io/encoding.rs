@@ -1,46 +1,1334 @@
 use anyhow::{Context, Result, anyhow};
 use futures_util::future::join_all;
 use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
-use std::fs::create_dir_all;
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tokio::fs;
-use tracing::{info, instrument};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::Semaphore;
+use tracing::{info, instrument, warn};
 
 use crate::{
-    cli::commands::Commands,
-    algorithm::gf256::Gf256,
-    codec::{encode_shards::shard_encoding, matrix::build_vandermonde},
+    algorithm::{gf256::Gf256, shuffle::permutation},
+    cli::commands::{Commands, EncodeArgs},
+    codec::{
+        encode_shards::{shard_encoding, shard_encoding_lazy},
+        execution::Execution,
+        lrc::LrcCodec,
+        matrix::{Matrix, MatrixType, check_mds, matrix_from_hex_rows, matrix_to_hex_rows},
+        product::{ProductCodec, ProductGeometry},
+        reconstruct_shards::Codec,
+    },
+    error::RseError,
+    io::{
+        atomic::WriteOptions,
+        budget::{ShardIoLimit, default_memory_budget},
+        checksum::{ChecksumAlgo, ShardChecksums},
+        compression::{Compression, ShardCompression, compress},
+        decoding::{DecodeOptions, decode_dir},
+        encryption::{EncryptKey, ShardEncryption},
+        manifest::{MANIFEST_FILE, Manifest, sha256_hex},
+        metadata::{Provenance, ShardMetadata, interleaved_parity_order},
+        objects::encode_objects,
+        partition::weighted_split,
+        report::{OperationReport, write_report},
+        rotation::rotate_stripes as rotate_stripes_across,
+        scramble::scramble,
+        self_healing::{check_self_healing_options, write_self_healing},
+        shard_log::{ShardLogEntry, ShardLogWriter, shard_log_path},
+        stats::{PhaseTimes, log_throughput},
+        store::{FilesystemStore, ShardStore},
+        stream::encode_stream,
+        throttle::{RateLimiter, read_throttled, read_to_end_throttled},
+        trailer::{ShardTrailer, append_trailer, check_trailer},
+        verify::stored_indices,
+        volumes::{split_volumes, volume_file_name},
+        wipe::Wiping,
+    },
 };
 
+/// Options shared by `encode` and `reshape`.
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub store_only: Option<Vec<usize>>,
+    pub manifest: bool,
+    pub low_memory: bool,
+    pub compression: Option<Compression>,
+    /// Compress each shard file on disk.
+    pub compress_shards: Option<Compression>,
+    pub rotate_stripes: Option<usize>,
+    pub scramble_seed: Option<u64>,
+    pub checksum_algo: ChecksumAlgo,
+    pub shard_weights: Option<Vec<usize>>,
+    pub interleave_parity: bool,
+    /// `[data_rows, parity_rows]` of a product code whose rows use
+    /// `data_shards`/`parity_shards`.
+    pub product_code: Option<Vec<usize>>,
+    /// Number of local parity groups of a local reconstruction code.
+    pub local_groups: Option<usize>,
+    /// Encoding matrix of a plain code.
+    pub matrix_type: MatrixType,
+    /// Parity rows when `matrix_type` is [`MatrixType::Custom`].
+    pub custom_matrix: Option<Matrix>,
+    /// Minimum number of lost shards the set must tolerate.
+    pub require_tolerance: Option<usize>,
+    /// Decode the set after writing it, ignoring `require_tolerance` random shards.
+    pub verify_after_encode: bool,
+    /// Seed choosing the shards `verify_after_encode` ignores; taken from the
+    /// clock, and logged, if `None`.
+    pub verify_seed: Option<u64>,
+    /// Encrypt shard files at rest with this key.
+    pub encrypt_key: Option<EncryptKey>,
+    /// Do nothing if the output already holds an intact set for this input.
+    pub skip_existing: bool,
+    /// Append a self-verifying trailer to every shard file.
+    pub shard_trailer: bool,
+    /// Round the shard length up to a multiple of this many bytes.
+    pub align: Option<usize>,
+    /// Split every shard file into volumes of this many bytes.
+    pub volume_size: Option<usize>,
+    /// Store each shard's checksum in a sidecar file next to it.
+    pub sidecar_metadata: bool,
+    /// Append the shards to one log file instead of a file each.
+    pub shard_log: bool,
+    /// Part of the input file to encode.
+    pub input_range: InputRange,
+    /// Leave the encode time and host out of the metadata, so encoding the
+    /// same input with the same options gives byte-identical output.
+    pub reproducible: bool,
+    /// Bytes of shard writes that may be in flight at once; bounds how many
+    /// shards are written concurrently, not the memory they take. Unbounded
+    /// if `None`.
+    pub memory_budget: Option<usize>,
+    /// Leave the shard files that were written when others fail to write,
+    /// instead of removing them.
+    pub keep_partial: bool,
+    /// Encode the input as a stream, this many bytes per data shard at a
+    /// time (see [`crate::io::stream`]).
+    pub stream_stripe: Option<usize>,
+    /// How shard files, the manifest and the metadata are written.
+    pub write: WriteOptions,
+    /// Serial or parallel splitting, parity computation and sealing.
+    pub execution: Execution,
+}
+
+/// The bytes of an input file to encode: `length` bytes from `offset`, or
+/// the rest of the file if `length` is `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputRange {
+    pub offset: u64,
+    pub length: Option<u64>,
+}
+
+impl InputRange {
+    pub fn is_whole_file(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The byte range of `input_path`, a file of `file_len` bytes, or an
+    /// error if it does not lie within the file.
+    pub fn resolve(&self, input_path: &Path, file_len: u64) -> Result<Range<u64>> {
+        if self.offset > file_len {
+            return Err(RseError::InvalidArgument(format!(
+                "--input-offset {} is past the end of {:?} ({} bytes)",
+                self.offset, input_path, file_len
+            ))
+            .into());
+        }
+        let end = match self.length {
+            Some(length) => match self.offset.checked_add(length) {
+                Some(end) if end <= file_len => end,
+                _ => {
+                    return Err(RseError::InvalidArgument(format!(
+                        "--input-offset {} plus --input-length {} runs past the end of {:?} \
+                         ({} bytes)",
+                        self.offset, length, input_path, file_len
+                    ))
+                    .into());
+                }
+            },
+            None => file_len,
+        };
+        Ok(self.offset..end)
+    }
+}
+
+impl EncodeOptions {
+    /// Grid of the product code, if one was requested.
+    pub fn product_geometry(&self) -> Option<ProductGeometry> {
+        match self.product_code.as_deref() {
+            Some(&[col_data, col_parity]) => Some(ProductGeometry {
+                row_data: self.data_shards,
+                row_parity: self.parity_shards,
+                col_data,
+                col_parity,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Data and parity shard counts of the whole set.
+    pub fn set_shards(&self) -> (usize, usize) {
+        match (self.product_geometry(), self.local_groups) {
+            (Some(geometry), _) => (
+                geometry.data_shards(),
+                geometry.total_shards() - geometry.data_shards(),
+            ),
+            (None, Some(groups)) => (self.data_shards, self.parity_shards + groups),
+            (None, None) => (self.data_shards, self.parity_shards),
+        }
+    }
+
+    /// Length of each shard for `input_len` bytes split evenly, rounded up
+    /// to the alignment.
+    pub fn aligned_shard_len(&self, input_len: usize) -> usize {
+        let (k, _) = self.set_shards();
+        let shard_len = input_len.div_ceil(k);
+        match self.align {
+            Some(align) => shard_len.next_multiple_of(align),
+            None => shard_len,
+        }
+    }
+
+    /// The `m x k` parity rows of a plain code.
+    pub fn encode_matrix(&self) -> Result<Matrix> {
+        match &self.custom_matrix {
+            Some(matrix) => Ok(matrix.clone()),
+            None => self
+                .matrix_type
+                .build(&Gf256::new(), self.data_shards, self.parity_shards),
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        Codec::validate_params(self.data_shards, self.parity_shards)?;
+        if self.matrix_type != MatrixType::Vandermonde
+            && (self.product_code.is_some() || self.local_groups.is_some())
+        {
+            return Err(RseError::InvalidArgument(
+                "--matrix-type and --matrix-file cannot be combined with --product-code or \
+                 --local-groups"
+                    .into(),
+            )
+            .into());
+        }
+        match (&self.custom_matrix, self.matrix_type) {
+            (Some(matrix), MatrixType::Custom) => {
+                let codec =
+                    Codec::with_matrix(self.data_shards, self.parity_shards, matrix.clone())?;
+                check_mds(codec.field(), matrix, self.data_shards)
+                    .map_err(|e| RseError::InvalidArgument(format!("--matrix-file: {:#}", e)))?;
+            }
+            (None, MatrixType::Custom) | (Some(_), _) => {
+                return Err(RseError::InvalidArgument(
+                    "A custom matrix needs matrix_type Custom and its rows together".into(),
+                )
+                .into());
+            }
+            (None, MatrixType::Xor) if self.parity_shards != 1 => {
+                return Err(RseError::InvalidArgument(format!(
+                    "--matrix-type xor needs exactly one parity shard, got {}",
+                    self.parity_shards
+                ))
+                .into());
+            }
+            (None, _) => {}
+        }
+        if let Some(rows) = &self.product_code {
+            let [data_rows, parity_rows] = rows[..] else {
+                return Err(RseError::InvalidArgument(
+                    "--product-code needs DATA_ROWS,PARITY_ROWS".into(),
+                )
+                .into());
+            };
+            Codec::validate_params(data_rows, parity_rows)?;
+            if self.rotate_stripes.is_some() || self.shard_weights.is_some() || self.low_memory {
+                return Err(RseError::InvalidArgument(
+                    "--product-code cannot be combined with --rotate-stripes, --shard-weights or \
+                     --low-memory"
+                        .into(),
+                )
+                .into());
+            }
+        }
+        if let Some(groups) = self.local_groups {
+            if groups == 0 || groups > self.data_shards {
+                return Err(RseError::InvalidArgument(format!(
+                    "--local-groups must be between 1 and the {} data shards",
+                    self.data_shards
+                ))
+                .into());
+            }
+            if self.product_code.is_some()
+                || self.rotate_stripes.is_some()
+                || self.shard_weights.is_some()
+                || self.low_memory
+            {
+                return Err(RseError::InvalidArgument(
+                    "--local-groups cannot be combined with --product-code, --rotate-stripes, \
+                     --shard-weights or --low-memory"
+                        .into(),
+                )
+                .into());
+            }
+        }
+        let (k, m) = self.set_shards();
+        if let Some(&bad) = self.store_only.iter().flatten().find(|&&i| i >= k + m) {
+            return Err(RseError::InvalidArgument(format!(
+                "--store-only index {} is out of range for {} shards",
+                bad,
+                k + m
+            ))
+            .into());
+        }
+        if self.rotate_stripes == Some(0) {
+            return Err(RseError::InvalidArgument("--rotate-stripes must be > 0".into()).into());
+        }
+        if self.align == Some(0) {
+            return Err(RseError::InvalidArgument("--align must be > 0".into()).into());
+        }
+        if self.align.is_some() && self.shard_weights.is_some() {
+            return Err(RseError::InvalidArgument(
+                "--align cannot be combined with --shard-weights".into(),
+            )
+            .into());
+        }
+        if self.volume_size == Some(0) {
+            return Err(RseError::InvalidArgument("--volume-size must be > 0".into()).into());
+        }
+        if let Some(weights) = &self.shard_weights {
+            if weights.len() != k || weights.iter().all(|&w| w == 0) {
+                return Err(RseError::InvalidArgument(format!(
+                    "--shard-weights needs {} weights (one per data shard), not all zero",
+                    k
+                ))
+                .into());
+            }
+            if self.rotate_stripes.is_some() {
+                return Err(RseError::InvalidArgument(
+                    "--shard-weights cannot be combined with --rotate-stripes".into(),
+                )
+                .into());
+            }
+        }
+        if let Some(tolerance) = self.require_tolerance {
+            // Local parity only adds tolerance for some loss patterns.
+            let guaranteed = match self.product_geometry() {
+                Some(geometry) => geometry.guaranteed_tolerance(),
+                None if self.local_groups.is_some() => self.parity_shards,
+                None => m,
+            };
+            if guaranteed < tolerance {
+                return Err(RseError::InvalidArgument(format!(
+                    "{} parity shards tolerate {} lost shards, but --require-tolerance is {}",
+                    m, guaranteed, tolerance
+                ))
+                .into());
+            }
+        }
+        if self.verify_after_encode && self.store_only.is_some() {
+            return Err(RseError::InvalidArgument(
+                "--verify-after-encode needs every shard in the output directory and cannot be \
+                 combined with --store-only"
+                    .into(),
+            )
+            .into());
+        }
+        if self.sidecar_metadata && self.checksum_algo == ChecksumAlgo::None {
+            return Err(RseError::InvalidArgument(
+                "--sidecar-metadata stores per-shard checksums and cannot be combined with \
+                 --checksum-algo none"
+                    .into(),
+            )
+            .into());
+        }
+        if self.shard_log && (self.volume_size.is_some() || self.sidecar_metadata || self.manifest)
+        {
+            return Err(RseError::InvalidArgument(
+                "--shard-log keeps every shard in one file and cannot be combined with \
+                 --volume-size, --sidecar-metadata or --manifest"
+                    .into(),
+            )
+            .into());
+        }
+        if self.interleave_parity && self.rotate_stripes.is_some() {
+            return Err(RseError::InvalidArgument(
+                "--interleave-parity cannot be combined with --rotate-stripes".into(),
+            )
+            .into());
+        }
+        if self.reproducible && self.encrypt_key.is_some() {
+            return Err(RseError::InvalidArgument(
+                "--reproducible cannot be combined with --encrypt-key, whose random nonces make \
+                 every encode differ"
+                    .into(),
+            )
+            .into());
+        }
+        if self.stream_stripe.is_some() {
+            self.validate_stream()?;
+        }
+        if self.rotate_stripes.is_some() && self.low_memory {
+            return Err(RseError::InvalidArgument(
+                "--rotate-stripes needs every shard in memory and cannot be combined with --low-memory"
+                    .into(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Checks `stream_stripe` and the options a stream cannot be encoded
+    /// with: those that need the whole input or whole shards at once.
+    fn validate_stream(&self) -> Result<()> {
+        if self.stream_stripe == Some(0) {
+            return Err(RseError::InvalidArgument("--stream-stripe must be > 0".into()).into());
+        }
+        let unsupported: Vec<&str> = [
+            (self.compression.is_some(), "--compress"),
+            (self.compress_shards.is_some(), "--compress-shards"),
+            (self.scramble_seed.is_some(), "--scramble"),
+            (self.encrypt_key.is_some(), "--encrypt-key"),
+            (self.shard_trailer, "--shard-trailer"),
+            (self.rotate_stripes.is_some(), "--rotate-stripes"),
+            (self.shard_weights.is_some(), "--shard-weights"),
+            (self.align.is_some(), "--align"),
+            (self.product_code.is_some(), "--product-code"),
+            (self.local_groups.is_some(), "--local-groups"),
+            (self.volume_size.is_some(), "--volume-size"),
+            (self.manifest, "--manifest"),
+            (self.shard_log, "--shard-log"),
+            (self.skip_existing, "--skip-existing"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect();
+        if !unsupported.is_empty() {
+            return Err(RseError::InvalidArgument(format!(
+                "--stream-stripe cannot be combined with {}",
+                unsupported.join(", ")
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
 #[instrument(skip(args))]
-pub async fn handle_encode(args: Commands) -> Result<()> {
-    let (input_path, out_dir, k, m) = match args {
-        Commands::Encode {
-            input,
-            output,
-            data_shards,
-            parity_shards,
-        } => (input, output, data_shards, parity_shards),
-        _ => unreachable!(),
+pub async fn handle_encode(args: Commands, execution: &Execution) -> Result<()> {
+    let Commands::Encode(args) = args else {
+        unreachable!()
+    };
+    let report_file = args.report_file.clone();
+    let mut report = OperationReport::new("encode");
+    let result = encode_command(args, execution, &mut report).await;
+    write_report(report_file.as_deref(), report, result).await
+}
+
+/// Runs `encode` as `args` asks, recording what it did in `report`.
+async fn encode_command(
+    args: Box<EncodeArgs>,
+    execution: &Execution,
+    report: &mut OperationReport,
+) -> Result<()> {
+    let EncodeArgs {
+        input: input_paths,
+        output: out_dir,
+        data_shards: k,
+        parity_shards: m,
+        no_space_check,
+        store_only,
+        rate_limit,
+        manifest: write_manifest,
+        low_memory,
+        compress: compression,
+        compress_shards,
+        rotate_stripes,
+        scramble: scramble_seed,
+        checksum_algo,
+        shard_weights,
+        product_code,
+        local_groups,
+        matrix_type,
+        matrix_file,
+        interleave_parity,
+        require_tolerance,
+        verify_after_encode,
+        verify_seed,
+        encrypt_key,
+        skip_existing,
+        shard_trailer,
+        align,
+        volume_size,
+        sidecar_metadata,
+        shard_log,
+        tmp_dir,
+        fsync,
+        reproducible,
+        memory_budget,
+        keep_partial,
+        max_shards,
+        min_shard_size,
+        force,
+        input_offset,
+        input_length,
+        parallel_files,
+        framed_objects,
+        stream_stripe,
+        self_healing,
+        report_file: _,
+    } = *args;
+    report.inputs = input_paths.clone();
+    report.output = out_dir.clone();
+    report.data_shards = Some(k);
+    report.parity_shards = Some(m);
+    report.stage("validating options");
+
+    let custom_matrix = match &matrix_file {
+        Some(path) => Some(read_matrix_file(path).await?),
+        None => None,
     };
+    let opts = EncodeOptions {
+        data_shards: k,
+        parity_shards: m,
+        matrix_type: if custom_matrix.is_some() {
+            MatrixType::Custom
+        } else {
+            matrix_type
+        },
+        custom_matrix,
+        store_only,
+        manifest: write_manifest,
+        low_memory,
+        compression,
+        compress_shards,
+        rotate_stripes,
+        scramble_seed,
+        checksum_algo,
+        shard_weights,
+        product_code,
+        local_groups,
+        interleave_parity,
+        require_tolerance,
+        verify_after_encode,
+        verify_seed,
+        encrypt_key,
+        skip_existing,
+        shard_trailer,
+        align,
+        volume_size,
+        sidecar_metadata,
+        shard_log,
+        input_range: InputRange {
+            offset: input_offset.unwrap_or(0),
+            length: input_length,
+        },
+        reproducible,
+        memory_budget: memory_budget.or_else(default_memory_budget),
+        keep_partial,
+        stream_stripe,
+        write: WriteOptions { tmp_dir, fsync },
+        execution: execution.clone(),
+    };
+    opts.validate()?;
+    if rate_limit.is_some_and(|r| r <= 0.0 || !r.is_finite()) {
+        return Err(RseError::InvalidArgument(
+            "--rate-limit must be a positive number of MiB/s".into(),
+        )
+        .into());
+    }
+    let limiter = rate_limit.map(|r| Arc::new(RateLimiter::from_mib_per_sec(r)));
+    let encoder = ParityEncoder::new(&opts)?;
+    let limit = (!force).then_some(ShardCountLimit {
+        max_shards,
+        min_shard_size,
+    });
 
-    if k == 0 || m == 0 || k + m > 256 {
-        return Err(anyhow!("Invalid k/m values. Must be > 0 and k+m <= 256"));
+    // Framed objects are checked one by one as they arrive, and stdin has no
+    // length to check up front.
+    if let Some(limit) = limit.filter(|_| !framed_objects) {
+        for input_path in input_paths.iter().filter(|p| *p != Path::new("-")) {
+            let input_len = input_len(input_path, &opts).await?;
+            limit.check(input_path, input_len, &opts)?;
+        }
     }
+    if framed_objects {
+        let [input_path] = <[PathBuf; 1]>::try_from(input_paths).map_err(|_| {
+            RseError::InvalidArgument("--framed-objects reads a single input stream".into())
+        })?;
+        if input_path == Path::new("-") {
+            encode_objects(
+                &mut tokio::io::stdin(),
+                &out_dir,
+                &opts,
+                limiter,
+                &encoder,
+                limit,
+            )
+            .await?;
+        } else {
+            let mut file = fs::File::open(&input_path)
+                .await
+                .with_context(|| format!("Failed to open input file: {:?}", input_path))?;
+            encode_objects(&mut file, &out_dir, &opts, limiter, &encoder, limit).await?;
+        }
+        return Ok(());
+    }
+    if stream_stripe.is_some() {
+        let [input_path] = <[PathBuf; 1]>::try_from(input_paths).map_err(|_| {
+            RseError::InvalidArgument("--stream-stripe reads a single input stream".into())
+        })?;
+        report.stage("encoding");
+        let (input_len, times) = if input_path == Path::new("-") {
+            encode_stream(&mut tokio::io::stdin(), &out_dir, &opts, limiter, &encoder).await?
+        } else {
+            let mut file = fs::File::open(&input_path)
+                .await
+                .with_context(|| format!("Failed to open input file: {:?}", input_path))?;
+            encode_stream(&mut file, &out_dir, &opts, limiter, &encoder).await?
+        };
+        record_encoded(report, &out_dir, input_len, &times).await?;
+        log_throughput(
+            &format!("✅ Successfully encoded '{}'", input_path.display()),
+            input_len,
+            times.total(),
+            &times,
+        );
+        return Ok(());
+    }
+    if self_healing {
+        let [input_path] = <[PathBuf; 1]>::try_from(input_paths).map_err(|_| {
+            RseError::InvalidArgument("--self-healing encodes a single input file".into())
+        })?;
+        check_self_healing_options(&opts)?;
+        report.stage("encoding");
+        let start = Instant::now();
+        let buf = read_input_range(&input_path, &opts.input_range, limiter.as_deref())
+            .await
+            .with_context(|| format!("Failed to read input file: {:?}", input_path))?;
+        let input_len = buf.len();
+        let read = start.elapsed();
+        let meta = write_self_healing(buf, &out_dir, &opts).await?;
+        report.record_set(&meta);
+        report.shards_written = (0..meta.total_shards()).collect();
+        report.bytes = Some(input_len);
+        let times = PhaseTimes {
+            read,
+            compute: start.elapsed() - read,
+            ..Default::default()
+        };
+        report.set_times(&times);
+        log_throughput(
+            &format!(
+                "✅ Successfully encoded '{}' into the self-healing file '{}'",
+                input_path.display(),
+                out_dir.display()
+            ),
+            input_len,
+            times.total(),
+            &times,
+        );
+        return Ok(());
+    }
+    if let Some(jobs) = parallel_files {
+        return encode_files(
+            input_paths,
+            &out_dir,
+            opts,
+            limiter,
+            no_space_check,
+            encoder,
+            jobs,
+        )
+        .await;
+    }
+    let [input_path] = <[PathBuf; 1]>::try_from(input_paths).map_err(|_| {
+        RseError::InvalidArgument("Encoding several input files requires --parallel-files".into())
+    })?;
 
-    let gf = Arc::new(Gf256::new());
+    if !no_space_check {
+        report.stage("checking free space");
+        check_free_space(&out_dir, required_space(&input_path, &opts).await?)?;
+    }
 
-    info!("Reading input file: {:?}", input_path);
-    let buf = fs::read(&input_path)
+    report.stage("encoding");
+    let (input_len, times) = encode_file(&input_path, &out_dir, &opts, limiter, &encoder).await?;
+    record_encoded(report, &out_dir, input_len, &times).await?;
+
+    // Reading the input is reported separately; the overall rate is that of
+    // the encode itself.
+    log_throughput(
+        &format!("✅ Successfully encoded '{}'", input_path.display()),
+        input_len,
+        times.compute + times.write,
+        &times,
+    );
+    Ok(())
+}
+
+/// Records in `report` the set of `input_len` bytes just encoded into
+/// `out_dir`, taking `times`.
+async fn record_encoded(
+    report: &mut OperationReport,
+    out_dir: &Path,
+    input_len: usize,
+    times: &PhaseTimes,
+) -> Result<()> {
+    report.stage("reading back metadata");
+    let meta = ShardMetadata::read(out_dir).await?;
+    report.record_set(&meta);
+    report.shards_written = stored_indices(&meta);
+    report.bytes = Some(input_len);
+    report.set_times(times);
+    Ok(())
+}
+
+/// Reads a `--matrix-file`: one row of two-digit hex elements per line, as
+/// `custom_matrix` in the metadata. Blank lines are skipped.
+async fn read_matrix_file(path: &Path) -> Result<Matrix> {
+    let text = fs::read_to_string(path)
         .await
-        .with_context(|| format!("Failed to read input file: {:?}", input_path))?;
-    let orig_len = buf.len();
+        .with_context(|| format!("Failed to read matrix file {:?}", path))?;
+    let rows: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if rows.is_empty() {
+        return Err(RseError::InvalidArgument(format!("Matrix file {:?} is empty", path)).into());
+    }
+    matrix_from_hex_rows(&rows)
+        .map_err(|e| RseError::InvalidArgument(format!("Matrix file {:?}: {:#}", path, e)).into())
+}
+
+/// Bytes of `input_path` that will be encoded, after `opts.input_range`.
+async fn input_len(input_path: &Path, opts: &EncodeOptions) -> Result<usize> {
+    let file_len = fs::metadata(input_path)
+        .await
+        .with_context(|| format!("Failed to stat input file: {:?}", input_path))?
+        .len();
+    let range = opts.input_range.resolve(input_path, file_len)?;
+    Ok((range.end - range.start) as usize)
+}
+
+/// Reads the part of `input_path` given by `range`, seeking past the rest.
+async fn read_input_range(
+    input_path: &Path,
+    range: &InputRange,
+    limiter: Option<&RateLimiter>,
+) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(input_path).await?;
+    let range = range.resolve(input_path, file.metadata().await?.len())?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+    let len = range.end - range.start;
+    let mut reader = file.take(len);
+    match limiter {
+        Some(limiter) => read_to_end_throttled(&mut reader, len as usize, limiter).await,
+        None => {
+            let mut buf = Vec::with_capacity(len as usize);
+            reader.read_to_end(&mut buf).await?;
+            Ok(buf)
+        }
+    }
+}
+
+/// Length of every shard of the set for an `input_len`-byte input.
+fn planned_shard_len(input_len: usize, opts: &EncodeOptions) -> usize {
+    match &opts.shard_weights {
+        Some(weights) => weighted_split(input_len, weights)
+            .into_iter()
+            .max()
+            .unwrap_or(0),
+        None => opts.aligned_shard_len(input_len),
+    }
+}
+
+/// The `--max-shards` and `--min-shard-size` guard, checked against every
+/// shard set before it is written.
+#[derive(Debug, Clone, Copy)]
+pub struct ShardCountLimit {
+    pub max_shards: usize,
+    pub min_shard_size: usize,
+}
+
+impl ShardCountLimit {
+    /// Refuses a set of more than `max_shards` shards that are each smaller
+    /// than `min_shard_size`: for a small input, so many shards are almost
+    /// certainly a mistake in the shard counts rather than a deliberate choice.
+    pub fn check(&self, input: &Path, input_len: usize, opts: &EncodeOptions) -> Result<()> {
+        let (k, m) = opts.set_shards();
+        let shard_len = planned_shard_len(input_len, opts);
+        if k + m > self.max_shards && shard_len < self.min_shard_size {
+            return Err(RseError::InvalidArgument(format!(
+                "{} shards of {} bytes each for the {}-byte input {:?}: more than --max-shards \
+                 {} and smaller than --min-shard-size {}; use fewer shards, or pass --force",
+                k + m,
+                shard_len,
+                input_len,
+                input,
+                self.max_shards,
+                self.min_shard_size
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Bytes the shard set for `input_path` will take on disk.
+async fn required_space(input_path: &Path, opts: &EncodeOptions) -> Result<u64> {
+    let input_len = input_len(input_path, opts).await?;
+    let (k, m) = opts.set_shards();
+    let shard_len = planned_shard_len(input_len, opts);
+    // Alignment pads the data shards as well as the parity.
+    let data_len = if opts.align.is_some() {
+        shard_len * k
+    } else {
+        input_len
+    };
+    Ok((data_len + shard_len * m) as u64)
+}
+
+/// Reads `input_path` and shards it into `out_dir`, returning the input length
+/// and the time spent in each phase.
+async fn encode_file(
+    input_path: &Path,
+    out_dir: &Path,
+    opts: &EncodeOptions,
+    limiter: Option<Arc<RateLimiter>>,
+    encoder: &ParityEncoder,
+) -> Result<(usize, PhaseTimes)> {
+    info!("Reading input file: {:?}", input_path);
+    let read_start = Instant::now();
+    let buf = if opts.input_range.is_whole_file() {
+        match &limiter {
+            Some(limiter) => read_throttled(input_path, limiter).await,
+            None => fs::read(input_path).await.map_err(Into::into),
+        }
+    } else {
+        read_input_range(input_path, &opts.input_range, limiter.as_deref()).await
+    }
+    .with_context(|| format!("Failed to read input file: {:?}", input_path))?;
+    let input_len = buf.len();
+    let read = read_start.elapsed();
+
+    if opts.skip_existing && existing_set_matches(out_dir, &buf, opts).await {
+        println!(
+            "Already encoded: {} matches the shard set in {}",
+            input_path.display(),
+            out_dir.display()
+        );
+        return Ok((
+            input_len,
+            PhaseTimes {
+                read,
+                ..Default::default()
+            },
+        ));
+    }
+    let times = encode_buffer_with(buf, out_dir, opts, limiter, encoder).await?;
+    Ok((input_len, PhaseTimes { read, ..times }))
+}
+
+/// Whether `out_dir` holds a shard set for exactly `buf` with the requested
+/// k/m, with every shard expected there present at full size and matching
+/// its checksum. Sets without checksums are also decoded as a check.
+pub(crate) async fn existing_set_matches(out_dir: &Path, buf: &[u8], opts: &EncodeOptions) -> bool {
+    if !ShardMetadata::exists(out_dir).await {
+        return false;
+    }
+    let Ok(meta) = ShardMetadata::read(out_dir).await else {
+        return false;
+    };
+    if (meta.data_shards, meta.parity_shards) != opts.set_shards()
+        || meta.matrix_type.unwrap_or_default() != opts.matrix_type
+        || meta.custom_matrix != opts.custom_matrix.as_deref().map(matrix_to_hex_rows)
+        || meta.input_sha256.as_deref() != Some(sha256_hex(buf).as_str())
+    {
+        return false;
+    }
+    for i in (0..meta.total_shards()).filter(|&i| meta.is_stored_here(i)) {
+        let intact = meta.read_shard(out_dir, i).await.is_ok_and(|shard| {
+            shard.len() == meta.stored_len(i)
+                && meta.checksums.as_ref().is_none_or(|c| c.matches(i, &shard))
+                && (meta.shard_trailer.is_none() || check_trailer(&shard).is_some())
+        });
+        if !intact {
+            return false;
+        }
+    }
+    if meta.checksums.is_none() {
+        let decode_opts = DecodeOptions {
+            encrypt_key: opts.encrypt_key.clone(),
+            execution: opts.execution.clone(),
+            ..Default::default()
+        };
+        return decode_dir(out_dir, &decode_opts).await.is_ok();
+    }
+    true
+}
+
+/// Encodes each input into `<out_dir>/<file name>`, at most `jobs` files at a
+/// time, and prints one result line per file. Every file is attempted even if
+/// some fail; the command fails afterwards if any did.
+async fn encode_files(
+    input_paths: Vec<PathBuf>,
+    out_dir: &Path,
+    mut opts: EncodeOptions,
+    limiter: Option<Arc<RateLimiter>>,
+    no_space_check: bool,
+    encoder: ParityEncoder,
+    jobs: usize,
+) -> Result<()> {
+    if jobs == 0 {
+        return Err(RseError::InvalidArgument("--parallel-files must be at least 1".into()).into());
+    }
+    // Files encoded at once share the memory budget.
+    opts.memory_budget = opts.memory_budget.map(|budget| budget / jobs);
+    let mut names = HashSet::new();
+    let mut targets = Vec::with_capacity(input_paths.len());
+    for input_path in input_paths {
+        let name = input_path
+            .file_name()
+            .with_context(|| format!("Input has no file name: {:?}", input_path))?
+            .to_owned();
+        if !names.insert(name.clone()) {
+            return Err(RseError::InvalidArgument(format!(
+                "Two inputs share the file name {:?}; each needs its own output subdirectory",
+                name
+            ))
+            .into());
+        }
+        targets.push((input_path, out_dir.join(name)));
+    }
+
+    if !no_space_check {
+        let mut required = 0;
+        for (input_path, _) in &targets {
+            required += required_space(input_path, &opts).await?;
+        }
+        check_free_space(out_dir, required)?;
+    }
+
+    let start = Instant::now();
+    let total = targets.len();
+    let permits = Arc::new(Semaphore::new(jobs));
+    let opts = Arc::new(opts);
+    let handles = targets.into_iter().map(|(input_path, file_out_dir)| {
+        let permits = permits.clone();
+        let opts = opts.clone();
+        let limiter = limiter.clone();
+        let encoder = encoder.clone();
+        tokio::spawn(async move {
+            let _permit = permits.acquire_owned().await?;
+            let started = Instant::now();
+            let input_len = encode_file(&input_path, &file_out_dir, &opts, limiter, &encoder).await;
+            Ok::<_, anyhow::Error>((input_path, file_out_dir, input_len, started.elapsed()))
+        })
+    });
+
+    let mut failed = 0;
+    for handle in join_all(handles).await {
+        let (input_path, file_out_dir, input_len, elapsed) =
+            handle.context("Join error in file encode task")??;
+        match input_len {
+            Ok((len, _)) => println!(
+                "OK      {} -> {} ({} bytes, {:.2?})",
+                input_path.display(),
+                file_out_dir.display(),
+                len,
+                elapsed
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {:#}", input_path.display(), e);
+            }
+        }
+    }
+    println!(
+        "Encoded {}/{} files in {:.2?}",
+        total - failed,
+        total,
+        start.elapsed()
+    );
+    if failed > 0 {
+        return Err(anyhow!("{} of {} files failed to encode", failed, total));
+    }
+    Ok(())
+}
+
+/// Field tables and encoding matrix for one set of options, built once and
+/// shared by every file of a `--parallel-files` run.
+#[derive(Clone)]
+pub struct ParityEncoder {
+    pub(crate) gf: Arc<Gf256>,
+    pub(crate) matrix: Arc<Matrix>,
+    pub(crate) execution: Execution,
+}
 
-    let shard_len = (orig_len + k - 1) / k;
+impl ParityEncoder {
+    pub fn new(opts: &EncodeOptions) -> Result<Self> {
+        Ok(Self {
+            gf: Arc::new(Gf256::new()),
+            matrix: Arc::new(opts.encode_matrix()?),
+            execution: opts.execution.clone(),
+        })
+    }
+}
+
+/// Lists shard file `index` in the manifest, one entry per volume if split.
+fn add_to_manifest(manifest: &mut Manifest, meta: &ShardMetadata, index: usize, data: &[u8]) {
+    match meta.volume_size {
+        Some(size) => {
+            for (v, volume) in split_volumes(data, size).into_iter().enumerate() {
+                manifest.add(volume_file_name(&meta.shard_file_name(index), v), volume);
+            }
+        }
+        None => manifest.add(meta.shard_file_name(index), data),
+    }
+}
+
+/// Shards `buf` into `out_dir` according to `opts`, which must already be
+/// validated. Writes are throttled by `limiter` if given.
+pub async fn encode_buffer(
+    buf: Vec<u8>,
+    out_dir: &Path,
+    opts: &EncodeOptions,
+    limiter: Option<Arc<RateLimiter>>,
+) -> Result<()> {
+    let encoder = ParityEncoder::new(opts)?;
+    encode_buffer_with(buf, out_dir, opts, limiter, &encoder).await?;
+    Ok(())
+}
+
+/// The input as it is sharded, after compression and scrambling, with what
+/// the metadata records about the original.
+pub(crate) struct PreparedInput {
+    pub data: Wiping<Vec<u8>>,
+    pub input_sha256: String,
+    pub uncompressed_len: usize,
+    pub compression: Option<Compression>,
+}
+
+impl PreparedInput {
+    pub fn new(buf: Vec<u8>, opts: &EncodeOptions) -> Result<Self> {
+        let buf = Wiping::new(buf);
+        let input_sha256 = sha256_hex(&buf);
+        let uncompressed_len = buf.len();
+
+        let (buf, compression) = match opts.compression {
+            Some(algorithm) => match compress(algorithm, &buf)? {
+                Some(compressed) => {
+                    info!(
+                        "Compressed input from {} to {} bytes",
+                        uncompressed_len,
+                        compressed.len()
+                    );
+                    (Wiping::new(compressed), Some(algorithm))
+                }
+                None => {
+                    info!("Input does not compress; storing it uncompressed");
+                    (buf, None)
+                }
+            },
+            None => (buf, None),
+        };
+        let data = match opts.scramble_seed {
+            Some(seed) => {
+                info!("Scrambling input bytes");
+                Wiping::new(scramble(&buf, seed))
+            }
+            None => buf,
+        };
+        Ok(Self {
+            data,
+            input_sha256,
+            uncompressed_len,
+            compression,
+        })
+    }
+
+    /// Metadata for a set of this input encoded with `opts`.
+    pub fn metadata(
+        &self,
+        opts: &EncodeOptions,
+        data_shard_lens: Option<Vec<usize>>,
+    ) -> ShardMetadata {
+        let mut meta = new_set_metadata(opts, self.data.len(), self.input_sha256.clone());
+        meta.data_shard_lens = data_shard_lens;
+        if self.compression.is_some() {
+            meta.compression = self.compression;
+            meta.uncompressed_len = Some(self.uncompressed_len);
+        }
+        meta
+    }
+}
+
+/// Splits `data` into the `k` zero-padded data shards, returning them with
+/// the logical length of each if the split is uneven.
+pub(crate) fn split_data_shards(
+    data: &[u8],
+    opts: &EncodeOptions,
+    pb: &ProgressBar,
+) -> (Option<Vec<usize>>, Vec<Vec<u8>>) {
+    let (k, _) = opts.set_shards();
+    let orig_len = data.len();
+    let data_shard_lens = opts
+        .shard_weights
+        .as_ref()
+        .map(|weights| weighted_split(orig_len, weights));
+    let piece_lens = data_shard_lens.clone().unwrap_or_else(|| {
+        let shard_len = opts.aligned_shard_len(orig_len);
+        (0..k)
+            .map(|i| shard_len.min(orig_len.saturating_sub(i * shard_len)))
+            .collect()
+    });
+    let shard_len = piece_lens.iter().copied().max().unwrap_or(0);
     let mut data_shards = vec![vec![0u8; shard_len]; k];
+    let mut pieces = Vec::with_capacity(k);
+    let mut rest = data;
+    for &len in &piece_lens {
+        let (piece, tail) = rest.split_at(len);
+        pieces.push(piece);
+        rest = tail;
+    }
+
+    opts.execution.for_each_mut(&mut data_shards, |i, shard| {
+        let chunk = pieces[i];
+        shard[..chunk.len()].copy_from_slice(chunk);
+        pb.inc(chunk.len() as u64);
+    });
+    (data_shard_lens, data_shards)
+}
+
+/// Every parity shard of `data_shards`, computed on a blocking thread.
+pub(crate) async fn compute_parities(
+    data_shards: Vec<Vec<u8>>,
+    opts: &EncodeOptions,
+    encoder: &ParityEncoder,
+    pb: ProgressBar,
+) -> Result<Vec<Vec<u8>>> {
+    let (k, m) = opts.set_shards();
+    let data_shards = Wiping::new(data_shards);
+    let parities = if let Some(geometry) = opts.product_geometry() {
+        let product = ProductCodec::new(geometry)?.with_execution(opts.execution.clone());
+        tokio::task::spawn_blocking(move || {
+            let parities = product.encode(&data_shards)?;
+            pb.set_position(m as u64);
+            pb.finish_with_message("Parity computed!");
+            Ok::<_, anyhow::Error>(parities)
+        })
+        .await??
+    } else if let Some(groups) = opts.local_groups {
+        let lrc =
+            LrcCodec::new(k, opts.parity_shards, groups)?.with_execution(opts.execution.clone());
+        tokio::task::spawn_blocking(move || {
+            let parities = lrc.encode(&data_shards)?;
+            pb.set_position(m as u64);
+            pb.finish_with_message("Parity computed!");
+            Ok::<_, anyhow::Error>(parities)
+        })
+        .await??
+    } else {
+        let encoder = encoder.clone();
+        tokio::task::spawn_blocking(move || {
+            let parities = shard_encoding(
+                encoder.gf.as_ref(),
+                &encoder.matrix,
+                &data_shards,
+                &pb,
+                &encoder.execution,
+            )?;
+            pb.finish_with_message("Parity computed!");
+            Ok::<_, anyhow::Error>(parities)
+        })
+        .await??
+    };
+    Ok(parities)
+}
 
-    let pb_read = ProgressBar::new(orig_len as u64);
+/// Metadata for a set of `orig_len` input bytes encoded with `opts`, before
+/// its shards are laid out and sealed.
+pub(crate) fn new_set_metadata(
+    opts: &EncodeOptions,
+    orig_len: usize,
+    input_sha256: String,
+) -> ShardMetadata {
+    let (k, m) = opts.set_shards();
+    let mut meta = ShardMetadata::new(orig_len, k, m);
+    meta.stored_shards = opts.store_only.clone();
+    meta.input_sha256 = Some(input_sha256);
+    meta.scramble_seed = opts.scramble_seed;
+    meta.product_code = opts.product_geometry();
+    meta.local_groups = opts.local_groups;
+    meta.matrix_type = Some(opts.matrix_type);
+    meta.custom_matrix = opts.custom_matrix.as_deref().map(matrix_to_hex_rows);
+    meta.provenance = Some(match opts.reproducible {
+        true => Provenance::reproducible(opts.matrix_type),
+        false => Provenance::current(opts.matrix_type),
+    });
+    meta.shard_align = opts.align;
+    meta.volume_size = opts.volume_size;
+    meta.disk_order = opts
+        .interleave_parity
+        .then(|| interleaved_parity_order(k, m));
+    meta
+}
+
+/// The data shards and `parities` in the order they are stored: data shards
+/// without the padding of an uneven split, then parity, rotated across the
+/// shard files if `opts` asks for it.
+pub(crate) fn layout_shards(
+    meta: &mut ShardMetadata,
+    mut shards: Vec<Vec<u8>>,
+    parities: Vec<Vec<u8>>,
+    opts: &EncodeOptions,
+) -> Vec<Vec<u8>> {
+    if let Some(lens) = &meta.data_shard_lens {
+        // Only the logical bytes of each data shard are stored; decode
+        // restores the zero padding.
+        for (shard, &len) in shards.iter_mut().zip(lens) {
+            shard.truncate(len);
+        }
+    }
+    shards.extend(parities);
+    if let Some(stripe_len) = opts.rotate_stripes {
+        shards = rotate_stripes_across(&shards, stripe_len);
+        meta.stripe_rotation = Some(stripe_len);
+    }
+    shards
+}
+
+/// Turns laid-out shards into the bytes stored for them: compressed,
+/// encrypted and given a trailer as the options ask, in that order, so
+/// checksums and the manifest describe what is stored.
+pub(crate) struct ShardSealer<'a> {
+    compression: Option<ShardCompression>,
+    encryption: Option<(ShardEncryption, &'a EncryptKey)>,
+    trailer: bool,
+    execution: &'a Execution,
+    checksum_algo: ChecksumAlgo,
+    /// Checksum of every shard sealed so far, in order.
+    checksums: Vec<String>,
+}
+
+impl<'a> ShardSealer<'a> {
+    /// Records in `meta` how shards will be sealed, except for the sizes of
+    /// compressed shards and the shard checksums, which
+    /// [`ShardSealer::finish`] adds.
+    pub fn new(meta: &mut ShardMetadata, opts: &'a EncodeOptions) -> Self {
+        let encryption = opts.encrypt_key.as_ref().map(|key| {
+            let encryption = ShardEncryption::generate();
+            meta.encryption = Some(encryption.clone());
+            (encryption, key)
+        });
+        if opts.shard_trailer {
+            meta.shard_trailer = Some(ShardTrailer::Crc32);
+        }
+        Self {
+            compression: opts.compress_shards.map(ShardCompression::new),
+            encryption,
+            trailer: opts.shard_trailer,
+            execution: &opts.execution,
+            checksum_algo: opts.checksum_algo,
+            checksums: Vec::new(),
+        }
+    }
+
+    /// Seals shards `0..shards.len()` at once, in parallel.
+    pub fn seal_all(&mut self, mut shards: Vec<Vec<u8>>) -> Result<Vec<Vec<u8>>> {
+        if let Some(compression) = &mut self.compression {
+            shards = compression.push_all(shards, self.execution)?;
+        }
+        if let Some((encryption, key)) = &self.encryption {
+            let indexed: Vec<(usize, &Vec<u8>)> = shards.iter().enumerate().collect();
+            shards = self
+                .execution
+                .map(&indexed, |&(i, shard)| encryption.encrypt(key, i, shard))
+                .into_iter()
+                .collect::<Result<_>>()?;
+        }
+        if self.trailer {
+            self.execution
+                .for_each_mut(&mut shards, |_, shard| append_trailer(shard));
+        }
+        let algo = self.checksum_algo;
+        self.checksums.extend(
+            self.execution
+                .map(&shards, |shard| algo.digest(shard))
+                .into_iter()
+                .flatten(),
+        );
+        Ok(shards)
+    }
+
+    /// Seals shard `index`, which must be the next one after those already
+    /// sealed.
+    pub fn seal(&mut self, index: usize, mut shard: Vec<u8>) -> Result<Vec<u8>> {
+        if let Some(compression) = &mut self.compression {
+            shard = compression.push(shard)?;
+        }
+        if let Some((encryption, key)) = &self.encryption {
+            let plaintext = Wiping::new(shard);
+            shard = encryption.encrypt(key, index, &plaintext)?;
+        }
+        if self.trailer {
+            append_trailer(&mut shard);
+        }
+        self.checksums.extend(self.checksum_algo.digest(&shard));
+        Ok(shard)
+    }
+
+    pub fn finish(self, meta: &mut ShardMetadata) {
+        meta.shard_compression = self.compression;
+        if self.checksum_algo != ChecksumAlgo::None {
+            meta.checksums = Some(ShardChecksums {
+                algorithm: self.checksum_algo,
+                shards: self.checksums,
+            });
+        }
+    }
+}
+
+/// [`encode_buffer`] with a prebuilt encoder, returning the time spent
+/// computing and writing the set.
+pub(crate) async fn encode_buffer_with(
+    buf: Vec<u8>,
+    out_dir: &Path,
+    opts: &EncodeOptions,
+    limiter: Option<Arc<RateLimiter>>,
+    encoder: &ParityEncoder,
+) -> Result<PhaseTimes> {
+    let store = FilesystemStore::with_write_options(out_dir, opts.write.clone())
+        .with_rate_limiter(limiter.clone());
+    encode_into_store(buf, &store, Some(out_dir), opts, limiter, encoder).await
+}
+
+/// Shards `buf` into `store`, the pipeline behind both `encode` and
+/// [`crate::io::store::encode_to_store`]. `out_dir` is the directory the
+/// store keeps the set in, if any; the manifest, the shard log and
+/// verification after encoding are only written or run there. `limiter`
+/// throttles appends to the shard log.
+pub(crate) async fn encode_into_store(
+    buf: Vec<u8>,
+    store: &dyn ShardStore,
+    out_dir: Option<&Path>,
+    opts: &EncodeOptions,
+    limiter: Option<Arc<RateLimiter>>,
+    encoder: &ParityEncoder,
+) -> Result<PhaseTimes> {
+    let compute_start = Instant::now();
+    let (k, m) = opts.set_shards();
+    let write_manifest = opts.manifest;
+
+    let input = PreparedInput::new(buf, opts)?;
+    let pb_read = ProgressBar::new(input.data.len() as u64);
     pb_read.set_style(
         ProgressStyle::with_template(
             "[{elapsed_precise}] [{bar:40.cyan/black}] Reading input {bytes}/{total_bytes}",
@@ -48,14 +1336,7 @@ pub async fn handle_encode(args: Commands) -> Result<()> {
         .unwrap()
         .progress_chars("=> "),
     );
-
-    data_shards
-        .par_iter_mut()
-        .zip(buf.par_chunks(shard_len))
-        .for_each(|(shard, chunk)| {
-            shard[..chunk.len()].copy_from_slice(chunk);
-            pb_read.inc(chunk.len() as u64);
-        });
+    let (data_shard_lens, data_shards) = split_data_shards(&input.data, opts, &pb_read);
     pb_read.finish_with_message("Input file loaded!");
 
     let pb_compute = ProgressBar::new(m as u64);
@@ -68,22 +1349,43 @@ pub async fn handle_encode(args: Commands) -> Result<()> {
     );
     pb_compute.set_position(0);
 
-    let gf_clone = gf.clone();
-    let data_shards_clone = data_shards.clone();
-    let parities = tokio::task::spawn_blocking(move || {
-        let matrix = build_vandermonde(&gf_clone, k, m);
-        let parities = shard_encoding(&gf_clone, &matrix, &data_shards_clone, &pb_compute)?;
-        pb_compute.finish_with_message("Parity computed!");
-        Ok::<_, anyhow::Error>(parities)
-    })
-    .await??;
+    // Product codes and local groups always compute their parity at once.
+    let streamed =
+        opts.low_memory && opts.product_geometry().is_none() && opts.local_groups.is_none();
+    let (data_shards, parities, stream) = if streamed {
+        // Parity rows are produced one at a time by a blocking task and
+        // written as they arrive, so at most a couple are resident at once.
+        // The task shares the data shards, which are written one at a time
+        // before the parity.
+        let data_shards = Arc::new(Wiping::new(data_shards));
+        let (gf, matrix) = (encoder.gf.clone(), encoder.matrix.clone());
+        let shared = Arc::clone(&data_shards);
+        let (tx, rx) = tokio::sync::mpsc::channel::<Vec<u8>>(1);
+        let producer = tokio::task::spawn_blocking(move || {
+            for parity in shard_encoding_lazy(gf.as_ref(), &matrix, &shared)? {
+                if tx.blocking_send(parity).is_err() {
+                    break;
+                }
+                pb_compute.inc(1);
+            }
+            pb_compute.finish_with_message("Parity computed!");
+            Ok::<_, anyhow::Error>(())
+        });
+        (Vec::new(), Vec::new(), Some((data_shards, rx, producer)))
+    } else {
+        let parities = compute_parities(data_shards.clone(), opts, encoder, pb_compute).await?;
+        (data_shards, parities, None)
+    };
 
-    info!(
-        "Writing {} data and {} parity shards to {:?}",
-        k, m, out_dir
-    );
-    create_dir_all(&out_dir)
-        .with_context(|| format!("Failed to create output directory: {:?}", out_dir))?;
+    let compute = compute_start.elapsed();
+    let write_start = Instant::now();
+    match out_dir {
+        Some(dir) => info!("Writing {} data and {} parity shards to {:?}", k, m, dir),
+        None => info!("Writing {} data and {} parity shards to the store", k, m),
+    }
+
+    let mut meta = input.metadata(opts, data_shard_lens);
+    drop(input);
 
     let pb_write = ProgressBar::new((k + m) as u64);
     pb_write.set_style(
@@ -94,32 +1396,281 @@ pub async fn handle_encode(args: Commands) -> Result<()> {
         .progress_chars("=> "),
     );
 
-    let mut shards = data_shards;
-    shards.extend(parities);
+    let shards = layout_shards(&mut meta, data_shards, parities, opts);
+    let mut sealer = ShardSealer::new(&mut meta, opts);
+    let shards = sealer.seal_all(shards)?;
+    store.begin_set(&meta).await?;
+
+    let mut manifest = Manifest::new();
+    if write_manifest {
+        for (i, shard) in shards.iter().enumerate() {
+            if meta.is_stored_here(i) {
+                add_to_manifest(&mut manifest, &meta, i, shard);
+            }
+        }
+    }
 
-    let mut write_handles = Vec::with_capacity(k + m);
+    // A log is appended to in shard order; it cannot be written in parallel.
+    let mut shard_log = match (opts.shard_log, out_dir) {
+        (true, Some(dir)) => Some(ShardLogWriter::open(&shard_log_path(dir), &opts.write).await?),
+        _ => None,
+    };
+    let mut log_entries = vec![None; k + m];
+
+    let io_limit = ShardIoLimit::new(
+        opts.memory_budget,
+        shards.iter().map(Vec::len).max().unwrap_or(0),
+        k + m,
+    );
+    let mut writes = Vec::with_capacity(k + m);
+    let mut write_indices = Vec::with_capacity(k + m);
     for (i, shard_data) in shards.into_iter().enumerate() {
-        let path = out_dir.join(format!("shard_{:02}.dat", i));
-        let pb_clone = pb_write.clone();
-        write_handles.push(tokio::spawn(async move {
-            fs::write(path, shard_data).await?;
-            pb_clone.inc(1);
+        let shard_data = Wiping::new(shard_data);
+        if !meta.is_stored_here(i) {
+            pb_write.inc(1);
+            continue;
+        }
+        if let Some(log) = &mut shard_log {
+            log_entries[i] =
+                Some(append_to_log(log, &meta, i, &shard_data, limiter.as_deref()).await?);
+            pb_write.inc(1);
+            continue;
+        }
+        let (io_limit, pb_write) = (&io_limit, &pb_write);
+        write_indices.push(i);
+        writes.push(async move {
+            let _permit = io_limit.acquire().await;
+            store.write_shard(i, &shard_data).await?;
+            pb_write.inc(1);
             Ok::<_, anyhow::Error>(())
-        }));
+        });
     }
 
-    for handle in join_all(write_handles).await {
-        handle??;
+    // Every write runs to completion, so a failure (e.g. a full disk) can be
+    // reported with exactly which shards made it.
+    let mut written = Vec::with_capacity(write_indices.len());
+    let mut failures = Vec::new();
+    for (i, result) in write_indices.into_iter().zip(join_all(writes).await) {
+        match result {
+            Ok(()) => written.push(i),
+            Err(e) => failures.push((i, e)),
+        }
+    }
+    io_limit.log_peak("Wrote");
+    if !failures.is_empty() {
+        pb_write.abandon();
+        return Err(partial_write_error(store, out_dir, &meta, opts, &written, failures).await);
+    }
+
+    if let Some((data_shards, mut rx, producer)) = stream {
+        for index in 0.. {
+            let shard = if index < k {
+                let len = meta
+                    .data_shard_lens
+                    .as_ref()
+                    .map_or(data_shards[index].len(), |lens| lens[index]);
+                data_shards[index][..len].to_vec()
+            } else {
+                match rx.recv().await {
+                    Some(parity) => parity,
+                    None => break,
+                }
+            };
+            let shard = Wiping::new(sealer.seal(index, shard)?);
+            if meta.is_stored_here(index) {
+                if write_manifest {
+                    add_to_manifest(&mut manifest, &meta, index, &shard);
+                }
+                if let Some(log) = &mut shard_log {
+                    log_entries[index] =
+                        Some(append_to_log(log, &meta, index, &shard, limiter.as_deref()).await?);
+                    pb_write.inc(1);
+                    continue;
+                }
+                if let Err(e) = store.write_shard(index, &shard).await {
+                    pb_write.abandon();
+                    drop(rx);
+                    let _ = producer.await;
+                    return Err(partial_write_error(
+                        store,
+                        out_dir,
+                        &meta,
+                        opts,
+                        &written,
+                        vec![(index, e)],
+                    )
+                    .await);
+                }
+                written.push(index);
+            }
+            pb_write.inc(1);
+        }
+        drop(data_shards);
+        producer.await??;
+    }
+    if let Some(log) = shard_log {
+        log.finish().await?;
+        meta.shard_log = Some(log_entries);
     }
     pb_write.finish_with_message("All shards written!");
+    sealer.finish(&mut meta);
 
-    let meta = format!("{}\n{} {}\n", orig_len, k, m);
-    fs::write(out_dir.join("meta.txt"), meta).await?;
+    if opts.sidecar_metadata {
+        meta.sidecar_checksums = Some(opts.checksum_algo);
+    }
+    if let (true, Some(dir)) = (write_manifest, out_dir) {
+        manifest
+            .write(&dir.join(MANIFEST_FILE), &opts.write)
+            .await?;
+    }
+    // The metadata goes last: a set only looks complete once every shard is
+    // in place.
+    store.write_metadata(&meta).await?;
+    let times = PhaseTimes {
+        compute,
+        write: write_start.elapsed(),
+        ..Default::default()
+    };
+    if let (true, Some(dir)) = (opts.verify_after_encode, out_dir) {
+        verify_encoded(dir, k + m, opts).await?;
+    }
+    Ok(times)
+}
+
+/// Appends shard `index` to the set's log. Nothing already in the log is
+/// removed on failure: it is append-only, and without metadata the partial
+/// set is never read.
+async fn append_to_log(
+    log: &mut ShardLogWriter,
+    meta: &ShardMetadata,
+    index: usize,
+    data: &[u8],
+    limiter: Option<&RateLimiter>,
+) -> Result<ShardLogEntry> {
+    log.append(data, limiter).await.with_context(|| {
+        format!(
+            "Failed to append {} to the shard log",
+            meta.shard_file_name(index)
+        )
+    })
+}
+
+/// Error for a set of which only the `written` shards were written, with
+/// the reason each of `failures` was not. The set has no metadata yet, so
+/// the written shard files are removed unless `opts.keep_partial`.
+async fn partial_write_error(
+    store: &dyn ShardStore,
+    out_dir: Option<&Path>,
+    meta: &ShardMetadata,
+    opts: &EncodeOptions,
+    written: &[usize],
+    failures: Vec<(usize, anyhow::Error)>,
+) -> anyhow::Error {
+    let place = out_dir.map_or_else(|| "the store".to_string(), |dir| format!("{:?}", dir));
+    let failed: Vec<String> = failures
+        .iter()
+        .map(|(i, e)| format!("{} ({:#})", meta.shard_file_name(*i), e))
+        .collect();
+    let outcome = if written.is_empty() {
+        "no shard files were written".to_string()
+    } else if opts.keep_partial {
+        warn!(
+            "Leaving {} of {} shard files in {}; without metadata the set cannot be decoded",
+            written.len(),
+            written.len() + failures.len(),
+            place
+        );
+        format!(
+            "kept the {} written shard files {:?}",
+            written.len(),
+            written
+        )
+    } else {
+        let mut removed = 0;
+        for &i in written {
+            match store.remove_shard(i).await {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("Failed to remove {}: {:#}", meta.shard_file_name(i), e),
+            }
+        }
+        format!(
+            "removed {} of the {} shard files that were written",
+            removed,
+            written.len()
+        )
+    };
+    anyhow!(
+        "Failed to write {} of {} shard files to {}: {}; {}",
+        failures.len(),
+        written.len() + failures.len(),
+        place,
+        failed.join(", "),
+        outcome
+    )
+}
+
+/// The `losses` of `n` shards that verification with `seed` ignores, ascending.
+pub(crate) fn verify_drops(n: usize, losses: usize, seed: u64) -> Vec<usize> {
+    let mut dropped = permutation(n, seed);
+    dropped.truncate(losses);
+    dropped.sort_unstable();
+    dropped
+}
+
+/// Decodes the `n` shards just written to `out_dir` while ignoring
+/// `opts.require_tolerance` ones chosen by `opts.verify_seed`, or at random.
+/// Decoding checks the result against the recorded input hash, so success
+/// means the set survives those losses. The seed is logged and named on
+/// failure, so a failing choice can be replayed.
+pub(crate) async fn verify_encoded(out_dir: &Path, n: usize, opts: &EncodeOptions) -> Result<()> {
+    let losses = opts.require_tolerance.unwrap_or(0);
+    let seed = opts.verify_seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    let dropped = verify_drops(n, losses, seed);
 
     info!(
-        "✅ Successfully encoded '{}' ({} bytes)",
-        input_path.display(),
-        orig_len
+        verify_seed = seed,
+        "Verifying {:?} with shards {:?} dropped (--verify-seed {})", out_dir, dropped, seed
     );
+    let opts = DecodeOptions {
+        ignore_shards: dropped.clone(),
+        encrypt_key: opts.encrypt_key.clone(),
+        execution: opts.execution.clone(),
+        ..Default::default()
+    };
+    decode_dir(out_dir, &opts).await.with_context(|| {
+        format!(
+            "Verification after encode failed with shards {:?} dropped (--verify-seed {})",
+            dropped, seed
+        )
+    })?;
+    info!(
+        "✅ Verified: decoded correctly without shards {:?}",
+        dropped
+    );
+    Ok(())
+}
+
+/// Fails if the filesystem that will hold `out_dir` has fewer than `required`
+/// bytes available. `out_dir` does not need to exist yet; the nearest existing
+/// ancestor is queried instead.
+pub fn check_free_space(out_dir: &Path, required: u64) -> Result<()> {
+    let probe = out_dir
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or_else(|| Path::new("."));
+    let available = fs2::available_space(probe)
+        .with_context(|| format!("Failed to query free space for {:?}", probe))?;
+    if available < required {
+        return Err(anyhow!(
+            "Not enough free space in {:?}: need {} bytes, have {} (use --no-space-check to skip)",
+            out_dir,
+            required,
+            available
+        ));
+    }
     Ok(())
 }
@@ -0,0 +1,103 @@
+//! Encryption of shard files at rest.
+//!
+//! Shards are encrypted after encoding, so parity is computed over plaintext
+//! and reconstruction works as usual once the surviving shards are decrypted.
+//! Each file gets a nonce made of a random per-set prefix and its index, so
+//! reusing a key across encodes never reuses a nonce. The authentication tag
+//! makes any modified shard fail decryption; decode treats it as missing.
+//!
+//! Only the algorithm and nonce prefix are stored in the metadata, never the key.
+
+use anyhow::{Result, anyhow};
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+    aead::{Aead, OsRng, rand_core::RngCore},
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Bytes the authentication tag adds to every encrypted shard file.
+pub const TAG_LEN: usize = 16;
+
+const NONCE_PREFIX_LEN: usize = 8;
+
+/// A 256-bit key, given on the command line as 64 hex digits.
+#[derive(Clone)]
+pub struct EncryptKey(Key);
+
+impl fmt::Debug for EncryptKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptKey(<redacted>)")
+    }
+}
+
+impl FromStr for EncryptKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = decode_hex(s.trim())
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or_else(|| "key must be 64 hex digits (32 bytes)".to_string())?;
+        Ok(Self(*Key::from_slice(&bytes)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EncryptionAlgo {
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+/// How the shard files of a set were encrypted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardEncryption {
+    pub algorithm: EncryptionAlgo,
+    /// Hex prefix of every shard's nonce; the shard index fills the rest.
+    pub nonce_prefix: String,
+}
+
+impl ShardEncryption {
+    /// Parameters for a new shard set, with a fresh random nonce prefix.
+    pub fn generate() -> Self {
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut prefix);
+        Self {
+            algorithm: EncryptionAlgo::ChaCha20Poly1305,
+            nonce_prefix: prefix.iter().map(|b| format!("{:02x}", b)).collect(),
+        }
+    }
+
+    fn nonce(&self, index: usize) -> Result<Nonce> {
+        let prefix = decode_hex(&self.nonce_prefix)
+            .filter(|prefix| prefix.len() == NONCE_PREFIX_LEN)
+            .ok_or_else(|| anyhow!("Invalid metadata: malformed encryption nonce prefix"))?;
+        let mut nonce = [0u8; 12];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&prefix);
+        nonce[NONCE_PREFIX_LEN..].copy_from_slice(&(index as u32).to_be_bytes());
+        Ok(nonce.into())
+    }
+
+    pub fn encrypt(&self, key: &EncryptKey, index: usize, plaintext: &[u8]) -> Result<Vec<u8>> {
+        ChaCha20Poly1305::new(&key.0)
+            .encrypt(&self.nonce(index)?, plaintext)
+            .map_err(|_| anyhow!("Failed to encrypt shard {}", index))
+    }
+
+    /// Fails if the key is wrong or the shard was modified.
+    pub fn decrypt(&self, key: &EncryptKey, index: usize, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        ChaCha20Poly1305::new(&key.0)
+            .decrypt(&self.nonce(index)?, ciphertext)
+            .map_err(|_| anyhow!("Shard {} failed authentication", index))
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
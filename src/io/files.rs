@@ -0,0 +1,56 @@
+//! Shard file names, hashing and synchronous atomic writes, shared by the
+//! full build and the `lean` path.
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Keeps temporary names unique across concurrent writes of the same file
+/// name, e.g. `--parallel-files` runs sharing a `--tmp-dir`.
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+pub fn shard_file_name(index: usize) -> String {
+    format!("shard_{:02}.dat", index)
+}
+
+pub fn shard_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(shard_file_name(index))
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub(crate) fn parent_dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// A fresh temporary name in `dir` for a write of `dest`.
+pub(crate) fn temp_path(dir: &Path, dest: &Path) -> PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let n = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!(".{}.{}-{}.tmp", name, std::process::id(), n))
+}
+
+/// Blocking counterpart of [`crate::io::atomic::write_atomic`] with the
+/// default options: writes `data` to a temporary file next to `dest` and
+/// renames it over `dest`.
+pub fn write_atomic_sync(dest: &Path, data: &[u8]) -> Result<()> {
+    let tmp = temp_path(parent_dir(dest), dest);
+    if let Err(e) = fs::write(&tmp, data) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to write {:?}", dest));
+    }
+    if let Err(e) = fs::rename(&tmp, dest) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to rename {:?} to {:?}", tmp, dest));
+    }
+    Ok(())
+}
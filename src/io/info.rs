@@ -0,0 +1,92 @@
+use anyhow::Result;
+use tracing::{info, instrument};
+
+use crate::{
+    cli::commands::Commands,
+    io::{
+        metadata::ShardMetadata,
+        verify::{ShardSample, stored_indices, verify_checksums},
+    },
+};
+
+/// Presence of each shard index relative to what the metadata says is stored
+/// in the directory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShardStatus {
+    pub present: Vec<usize>,
+    /// Expected to be stored here but not found: these are lost.
+    pub missing_expected: Vec<usize>,
+    /// Never stored in this directory, e.g. parity placed elsewhere.
+    pub not_stored_here: Vec<usize>,
+}
+
+impl ShardStatus {
+    pub fn classify(meta: &ShardMetadata, present: &[bool]) -> Self {
+        let mut status = Self::default();
+        for (i, &is_present) in present.iter().enumerate() {
+            if is_present {
+                status.present.push(i);
+            } else if meta.is_stored_here(i) {
+                status.missing_expected.push(i);
+            } else {
+                status.not_stored_here.push(i);
+            }
+        }
+        status
+    }
+}
+
+#[instrument(skip(args))]
+pub async fn handle_info(args: Commands) -> Result<()> {
+    let (shard_dir, count, seed) = match args {
+        Commands::Info { input, count, seed } => (input, count, seed),
+        _ => unreachable!(),
+    };
+
+    info!("Reading metadata from: {:?}", shard_dir);
+    let meta = ShardMetadata::read(&shard_dir).await?;
+    let n = meta.total_shards();
+
+    let mut present = Vec::with_capacity(n);
+    for i in 0..n {
+        present.push(meta.shard_exists(&shard_dir, i).await?);
+    }
+    let status = ShardStatus::classify(&meta, &present);
+
+    println!("Original length: {} bytes", meta.orig_len);
+    println!(
+        "Shards: {} data + {} parity = {}",
+        meta.data_shards, meta.parity_shards, n
+    );
+    if let Some(g) = &meta.product_code {
+        println!(
+            "Product code: {}+{} rows of {}+{} shards",
+            g.col_data, g.col_parity, g.row_data, g.row_parity
+        );
+    }
+    if let Some(groups) = meta.local_groups {
+        println!(
+            "Local reconstruction code: {} global parity, {} local groups",
+            meta.parity_shards - groups,
+            groups
+        );
+    }
+    match &meta.provenance {
+        Some(provenance) => println!("{}", provenance),
+        None => println!("Provenance: not recorded (encoded by an older version)"),
+    }
+    println!("Present: {:?}", status.present);
+    println!("Missing (expected here): {:?}", status.missing_expected);
+    println!("Not stored here: {:?}", status.not_stored_here);
+
+    if let Some(count) = count {
+        let seed = ShardSample::seed_or_random(seed);
+        let sample = ShardSample::choose(&stored_indices(&meta), count, seed);
+        sample.print();
+        match verify_checksums(&shard_dir, &meta, &sample.indices).await? {
+            Some(failed) => sample.print_estimate(failed.len()),
+            None => println!("Sample not checked: the set was encoded without checksums"),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,166 @@
+//! Synchronous encode and decode with `std::fs`, for builds without the
+//! `full` feature: no tokio, no progress bars, no async.
+//!
+//! Only plain shard sets are handled: no compression, scrambling, encryption,
+//! rotation, uneven, product-code or local-group sets. Shard files are the
+//! same bytes the full build writes for the same input and `k`/`m`, and the
+//! two builds decode each other's sets. Checksums in the metadata are not
+//! checked; the recorded input SHA-256 is, so a corrupt shard fails the
+//! decode instead of being routed around.
+//!
+//! Everything is held in memory at once: the input, then the shards.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::{
+    codec::{matrix::MatrixType, reconstruct_shards::Codec},
+    error::RseError,
+    io::files::{sha256_hex, shard_path, write_atomic_sync},
+};
+
+/// Same file as the full build's metadata.
+const META_FILE: &str = "meta.json";
+
+/// Metadata keys of the full build that do not change how a set decodes.
+const IGNORED_KEYS: [&str; 4] = [
+    "checksums",
+    "provenance",
+    "sidecar_checksums",
+    "stored_shards",
+];
+
+/// The subset of the full build's `meta.json` this path understands.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LeanMetadata {
+    pub orig_len: usize,
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix_type: Option<MatrixType>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_matrix: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_sha256: Option<String>,
+    /// Any other keys, rejected on decode unless in `IGNORED_KEYS`.
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl LeanMetadata {
+    fn codec(&self) -> Result<Codec> {
+        Codec::from_recorded(
+            self.data_shards,
+            self.parity_shards,
+            self.matrix_type,
+            self.custom_matrix.as_deref(),
+        )
+    }
+}
+
+/// Encodes `input` into `k` data and `m` parity shards in `out_dir` with the
+/// default Vandermonde matrix.
+pub fn encode_file(input: &Path, out_dir: &Path, k: usize, m: usize) -> Result<LeanMetadata> {
+    Codec::validate_params(k, m)?;
+    let buf = fs::read(input).with_context(|| format!("Failed to read input file: {:?}", input))?;
+    let shard_len = buf.len().div_ceil(k);
+    let mut shards: Vec<Vec<u8>> = (0..k)
+        .map(|i| {
+            let start = (i * shard_len).min(buf.len());
+            let mut shard = buf[start..(start + shard_len).min(buf.len())].to_vec();
+            shard.resize(shard_len, 0);
+            shard
+        })
+        .collect();
+    let codec = Codec::try_new(k, m)?;
+    let parities = codec.encode(&shards)?;
+    shards.extend(parities);
+
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create output directory: {:?}", out_dir))?;
+    for (i, shard) in shards.iter().enumerate() {
+        write_atomic_sync(&shard_path(out_dir, i), shard)?;
+    }
+    let meta = LeanMetadata {
+        orig_len: buf.len(),
+        data_shards: k,
+        parity_shards: m,
+        matrix_type: Some(MatrixType::Vandermonde),
+        custom_matrix: None,
+        input_sha256: Some(sha256_hex(&buf)),
+        other: Map::new(),
+    };
+    write_atomic_sync(&out_dir.join(META_FILE), &serde_json::to_vec_pretty(&meta)?)?;
+    info!("Wrote {} data and {} parity shards to {:?}", k, m, out_dir);
+    Ok(meta)
+}
+
+/// Decodes the set in `shard_dir` into `output`, rebuilding missing or
+/// wrong-sized shards. Returns the number of bytes written.
+pub fn decode_file(shard_dir: &Path, output: &Path) -> Result<usize> {
+    let raw = fs::read_to_string(shard_dir.join(META_FILE))
+        .with_context(|| format!("Failed to read {} in {:?}", META_FILE, shard_dir))?;
+    let meta: LeanMetadata =
+        serde_json::from_str(&raw).with_context(|| format!("Invalid {}", META_FILE))?;
+    if let Some(key) = meta
+        .other
+        .keys()
+        .find(|key| !IGNORED_KEYS.contains(&key.as_str()))
+    {
+        return Err(RseError::InvalidArgument(format!(
+            "Shard sets with `{}` in their metadata need the full build",
+            key
+        ))
+        .into());
+    }
+    let codec = meta.codec()?;
+    let k = meta.data_shards;
+    let shard_len = meta.orig_len.div_ceil(k);
+
+    let mut shards = Vec::with_capacity(codec.total_shards());
+    for i in 0..codec.total_shards() {
+        let path = shard_path(shard_dir, i);
+        let shard = match fs::read(&path) {
+            Ok(shard) if shard.len() == shard_len => Some(shard),
+            Ok(shard) => {
+                warn!(
+                    "Shard {:?} is {} bytes, expected {}; rebuilding it",
+                    path,
+                    shard.len(),
+                    shard_len
+                );
+                None
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Cannot read shard {:?} ({}); rebuilding it", path, e);
+                None
+            }
+        };
+        shards.push(shard);
+    }
+    let report = codec.reconstruct_data(&mut shards)?;
+    info!("Reconstruction {}", report);
+
+    let mut data: Vec<u8> = shards
+        .into_iter()
+        .take(k)
+        .flat_map(|shard| shard.expect("data shards are reconstructed"))
+        .collect();
+    data.truncate(meta.orig_len);
+    if let Some(expected) = &meta.input_sha256
+        && sha256_hex(&data) != *expected
+    {
+        return Err(RseError::Corruption(
+            "Decoded output does not match the recorded input SHA-256".into(),
+        )
+        .into());
+    }
+    write_atomic_sync(output, &data)?;
+    Ok(data.len())
+}
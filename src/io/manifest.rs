@@ -0,0 +1,147 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+use tokio::fs;
+
+use crate::error::RseError;
+use crate::io::atomic::{WriteOptions, write_atomic};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Tool-agnostic listing of shard files and their content hashes.
+///
+/// External integrity monitors can check a shard directory against this file
+/// without understanding the erasure-coding format:
+///
+/// ```json
+/// {
+///   "algorithm": "sha256",
+///   "shards": {
+///     "shard_00.dat": { "size": 4096, "hash": "9f86d0..." }
+///   }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub algorithm: String,
+    pub shards: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub size: u64,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestMismatch {
+    Missing(String),
+    SizeMismatch {
+        file: String,
+        expected: u64,
+        actual: u64,
+    },
+    HashMismatch(String),
+}
+
+pub use crate::io::files::sha256_hex;
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self {
+            algorithm: "sha256".to_string(),
+            shards: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, file_name: String, data: &[u8]) {
+        self.shards.insert(
+            file_name,
+            ManifestEntry {
+                size: data.len() as u64,
+                hash: sha256_hex(data),
+            },
+        );
+    }
+
+    pub async fn read(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read manifest {:?}", path))?;
+        let manifest: Self =
+            serde_json::from_str(&raw).with_context(|| format!("Invalid manifest {:?}", path))?;
+        manifest
+            .validate()
+            .with_context(|| format!("Invalid manifest {:?}", path))?;
+        Ok(manifest)
+    }
+
+    /// Checks that every entry names a plain file in the shard directory, so
+    /// a crafted manifest cannot make verification read files outside it
+    /// through `..`, absolute paths or separators.
+    pub fn validate(&self) -> Result<()> {
+        for file in self.shards.keys() {
+            let mut components = Path::new(file).components();
+            let plain = matches!(components.next(), Some(Component::Normal(_)))
+                && components.next().is_none()
+                && !file.contains(['/', '\\']);
+            if !plain {
+                return Err(RseError::Corruption(format!(
+                    "Manifest entry {:?} is not a file name in the shard directory",
+                    file
+                ))
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn write(&self, path: &Path, opts: &WriteOptions) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomic(path, json.as_bytes(), opts, None)
+            .await
+            .with_context(|| format!("Failed to write manifest {:?}", path))
+    }
+
+    /// Checks every listed file in `dir` and returns the ones that do not match.
+    pub async fn verify_dir(&self, dir: &Path) -> Result<Vec<ManifestMismatch>> {
+        self.verify_files(dir, |_| true).await
+    }
+
+    /// Like [`Manifest::verify_dir`], but only checks the files `keep` accepts.
+    pub async fn verify_files(
+        &self,
+        dir: &Path,
+        keep: impl Fn(&str) -> bool,
+    ) -> Result<Vec<ManifestMismatch>> {
+        self.validate()?;
+        let mut mismatches = Vec::new();
+        for (file, entry) in self.shards.iter().filter(|(file, _)| keep(file)) {
+            let path = dir.join(file);
+            if !fs::try_exists(&path).await? {
+                mismatches.push(ManifestMismatch::Missing(file.clone()));
+                continue;
+            }
+            let data = fs::read(&path)
+                .await
+                .with_context(|| format!("Failed to read {:?}", path))?;
+            if data.len() as u64 != entry.size {
+                mismatches.push(ManifestMismatch::SizeMismatch {
+                    file: file.clone(),
+                    expected: entry.size,
+                    actual: data.len() as u64,
+                });
+            } else if sha256_hex(&data) != entry.hash {
+                mismatches.push(ManifestMismatch::HashMismatch(file.clone()));
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,745 @@
+use anyhow::{Context, Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tracing::warn;
+
+use crate::{
+    algorithm::gf256::Gf256,
+    codec::{
+        matrix::{MATRIX_CONSTRUCTION, MatrixType, matrix_from_hex_rows},
+        product::ProductGeometry,
+        reconstruct_shards::Codec,
+    },
+    io::{
+        atomic::{WriteOptions, write_atomic},
+        checksum::{ChecksumAlgo, ShardChecksums},
+        compression::{Compression, ShardCompression},
+        encryption::{ShardEncryption, TAG_LEN},
+        shard_log::{ShardLogEntry, log_len, read_log_shard, shard_log_path},
+        sidecar::{ShardSidecar, read_sidecar_checksum},
+        stream::stream_shard_len,
+        trailer::{ShardTrailer, TRAILER_LEN},
+        volumes::{read_shard_file, volume_count, volume_path, volumes_len},
+    },
+};
+
+pub const META_FILE: &str = "meta.json";
+/// Plain-text metadata written by older versions: `orig_len\nk m\n`.
+pub const LEGACY_META_FILE: &str = "meta.txt";
+
+pub use crate::io::files::{shard_file_name, shard_path};
+
+/// On-disk order that spreads the `m` parity shards evenly among the `k` data
+/// shards, e.g. `D0 D1 P0 D2 D3 P1` for k=4, m=2, so parity sits next to the
+/// data it protects in a sequential listing.
+pub fn interleaved_parity_order(k: usize, m: usize) -> Vec<usize> {
+    let mut order = Vec::with_capacity(k + m);
+    let mut parity = 0;
+    for data in 0..k {
+        order.push(data);
+        while parity < (data + 1) * m / k {
+            order.push(k + parity);
+            parity += 1;
+        }
+    }
+    order
+}
+
+/// When, where and with what code a shard set was encoded, so sets written by
+/// different versions can be told apart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    /// Seconds since the Unix epoch. Left out, like `hostname`, from sets
+    /// encoded with `--reproducible`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoded_at: Option<u64>,
+    pub tool_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Field and primitive polynomial, e.g. `GF(2^8)/0x11d`.
+    pub field: String,
+    pub matrix_type: MatrixType,
+    /// [`MATRIX_CONSTRUCTION`] of the encoding build. Absent for sets
+    /// written before it was recorded, which use construction 1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub construction: Option<u32>,
+}
+
+/// Field and primitive polynomial of this build, as recorded in provenance.
+fn current_field() -> String {
+    format!("GF(2^8)/{:#x}", Gf256::POLYNOMIAL)
+}
+
+impl Provenance {
+    /// Provenance of a set encoded now by this build.
+    pub fn current(matrix_type: MatrixType) -> Self {
+        Self {
+            encoded_at: Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+            ),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            hostname: gethostname::gethostname().into_string().ok(),
+            field: current_field(),
+            matrix_type,
+            construction: Some(MATRIX_CONSTRUCTION),
+        }
+    }
+
+    /// Provenance of a set encoded by this build, without the time and
+    /// host, which would make otherwise identical sets differ.
+    pub fn reproducible(matrix_type: MatrixType) -> Self {
+        Self {
+            encoded_at: None,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            hostname: None,
+            field: current_field(),
+            matrix_type,
+            construction: Some(MATRIX_CONSTRUCTION),
+        }
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.encoded_at {
+            Some(encoded_at) => write!(
+                f,
+                "Encoded at {} (Unix time) by litiaina-rse {}",
+                encoded_at, self.tool_version
+            )?,
+            None => write!(f, "Encoded by litiaina-rse {}", self.tool_version)?,
+        }
+        if let Some(hostname) = &self.hostname {
+            write!(f, " on {}", hostname)?;
+        }
+        write!(
+            f,
+            "\nCode: {} with {:?} matrix (construction {})",
+            self.field,
+            self.matrix_type,
+            self.construction.unwrap_or(1)
+        )
+    }
+}
+
+/// What parity shards depend on besides the data: the matrix, its field
+/// and the version of its construction. Shards only combine with shards of
+/// the same fingerprint; rebuilding from a mix silently yields garbage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatFingerprint {
+    pub matrix_type: MatrixType,
+    pub field: String,
+    pub construction: u32,
+}
+
+impl fmt::Display for FormatFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} matrix over {}, construction {}",
+            self.matrix_type, self.field, self.construction
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardMetadata {
+    pub orig_len: usize,
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    /// Shard indices expected to be stored in this directory. `None` means
+    /// every shard is stored here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stored_shards: Option<Vec<usize>>,
+    /// Compression applied to the input before sharding. `orig_len` is then
+    /// the compressed length and `uncompressed_len` the original one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncompressed_len: Option<usize>,
+    /// Stripe length in bytes when shards are rotated across device files
+    /// (see [`crate::io::rotation`]). `None` for the plain layout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stripe_rotation: Option<usize>,
+    /// Seed of the byte permutation applied before sharding, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scramble_seed: Option<u64>,
+    /// File order when parity is interleaved with data (see
+    /// [`interleaved_parity_order`]): `shard_{f}.dat` holds shard
+    /// `disk_order[f]`. Every other field indexes shards logically.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disk_order: Option<Vec<usize>>,
+    /// Logical length of each data shard when the input was split unevenly
+    /// (see [`crate::io::partition`]). Data shard files hold exactly this many
+    /// bytes and are zero-padded to [`ShardMetadata::shard_len`] for decoding.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_shard_lens: Option<Vec<usize>>,
+    /// Grid of a two-dimensional product code (see [`crate::codec::product`]).
+    /// `data_shards` and `parity_shards` then count the whole set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_code: Option<ProductGeometry>,
+    /// Number of local groups of a local reconstruction code (see
+    /// [`crate::codec::lrc`]). `parity_shards` then counts the global parity
+    /// and one local parity shard per group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_groups: Option<usize>,
+    /// Encoding matrix of a plain code. Absent for sets written before it
+    /// was recorded, which use the Vandermonde matrix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix_type: Option<MatrixType>,
+    /// Parity rows of a [`MatrixType::Custom`] matrix, one hex string per
+    /// row as written by [`crate::codec::matrix::matrix_to_hex_rows`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom_matrix: Option<Vec<String>>,
+    /// Per-shard checksums, absent when encoded with `--checksum-algo none`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<ShardChecksums>,
+    /// Set when each shard's checksum is stored in a sidecar file next to it
+    /// (see [`crate::io::sidecar`]) instead of in `meta.json`. `checksums` is
+    /// then filled in from the sidecars when the metadata is read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sidecar_checksums: Option<ChecksumAlgo>,
+    /// Set when each shard file is compressed on disk. Sizes and checksums
+    /// then describe the compressed files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard_compression: Option<ShardCompression>,
+    /// Set when shard files are encrypted at rest. Checksums and sizes then
+    /// describe the encrypted files.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<ShardEncryption>,
+    /// Set when every shard file ends in a self-verifying trailer (see
+    /// [`crate::io::trailer`]). Sizes and checksums include it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard_trailer: Option<ShardTrailer>,
+    /// Shard length is rounded up to a multiple of this many bytes, the
+    /// data shards zero-padded to it. `orig_len` still gives the input length.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard_align: Option<usize>,
+    /// Set when the input was encoded as a stream (see
+    /// [`crate::io::stream`]): each stripe of `k * stream_stripe` input bytes
+    /// fills the next `stream_stripe` bytes of every data shard in turn, and
+    /// the last, shorter stripe is split evenly across them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_stripe: Option<usize>,
+    /// Set when every shard file is split into volumes of this many bytes
+    /// (see [`crate::io::volumes`]). Sizes and checksums describe the
+    /// reassembled file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume_size: Option<usize>,
+    /// Set when the shards are appended to one log file instead of a file
+    /// each (see [`crate::io::shard_log`]): where in the log each shard lies,
+    /// `None` for shards not stored here.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard_log: Option<Vec<Option<ShardLogEntry>>>,
+    /// Absent for sets written before provenance was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    /// SHA-256 of the original input, checked after decoding.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_sha256: Option<String>,
+}
+
+impl ShardMetadata {
+    pub fn new(orig_len: usize, data_shards: usize, parity_shards: usize) -> Self {
+        Self {
+            orig_len,
+            data_shards,
+            parity_shards,
+            stored_shards: None,
+            compression: None,
+            uncompressed_len: None,
+            stripe_rotation: None,
+            scramble_seed: None,
+            checksums: None,
+            sidecar_checksums: None,
+            data_shard_lens: None,
+            disk_order: None,
+            product_code: None,
+            local_groups: None,
+            matrix_type: None,
+            custom_matrix: None,
+            shard_compression: None,
+            encryption: None,
+            shard_trailer: None,
+            shard_align: None,
+            stream_stripe: None,
+            volume_size: None,
+            shard_log: None,
+            provenance: None,
+            input_sha256: None,
+        }
+    }
+
+    pub fn total_shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+
+    /// Codec with the set's encoding matrix. Product-code and local-group
+    /// sets build their own codecs instead.
+    pub fn codec(&self) -> Result<Codec> {
+        Codec::from_recorded(
+            self.data_shards,
+            self.parity_shards,
+            self.matrix_type,
+            self.custom_matrix.as_deref(),
+        )
+    }
+
+    /// Number of lost shards the set always survives, whichever they are.
+    /// Local parity only adds tolerance for some loss patterns, so a
+    /// local-group set counts its global parity alone.
+    pub fn guaranteed_tolerance(&self) -> usize {
+        match (&self.product_code, self.local_groups) {
+            (Some(geometry), _) => geometry.guaranteed_tolerance(),
+            (None, Some(groups)) => self.parity_shards - groups,
+            (None, None) => self.parity_shards,
+        }
+    }
+
+    /// Length every shard is padded to for the field arithmetic.
+    pub fn shard_len(&self) -> usize {
+        if let Some(stripe) = self.stream_stripe {
+            return stream_shard_len(self.orig_len, self.data_shards, stripe);
+        }
+        match (&self.data_shard_lens, self.shard_align) {
+            (Some(lens), _) => lens.iter().copied().max().unwrap_or(0),
+            (None, Some(align)) => self
+                .orig_len
+                .div_ceil(self.data_shards)
+                .next_multiple_of(align),
+            (None, None) => self.orig_len.div_ceil(self.data_shards),
+        }
+    }
+
+    /// Length of shard `index` as encoded, before any on-disk compression,
+    /// encryption or trailer.
+    pub fn plain_len(&self, index: usize) -> usize {
+        match &self.data_shard_lens {
+            Some(lens) if index < self.data_shards => lens[index],
+            _ => self.shard_len(),
+        }
+    }
+
+    /// Expected size of shard file `index` on disk.
+    pub fn stored_len(&self, index: usize) -> usize {
+        let compressed_len = self
+            .shard_compression
+            .as_ref()
+            .and_then(|c| c.sizes.get(index).copied().flatten())
+            .unwrap_or_else(|| self.plain_len(index));
+        let encrypted_len = if self.encryption.is_some() {
+            compressed_len + TAG_LEN
+        } else {
+            compressed_len
+        };
+        if self.shard_trailer.is_some() {
+            encrypted_len + TRAILER_LEN
+        } else {
+            encrypted_len
+        }
+    }
+
+    /// File name holding logical shard `index`.
+    pub fn shard_file_name(&self, index: usize) -> String {
+        let file_index = match &self.disk_order {
+            Some(order) => order
+                .iter()
+                .position(|&s| s == index)
+                .expect("disk_order is validated to be a permutation"),
+            None => index,
+        };
+        shard_file_name(file_index)
+    }
+
+    /// Path of the file holding logical shard `index` in `dir`.
+    pub fn shard_path(&self, dir: &Path, index: usize) -> PathBuf {
+        dir.join(self.shard_file_name(index))
+    }
+
+    /// Number of volumes shard file `index` is split into, or `None` if
+    /// shard files are not split.
+    pub fn volumes(&self, index: usize) -> Option<usize> {
+        self.volume_size
+            .map(|size| volume_count(self.stored_len(index), size))
+    }
+
+    /// Where shard `index` lies in the shard log, if the set has one and
+    /// the shard is stored.
+    pub fn shard_log_entry(&self, index: usize) -> Option<ShardLogEntry> {
+        self.shard_log.as_ref()?.get(index).copied().flatten()
+    }
+
+    /// The shard log entry of `index`, or [`io::ErrorKind::NotFound`] if the
+    /// log holds no such shard.
+    fn logged_shard(&self, index: usize) -> io::Result<ShardLogEntry> {
+        self.shard_log_entry(index).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is not in the shard log", self.shard_file_name(index)),
+            )
+        })
+    }
+
+    /// Reads shard file `index` from `dir`, reassembling its volumes, or the
+    /// shard from the set's log.
+    pub async fn read_shard(&self, dir: &Path, index: usize) -> io::Result<Vec<u8>> {
+        if self.shard_log.is_some() {
+            return read_log_shard(&shard_log_path(dir), &self.logged_shard(index)?).await;
+        }
+        read_shard_file(&self.shard_path(dir, index), self.volumes(index)).await
+    }
+
+    /// Size of shard file `index` in `dir`, summed over its volumes. For a
+    /// logged shard, the bytes of it the log holds.
+    pub async fn shard_file_len(&self, dir: &Path, index: usize) -> io::Result<u64> {
+        if self.shard_log.is_some() {
+            let entry = self.logged_shard(index)?;
+            let len = log_len(&shard_log_path(dir)).await?;
+            return entry
+                .present_len(len)
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound));
+        }
+        let path = self.shard_path(dir, index);
+        match self.volumes(index) {
+            Some(count) => volumes_len(&path, count).await,
+            None => Ok(fs::metadata(&path).await?.len()),
+        }
+    }
+
+    /// Whether shard file `index` exists in `dir`, judged by its first
+    /// volume when split. A logged shard exists if the log reaches it.
+    pub async fn shard_exists(&self, dir: &Path, index: usize) -> io::Result<bool> {
+        if self.shard_log.is_some() {
+            return match self.shard_file_len(dir, index).await {
+                Ok(_) => Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e),
+            };
+        }
+        let path = self.shard_path(dir, index);
+        match self.volume_size {
+            Some(_) => fs::try_exists(volume_path(&path, 0)).await,
+            None => fs::try_exists(&path).await,
+        }
+    }
+
+    /// Fingerprint of the encoding the set's shards were made with.
+    pub fn fingerprint(&self) -> FormatFingerprint {
+        let provenance = self.provenance.as_ref();
+        FormatFingerprint {
+            matrix_type: self.matrix_type.unwrap_or_default(),
+            field: provenance.map_or_else(current_field, |p| p.field.clone()),
+            construction: provenance.and_then(|p| p.construction).unwrap_or(1),
+        }
+    }
+
+    pub fn is_stored_here(&self, index: usize) -> bool {
+        self.stored_shards
+            .as_ref()
+            .is_none_or(|stored| stored.contains(&index))
+    }
+
+    /// Whether `dir` holds metadata in either the current or the legacy format.
+    pub async fn exists(dir: &Path) -> bool {
+        for name in [META_FILE, LEGACY_META_FILE] {
+            if fs::try_exists(dir.join(name)).await.unwrap_or(false) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Reads the metadata in `dir`, including the checksums of any sidecar
+    /// files there.
+    pub async fn read(dir: &Path) -> Result<Self> {
+        let mut meta = Self::read_descriptor(dir).await?;
+        meta.load_sidecars(dir).await?;
+        Ok(meta)
+    }
+
+    /// Reads `meta.json` (or the legacy format) alone. For a set with
+    /// sidecars, `checksums` stays empty until
+    /// [`ShardMetadata::load_sidecars`].
+    pub async fn read_descriptor(dir: &Path) -> Result<Self> {
+        let json_path = dir.join(META_FILE);
+        if fs::try_exists(&json_path).await.unwrap_or(false) {
+            let raw = fs::read_to_string(&json_path)
+                .await
+                .with_context(|| format!("Failed to read {:?}", json_path))?;
+            let meta: Self =
+                serde_json::from_str(&raw).with_context(|| format!("Invalid {}", META_FILE))?;
+            meta.validate()?;
+            return Ok(meta);
+        }
+
+        let raw = fs::read_to_string(dir.join(LEGACY_META_FILE))
+            .await
+            .context("Failed to read meta.json or meta.txt. Is the shard directory correct?")?;
+        let meta = Self::parse_legacy(&raw)?;
+        meta.validate()?;
+        Ok(meta)
+    }
+
+    /// Fills in `checksums` from the sidecar of every shard in `dir`. A
+    /// shard without a usable sidecar gets an empty checksum, so it fails
+    /// verification and is treated like a damaged shard.
+    pub async fn load_sidecars(&mut self, dir: &Path) -> Result<()> {
+        let Some(algorithm) = self.sidecar_checksums else {
+            return Ok(());
+        };
+        let format = self.fingerprint();
+        let mut shards = Vec::with_capacity(self.total_shards());
+        for i in 0..self.total_shards() {
+            let checksum =
+                read_sidecar_checksum(&[self.shard_path(dir, i)], i, self.stored_len(i), &format)
+                    .await?;
+            if checksum.is_none() && self.is_stored_here(i) {
+                warn!("No sidecar for {}", self.shard_file_name(i));
+            }
+            shards.push(checksum.unwrap_or_default());
+        }
+        self.checksums = Some(ShardChecksums { algorithm, shards });
+        Ok(())
+    }
+
+    /// Writes the sidecar of every shard stored in `dir`, from `checksums`
+    /// and the expected shard file sizes.
+    pub async fn write_sidecars(&self, dir: &Path, opts: &WriteOptions) -> Result<()> {
+        let Some(checksums) = &self.checksums else {
+            return Err(anyhow!("Sidecar metadata needs per-shard checksums"));
+        };
+        for i in (0..self.total_shards()).filter(|&i| self.is_stored_here(i)) {
+            ShardSidecar {
+                shard: i,
+                size: self.stored_len(i),
+                checksum: checksums.shards[i].clone(),
+                format: Some(self.fingerprint()),
+            }
+            .write(&self.shard_path(dir, i), opts)
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn write(&self, dir: &Path) -> Result<()> {
+        self.write_with(dir, &WriteOptions::default()).await
+    }
+
+    /// Writes the metadata atomically, so a crash never leaves a partial file.
+    /// Checksums kept in sidecars are left out.
+    pub async fn write_with(&self, dir: &Path, opts: &WriteOptions) -> Result<()> {
+        let json = if self.sidecar_checksums.is_some() {
+            serde_json::to_string_pretty(&Self {
+                checksums: None,
+                ..self.clone()
+            })?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        write_atomic(&dir.join(META_FILE), json.as_bytes(), opts, None)
+            .await
+            .with_context(|| format!("Failed to write {} in {:?}", META_FILE, dir))
+    }
+
+    fn parse_legacy(raw: &str) -> Result<Self> {
+        let mut lines = raw.lines();
+        let orig_len: usize = lines
+            .next()
+            .ok_or(anyhow!("Invalid meta.txt: missing length"))?
+            .trim()
+            .parse()?;
+        let km_line = lines
+            .next()
+            .ok_or(anyhow!("Invalid meta.txt: missing k/m"))?;
+        let mut parts = km_line.split_whitespace();
+        let k: usize = parts
+            .next()
+            .ok_or(anyhow!("Invalid meta.txt: missing k"))?
+            .parse()?;
+        let m: usize = parts
+            .next()
+            .ok_or(anyhow!("Invalid meta.txt: missing m"))?
+            .parse()?;
+        Ok(Self::new(orig_len, k, m))
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.compression.is_some() != self.uncompressed_len.is_some() {
+            return Err(anyhow!(
+                "Invalid metadata: compression and uncompressed_len must be set together"
+            ));
+        }
+        if self.stripe_rotation == Some(0) {
+            return Err(anyhow!("Invalid metadata: stripe_rotation must be > 0"));
+        }
+        if self.shard_align == Some(0) {
+            return Err(anyhow!("Invalid metadata: shard_align must be > 0"));
+        }
+        if self.shard_align.is_some() && self.data_shard_lens.is_some() {
+            return Err(anyhow!(
+                "Invalid metadata: shard_align cannot be combined with data_shard_lens"
+            ));
+        }
+        if self.stream_stripe == Some(0) {
+            return Err(anyhow!("Invalid metadata: stream_stripe must be > 0"));
+        }
+        if self.stream_stripe.is_some()
+            && (self.shard_align.is_some()
+                || self.data_shard_lens.is_some()
+                || self.stripe_rotation.is_some())
+        {
+            return Err(anyhow!(
+                "Invalid metadata: stream_stripe cannot be combined with shard_align, \
+                 data_shard_lens or stripe_rotation"
+            ));
+        }
+        if self.sidecar_checksums == Some(ChecksumAlgo::None) {
+            return Err(anyhow!(
+                "Invalid metadata: sidecar_checksums needs a checksum algorithm"
+            ));
+        }
+        if self.volume_size == Some(0) {
+            return Err(anyhow!("Invalid metadata: volume_size must be > 0"));
+        }
+        if let Some(log) = &self.shard_log {
+            if log.len() != self.total_shards() {
+                return Err(anyhow!(
+                    "Invalid metadata: shard_log must have {} entries",
+                    self.total_shards()
+                ));
+            }
+            if self.volume_size.is_some() || self.sidecar_checksums.is_some() {
+                return Err(anyhow!(
+                    "Invalid metadata: shard_log cannot be combined with volume_size or \
+                     sidecar_checksums"
+                ));
+            }
+        }
+        if let Some(lens) = &self.data_shard_lens
+            && (lens.len() != self.data_shards || lens.iter().sum::<usize>() != self.orig_len)
+        {
+            return Err(anyhow!(
+                "Invalid metadata: data_shard_lens must have {} entries summing to {}",
+                self.data_shards,
+                self.orig_len
+            ));
+        }
+        if let Some(order) = &self.disk_order {
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            if !sorted.into_iter().eq(0..self.total_shards()) {
+                return Err(anyhow!(
+                    "Invalid metadata: disk_order must list each of the {} shards once",
+                    self.total_shards()
+                ));
+            }
+            if self.stripe_rotation.is_some() {
+                return Err(anyhow!(
+                    "Invalid metadata: disk_order cannot be combined with stripe_rotation"
+                ));
+            }
+        }
+        if self.data_shard_lens.is_some() && self.stripe_rotation.is_some() {
+            return Err(anyhow!(
+                "Invalid metadata: data_shard_lens cannot be combined with stripe_rotation"
+            ));
+        }
+        if let Some(geometry) = &self.product_code {
+            if geometry.data_shards() != self.data_shards
+                || geometry.total_shards() != self.total_shards()
+            {
+                return Err(anyhow!(
+                    "Invalid metadata: product_code grid does not match k={} m={}",
+                    self.data_shards,
+                    self.parity_shards
+                ));
+            }
+            if self.stripe_rotation.is_some() || self.data_shard_lens.is_some() {
+                return Err(anyhow!(
+                    "Invalid metadata: product_code cannot be combined with stripe_rotation or \
+                     data_shard_lens"
+                ));
+            }
+        }
+        if let Some(groups) = self.local_groups {
+            if groups == 0 || groups > self.data_shards || groups >= self.parity_shards {
+                return Err(anyhow!(
+                    "Invalid metadata: {} local groups do not fit k={} m={}",
+                    groups,
+                    self.data_shards,
+                    self.parity_shards
+                ));
+            }
+            if self.product_code.is_some()
+                || self.stripe_rotation.is_some()
+                || self.data_shard_lens.is_some()
+            {
+                return Err(anyhow!(
+                    "Invalid metadata: local_groups cannot be combined with product_code, \
+                     stripe_rotation or data_shard_lens"
+                ));
+            }
+        }
+        if self.custom_matrix.is_some() != (self.matrix_type == Some(MatrixType::Custom)) {
+            return Err(anyhow!(
+                "Invalid metadata: custom_matrix must be given exactly when matrix_type is custom"
+            ));
+        }
+        if self
+            .matrix_type
+            .is_some_and(|ty| ty != MatrixType::Vandermonde)
+            && (self.product_code.is_some() || self.local_groups.is_some())
+        {
+            return Err(anyhow!(
+                "Invalid metadata: product_code and local_groups sets use the Vandermonde matrix"
+            ));
+        }
+        if let Some(rows) = &self.custom_matrix {
+            let matrix = matrix_from_hex_rows(rows)
+                .map_err(|e| anyhow!("Invalid metadata: custom_matrix: {:#}", e))?;
+            if matrix.len() != self.parity_shards
+                || matrix.iter().any(|row| row.len() != self.data_shards)
+            {
+                return Err(anyhow!(
+                    "Invalid metadata: custom_matrix must be {} x {}",
+                    self.parity_shards,
+                    self.data_shards
+                ));
+            }
+        }
+        if let Some(compression) = &self.shard_compression
+            && compression.sizes.len() != self.total_shards()
+        {
+            return Err(anyhow!(
+                "Invalid metadata: {} compressed shard sizes for {} shards",
+                compression.sizes.len(),
+                self.total_shards()
+            ));
+        }
+        if let Some(checksums) = &self.checksums
+            && checksums.shards.len() != self.total_shards()
+        {
+            return Err(anyhow!(
+                "Invalid metadata: {} checksums for {} shards",
+                checksums.shards.len(),
+                self.total_shards()
+            ));
+        }
+        if let Some(stored) = &self.stored_shards
+            && let Some(&bad) = stored.iter().find(|&&i| i >= self.total_shards())
+        {
+            return Err(anyhow!(
+                "Invalid metadata: stored shard index {} is out of range for {} shards",
+                bad,
+                self.total_shards()
+            ));
+        }
+        Ok(())
+    }
+}
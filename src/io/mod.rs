@@ -1,2 +1,73 @@
+#[cfg(feature = "full")]
+pub mod atomic;
+#[cfg(feature = "full")]
+pub mod blockwise;
+#[cfg(feature = "full")]
+pub mod budget;
+#[cfg(feature = "full")]
+pub mod checksum;
+#[cfg(feature = "full")]
+pub mod compare;
+#[cfg(feature = "full")]
+pub mod compression;
+#[cfg(feature = "full")]
+pub mod consistency;
+#[cfg(feature = "full")]
+pub mod decoding;
+#[cfg(feature = "full")]
+pub mod dump_matrix;
+#[cfg(feature = "full")]
 pub mod encoding;
-pub mod decoding;
\ No newline at end of file
+#[cfg(feature = "full")]
+pub mod encryption;
+pub mod files;
+#[cfg(feature = "full")]
+pub mod info;
+#[cfg(feature = "lean")]
+pub mod lean;
+#[cfg(feature = "full")]
+pub mod manifest;
+#[cfg(feature = "full")]
+pub mod metadata;
+#[cfg(feature = "full")]
+pub mod objects;
+#[cfg(feature = "full")]
+pub mod partition;
+#[cfg(feature = "full")]
+pub mod report;
+#[cfg(feature = "full")]
+pub mod reshape;
+#[cfg(feature = "full")]
+pub mod retry;
+#[cfg(feature = "full")]
+pub mod rotation;
+pub mod scramble;
+#[cfg(feature = "full")]
+pub mod scrub;
+#[cfg(feature = "full")]
+pub mod self_healing;
+#[cfg(feature = "full")]
+pub mod serve;
+#[cfg(feature = "full")]
+pub mod shard_log;
+#[cfg(feature = "full")]
+pub mod sidecar;
+#[cfg(feature = "full")]
+pub mod split;
+#[cfg(feature = "full")]
+pub mod stats;
+#[cfg(feature = "full")]
+pub mod store;
+#[cfg(feature = "full")]
+pub mod stream;
+#[cfg(feature = "full")]
+pub mod suggest;
+#[cfg(feature = "full")]
+pub mod throttle;
+#[cfg(feature = "full")]
+pub mod trailer;
+#[cfg(feature = "full")]
+pub mod verify;
+#[cfg(feature = "full")]
+pub mod volumes;
+pub mod wipe;
@@ -0,0 +1,131 @@
+//! Encoding a stream of independent objects, one shard set each.
+//!
+//! The input is a sequence of frames, each an 8-byte little-endian length
+//! followed by that many bytes of object data. Object `n` (counting from 0)
+//! is encoded into `<output>/object_NNNNNNNN` as if it had been its own input
+//! file, with the field tables and encoding matrix built once for the whole
+//! stream. Empty objects and objects shorter than k bytes are valid; their
+//! shard sets decode like any other.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    error::RseError,
+    io::{
+        encoding::{
+            EncodeOptions, ParityEncoder, ShardCountLimit, encode_buffer_with, existing_set_matches,
+        },
+        throttle::RateLimiter,
+    },
+};
+
+/// Bytes in the length prefix of every frame.
+pub const FRAME_HEADER_LEN: usize = 8;
+
+/// Name of the subdirectory holding the shard set of object `id`.
+pub fn object_dir_name(id: usize) -> String {
+    format!("object_{:08}", id)
+}
+
+/// Path of the shard set of object `id` under `out_dir`.
+pub fn object_dir(out_dir: &Path, id: usize) -> PathBuf {
+    out_dir.join(object_dir_name(id))
+}
+
+/// `objects` as a framed stream, in the format [`read_frame`] expects.
+pub fn frame_objects<T: AsRef<[u8]>>(objects: &[T]) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for object in objects {
+        let object = object.as_ref();
+        stream.extend_from_slice(&(object.len() as u64).to_le_bytes());
+        stream.extend_from_slice(object);
+    }
+    stream
+}
+
+/// Reads the next frame of `reader`, or `None` at a clean end of the stream.
+/// A stream that ends inside a header or an object is an error.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    let mut filled = 0;
+    while filled < FRAME_HEADER_LEN {
+        let n = reader.read(&mut header[filled..]).await?;
+        if n == 0 {
+            if filled == 0 {
+                return Ok(None);
+            }
+            return Err(RseError::InvalidArgument(format!(
+                "Object stream ends inside a frame header ({} of {} bytes)",
+                filled, FRAME_HEADER_LEN
+            ))
+            .into());
+        }
+        filled += n;
+    }
+    let len = usize::try_from(u64::from_le_bytes(header)).map_err(|_| {
+        RseError::InvalidArgument("Object frame length does not fit in memory".into())
+    })?;
+
+    let mut object = Vec::new();
+    let read = reader.take(len as u64).read_to_end(&mut object).await?;
+    if read < len {
+        return Err(RseError::InvalidArgument(format!(
+            "Object stream ends inside an object ({} of {} bytes)",
+            read, len
+        ))
+        .into());
+    }
+    Ok(Some(object))
+}
+
+/// Encodes every object framed in `reader` into its own subdirectory of
+/// `out_dir` (see [`object_dir_name`]), one at a time, printing a line per
+/// object. Returns the number of objects. `opts` must already be validated.
+/// With a `limit`, an object whose shard set it refuses stops the stream.
+pub async fn encode_objects<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    out_dir: &Path,
+    opts: &EncodeOptions,
+    limiter: Option<Arc<RateLimiter>>,
+    encoder: &ParityEncoder,
+    limit: Option<ShardCountLimit>,
+) -> Result<usize> {
+    let start = Instant::now();
+    let mut count = 0;
+    let mut total_len = 0;
+    while let Some(object) = read_frame(reader)
+        .await
+        .with_context(|| format!("Failed to read object {}", count))?
+    {
+        let object_out_dir = object_dir(out_dir, count);
+        let len = object.len();
+        if opts.skip_existing && existing_set_matches(&object_out_dir, &object, opts).await {
+            println!(
+                "SKIPPED {} ({} bytes, already encoded)",
+                object_out_dir.display(),
+                len
+            );
+        } else {
+            if let Some(limit) = limit {
+                limit.check(&object_out_dir, len, opts)?;
+            }
+            encode_buffer_with(object, &object_out_dir, opts, limiter.clone(), encoder)
+                .await
+                .with_context(|| format!("Failed to encode object {}", count))?;
+            println!("OK      {} ({} bytes)", object_out_dir.display(), len);
+        }
+        count += 1;
+        total_len += len;
+    }
+    println!(
+        "Encoded {} objects ({} bytes) in {:.2?}",
+        count,
+        total_len,
+        start.elapsed()
+    );
+    Ok(count)
+}
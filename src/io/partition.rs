@@ -0,0 +1,32 @@
+//! Uneven logical partitioning of the input across data shards.
+//!
+//! Reed-Solomon needs equal-length operands, so uneven data shards are padded
+//! with zeros to the longest one for the field arithmetic. Only the logical
+//! bytes of each data shard are stored, so a data shard's file is sized to its
+//! weight. Parity shards are always as long as the longest data shard: the
+//! savings apply to data nodes only.
+
+/// Splits `total` bytes into pieces proportional to `weights`, handing any
+/// remainder out one byte at a time from the first piece. The pieces always
+/// sum to `total`.
+pub fn weighted_split(total: usize, weights: &[usize]) -> Vec<usize> {
+    let weight_sum: u128 = weights.iter().map(|&w| w as u128).sum();
+    if weight_sum == 0 {
+        return vec![0; weights.len()];
+    }
+    let mut lens: Vec<usize> = weights
+        .iter()
+        .map(|&w| (total as u128 * w as u128 / weight_sum) as usize)
+        .collect();
+    let mut remainder = total - lens.iter().sum::<usize>();
+    for (len, &w) in lens.iter_mut().zip(weights) {
+        if remainder == 0 {
+            break;
+        }
+        if w > 0 {
+            *len += 1;
+            remainder -= 1;
+        }
+    }
+    lens
+}
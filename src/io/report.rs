@@ -0,0 +1,172 @@
+//! Machine-readable summary of one `encode` or `decode` run.
+//!
+//! With `--report-file`, the command writes an [`OperationReport`] as JSON to
+//! the given path once it finishes, whether it succeeded or not, so backup
+//! orchestration can record exactly what each run did without parsing
+//! stdout or the logs. A failed run still gets a report: it names the stage
+//! the run was in and the error, and holds whatever was known by then.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use crate::io::{
+    atomic::{WriteOptions, write_atomic},
+    checksum::ShardChecksums,
+    metadata::ShardMetadata,
+    stats::PhaseTimes,
+};
+
+/// Contents of a report file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OperationReport {
+    /// `encode` or `decode`.
+    pub operation: String,
+    pub tool_version: String,
+    /// Seconds since the Unix epoch when the run started.
+    pub started_at: u64,
+    pub success: bool,
+    /// Stage the run was in when it failed; absent on success.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_stage: Option<String>,
+    /// The error, with its causes; absent on success.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Input file of an encode, shard directories of a decode.
+    pub inputs: Vec<PathBuf>,
+    /// Shard directory of an encode, output file of a decode.
+    pub output: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_shards: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parity_shards: Option<usize>,
+    /// Bytes of original input encoded or decoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<usize>,
+    /// SHA-256 of the original input as recorded in the metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_sha256: Option<String>,
+    /// Shards an encode wrote, or with `--skip-existing` found in place.
+    #[serde(default)]
+    pub shards_written: Vec<usize>,
+    /// Shards a decode read intact.
+    #[serde(default)]
+    pub shards_read: Vec<usize>,
+    /// Data shards rebuilt from the others.
+    #[serde(default)]
+    pub shards_reconstructed: Vec<usize>,
+    /// Checksums of the shards, as recorded in the metadata.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksums: Option<ShardChecksums>,
+    pub durations: ReportDurations,
+    /// Stage reached so far, for `failed_stage`.
+    #[serde(skip)]
+    stage: String,
+    #[serde(skip)]
+    start: Option<Instant>,
+}
+
+/// Time spent in each phase, in seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReportDurations {
+    pub read_secs: f64,
+    pub compute_secs: f64,
+    pub write_secs: f64,
+    pub total_secs: f64,
+}
+
+impl OperationReport {
+    /// An empty report for `operation`, starting now.
+    pub fn new(operation: &str) -> Self {
+        Self {
+            operation: operation.to_string(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            success: false,
+            failed_stage: None,
+            error: None,
+            inputs: Vec::new(),
+            output: PathBuf::new(),
+            data_shards: None,
+            parity_shards: None,
+            bytes: None,
+            input_sha256: None,
+            shards_written: Vec::new(),
+            shards_read: Vec::new(),
+            shards_reconstructed: Vec::new(),
+            checksums: None,
+            durations: ReportDurations::default(),
+            stage: "starting".to_string(),
+            start: Some(Instant::now()),
+        }
+    }
+
+    /// Records that the run has moved on to `stage`.
+    pub fn stage(&mut self, stage: &str) {
+        self.stage = stage.to_string();
+    }
+
+    /// Records the shape, input hash and shard checksums of the set `meta`
+    /// describes.
+    pub fn record_set(&mut self, meta: &ShardMetadata) {
+        self.data_shards = Some(meta.data_shards);
+        self.parity_shards = Some(meta.parity_shards);
+        self.input_sha256 = meta.input_sha256.clone();
+        self.checksums = meta.checksums.clone();
+    }
+
+    pub fn set_times(&mut self, times: &PhaseTimes) {
+        self.durations.read_secs = times.read.as_secs_f64();
+        self.durations.compute_secs = times.compute.as_secs_f64();
+        self.durations.write_secs = times.write.as_secs_f64();
+    }
+
+    /// Records the outcome of the run.
+    pub fn finish(&mut self, result: &Result<()>) {
+        if let Some(start) = self.start {
+            self.durations.total_secs = start.elapsed().as_secs_f64();
+        }
+        match result {
+            Ok(()) => self.success = true,
+            Err(e) => {
+                self.failed_stage = Some(self.stage.clone());
+                self.error = Some(format!("{:#}", e));
+            }
+        }
+    }
+
+    /// Writes the report to `path` atomically.
+    pub async fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomic(path, json.as_bytes(), &WriteOptions::default(), None)
+            .await
+            .with_context(|| format!("Failed to write report file {:?}", path))
+    }
+}
+
+/// Finishes `report` with `result` and writes it to `path`, if given, then
+/// returns `result`. A report that fails to write fails an otherwise
+/// successful run; after a failed run, the original error is kept.
+pub async fn write_report(
+    path: Option<&Path>,
+    mut report: OperationReport,
+    result: Result<()>,
+) -> Result<()> {
+    let Some(path) = path else {
+        return result;
+    };
+    report.finish(&result);
+    match (report.write(path).await, result) {
+        (Ok(()), result) => result,
+        (Err(e), Ok(())) => Err(e),
+        (Err(e), Err(original)) => {
+            warn!("{:#}", e);
+            Err(original)
+        }
+    }
+}
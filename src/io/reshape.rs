@@ -0,0 +1,94 @@
+use anyhow::Result;
+use tracing::{info, instrument};
+
+use crate::{
+    cli::commands::Commands,
+    codec::{execution::Execution, matrix::MatrixType},
+    error::RseError,
+    io::{
+        checksum::ChecksumAlgo,
+        decoding::{DecodeOptions, decode_dir},
+        encoding::{EncodeOptions, check_free_space, encode_buffer},
+        metadata::ShardMetadata,
+    },
+};
+
+#[instrument(skip(args))]
+pub async fn handle_reshape(args: Commands, execution: &Execution) -> Result<()> {
+    let Commands::Reshape {
+        input,
+        output,
+        new_data_shards,
+        new_parity_shards,
+        encrypt_key,
+    } = args
+    else {
+        unreachable!()
+    };
+
+    if input.canonicalize().ok() == output.canonicalize().ok() {
+        return Err(RseError::InvalidArgument(
+            "Reshape output must be a different directory from the input".into(),
+        )
+        .into());
+    }
+
+    let meta = ShardMetadata::read(&input).await?;
+    // Compression of the input and of each shard, scrambling, encryption,
+    // trailers, alignment, volumes, sidecars and reproducibility carry over.
+    // Per-device choices such as --store-only and stripe rotation refer to the
+    // old shard layout and do not.
+    let opts = EncodeOptions {
+        data_shards: new_data_shards,
+        parity_shards: new_parity_shards,
+        compression: meta.compression,
+        compress_shards: meta.shard_compression.as_ref().map(|c| c.algorithm),
+        // Custom rows and XOR parity only fit the old k/m; Cauchy fits any.
+        matrix_type: match meta.matrix_type {
+            Some(MatrixType::Cauchy) => MatrixType::Cauchy,
+            _ => MatrixType::Vandermonde,
+        },
+        scramble_seed: meta.scramble_seed,
+        interleave_parity: meta.disk_order.is_some(),
+        encrypt_key: meta.encryption.as_ref().and(encrypt_key.clone()),
+        shard_trailer: meta.shard_trailer.is_some(),
+        align: meta.shard_align,
+        volume_size: meta.volume_size,
+        sidecar_metadata: meta.sidecar_checksums.is_some(),
+        shard_log: meta.shard_log.is_some(),
+        reproducible: meta
+            .provenance
+            .as_ref()
+            .is_some_and(|p| p.encoded_at.is_none()),
+        checksum_algo: meta
+            .checksums
+            .as_ref()
+            .map_or_else(ChecksumAlgo::default, |c| c.algorithm),
+        execution: execution.clone(),
+        ..Default::default()
+    };
+    opts.validate()?;
+
+    info!(
+        "Reshaping {:?} from k={} m={} to k={} m={}",
+        input, meta.data_shards, meta.parity_shards, new_data_shards, new_parity_shards
+    );
+    // Full decode: fails unless the source set is recoverable and, when a hash
+    // was recorded, reproduces the original input exactly.
+    let data = decode_dir(
+        &input,
+        &DecodeOptions {
+            encrypt_key,
+            execution: execution.clone(),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    let required = opts.aligned_shard_len(data.len()) * (new_data_shards + new_parity_shards);
+    check_free_space(&output, required as u64)?;
+    encode_buffer(data, &output, &opts, None).await?;
+
+    info!("✅ Reshaped shard set written to {:?}", output);
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! Retrying shard reads on flaky storage such as network mounts or failing
+//! disks.
+//!
+//! Only genuine IO errors are retried. A file that does not exist is missing
+//! straight away: waiting will not make it appear.
+
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tracing::warn;
+
+/// How often, and how patiently, a failed read is retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// Delay before the first retry; it doubles before each further one.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 2,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `read` until it succeeds, reports the file as not found
+    /// (`Ok(None)`), or has failed `retries + 1` times, in which case the
+    /// last error is returned.
+    pub async fn read<F, Fut>(&self, what: &str, mut read: F) -> io::Result<Option<Vec<u8>>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<Vec<u8>>>,
+    {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match read().await {
+                Ok(data) => return Ok(Some(data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+                Err(e) if attempt < self.retries => {
+                    attempt += 1;
+                    warn!(
+                        "Reading {} failed ({}); retry {}/{} in {:?}",
+                        what, e, attempt, self.retries, backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! RAID-style rotated layout.
+//!
+//! Every byte column across the `n` shards is an independent codeword, so each
+//! shard can be cut into fixed-size stripes and stripe `s` of shard `i` stored
+//! in device file `(i + s) % n`. This spreads parity over all device files
+//! instead of concentrating it in the last `m`. Losing one device file then
+//! loses a different shard in every stripe, so decoding reconstructs stripe by
+//! stripe.
+
+use anyhow::Result;
+
+use crate::{codec::reconstruct_shards::Codec, error::RseError};
+
+pub fn device_for(shard: usize, stripe: usize, n: usize) -> usize {
+    (shard + stripe) % n
+}
+
+/// Redistributes equal-length shards into rotated device buffers.
+pub fn rotate_stripes(shards: &[Vec<u8>], stripe_len: usize) -> Vec<Vec<u8>> {
+    let n = shards.len();
+    let shard_len = shards.first().map_or(0, |s| s.len());
+    let mut devices = vec![vec![0u8; shard_len]; n];
+    for (s, start) in (0..shard_len).step_by(stripe_len).enumerate() {
+        let end = (start + stripe_len).min(shard_len);
+        for (i, shard) in shards.iter().enumerate() {
+            devices[device_for(i, s, n)][start..end].copy_from_slice(&shard[start..end]);
+        }
+    }
+    devices
+}
+
+/// Reverses [`rotate_stripes`], reconstructing each stripe whose chunks were
+/// lost with missing device files. Returns every shard in logical order.
+pub fn reconstruct_rotated(
+    codec: &Codec,
+    devices: &[Option<Vec<u8>>],
+    stripe_len: usize,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    let n = devices.len();
+    let shard_len = devices
+        .iter()
+        .find_map(|d| d.as_ref().map(|v| v.len()))
+        .ok_or(RseError::InsufficientShards {
+            have: 0,
+            need: codec.data_shards(),
+        })?;
+    if let Some(i) = devices
+        .iter()
+        .position(|d| d.as_ref().is_some_and(|v| v.len() != shard_len))
+    {
+        return Err(RseError::Corruption(format!(
+            "Device file {} has a different length than the others",
+            i
+        ))
+        .into());
+    }
+
+    let mut shards = vec![vec![0u8; shard_len]; n];
+    for (s, start) in (0..shard_len).step_by(stripe_len).enumerate() {
+        let end = (start + stripe_len).min(shard_len);
+        let mut stripe: Vec<Option<Vec<u8>>> = (0..n)
+            .map(|i| {
+                devices[device_for(i, s, n)]
+                    .as_ref()
+                    .map(|d| d[start..end].to_vec())
+            })
+            .collect();
+        if stripe.iter().any(|c| c.is_none()) {
+            codec.reconstruct(&mut stripe)?;
+        }
+        for (shard, chunk) in shards.iter_mut().zip(stripe) {
+            shard[start..end].copy_from_slice(&chunk.expect("reconstructed"));
+        }
+    }
+    Ok(shards.into_iter().map(Some).collect())
+}
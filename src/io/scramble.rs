@@ -0,0 +1,25 @@
+//! Seed-derived byte permutation applied before sharding so no data shard
+//! holds contiguous input. This is obfuscation, not encryption: anyone with
+//! the metadata (which records the seed) can reverse it.
+//!
+//! The permutation is materialized as one `usize` per input byte, so
+//! scrambling needs roughly nine times the input size in memory.
+
+use crate::algorithm::shuffle::permutation;
+
+/// Returns `data` with its bytes permuted by the seed: `out[i] = data[perm[i]]`.
+pub fn scramble(data: &[u8], seed: u64) -> Vec<u8> {
+    permutation(data.len(), seed)
+        .into_iter()
+        .map(|src| data[src])
+        .collect()
+}
+
+/// Exactly reverses [`scramble`] for the same seed.
+pub fn unscramble(data: &[u8], seed: u64) -> Vec<u8> {
+    let mut out = vec![0u8; data.len()];
+    for (&byte, dst) in data.iter().zip(permutation(data.len(), seed)) {
+        out[dst] = byte;
+    }
+    out
+}
@@ -0,0 +1,277 @@
+//! Full reads of every shard to catch bit rot before it costs the set.
+//!
+//! Unlike `verify`, which checks whatever the manifest or checksums cover,
+//! and `info`, which only looks at which files exist, a scrub reads the bytes
+//! of every shard stored in the directory and checks its size, checksum and
+//! trailer. It then reports how many more losses the set is guaranteed to
+//! survive, and can rewrite lost shards when that margin runs thin.
+//!
+//! A repair writes each rebuilt shard as soon as it is ready, through an
+//! atomic rename, so the shards themselves are its checkpoint: an interrupted
+//! repair is resumed by running it again, which checks every lost shard once
+//! more and skips those an earlier run already restored.
+
+use anyhow::Result;
+use std::path::Path;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    cli::commands::Commands,
+    codec::execution::Execution,
+    error::RseError,
+    io::{
+        atomic::{WriteOptions, remove_stale_temps},
+        decoding::{DecodeOptions, Recovery, recover_data_shards},
+        metadata::ShardMetadata,
+        split::encode_parity,
+        trailer::append_trailer,
+        verify::{ChecksumScan, ShardHealth, check_shard, stored_indices},
+        volumes::write_shard_file,
+    },
+};
+
+/// Health of every shard of a set after a full read.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrubReport {
+    /// The shards stored in the directory. Corrupt ones are the wrong size,
+    /// fail their checksum or trailer, or are unreadable.
+    pub scan: ChecksumScan,
+    /// Stored in another directory, so not read.
+    pub elsewhere: Vec<usize>,
+    /// Lost shards the set always survives (see
+    /// [`ShardMetadata::guaranteed_tolerance`]).
+    pub tolerance: usize,
+}
+
+impl ScrubReport {
+    /// Further losses the set is still guaranteed to survive, or `None` once
+    /// more shards are lost than that. Shards stored elsewhere count as
+    /// healthy.
+    pub fn margin(&self) -> Option<usize> {
+        self.tolerance
+            .checked_sub(self.scan.corrupt.len() + self.scan.missing.len())
+    }
+}
+
+/// Reads every shard of the set in `dir` that `meta` says is stored there,
+/// printing a line for each.
+pub async fn scrub_dir(dir: &Path, meta: &ShardMetadata) -> Result<ScrubReport> {
+    Ok(ScrubReport {
+        scan: ChecksumScan::scan(dir, meta, &stored_indices(meta), true).await,
+        elsewhere: (0..meta.total_shards())
+            .filter(|&i| !meta.is_stored_here(i))
+            .collect(),
+        tolerance: meta.guaranteed_tolerance(),
+    })
+}
+
+/// Rebuilds the `lost` shards of the set in `dir` and writes them back in
+/// place, returning the ones actually rewritten. The rebuilt files are
+/// byte-identical to the originals, which is checked against the recorded
+/// checksums where there are any, so the metadata and manifest stay valid.
+///
+/// Each lost shard is checked again first: one that is now intact, e.g.
+/// written by an interrupted earlier repair, is kept as it is. One that is
+/// still short or damaged is redone, and temporary files left behind by an
+/// interrupted write are removed.
+pub async fn repair_shards(
+    dir: &Path,
+    meta: &ShardMetadata,
+    lost: &[usize],
+    verify_parity: bool,
+    execution: &Execution,
+) -> Result<Vec<usize>> {
+    check_repairable(meta)?;
+    let mut pending = Vec::with_capacity(lost.len());
+    for &i in lost {
+        let path = meta.shard_path(dir, i);
+        let stale = remove_stale_temps(&path).await?;
+        if stale > 0 {
+            info!(
+                "Removed {} temporary files of an interrupted write of {}",
+                stale,
+                meta.shard_file_name(i)
+            );
+        }
+        if check_shard(dir, meta, i).await == ShardHealth::Healthy {
+            println!("INTACT    {} (already repaired)", meta.shard_file_name(i));
+        } else {
+            pending.push(i);
+        }
+    }
+    if pending.is_empty() {
+        return Ok(pending);
+    }
+
+    let opts = DecodeOptions {
+        execution: execution.clone(),
+        ..Default::default()
+    };
+    let mut shards = match recover_data_shards(dir, &opts).await? {
+        Recovery::Shards(_, shards, ..) => shards,
+        Recovery::Partial(_) => unreachable!("partial recovery was not requested"),
+    };
+    let k = meta.data_shards;
+    let present_parity = shards.split_off(k);
+    let data: Vec<Vec<u8>> = shards
+        .into_iter()
+        .map(|s| s.expect("every data shard is recovered"))
+        .collect();
+    // Parity is only recomputed when some of it needs rewriting or checking.
+    let parity = if verify_parity || pending.iter().any(|&i| i >= k) {
+        encode_parity(meta, &data, execution)?
+    } else {
+        Vec::new()
+    };
+    if verify_parity {
+        check_present_parity(meta, &present_parity, &parity)?;
+    }
+
+    for &i in &pending {
+        let mut file = if i < k {
+            data[i][..meta.plain_len(i)].to_vec()
+        } else {
+            parity[i - k].clone()
+        };
+        if meta.shard_trailer.is_some() {
+            append_trailer(&mut file);
+        }
+        if let Some(checksums) = &meta.checksums
+            && !checksums.matches(i, &file)
+        {
+            return Err(RseError::Corruption(format!(
+                "Rebuilt {} does not match its recorded checksum; not writing it",
+                meta.shard_file_name(i)
+            ))
+            .into());
+        }
+        write_shard_file(
+            &meta.shard_path(dir, i),
+            &file,
+            meta.volume_size,
+            &WriteOptions::default(),
+            None,
+        )
+        .await?;
+        println!("REPAIRED  {}", meta.shard_file_name(i));
+    }
+    Ok(pending)
+}
+
+/// Fails if a parity shard that was read intact differs from `parity`,
+/// re-encoded from the recovered data: the set is then inconsistent, and
+/// shards rebuilt from it could be wrong too.
+fn check_present_parity(
+    meta: &ShardMetadata,
+    present: &[Option<Vec<u8>>],
+    parity: &[Vec<u8>],
+) -> Result<()> {
+    let k = meta.data_shards;
+    let mismatched: Vec<String> = present
+        .iter()
+        .zip(parity)
+        .enumerate()
+        .filter(|(_, (stored, expected))| stored.as_ref().is_some_and(|s| s != *expected))
+        .map(|(r, _)| meta.shard_file_name(k + r))
+        .collect();
+    if !mismatched.is_empty() {
+        for name in &mismatched {
+            println!("MISMATCH  {}", name);
+        }
+        return Err(RseError::Corruption(format!(
+            "Present parity shards {} differ from the parity re-encoded from the recovered \
+             data; not repairing",
+            mismatched.join(", ")
+        ))
+        .into());
+    }
+    info!("Present parity agrees with the recovered data");
+    Ok(())
+}
+
+/// Shard files can only be rewritten as they were when no randomness or
+/// per-file state went into them.
+fn check_repairable(meta: &ShardMetadata) -> Result<()> {
+    if meta.shard_compression.is_some()
+        || meta.encryption.is_some()
+        || meta.stripe_rotation.is_some()
+        || meta.shard_log.is_some()
+    {
+        return Err(RseError::InvalidArgument(
+            "Scrub cannot rewrite compressed, encrypted, stripe-rotated or logged shards; decode \
+             and re-encode the set instead"
+                .into(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[instrument(skip(args))]
+pub async fn handle_scrub(args: Commands, execution: &Execution) -> Result<()> {
+    let Commands::Scrub {
+        input,
+        repair_below,
+        verify_parity,
+    } = args
+    else {
+        unreachable!()
+    };
+
+    let meta = ShardMetadata::read(&input).await?;
+    if repair_below.is_some() {
+        check_repairable(&meta)?;
+    }
+    info!("Scrubbing {:?}", input);
+    let report = scrub_dir(&input, &meta).await?;
+    let scan = &report.scan;
+    println!(
+        "Scrubbed {} shard files: {} healthy, {} corrupt, {} missing",
+        scan.intact.len() + scan.corrupt.len() + scan.missing.len(),
+        scan.intact.len(),
+        scan.corrupt.len(),
+        scan.missing.len()
+    );
+    if !report.elsewhere.is_empty() {
+        println!(
+            "{} shards stored elsewhere were not checked",
+            report.elsewhere.len()
+        );
+    }
+    if scan.intact.len() + report.elsewhere.len() < meta.data_shards {
+        return Err(RseError::InsufficientShards {
+            have: scan.intact.len() + report.elsewhere.len(),
+            need: meta.data_shards,
+        }
+        .into());
+    }
+
+    let lost = scan.lost();
+    match report.margin() {
+        Some(margin) if lost.is_empty() => println!(
+            "Margin: {} of {} tolerated shard losses remaining",
+            margin, report.tolerance
+        ),
+        Some(margin) => println!(
+            "Margin: {} of {} tolerated shard losses remaining - repair recommended",
+            margin, report.tolerance
+        ),
+        None => println!(
+            "Margin: {} shards lost, beyond the {} always tolerated - repair now",
+            lost.len(),
+            report.tolerance
+        ),
+    }
+    if let Some(threshold) = repair_below
+        && !lost.is_empty()
+    {
+        if report.margin().unwrap_or(0) < threshold {
+            warn!("Margin below {}; repairing shards {:?}", threshold, lost);
+            let repaired = repair_shards(&input, &meta, &lost, verify_parity, execution).await?;
+            println!("Repaired {} shards", repaired.len());
+        } else {
+            info!("Margin is at least {}; not repairing", threshold);
+        }
+    }
+    Ok(())
+}
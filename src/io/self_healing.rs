@@ -0,0 +1,476 @@
+//! Single files that carry their own parity.
+//!
+//! `encode --self-healing` writes one file instead of a shard directory:
+//!
+//! ```text
+//! header | region 0 | ... | region k+m-1 | manifest | manifest copy | header copy
+//! ```
+//!
+//! The regions are the shards of a plain set, all `region_len` bytes, so the
+//! data regions hold the input itself, zero-padded, right after the header,
+//! and the parity regions follow. The manifest is the set's metadata as JSON,
+//! including the checksum of every region. The header gives the shard
+//! counts, the region length and the manifest length, which fix the offset
+//! of everything else; it is [`HEADER_LEN`] bytes ending in its own CRC-32,
+//! and the manifest's CRC-32 is in the header. Both are stored twice, at
+//! either end of the file, so a damaged file can still be laid out as long
+//! as one copy of each survives.
+//!
+//! `repair --self` checks every region against the manifest, rebuilds the
+//! damaged ones from the others as a decode would, and rewrites the file
+//! atomically with both copies of the header and the manifest restored. A
+//! truncated or extended file is brought back to the length its header gives.
+
+use anyhow::{Context, Result};
+use futures_util::future::BoxFuture;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tracing::{info, instrument};
+
+use crate::{
+    cli::commands::Commands,
+    codec::execution::Execution,
+    error::RseError,
+    io::{
+        atomic::{WriteOptions, write_atomic},
+        checksum::ChecksumAlgo,
+        decoding::{DecodeOptions, Recovery, recover_read_shards},
+        encoding::EncodeOptions,
+        metadata::ShardMetadata,
+        split::encode_parity,
+        store::{ShardStore, encode_to_store, is_intact},
+    },
+};
+
+pub const SELF_HEALING_MAGIC: [u8; 8] = *b"RSEheal1";
+
+/// Bytes of each copy of the header: the magic, `k` and `m` as `u32`, the
+/// region and manifest lengths as `u64`, the manifest's CRC-32 and the
+/// header's own, all little-endian.
+pub const HEADER_LEN: usize = SELF_HEALING_MAGIC.len() + 4 + 4 + 8 + 8 + 4 + 4;
+
+/// Layout of a self-healing file, as its header records it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Header {
+    data_shards: usize,
+    parity_shards: usize,
+    region_len: usize,
+    manifest_len: usize,
+    manifest_crc: u32,
+}
+
+impl Header {
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(&SELF_HEALING_MAGIC);
+        bytes.extend_from_slice(&(self.data_shards as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.parity_shards as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.region_len as u64).to_le_bytes());
+        bytes.extend_from_slice(&(self.manifest_len as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.manifest_crc.to_le_bytes());
+        let crc = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// The header in `bytes`, if they are one with a matching CRC.
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let (body, crc) = bytes.get(..HEADER_LEN)?.split_at(HEADER_LEN - 4);
+        if !body.starts_with(&SELF_HEALING_MAGIC) || crc != crc32fast::hash(body).to_le_bytes() {
+            return None;
+        }
+        let field = |at: usize, len: usize| -> Option<usize> {
+            let mut le = [0u8; 8];
+            le[..len].copy_from_slice(&body[at..at + len]);
+            usize::try_from(u64::from_le_bytes(le)).ok()
+        };
+        Some(Self {
+            data_shards: field(8, 4)?,
+            parity_shards: field(12, 4)?,
+            region_len: field(16, 8)?,
+            manifest_len: field(24, 8)?,
+            manifest_crc: u32::from_le_bytes(body[32..36].try_into().ok()?),
+        })
+    }
+
+    fn total_shards(&self) -> usize {
+        self.data_shards + self.parity_shards
+    }
+
+    fn region_offset(&self, index: usize) -> usize {
+        HEADER_LEN + index * self.region_len
+    }
+
+    fn manifest_offset(&self) -> usize {
+        self.region_offset(self.total_shards())
+    }
+
+    /// Length of the whole file, or `None` if it would overflow.
+    fn file_len(&self) -> Option<usize> {
+        self.total_shards()
+            .checked_mul(self.region_len)?
+            .checked_add(self.manifest_len.checked_mul(2)?)?
+            .checked_add(2 * HEADER_LEN)
+    }
+}
+
+/// Collects the shards and metadata of an encode in memory.
+#[derive(Default)]
+struct Regions {
+    shards: Mutex<BTreeMap<usize, Vec<u8>>>,
+    meta: Mutex<Option<ShardMetadata>>,
+}
+
+impl ShardStore for Regions {
+    fn read_shard(&self, index: usize) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move { Ok(self.shards.lock().unwrap().get(&index).cloned()) })
+    }
+
+    fn write_shard<'a>(&'a self, index: usize, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            self.shards.lock().unwrap().insert(index, data.to_vec());
+            Ok(())
+        })
+    }
+
+    fn read_metadata(&self) -> BoxFuture<'_, Result<Option<ShardMetadata>>> {
+        Box::pin(async move { Ok(self.meta.lock().unwrap().clone()) })
+    }
+
+    fn write_metadata<'a>(&'a self, meta: &'a ShardMetadata) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            *self.meta.lock().unwrap() = Some(meta.clone());
+            Ok(())
+        })
+    }
+}
+
+/// Fails unless `opts` give a plain set with per-shard checksums, whose
+/// shards all have the same length and can be rebuilt byte for byte.
+pub fn check_self_healing_options(opts: &EncodeOptions) -> Result<()> {
+    let unsupported: Vec<&str> = [
+        (opts.compression.is_some(), "--compress"),
+        (opts.compress_shards.is_some(), "--compress-shards"),
+        (opts.scramble_seed.is_some(), "--scramble"),
+        (opts.encrypt_key.is_some(), "--encrypt-key"),
+        (opts.shard_trailer, "--shard-trailer"),
+        (opts.rotate_stripes.is_some(), "--rotate-stripes"),
+        (opts.shard_weights.is_some(), "--shard-weights"),
+        (opts.align.is_some(), "--align"),
+        (opts.product_code.is_some(), "--product-code"),
+        (opts.local_groups.is_some(), "--local-groups"),
+        (opts.store_only.is_some(), "--store-only"),
+        (opts.low_memory, "--low-memory"),
+        (opts.volume_size.is_some(), "--volume-size"),
+        (opts.sidecar_metadata, "--sidecar-metadata"),
+        (opts.manifest, "--manifest"),
+        (opts.shard_log, "--shard-log"),
+        (opts.interleave_parity, "--interleave-parity"),
+        (opts.verify_after_encode, "--verify-after-encode"),
+        (opts.skip_existing, "--skip-existing"),
+        (opts.stream_stripe.is_some(), "--stream-stripe"),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect();
+    if !unsupported.is_empty() {
+        return Err(RseError::InvalidArgument(format!(
+            "--self-healing cannot be combined with {}",
+            unsupported.join(", ")
+        ))
+        .into());
+    }
+    if opts.checksum_algo == ChecksumAlgo::None {
+        return Err(RseError::InvalidArgument(
+            "--self-healing checks each region against its checksum and cannot be combined \
+             with --checksum-algo none"
+                .into(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Encodes `buf` as `opts` ask into one self-healing file at `path`,
+/// returning the metadata stored in it.
+pub async fn write_self_healing(
+    buf: Vec<u8>,
+    path: &Path,
+    opts: &EncodeOptions,
+) -> Result<ShardMetadata> {
+    check_self_healing_options(opts)?;
+    let regions = Regions::default();
+    encode_to_store(buf, &regions, opts).await?;
+    let meta = regions
+        .meta
+        .into_inner()
+        .unwrap()
+        .context("The encode wrote no metadata")?;
+    let shards: Vec<Vec<u8>> = regions.shards.into_inner().unwrap().into_values().collect();
+
+    let manifest = serde_json::to_vec_pretty(&meta)?;
+    let header = Header {
+        data_shards: meta.data_shards,
+        parity_shards: meta.parity_shards,
+        region_len: meta.stored_len(0),
+        manifest_len: manifest.len(),
+        manifest_crc: crc32fast::hash(&manifest),
+    }
+    .to_bytes();
+    let regions_len: usize = shards.iter().map(Vec::len).sum();
+    let mut file = Vec::with_capacity(2 * (header.len() + manifest.len()) + regions_len);
+    file.extend_from_slice(&header);
+    for shard in &shards {
+        file.extend_from_slice(shard);
+    }
+    file.extend_from_slice(&manifest);
+    file.extend_from_slice(&manifest);
+    file.extend_from_slice(&header);
+    info!(
+        "Writing self-healing file {:?}: {} data and {} parity regions of {} bytes",
+        path,
+        meta.data_shards,
+        meta.parity_shards,
+        meta.stored_len(0)
+    );
+    write_atomic(path, &file, &opts.write, None).await?;
+    Ok(meta)
+}
+
+/// What [`repair_self_healing`] found damaged and rewrote.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SelfRepairReport {
+    pub regions: Vec<usize>,
+    /// Damaged copies of the header and manifest: `header`, `manifest`,
+    /// `manifest copy` or `header copy`.
+    pub copies: Vec<&'static str>,
+    /// The file's length when it was not the one its header gives, i.e. it
+    /// was truncated or had bytes appended.
+    pub resized_from: Option<usize>,
+}
+
+impl SelfRepairReport {
+    pub fn is_intact(&self) -> bool {
+        self.regions.is_empty() && self.copies.is_empty() && self.resized_from.is_none()
+    }
+}
+
+/// Checks the self-healing file at `path` and rewrites it with every
+/// damaged region, header and manifest copy restored. A file that was
+/// truncated or had bytes appended is cut back or extended to its recorded
+/// length, and whatever was missing counts as damaged. The file is left
+/// untouched when it is intact, and when it cannot be repaired.
+pub async fn repair_self_healing(path: &Path, execution: &Execution) -> Result<SelfRepairReport> {
+    let mut file = fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    let mut report = SelfRepairReport::default();
+
+    // The copy at the start is where it always was. The one at the end is
+    // only trusted where its own length puts it: where the first copy says
+    // the file ends or, without a first copy, the last such spot before any
+    // appended bytes.
+    let first = Header::parse(&file).filter(|h| h.file_len().is_some());
+    let copy_ending_at = |end: usize| {
+        let at = end.checked_sub(HEADER_LEN)?;
+        Header::parse(file.get(at..)?).filter(|h| h.file_len() == Some(end))
+    };
+    let last = match first.and_then(|h| h.file_len()) {
+        Some(end) => copy_ending_at(end),
+        None => (HEADER_LEN..=file.len())
+            .rev()
+            .filter(|&end| file[end - HEADER_LEN..].starts_with(&SELF_HEALING_MAGIC))
+            .find_map(copy_ending_at),
+    };
+    let header = first.or(last).ok_or_else(|| {
+        RseError::Corruption(format!(
+            "{:?} has no intact header; it is not a self-healing file, or both copies of its \
+             header are damaged",
+            path
+        ))
+    })?;
+    if first != Some(header) {
+        report.copies.push("header");
+    }
+    if last != Some(header) {
+        report.copies.push("header copy");
+    }
+    // Checked above, for whichever copy was chosen.
+    let file_len = header
+        .file_len()
+        .expect("the header's length fits in usize");
+    // Bytes that were read; anything past them is lost.
+    let read_len = file.len();
+    if read_len != file_len {
+        report.resized_from = Some(read_len);
+        file.resize(file_len, 0);
+    }
+
+    let (at, len) = (header.manifest_offset(), header.manifest_len);
+    let copies = [&file[at..at + len], &file[at + len..at + 2 * len]];
+    let intact: Vec<bool> = [at, at + len]
+        .into_iter()
+        .zip(copies)
+        .map(|(start, copy)| {
+            start + len <= read_len && crc32fast::hash(copy) == header.manifest_crc
+        })
+        .collect();
+    let manifest = match intact.iter().position(|&ok| ok) {
+        Some(i) => copies[i].to_vec(),
+        None => {
+            return Err(RseError::Corruption(format!(
+                "Both copies of the manifest in {:?} are damaged",
+                path
+            ))
+            .into());
+        }
+    };
+    for (ok, name) in intact.into_iter().zip(["manifest", "manifest copy"]) {
+        if !ok {
+            report.copies.push(name);
+        }
+    }
+    let meta: ShardMetadata = serde_json::from_slice(&manifest)
+        .with_context(|| format!("Invalid manifest in {:?}", path))?;
+    meta.validate()?;
+    if meta.data_shards != header.data_shards
+        || meta.parity_shards != header.parity_shards
+        || (0..meta.total_shards()).any(|i| meta.stored_len(i) != header.region_len)
+    {
+        return Err(RseError::Corruption(format!(
+            "The manifest in {:?} does not describe the regions its header lays out",
+            path
+        ))
+        .into());
+    }
+
+    let n = header.total_shards();
+    let region = |i: usize| header.region_offset(i)..header.region_offset(i + 1);
+    let shards_opt: Vec<Option<Vec<u8>>> = (0..n)
+        .map(|i| {
+            let range = region(i);
+            let data = &file[range.clone()];
+            (range.end <= read_len && is_intact(&meta, i, data)).then(|| data.to_vec())
+        })
+        .collect();
+    report.regions = (0..n).filter(|&i| shards_opt[i].is_none()).collect();
+    if report.is_intact() {
+        return Ok(report);
+    }
+
+    if !report.regions.is_empty() {
+        let k = meta.data_shards;
+        let mut shards = match recover_read_shards(
+            meta.clone(),
+            shards_opt,
+            &DecodeOptions {
+                execution: execution.clone(),
+                ..Default::default()
+            },
+            Duration::ZERO,
+        )
+        .await?
+        {
+            Recovery::Shards(_, shards, ..) => shards,
+            Recovery::Partial(_) => unreachable!("partial recovery was not requested"),
+        };
+        shards.truncate(k);
+        let data: Vec<Vec<u8>> = shards
+            .into_iter()
+            .map(|s| s.expect("every data shard is recovered"))
+            .collect();
+        let parity = if report.regions.iter().any(|&i| i >= k) {
+            encode_parity(&meta, &data, execution)?
+        } else {
+            Vec::new()
+        };
+        for &i in &report.regions {
+            let rebuilt = if i < k { &data[i] } else { &parity[i - k] };
+            if !is_intact(&meta, i, rebuilt) {
+                return Err(RseError::Corruption(format!(
+                    "Rebuilt region {} of {:?} does not match its recorded checksum; not \
+                     writing it",
+                    i, path
+                ))
+                .into());
+            }
+            file[region(i)].copy_from_slice(rebuilt);
+        }
+    }
+
+    let header_bytes = header.to_bytes();
+    let end = file.len() - HEADER_LEN;
+    file[..HEADER_LEN].copy_from_slice(&header_bytes);
+    file[end..].copy_from_slice(&header_bytes);
+    file[at..at + len].copy_from_slice(&manifest);
+    file[at + len..at + 2 * len].copy_from_slice(&manifest);
+    write_atomic(path, &file, &WriteOptions::default(), None).await?;
+    Ok(report)
+}
+
+/// Whether the file at `path` begins with [`SELF_HEALING_MAGIC`].
+async fn starts_with_magic(path: &Path) -> Result<bool> {
+    let mut file = fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {:?}", path))?;
+    let mut magic = [0u8; SELF_HEALING_MAGIC.len()];
+    match file.read_exact(&mut magic).await {
+        Ok(_) => Ok(magic == SELF_HEALING_MAGIC),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {:?}", path)),
+    }
+}
+
+#[instrument(skip(args))]
+pub async fn handle_repair(args: Commands, execution: &Execution) -> Result<()> {
+    let Commands::Repair {
+        input,
+        self_healing,
+    } = args
+    else {
+        unreachable!()
+    };
+
+    if fs::metadata(&input).await.is_ok_and(|m| m.is_dir()) {
+        return Err(RseError::InvalidArgument(format!(
+            "{:?} is a shard directory; repair those with `scrub --repair-below`",
+            input
+        ))
+        .into());
+    }
+    if !self_healing && !starts_with_magic(&input).await? {
+        return Err(RseError::InvalidArgument(format!(
+            "{:?} does not start with a self-healing header; pass --self to repair it from \
+             the header copy at its end",
+            input
+        ))
+        .into());
+    }
+
+    info!("Checking self-healing file {:?}", input);
+    let report = repair_self_healing(&input, execution).await?;
+    if report.is_intact() {
+        println!("{} is intact", input.display());
+        return Ok(());
+    }
+    if let Some(len) = report.resized_from {
+        println!("REPAIRED  file length (was {} bytes)", len);
+    }
+    for copy in &report.copies {
+        println!("REPAIRED  {}", copy);
+    }
+    for i in &report.regions {
+        println!("REPAIRED  region {}", i);
+    }
+    println!(
+        "Repaired {} regions and {} header or manifest copies of {}",
+        report.regions.len(),
+        report.copies.len(),
+        input.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,415 @@
+//! `serve`: an HTTP front end for embedding the codec in a long-running
+//! service. One [`Codec`] is kept warm for the life of the process, so the
+//! inverse cache fills up across requests instead of starting empty on every
+//! decode.
+//!
+//! Shard sets live in memory and are addressed by the id returned on upload.
+//! At most [`ServeOptions::max_sets`] are kept; storing one more evicts the
+//! oldest. Request bodies are capped at [`ServeOptions::max_body_bytes`].
+//!
+//! | Method   | Path                          | Effect                                   |
+//! |----------|-------------------------------|------------------------------------------|
+//! | `GET`    | `/health`                     | `ok`                                     |
+//! | `GET`    | `/stats`                      | [`StatsResponse`]                        |
+//! | `POST`   | `/decode?orig_len=&missing=`  | decode the shards in the body            |
+//! | `POST`   | `/sets`                       | encode the body, [`SetResponse`]         |
+//! | `GET`    | `/sets/{id}`                  | reconstruct and return the original blob |
+//! | `GET`    | `/sets/{id}/shards/{index}`   | raw shard bytes                          |
+//! | `PUT`    | `/sets/{id}/shards/{index}`   | replace a shard, [`SetResponse`]         |
+//! | `DELETE` | `/sets/{id}/shards/{index}`   | drop a shard, [`SetResponse`]            |
+
+use anyhow::{Context, Result};
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+use tokio::net::TcpListener;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    cli::commands::Commands,
+    codec::{execution::Execution, reconstruct_shards::Codec},
+    error::RseError,
+    io::decoding::assemble_data_shards,
+};
+
+/// A shard set held by the server.
+struct StoredSet {
+    orig_len: usize,
+    shards: Vec<Option<Vec<u8>>>,
+}
+
+impl StoredSet {
+    fn shard_len(&self, k: usize) -> usize {
+        self.orig_len.div_ceil(k)
+    }
+}
+
+/// Limits and execution mode of a server.
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Largest request body accepted; larger ones get 413.
+    pub max_body_bytes: usize,
+    /// Most shard sets held at once.
+    pub max_sets: usize,
+    pub execution: Execution,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            max_body_bytes: 64 * 1024 * 1024,
+            max_sets: 1024,
+            execution: Execution::default(),
+        }
+    }
+}
+
+pub struct ServerState {
+    codec: Arc<Codec>,
+    /// Keyed by id, which grows with every upload, so the first entry is the
+    /// oldest set.
+    sets: Mutex<BTreeMap<u64, StoredSet>>,
+    next_id: AtomicU64,
+    opts: ServeOptions,
+}
+
+impl ServerState {
+    pub fn new(k: usize, m: usize) -> Result<Self> {
+        Self::with_options(k, m, ServeOptions::default())
+    }
+
+    pub fn with_options(k: usize, m: usize, opts: ServeOptions) -> Result<Self> {
+        if opts.max_sets == 0 {
+            return Err(RseError::InvalidArgument("--max-sets must be at least 1".into()).into());
+        }
+        Ok(Self {
+            codec: Arc::new(Codec::try_new(k, m)?.with_execution(opts.execution.clone())),
+            sets: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(1),
+            opts,
+        })
+    }
+
+    /// Stores `set` under a new id, evicting the oldest sets beyond the cap.
+    fn insert(&self, set: StoredSet) -> SetResponse {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let response = self.describe(id, &set);
+        let mut sets = self.sets.lock().unwrap();
+        while sets.len() >= self.opts.max_sets {
+            if let Some((evicted, _)) = sets.pop_first() {
+                warn!("Evicted shard set {} to stay within --max-sets", evicted);
+            }
+        }
+        sets.insert(id, set);
+        response
+    }
+
+    fn describe(&self, id: u64, set: &StoredSet) -> SetResponse {
+        let (present, missing) = (0..set.shards.len()).partition(|&i| set.shards[i].is_some());
+        SetResponse {
+            id,
+            orig_len: set.orig_len,
+            data_shards: self.codec.data_shards(),
+            parity_shards: self.codec.parity_shards(),
+            present,
+            missing,
+        }
+    }
+
+    /// Runs `f` on set `id` under the lock.
+    fn with_set<T>(&self, id: u64, f: impl FnOnce(&mut StoredSet) -> ApiResult<T>) -> ApiResult<T> {
+        let mut sets = self.sets.lock().unwrap();
+        let set = sets
+            .get_mut(&id)
+            .ok_or_else(|| ApiError::not_found(format!("No shard set {}", id)))?;
+        f(set)
+    }
+}
+
+/// Returned for every request that creates or modifies a set.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SetResponse {
+    pub id: u64,
+    pub orig_len: usize,
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub present: Vec<usize>,
+    pub missing: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatsResponse {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    pub sets: usize,
+    /// Survivor sets whose inverse is cached.
+    pub cached_inverses: usize,
+    pub mac_passes: usize,
+}
+
+/// An error response: `{"error": "..."}` with a status derived from the
+/// [`RseError`] kind, if any.
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn not_found(what: impl Into<String>) -> Self {
+        Self(StatusCode::NOT_FOUND, what.into())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        let status = match err.chain().find_map(|c| c.downcast_ref::<RseError>()) {
+            Some(RseError::InsufficientShards { .. }) => StatusCode::CONFLICT,
+            Some(RseError::InvalidArgument(_)) => StatusCode::BAD_REQUEST,
+            Some(RseError::Corruption(_)) | None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self(status, format!("{:#}", err))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = Json(serde_json::json!({ "error": self.1 }));
+        (self.0, body).into_response()
+    }
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+pub fn router(state: Arc<ServerState>) -> Router {
+    let max_body_bytes = state.opts.max_body_bytes;
+    Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .route("/stats", get(stats))
+        .route("/decode", post(decode))
+        .route("/sets", post(upload))
+        .route("/sets/{id}", get(download))
+        .route(
+            "/sets/{id}/shards/{index}",
+            get(get_shard).put(put_shard).delete(delete_shard),
+        )
+        .layer(DefaultBodyLimit::max(max_body_bytes))
+        .with_state(state)
+}
+
+#[instrument(skip(args))]
+pub async fn handle_serve(args: Commands, execution: &Execution) -> Result<()> {
+    let Commands::Serve {
+        addr,
+        data_shards: k,
+        parity_shards: m,
+        max_body_bytes,
+        max_sets,
+    } = args
+    else {
+        unreachable!()
+    };
+
+    let opts = ServeOptions {
+        max_body_bytes,
+        max_sets,
+        execution: execution.clone(),
+    };
+    let state = Arc::new(ServerState::with_options(k, m, opts)?);
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    info!("Serving k={} m={} on http://{}", k, m, addr);
+    axum::serve(listener, router(state))
+        .await
+        .context("HTTP server failed")
+}
+
+fn check_index(set: &StoredSet, index: usize) -> ApiResult<()> {
+    if index >= set.shards.len() {
+        return Err(ApiError::not_found(format!(
+            "Shard index {} is out of range for {} shards",
+            index,
+            set.shards.len()
+        )));
+    }
+    Ok(())
+}
+
+async fn stats(State(state): State<Arc<ServerState>>) -> Json<StatsResponse> {
+    let codec = &state.codec;
+    Json(StatsResponse {
+        data_shards: codec.data_shards(),
+        parity_shards: codec.parity_shards(),
+        sets: state.sets.lock().unwrap().len(),
+        cached_inverses: codec.cached_inverses(),
+        mac_passes: codec.mac_passes(),
+    })
+}
+
+async fn upload(
+    State(state): State<Arc<ServerState>>,
+    body: Bytes,
+) -> ApiResult<Json<SetResponse>> {
+    let codec = state.codec.clone();
+    let orig_len = body.len();
+    let shards = tokio::task::spawn_blocking(move || {
+        let k = codec.data_shards();
+        let shard_len = orig_len.div_ceil(k);
+        let mut data_shards = vec![vec![0u8; shard_len]; k];
+        for (shard, chunk) in data_shards.iter_mut().zip(body.chunks(shard_len.max(1))) {
+            shard[..chunk.len()].copy_from_slice(chunk);
+        }
+        let parities = codec.encode_with_matrix(&data_shards, codec.encode_matrix())?;
+        Ok::<_, anyhow::Error>(data_shards.into_iter().chain(parities).map(Some).collect())
+    })
+    .await
+    .context("Encode task panicked")??;
+
+    let response = state.insert(StoredSet { orig_len, shards });
+    info!("Stored shard set {} ({} bytes)", response.id, orig_len);
+    Ok(Json(response))
+}
+
+/// Query of `POST /decode`.
+#[derive(Debug, Deserialize)]
+struct DecodeQuery {
+    orig_len: usize,
+    /// Comma-separated indices of the shards left out of the body.
+    #[serde(default)]
+    missing: String,
+}
+
+/// Decodes a shard set sent whole, without storing it: the body holds every
+/// shard not listed in `missing`, in index order, each
+/// `ceil(orig_len / data_shards)` bytes.
+async fn decode(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<DecodeQuery>,
+    body: Bytes,
+) -> ApiResult<Vec<u8>> {
+    let codec = state.codec.clone();
+    let (k, n) = (codec.data_shards(), codec.total_shards());
+    let bad_request = |msg: String| ApiError(StatusCode::BAD_REQUEST, msg);
+    let mut missing = Vec::new();
+    for index in query.missing.split(',').filter(|s| !s.is_empty()) {
+        let index: usize = index
+            .trim()
+            .parse()
+            .map_err(|_| bad_request(format!("Invalid shard index {:?}", index)))?;
+        if index >= n || missing.contains(&index) {
+            return Err(bad_request(format!(
+                "Missing shard index {} is repeated or out of range for {} shards",
+                index, n
+            )));
+        }
+        missing.push(index);
+    }
+    let orig_len = query.orig_len;
+    let shard_len = orig_len.div_ceil(k);
+    let expected = (n - missing.len()) * shard_len;
+    if body.len() != expected {
+        return Err(bad_request(format!(
+            "Expected {} shards of {} bytes ({} bytes), got {} bytes",
+            n - missing.len(),
+            shard_len,
+            expected,
+            body.len()
+        )));
+    }
+
+    let data = tokio::task::spawn_blocking(move || {
+        let mut offset = 0;
+        let mut shards: Vec<Option<Vec<u8>>> = (0..n)
+            .map(|i| {
+                (!missing.contains(&i)).then(|| {
+                    offset += shard_len;
+                    body[offset - shard_len..offset].to_vec()
+                })
+            })
+            .collect();
+        let report = codec.reconstruct_data(&mut shards)?;
+        info!("Decode request: reconstruction {}", report);
+        assemble_data_shards(&shards[..k], orig_len, shard_len, &ProgressBar::hidden())
+    })
+    .await
+    .context("Reconstruction task panicked")??;
+    Ok(data)
+}
+
+async fn download(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<u64>,
+) -> ApiResult<Vec<u8>> {
+    let codec = state.codec.clone();
+    let (orig_len, shard_len, mut shards) = state.with_set(id, |set| {
+        Ok((
+            set.orig_len,
+            set.shard_len(codec.data_shards()),
+            set.shards.clone(),
+        ))
+    })?;
+    let data = tokio::task::spawn_blocking(move || {
+        let report = codec.reconstruct_data(&mut shards)?;
+        info!("Set {}: reconstruction {}", id, report);
+        assemble_data_shards(
+            &shards[..codec.data_shards()],
+            orig_len,
+            shard_len,
+            &ProgressBar::hidden(),
+        )
+    })
+    .await
+    .context("Reconstruction task panicked")??;
+    Ok(data)
+}
+
+async fn get_shard(
+    State(state): State<Arc<ServerState>>,
+    Path((id, index)): Path<(u64, usize)>,
+) -> ApiResult<Vec<u8>> {
+    state.with_set(id, |set| {
+        check_index(set, index)?;
+        set.shards[index]
+            .clone()
+            .ok_or_else(|| ApiError::not_found(format!("Shard {} of set {} is missing", index, id)))
+    })
+}
+
+async fn put_shard(
+    State(state): State<Arc<ServerState>>,
+    Path((id, index)): Path<(u64, usize)>,
+    body: Bytes,
+) -> ApiResult<Json<SetResponse>> {
+    state.with_set(id, |set| {
+        check_index(set, index)?;
+        let shard_len = set.shard_len(state.codec.data_shards());
+        if body.len() != shard_len {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                format!("Shard must be {} bytes, got {}", shard_len, body.len()),
+            ));
+        }
+        set.shards[index] = Some(body.to_vec());
+        Ok(Json(state.describe(id, set)))
+    })
+}
+
+async fn delete_shard(
+    State(state): State<Arc<ServerState>>,
+    Path((id, index)): Path<(u64, usize)>,
+) -> ApiResult<Json<SetResponse>> {
+    state.with_set(id, |set| {
+        check_index(set, index)?;
+        set.shards[index] = None;
+        Ok(Json(state.describe(id, set)))
+    })
+}
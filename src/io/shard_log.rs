@@ -0,0 +1,131 @@
+//! Shards appended to a single log file.
+//!
+//! With `--shard-log`, encode appends every shard to `shards.log` in the
+//! output directory, one after another, instead of writing a file per shard,
+//! and `meta.json` records where in the log each shard lies. The log is only
+//! ever appended to: encoding into a directory that already has one adds the
+//! new shards after the old bytes, which then go unused. This suits
+//! append-only media and object stores that prefer one large sequential
+//! write. A log cut short loses the shards past its end, and decode rebuilds
+//! them like any other missing shard.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{self, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::io::{
+    atomic::WriteOptions,
+    throttle::{RateLimiter, write_throttled},
+};
+
+/// Name of the log file in the set's directory.
+pub const SHARD_LOG_FILE: &str = "shards.log";
+
+/// Path of the shard log of the set in `dir`.
+pub fn shard_log_path(dir: &Path) -> PathBuf {
+    dir.join(SHARD_LOG_FILE)
+}
+
+/// Where one shard lies in the log. Its checksum is the set's, in
+/// [`crate::io::metadata::ShardMetadata::checksums`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardLogEntry {
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl ShardLogEntry {
+    /// Bytes of this shard present in a log of `log_len` bytes, or `None` if
+    /// the log ends before the shard starts.
+    pub fn present_len(&self, log_len: u64) -> Option<u64> {
+        (self.offset < log_len || (self.len == 0 && self.offset == log_len))
+            .then(|| self.len.min(log_len - self.offset))
+    }
+}
+
+/// Appends shards to a log, handing out the entry of each.
+pub struct ShardLogWriter {
+    file: File,
+    path: PathBuf,
+    offset: u64,
+    fsync: bool,
+}
+
+impl ShardLogWriter {
+    /// Opens the log at `path` for appending, creating it if needed.
+    pub async fn open(path: &Path, opts: &WriteOptions) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("Failed to open shard log {:?}", path))?;
+        let offset = file.metadata().await?.len();
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+            offset,
+            fsync: opts.fsync,
+        })
+    }
+
+    /// Appends `data` and returns where it went.
+    pub async fn append(
+        &mut self,
+        data: &[u8],
+        limiter: Option<&RateLimiter>,
+    ) -> Result<ShardLogEntry> {
+        match limiter {
+            Some(limiter) => write_throttled(&mut self.file, data, limiter).await?,
+            None => self.file.write_all(data).await?,
+        }
+        let entry = ShardLogEntry {
+            offset: self.offset,
+            len: data.len() as u64,
+        };
+        self.offset += entry.len;
+        Ok(entry)
+    }
+
+    /// Flushes the log, and syncs it to stable storage if asked to.
+    pub async fn finish(mut self) -> Result<()> {
+        self.file.flush().await?;
+        if self.fsync {
+            self.file
+                .sync_all()
+                .await
+                .with_context(|| format!("Failed to sync {:?}", self.path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the shard at `entry` from the log at `log_path`. A log that ends
+/// inside the shard gives the bytes that are there, like a truncated shard
+/// file; one that ends before it fails with [`ErrorKind::NotFound`], like a
+/// missing shard file.
+pub async fn read_log_shard(log_path: &Path, entry: &ShardLogEntry) -> io::Result<Vec<u8>> {
+    let mut file = File::open(log_path).await?;
+    let log_len = file.metadata().await?.len();
+    let len = entry.present_len(log_len).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "{:?} ends at byte {}, before the shard at {}",
+                log_path, log_len, entry.offset
+            ),
+        )
+    })?;
+    file.seek(SeekFrom::Start(entry.offset)).await?;
+    let mut data = vec![0u8; len as usize];
+    file.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+/// Size of the log at `log_path`.
+pub async fn log_len(log_path: &Path) -> io::Result<u64> {
+    Ok(fs::metadata(log_path).await?.len())
+}
@@ -0,0 +1,114 @@
+//! Per-shard metadata kept next to each shard file.
+//!
+//! For large sets, `meta.json` can hold just the set-wide description while
+//! each shard's checksum lives in a small `shard_XX.meta` sidecar beside
+//! `shard_XX.dat`. A sidecar travels with its shard, so a directory holding
+//! only some shards (see `--store-only`) or a fallback copy carries exactly
+//! the metadata it needs. Decode reads each sidecar together with its shard;
+//! `--block-size`, `--verify-checksums-only` and the other commands load all
+//! of them up front with the metadata.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tracing::warn;
+
+use crate::{
+    error::RseError,
+    io::{
+        atomic::{WriteOptions, write_atomic},
+        metadata::FormatFingerprint,
+    },
+};
+
+/// Extension of a sidecar file, replacing the shard file's `.dat`.
+pub const SIDECAR_EXTENSION: &str = "meta";
+
+/// Path of the sidecar belonging to the shard file at `shard_path`.
+pub fn sidecar_path(shard_path: &Path) -> PathBuf {
+    shard_path.with_extension(SIDECAR_EXTENSION)
+}
+
+/// Contents of one sidecar file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardSidecar {
+    /// Logical shard index, so a sidecar renamed along with a reordered
+    /// shard file is not trusted for the wrong shard.
+    pub shard: usize,
+    /// Size of the shard file on disk. A sidecar whose size disagrees with
+    /// the set's is not trusted.
+    pub size: usize,
+    /// Checksum of the shard file, with the set's algorithm.
+    pub checksum: String,
+    /// Encoding the shard was made with, so a copy from another encoding is
+    /// caught even where the checksum vouches for it. Absent in sidecars
+    /// written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<FormatFingerprint>,
+}
+
+impl ShardSidecar {
+    /// Reads the sidecar of the shard file at `shard_path`, or `None` if
+    /// there is none.
+    pub async fn read(shard_path: &Path) -> Result<Option<Self>> {
+        let path = sidecar_path(shard_path);
+        let raw = match fs::read_to_string(&path).await {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        let sidecar =
+            serde_json::from_str(&raw).with_context(|| format!("Invalid sidecar {:?}", path))?;
+        Ok(Some(sidecar))
+    }
+
+    /// Writes the sidecar of the shard file at `shard_path` atomically.
+    pub async fn write(&self, shard_path: &Path, opts: &WriteOptions) -> Result<()> {
+        let path = sidecar_path(shard_path);
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomic(&path, json.as_bytes(), opts, None)
+            .await
+            .with_context(|| format!("Failed to write {:?}", path))
+    }
+}
+
+/// Checksum of shard `index` from the first of `shard_paths` (copies of the
+/// shard, most preferred first) whose sidecar exists, is readable and
+/// describes that shard at `size` bytes. `None` if no copy has one. A
+/// sidecar recording an encoding other than `format` is an error: its shard
+/// must not be combined with the set's others.
+pub async fn read_sidecar_checksum(
+    shard_paths: &[PathBuf],
+    index: usize,
+    size: usize,
+    format: &FormatFingerprint,
+) -> Result<Option<String>> {
+    for shard_path in shard_paths {
+        match ShardSidecar::read(shard_path).await {
+            Ok(Some(sidecar)) if sidecar.format.as_ref().is_some_and(|f| f != format) => {
+                return Err(RseError::Corruption(format!(
+                    "{:?} was encoded with a {}, but the set with a {}; shards from different \
+                     encodings cannot be combined",
+                    shard_path,
+                    sidecar.format.unwrap(),
+                    format
+                ))
+                .into());
+            }
+            Ok(Some(sidecar)) if sidecar.shard != index => warn!(
+                "Sidecar of {:?} describes shard {}, not {}; ignoring it",
+                shard_path, sidecar.shard, index
+            ),
+            Ok(Some(sidecar)) if sidecar.size != size => warn!(
+                "Sidecar of {:?} records {} bytes, but the set's shard is {}; ignoring it",
+                shard_path, sidecar.size, size
+            ),
+            Ok(Some(sidecar)) => return Ok(Some(sidecar.checksum)),
+            Ok(None) => {}
+            Err(e) => warn!("{:#}; ignoring it", e),
+        }
+    }
+    Ok(None)
+}
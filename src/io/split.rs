@@ -0,0 +1,123 @@
+//! Writing the recovered shards of a set as separate files instead of
+//! reassembling the input, to inspect them or feed them to another tool.
+//!
+//! Shards are written as the codec sees them: decrypted, decompressed and
+//! un-rotated, but still padded to the full shard length. Input compression
+//! and scrambling are not undone. `split.json` records `orig_len`, so
+//! concatenating the data shards and truncating to it gives back what was
+//! encoded.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+use tracing::info;
+
+use crate::{
+    codec::{execution::Execution, lrc::LrcCodec, product::ProductCodec},
+    error::RseError,
+    io::{
+        atomic::{WriteOptions, write_atomic},
+        decoding::{DecodeOptions, Recovery, recover_data_shards},
+        metadata::{ShardMetadata, shard_file_name},
+    },
+};
+
+pub const SPLIT_FILE: &str = "split.json";
+
+/// Contents of `split.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitInfo {
+    /// Bytes of the concatenated data shards that belong to the input.
+    pub orig_len: usize,
+    pub data_shards: usize,
+    /// Parity shard files written; zero unless parity was requested.
+    pub parity_shards: usize,
+    pub shard_len: usize,
+    /// Logical length of each data shard for an unevenly split input. Each
+    /// data shard file is still `shard_len` bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_shard_lens: Option<Vec<usize>>,
+    /// Bytes per data shard of each stripe for an input encoded as a stream:
+    /// the input is then the data shards' pieces of each stripe in turn.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_stripe: Option<usize>,
+}
+
+/// Recomputes every parity shard of the set from its data shards.
+pub(crate) fn encode_parity(
+    meta: &ShardMetadata,
+    data: &[Vec<u8>],
+    execution: &Execution,
+) -> Result<Vec<Vec<u8>>> {
+    let (k, m) = (meta.data_shards, meta.parity_shards);
+    let execution = execution.clone();
+    if let Some(geometry) = meta.product_code {
+        ProductCodec::new(geometry)?
+            .with_execution(execution)
+            .encode(data)
+    } else if let Some(groups) = meta.local_groups {
+        LrcCodec::new(k, m - groups, groups)?
+            .with_execution(execution)
+            .encode(data)
+    } else {
+        meta.codec()?.with_execution(execution).encode(data)
+    }
+}
+
+/// Recovers the set in `shard_dir` and writes its data shards, and with
+/// `with_parity` its parity shards, to `out_dir` as `shard_{i}.dat`.
+pub async fn decode_split(
+    shard_dir: &Path,
+    opts: &DecodeOptions,
+    out_dir: &Path,
+    with_parity: bool,
+) -> Result<SplitInfo> {
+    if opts.partial_ok {
+        return Err(RseError::InvalidArgument(
+            "--split-output cannot be combined with --partial-ok".into(),
+        )
+        .into());
+    }
+    let (meta, shards) = match recover_data_shards(shard_dir, opts).await? {
+        Recovery::Shards(meta, shards, ..) => (*meta, shards),
+        Recovery::Partial(_) => unreachable!("partial output was rejected above"),
+    };
+    let k = meta.data_shards;
+    let data: Vec<Vec<u8>> = shards
+        .into_iter()
+        .take(k)
+        .map(|s| s.expect("every data shard is recovered"))
+        .collect();
+    let parity = if with_parity {
+        encode_parity(&meta, &data, &opts.execution)?
+    } else {
+        Vec::new()
+    };
+
+    fs::create_dir_all(out_dir)
+        .await
+        .with_context(|| format!("Failed to create split output directory: {:?}", out_dir))?;
+    let write = WriteOptions::default();
+    for (i, shard) in data.iter().chain(&parity).enumerate() {
+        write_atomic(&out_dir.join(shard_file_name(i)), shard, &write, None).await?;
+    }
+    let split = SplitInfo {
+        orig_len: meta.orig_len,
+        data_shards: k,
+        parity_shards: parity.len(),
+        shard_len: meta.shard_len(),
+        data_shard_lens: meta.data_shard_lens.clone(),
+        stream_stripe: meta.stream_stripe,
+    };
+    // `split.json` goes last, so its presence means every shard is complete.
+    let json = serde_json::to_vec_pretty(&split)?;
+    write_atomic(&out_dir.join(SPLIT_FILE), &json, &write, None).await?;
+    info!(
+        "Wrote {} data and {} parity shards to {:?}",
+        k,
+        parity.len(),
+        out_dir
+    );
+    Ok(split)
+}
@@ -0,0 +1,48 @@
+//! Throughput figures for the final log line of an encode or decode.
+
+use std::time::Duration;
+use tracing::info;
+
+/// Time spent in each phase of a run. Phases that overlap, e.g. parity
+/// written as it is computed with `--low-memory`, are counted in the later
+/// one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTimes {
+    pub read: Duration,
+    pub compute: Duration,
+    pub write: Duration,
+}
+
+impl PhaseTimes {
+    pub fn total(&self) -> Duration {
+        self.read + self.compute + self.write
+    }
+}
+
+/// `bytes` over `elapsed` in MiB/s, or 0 if no time was measured.
+pub fn mib_per_sec(bytes: usize, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    bytes as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+}
+
+/// Logs `message` with the overall throughput of `bytes` in `overall` and
+/// that of each phase as fields, so `--log-format json` carries them too.
+pub fn log_throughput(message: &str, bytes: usize, overall: Duration, times: &PhaseTimes) {
+    let overall_rate = mib_per_sec(bytes, overall);
+    info!(
+        bytes,
+        mib_per_sec = overall_rate,
+        read_mib_per_sec = mib_per_sec(bytes, times.read),
+        compute_mib_per_sec = mib_per_sec(bytes, times.compute),
+        write_mib_per_sec = mib_per_sec(bytes, times.write),
+        "{} ({} bytes, {:.1} MiB/s; read {:.2?}, compute {:.2?}, write {:.2?})",
+        message,
+        bytes,
+        overall_rate,
+        times.read,
+        times.compute,
+        times.write
+    );
+}
@@ -0,0 +1,394 @@
+//! Pluggable storage for a shard set.
+//!
+//! [`ShardStore`] is the extension point for keeping shards somewhere other
+//! than a directory, e.g. an object store or a database: implement its four
+//! methods and pass it to [`encode_to_store`] and [`decode_from_store`].
+//! Shards are addressed by their logical index and the metadata is handed
+//! over as a whole; how either is laid out is up to the store.
+//!
+//! `encode` writes its directory through a [`FilesystemStore`], and
+//! [`encode_to_store`] runs the same pipeline against any store. The
+//! directory layout lives in the [`FilesystemStore`]: volumes, interleaved
+//! parity, sidecars, crash-safe writes and removing the shards of a set that
+//! failed to be written. Decoding shares everything after the shards are
+//! read; `decode` reads a directory itself, since it also looks in fallback
+//! directories and waits for shards still being written.
+//!
+//! Options that only describe a directory (volumes, sidecars, manifests,
+//! shard logs, interleaved parity, verifying the written files) and those
+//! that read more than one place (fallback directories, waiting for shards)
+//! are refused with any other store.
+//!
+//! A store is typically remote, so [`decode_from_store`] does not wait for
+//! every shard: it fetches them concurrently and starts reconstructing as
+//! soon as enough intact ones have arrived, dropping the fetches still in
+//! flight (see [`prefetch_shards`]).
+
+use anyhow::{Context, Result, anyhow};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio::fs;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::{
+    codec::{lrc::LrcCodec, product::ProductCodec, reconstruct_shards::Codec},
+    error::RseError,
+    io::{
+        atomic::WriteOptions,
+        budget::shard_concurrency,
+        decoding::{DecodeOptions, Recovery, assemble_output, recover_read_shards},
+        encoding::{EncodeOptions, ParityEncoder, encode_into_store},
+        metadata::{ShardMetadata, shard_path},
+        rotation::device_for,
+        throttle::RateLimiter,
+        trailer::check_trailer,
+        volumes::{remove_shard_file, write_shard_file},
+    },
+};
+
+/// Somewhere to keep the shards and metadata of one set.
+///
+/// The methods return boxed futures so the trait can be used as
+/// `&dyn ShardStore`.
+pub trait ShardStore: Send + Sync {
+    /// Shard `index` as stored, or `None` if the store does not have it.
+    fn read_shard(&self, index: usize) -> BoxFuture<'_, Result<Option<Vec<u8>>>>;
+
+    /// Stores shard `index`, replacing any earlier copy.
+    fn write_shard<'a>(&'a self, index: usize, data: &'a [u8]) -> BoxFuture<'a, Result<()>>;
+
+    /// The set's metadata, or `None` if none has been written.
+    fn read_metadata(&self) -> BoxFuture<'_, Result<Option<ShardMetadata>>>;
+
+    /// Stores the set's metadata. It is written after every shard, so a set
+    /// only looks complete once all of them are in place.
+    fn write_metadata<'a>(&'a self, meta: &'a ShardMetadata) -> BoxFuture<'a, Result<()>>;
+
+    /// Called with the metadata of a set before any of its shards is
+    /// written, for a store that places shards by the layout it records.
+    /// Its checksums are not filled in yet. Does nothing by default.
+    fn begin_set<'a>(&'a self, _meta: &'a ShardMetadata) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Removes shard `index`, to clean up after a set could not be written
+    /// in full. Unsupported by default.
+    fn remove_shard(&self, index: usize) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move { Err(anyhow!("The store cannot remove shard {}", index)) })
+    }
+}
+
+/// Shards and metadata in a directory, as `encode` writes them.
+pub struct FilesystemStore {
+    dir: PathBuf,
+    write: WriteOptions,
+    limiter: Option<Arc<RateLimiter>>,
+    /// The metadata last begun, read or written, which says where each shard
+    /// of a set with volumes, a shard log or interleaved parity lies.
+    meta: RwLock<Option<Arc<ShardMetadata>>>,
+}
+
+impl FilesystemStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self::with_write_options(dir, WriteOptions::default())
+    }
+
+    pub fn with_write_options(dir: impl Into<PathBuf>, write: WriteOptions) -> Self {
+        Self {
+            dir: dir.into(),
+            write,
+            limiter: None,
+            meta: RwLock::new(None),
+        }
+    }
+
+    /// Throttles shard writes with `limiter`, if given.
+    pub fn with_rate_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Where shard `index` goes and the volume size it is split by.
+    fn shard_location(&self, index: usize) -> (PathBuf, Option<usize>) {
+        match self.meta.read().unwrap().as_deref() {
+            Some(meta) => (meta.shard_path(&self.dir, index), meta.volume_size),
+            None => (shard_path(&self.dir, index), None),
+        }
+    }
+}
+
+impl ShardStore for FilesystemStore {
+    fn read_shard(&self, index: usize) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            let meta = self.meta.read().unwrap().clone();
+            let read = match meta {
+                Some(meta) => meta.read_shard(&self.dir, index).await,
+                None => fs::read(shard_path(&self.dir, index)).await,
+            };
+            match read {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).with_context(|| format!("Failed to read shard {}", index)),
+            }
+        })
+    }
+
+    fn write_shard<'a>(&'a self, index: usize, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            fs::create_dir_all(&self.dir)
+                .await
+                .with_context(|| format!("Failed to create {:?}", self.dir))?;
+            let (path, volume_size) = self.shard_location(index);
+            write_shard_file(
+                &path,
+                data,
+                volume_size,
+                &self.write,
+                self.limiter.as_deref(),
+            )
+            .await
+        })
+    }
+
+    fn read_metadata(&self) -> BoxFuture<'_, Result<Option<ShardMetadata>>> {
+        Box::pin(async move {
+            if !ShardMetadata::exists(&self.dir).await {
+                return Ok(None);
+            }
+            let meta = ShardMetadata::read(&self.dir).await?;
+            *self.meta.write().unwrap() = Some(Arc::new(meta.clone()));
+            Ok(Some(meta))
+        })
+    }
+
+    fn write_metadata<'a>(&'a self, meta: &'a ShardMetadata) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            fs::create_dir_all(&self.dir)
+                .await
+                .with_context(|| format!("Failed to create {:?}", self.dir))?;
+            if meta.sidecar_checksums.is_some() {
+                meta.write_sidecars(&self.dir, &self.write).await?;
+            }
+            meta.write_with(&self.dir, &self.write).await?;
+            *self.meta.write().unwrap() = Some(Arc::new(meta.clone()));
+            Ok(())
+        })
+    }
+
+    fn begin_set<'a>(&'a self, meta: &'a ShardMetadata) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            fs::create_dir_all(&self.dir)
+                .await
+                .with_context(|| format!("Failed to create output directory: {:?}", self.dir))?;
+            if let Some(tmp_dir) = &self.write.tmp_dir {
+                fs::create_dir_all(tmp_dir).await.with_context(|| {
+                    format!("Failed to create temporary directory: {:?}", tmp_dir)
+                })?;
+            }
+            *self.meta.write().unwrap() = Some(Arc::new(meta.clone()));
+            Ok(())
+        })
+    }
+
+    fn remove_shard(&self, index: usize) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let (path, volume_size) = self.shard_location(index);
+            remove_shard_file(&path, volume_size)
+                .await
+                .with_context(|| format!("Failed to remove {:?}", path))
+        })
+    }
+}
+
+/// Options of `opts` that only make sense for shard files in a directory.
+fn directory_only_options(opts: &EncodeOptions) -> Vec<&'static str> {
+    [
+        (opts.volume_size.is_some(), "--volume-size"),
+        (opts.sidecar_metadata, "--sidecar-metadata"),
+        (opts.manifest, "--manifest"),
+        (opts.shard_log, "--shard-log"),
+        (opts.interleave_parity, "--interleave-parity"),
+        (opts.verify_after_encode, "--verify-after-encode"),
+    ]
+    .into_iter()
+    .filter_map(|(set, name)| set.then_some(name))
+    .collect()
+}
+
+/// Shards `buf` into `store` according to `opts`, which must already be
+/// validated. Every stored shard is written before the metadata.
+pub async fn encode_to_store(
+    buf: Vec<u8>,
+    store: &dyn ShardStore,
+    opts: &EncodeOptions,
+) -> Result<()> {
+    let unsupported = directory_only_options(opts);
+    if !unsupported.is_empty() {
+        return Err(RseError::InvalidArgument(format!(
+            "{} cannot be used with a shard store",
+            unsupported.join(", ")
+        ))
+        .into());
+    }
+    let encoder = ParityEncoder::new(opts)?;
+    encode_into_store(buf, store, None, opts, None, &encoder).await?;
+    Ok(())
+}
+
+/// Reads the set in `store`, reconstructs what is missing and returns the
+/// original input, checked against the recorded hash if any.
+pub async fn decode_from_store(store: &dyn ShardStore, opts: &DecodeOptions) -> Result<Vec<u8>> {
+    if !opts.fallback_dirs.is_empty() || opts.wait_for_shards.is_some() {
+        return Err(RseError::InvalidArgument(
+            "--fallback-dir and --wait-for-shards cannot be used with a shard store".into(),
+        )
+        .into());
+    }
+    let start = Instant::now();
+    let meta = store
+        .read_metadata()
+        .await?
+        .context("The shard store holds no metadata")?;
+    if meta.product_code.is_none() && meta.local_groups.is_none() {
+        Codec::validate_params(meta.data_shards, meta.parity_shards)?;
+    }
+
+    info!("Fetching shards from the store...");
+    let (shards_opt, prefetch) = prefetch_shards(store, &meta, opts).await?;
+    let read = start.elapsed();
+    if !prefetch.canceled.is_empty() {
+        info!(
+            "Reconstructing from the first {} intact shards; canceled the fetches of {:?}",
+            prefetch.arrived.len(),
+            prefetch.canceled
+        );
+    }
+
+    match recover_read_shards(meta, shards_opt, opts, read).await? {
+        Recovery::Shards(meta, shards_opt, read, lost) => {
+            assemble_output(*meta, shards_opt, read, lost, start).map(|output| output.data)
+        }
+        Recovery::Partial(output) => Ok(output.data),
+    }
+}
+
+/// What [`prefetch_shards`] fetched.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefetchReport {
+    /// Shards that arrived intact, in order of arrival.
+    pub arrived: Vec<usize>,
+    /// Shards that arrived missing, damaged or with an error.
+    pub rejected: Vec<usize>,
+    /// Shards whose fetch was canceled, or never started, because enough
+    /// had already arrived.
+    pub canceled: Vec<usize>,
+}
+
+/// Fetches the shards of the set `meta` describes from `store`, except those
+/// `opts.ignore_shards` names, as many at once as `opts.memory_budget`
+/// allows (all of them without one), and returns as soon as the intact ones
+/// suffice to rebuild the data. That is usually the first `k` to arrive;
+/// when those cannot rebuild it, e.g. a local group and its local parity in
+/// a local-group set, it waits for more. Fetches still in flight are then
+/// dropped, which cancels them. Shards that were not fetched intact are
+/// `None`.
+pub async fn prefetch_shards(
+    store: &dyn ShardStore,
+    meta: &ShardMetadata,
+    opts: &DecodeOptions,
+) -> Result<(Vec<Option<Vec<u8>>>, PrefetchReport)> {
+    let n = meta.total_shards();
+    let concurrency = shard_concurrency(
+        opts.memory_budget,
+        (0..n).map(|i| meta.stored_len(i)).max().unwrap_or(0),
+        n,
+    );
+    let wanted = |i: &usize| meta.is_stored_here(*i) && !opts.ignore_shards.contains(i);
+    let mut pending = (0..n).filter(wanted);
+    let mut in_flight = FuturesUnordered::new();
+    let fetch = |i: usize| async move { (i, store.read_shard(i).await) };
+    in_flight.extend(pending.by_ref().take(concurrency).map(fetch));
+
+    let mut shards_opt: Vec<Option<Vec<u8>>> = vec![None; n];
+    let mut report = PrefetchReport::default();
+    while let Some((i, fetched)) = in_flight.next().await {
+        match fetched {
+            Ok(Some(shard)) if is_intact(meta, i, &shard) => {
+                shards_opt[i] = Some(shard);
+                report.arrived.push(i);
+            }
+            Ok(Some(_)) => {
+                warn!("Shard {} from the store is damaged; fetching another", i);
+                report.rejected.push(i);
+            }
+            Ok(None) => report.rejected.push(i),
+            Err(e) => {
+                warn!("{:#}; fetching another shard instead", e);
+                report.rejected.push(i);
+            }
+        }
+        if report.arrived.len() >= meta.data_shards && can_recover(meta, &shards_opt) {
+            break;
+        }
+        in_flight.extend(pending.next().map(fetch));
+    }
+    drop(in_flight);
+    report.canceled = (0..n)
+        .filter(wanted)
+        .filter(|i| !report.arrived.contains(i) && !report.rejected.contains(i))
+        .collect();
+    Ok((shards_opt, report))
+}
+
+/// Whether `shard` is shard `index` as stored: the right size, passing its
+/// checksum and trailer where the set has them.
+pub(crate) fn is_intact(meta: &ShardMetadata, index: usize, shard: &[u8]) -> bool {
+    shard.len() == meta.stored_len(index)
+        && meta
+            .checksums
+            .as_ref()
+            .is_none_or(|c| c.matches(index, shard))
+        && (meta.shard_trailer.is_none() || check_trailer(shard).is_some())
+}
+
+/// Whether the shards present in `shards_opt` suffice to rebuild every data
+/// shard. For product codes and local groups reconstruction is tried on
+/// one-byte stand-ins for them. A plain set needs `k` of them whose generator
+/// rows are independent, which any `k` are only for an MDS matrix; a
+/// stripe-rotated set needs that in every stripe.
+fn can_recover(meta: &ShardMetadata, shards_opt: &[Option<Vec<u8>>]) -> bool {
+    let k = meta.data_shards;
+    let mut probe: Vec<Option<Vec<u8>>> = shards_opt
+        .iter()
+        .map(|s| s.as_ref().map(|_| vec![0]))
+        .collect();
+    if let Some(geometry) = meta.product_code {
+        ProductCodec::new(geometry)
+            .and_then(|product| product.reconstruct(&mut probe))
+            .is_ok_and(|_| probe[..k].iter().all(Option::is_some))
+    } else if let Some(groups) = meta.local_groups {
+        LrcCodec::new(k, meta.parity_shards - groups, groups)
+            .and_then(|lrc| lrc.reconstruct(&mut probe))
+            .is_ok()
+    } else {
+        let Ok(codec) = meta.codec() else {
+            return false;
+        };
+        let n = probe.len();
+        let rotations = meta
+            .stripe_rotation
+            .map_or(1, |stripe| meta.shard_len().div_ceil(stripe).clamp(1, n));
+        (0..rotations).all(|stripe| {
+            let present: Vec<usize> = (0..n)
+                .filter(|&i| probe[device_for(i, stripe, n)].is_some())
+                .collect();
+            codec
+                .suggest_shards(&present)
+                .is_ok_and(|needed| needed.is_empty())
+        })
+    }
+}
@@ -0,0 +1,333 @@
+//! Encoding a stream of unknown length without holding it in memory.
+//!
+//! With `--stream-stripe S`, the input is read `k * S` bytes at a time. Each
+//! stripe is split into `k` pieces of `S` bytes, its parity computed, and
+//! every piece appended to its shard file, so only one stripe is resident
+//! at once. The length of the stream is only known once it ends: the last,
+//! shorter stripe is split evenly into pieces of `ceil(rest / k)` bytes,
+//! zero-padded, and the metadata is written with the final length and
+//! checksums after every shard file is in place. A stream shorter than one
+//! stripe is thus laid out exactly as a plain encode of the same bytes.
+//!
+//! Shard files are written to temporary files and renamed into place only
+//! once the stream has ended, so an interrupted stream leaves no shards and
+//! no metadata behind, and the output of an earlier encode untouched.
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tracing::info;
+
+use crate::{
+    codec::encode_shards::shard_encoding,
+    error::RseError,
+    io::{
+        atomic::StagedFile,
+        checksum::{ChecksumAlgo, ChecksumHasher, ShardChecksums},
+        encoding::{EncodeOptions, ParityEncoder, new_set_metadata, verify_encoded},
+        metadata::ShardMetadata,
+        stats::PhaseTimes,
+        throttle::{RateLimiter, write_throttled},
+    },
+};
+
+/// Length of every shard of an `orig_len`-byte stream encoded over `k` data
+/// shards, `stripe` bytes per data shard at a time.
+pub fn stream_shard_len(orig_len: usize, k: usize, stripe: usize) -> usize {
+    let stripe_bytes = k * stripe;
+    (orig_len / stripe_bytes) * stripe + (orig_len % stripe_bytes).div_ceil(k)
+}
+
+/// Bytes each data shard holds of the stripe that starts `remaining` bytes
+/// before the end of the input.
+fn piece_len(remaining: usize, k: usize, stripe: usize) -> usize {
+    if remaining >= k * stripe {
+        stripe
+    } else {
+        remaining.div_ceil(k)
+    }
+}
+
+/// Reassembles a streamed input of `orig_len` bytes from its data shards,
+/// taking each stripe's piece from every shard in turn.
+pub fn assemble_streamed_data_shards(
+    data_shards: &[Option<Vec<u8>>],
+    orig_len: usize,
+    stripe: usize,
+    progress: &ProgressBar,
+) -> Result<Vec<u8>> {
+    let k = data_shards.len();
+    let expected_shard_len = stream_shard_len(orig_len, k, stripe);
+    let mut shards = Vec::with_capacity(k);
+    for (i, shard) in data_shards.iter().enumerate() {
+        let shard = shard
+            .as_ref()
+            .context("Reconstructed data shard is missing unexpectedly")?;
+        if shard.len() != expected_shard_len {
+            return Err(RseError::Corruption(format!(
+                "Data shard {} is {} bytes, but a {}-byte stream in stripes of {} bytes over {} \
+                 shards implies {} bytes per shard; metadata and shards are inconsistent",
+                i,
+                shard.len(),
+                orig_len,
+                stripe,
+                k,
+                expected_shard_len
+            ))
+            .into());
+        }
+        shards.push(shard);
+    }
+
+    let mut out_buf = Vec::with_capacity(orig_len);
+    let mut offset = 0;
+    while out_buf.len() < orig_len {
+        let piece = piece_len(orig_len - out_buf.len(), k, stripe);
+        for shard in &shards {
+            let to_write = piece.min(orig_len - out_buf.len());
+            out_buf.extend_from_slice(&shard[offset..offset + to_write]);
+            progress.inc(to_write as u64);
+        }
+        offset += piece;
+    }
+    Ok(out_buf)
+}
+
+/// Reads until `buf` is full or the stream ends, returning the bytes read.
+async fn read_stripe<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut [u8],
+    limiter: Option<&RateLimiter>,
+) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        if let Some(limiter) = limiter {
+            limiter.acquire(n).await;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// A shard file being appended to, not yet moved into place.
+struct OpenShard {
+    file: File,
+    staged: StagedFile,
+}
+
+/// Encodes `reader` into `out_dir` stripe by stripe, as `opts.stream_stripe`
+/// asks, returning the input length and the time spent in each phase.
+/// `opts` must already be validated.
+pub async fn encode_stream<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    out_dir: &Path,
+    opts: &EncodeOptions,
+    limiter: Option<Arc<RateLimiter>>,
+    encoder: &ParityEncoder,
+) -> Result<(usize, PhaseTimes)> {
+    let stripe = opts
+        .stream_stripe
+        .context("Encoding a stream needs --stream-stripe")?;
+    fs::create_dir_all(out_dir)
+        .await
+        .with_context(|| format!("Failed to create output directory: {:?}", out_dir))?;
+    if let Some(tmp_dir) = &opts.write.tmp_dir {
+        fs::create_dir_all(tmp_dir)
+            .await
+            .with_context(|| format!("Failed to create temporary directory: {:?}", tmp_dir))?;
+    }
+
+    // The length and input hash are filled in once the stream ends.
+    let mut meta = new_set_metadata(opts, 0, String::new());
+    meta.stream_stripe = Some(stripe);
+
+    let mut shards = Vec::with_capacity(meta.total_shards());
+    let result = write_stripes(
+        reader,
+        out_dir,
+        &mut meta,
+        &mut shards,
+        opts,
+        limiter.as_deref(),
+        encoder,
+    )
+    .await;
+    let times = match result {
+        Ok(times) => times,
+        Err(e) => {
+            for shard in shards.into_iter().flatten() {
+                let _ = fs::remove_file(shard.staged.temp_path()).await;
+            }
+            return Err(e);
+        }
+    };
+
+    let write_start = Instant::now();
+    for OpenShard { file, staged } in shards.into_iter().flatten() {
+        drop(file);
+        staged.commit(&opts.write).await?;
+    }
+    if opts.sidecar_metadata {
+        meta.sidecar_checksums = Some(opts.checksum_algo);
+        meta.write_sidecars(out_dir, &opts.write).await?;
+    }
+    // The metadata goes last, with the length now known: a set only looks
+    // complete once every shard is in place.
+    meta.write_with(out_dir, &opts.write).await?;
+    let times = PhaseTimes {
+        write: times.write + write_start.elapsed(),
+        ..times
+    };
+    if opts.verify_after_encode {
+        verify_encoded(out_dir, meta.total_shards(), opts).await?;
+    }
+    Ok((meta.orig_len, times))
+}
+
+/// Opens the shard files stored in `out_dir` into `shards` and appends the
+/// stream to them stripe by stripe, recording the final length, input hash
+/// and checksums in `meta`. The files are flushed but not yet in place.
+async fn write_stripes<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    out_dir: &Path,
+    meta: &mut ShardMetadata,
+    shards: &mut Vec<Option<OpenShard>>,
+    opts: &EncodeOptions,
+    limiter: Option<&RateLimiter>,
+    encoder: &ParityEncoder,
+) -> Result<PhaseTimes> {
+    let (k, m) = opts.set_shards();
+    let stripe = meta.stream_stripe.expect("set by encode_stream");
+    for i in 0..k + m {
+        shards.push(match meta.is_stored_here(i) {
+            true => {
+                let (file, staged) =
+                    StagedFile::create(&meta.shard_path(out_dir, i), &opts.write).await?;
+                Some(OpenShard { file, staged })
+            }
+            false => None,
+        });
+    }
+    info!(
+        "Encoding a stream into {} data and {} parity shards in {:?}, {} bytes per data shard \
+         at a time",
+        k, m, out_dir, stripe
+    );
+    let pb = ProgressBar::no_length();
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] Encoding stream {bytes} ({bytes_per_sec})",
+        )
+        .unwrap(),
+    );
+
+    let mut hashers: Vec<ChecksumHasher> = (0..k + m)
+        .filter_map(|_| opts.checksum_algo.hasher())
+        .collect();
+    let mut input_hash = Sha256::new();
+    let mut buf = vec![0u8; k * stripe];
+    let mut orig_len = 0;
+    let mut stripes = 0;
+    let mut times = PhaseTimes::default();
+    loop {
+        let read_start = Instant::now();
+        let filled = read_stripe(reader, &mut buf, limiter)
+            .await
+            .context("Failed to read the input stream")?;
+        times.read += read_start.elapsed();
+        if filled == 0 {
+            break;
+        }
+        orig_len += filled;
+        input_hash.update(&buf[..filled]);
+
+        let compute_start = Instant::now();
+        let piece = piece_len(filled, k, stripe);
+        let data: Vec<Vec<u8>> = (0..k)
+            .map(|i| {
+                let (start, end) = ((i * piece).min(filled), ((i + 1) * piece).min(filled));
+                let mut shard = vec![0u8; piece];
+                shard[..end - start].copy_from_slice(&buf[start..end]);
+                shard
+            })
+            .collect();
+        let encoder = encoder.clone();
+        let (data, parity) = tokio::task::spawn_blocking(move || {
+            let parity = shard_encoding(
+                encoder.gf.as_ref(),
+                &encoder.matrix,
+                &data,
+                &(),
+                &encoder.execution,
+            )?;
+            Ok::<_, anyhow::Error>((data, parity))
+        })
+        .await??;
+        times.compute += compute_start.elapsed();
+
+        let write_start = Instant::now();
+        for (i, piece) in data.iter().chain(&parity).enumerate() {
+            if let Some(hasher) = hashers.get_mut(i) {
+                hasher.update(piece);
+            }
+            if let Some(shard) = &mut shards[i] {
+                match limiter {
+                    Some(limiter) => write_throttled(&mut shard.file, piece, limiter).await,
+                    None => shard.file.write_all(piece).await.map_err(Into::into),
+                }
+                .with_context(|| format!("Failed to write {}", meta.shard_file_name(i)))?;
+            }
+        }
+        times.write += write_start.elapsed();
+        stripes += 1;
+        pb.inc(filled as u64);
+        if filled < buf.len() {
+            break;
+        }
+    }
+    pb.finish_and_clear();
+
+    let write_start = Instant::now();
+    for shard in shards.iter_mut().flatten() {
+        shard.file.flush().await?;
+        if opts.write.fsync {
+            shard
+                .file
+                .sync_all()
+                .await
+                .with_context(|| format!("Failed to sync {:?}", shard.staged.temp_path()))?;
+        }
+    }
+    times.write += write_start.elapsed();
+
+    meta.orig_len = orig_len;
+    meta.input_sha256 = Some(
+        input_hash
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect(),
+    );
+    if opts.checksum_algo != ChecksumAlgo::None {
+        meta.checksums = Some(ShardChecksums {
+            algorithm: opts.checksum_algo,
+            shards: hashers.into_iter().map(ChecksumHasher::finish).collect(),
+        });
+    }
+    info!(
+        "Encoded {} bytes in {} stripes; shards are {} bytes",
+        orig_len,
+        stripes,
+        meta.shard_len()
+    );
+    Ok(times)
+}
@@ -0,0 +1,126 @@
+//! Picking `k`/`m` for a required fault tolerance and storage budget.
+//!
+//! Every candidate keeps `m >= tolerance` and `m / (k + m) <= max_overhead`.
+//! Smaller sets rank first: encoding costs `m` multiply-adds per data byte
+//! and rebuilding a lost shard reads `k` shards, so `k + m` is the cost
+//! estimate, with lower overhead breaking ties. Each candidate is checked to
+//! be MDS with the default matrix, falling back to Cauchy when it is not.
+//! The check is exhaustive for small codes only; for larger ones
+//! [`check_mds`] tests a fixed sample of survivor sets, so a Vandermonde
+//! pick there is likely but not proven MDS.
+
+use anyhow::Result;
+use tracing::instrument;
+
+use crate::{
+    algorithm::gf256::Gf256,
+    cli::commands::Commands,
+    codec::matrix::{MatrixType, check_mds},
+    error::RseError,
+};
+
+/// Extra parity shards beyond the tolerance that are still suggested.
+const EXTRA_PARITY: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Suggestion {
+    pub data_shards: usize,
+    pub parity_shards: usize,
+    /// Share of stored bytes that is parity, `m / (k + m)`.
+    pub overhead: f64,
+    /// First matrix type whose code passes [`check_mds`] for this `k`/`m`.
+    pub matrix_type: MatrixType,
+}
+
+fn fits(k: usize, m: usize, max_overhead: f64) -> bool {
+    m as f64 / (k + m) as f64 <= max_overhead
+}
+
+fn mds_matrix_type(k: usize, m: usize) -> Option<MatrixType> {
+    let gf = Gf256::new();
+    [MatrixType::Vandermonde, MatrixType::Cauchy]
+        .into_iter()
+        .find(|ty| {
+            ty.build(&gf, k, m)
+                .is_ok_and(|matrix| check_mds(&gf, &matrix, k).is_ok())
+        })
+}
+
+/// Up to `limit` configurations tolerating `tolerance` lost shards within
+/// `max_overhead`, cheapest first.
+pub fn suggest(tolerance: usize, max_overhead: f64, limit: usize) -> Result<Vec<Suggestion>> {
+    if tolerance == 0 || !(max_overhead > 0.0 && max_overhead < 1.0) {
+        return Err(RseError::InvalidArgument(format!(
+            "Need a tolerance of at least 1 and an overhead between 0 and 1, got {} and {}",
+            tolerance, max_overhead
+        ))
+        .into());
+    }
+    let mut candidates = Vec::new();
+    for m in tolerance..=tolerance + EXTRA_PARITY {
+        let Some(k_min) = (1..=256usize.saturating_sub(m)).find(|&k| fits(k, m, max_overhead))
+        else {
+            continue;
+        };
+        for k in k_min..=(k_min + limit).min(256 - m) {
+            candidates.push((k, m));
+        }
+    }
+    candidates.sort_by(|&(ka, ma), &(kb, mb)| {
+        (ka + ma)
+            .cmp(&(kb + mb))
+            .then((ma * (kb + mb)).cmp(&(mb * (ka + ma))))
+    });
+
+    Ok(candidates
+        .into_iter()
+        .filter_map(|(k, m)| {
+            Some(Suggestion {
+                data_shards: k,
+                parity_shards: m,
+                overhead: m as f64 / (k + m) as f64,
+                matrix_type: mds_matrix_type(k, m)?,
+            })
+        })
+        .take(limit)
+        .collect())
+}
+
+#[instrument(skip(args))]
+pub async fn handle_suggest(args: Commands) -> Result<()> {
+    let Commands::Suggest {
+        tolerance,
+        max_overhead,
+        limit,
+    } = args
+    else {
+        unreachable!()
+    };
+
+    let suggestions = suggest(tolerance, max_overhead, limit)?;
+    if suggestions.is_empty() {
+        return Err(RseError::InvalidArgument(format!(
+            "No k/m up to 256 shards tolerates {} lost shards within {:.1}% overhead",
+            tolerance,
+            max_overhead * 100.0
+        ))
+        .into());
+    }
+    println!(
+        "Tolerating {} lost shards within {:.1}% overhead:",
+        tolerance,
+        max_overhead * 100.0
+    );
+    println!("   k    m  overhead  rebuild reads  matrix");
+    for s in &suggestions {
+        println!(
+            "{:>4} {:>4}  {:>7.1}%  {:>13}  {:?}",
+            s.data_shards,
+            s.parity_shards,
+            s.overhead * 100.0,
+            s.data_shards,
+            s.matrix_type
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::time::Duration;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    sync::Mutex,
+    time::Instant,
+};
+
+/// Granularity of throttled reads and writes, so that throughput stays smooth
+/// instead of bursting one whole shard at a time.
+pub const THROTTLE_CHUNK: usize = 1 << 20;
+
+/// Token-bucket limiter shared by all IO tasks of one operation.
+///
+/// The bucket holds at most one second worth of bytes. Callers may take more
+/// tokens than are available; the resulting debt is paid off by sleeping, so
+/// the long-run throughput never exceeds `bytes_per_sec`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: f64) -> Self {
+        Self {
+            bytes_per_sec,
+            state: Mutex::new((bytes_per_sec, Instant::now())),
+        }
+    }
+
+    pub fn from_mib_per_sec(mib_per_sec: f64) -> Self {
+        Self::new(mib_per_sec * 1024.0 * 1024.0)
+    }
+
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().await;
+            let (tokens, last) = &mut *state;
+            let now = Instant::now();
+            *tokens = (*tokens + now.duration_since(*last).as_secs_f64() * self.bytes_per_sec)
+                .min(self.bytes_per_sec);
+            *last = now;
+            *tokens -= bytes as f64;
+            if *tokens < 0.0 {
+                Duration::from_secs_f64(-*tokens / self.bytes_per_sec)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+pub async fn read_throttled(path: &Path, limiter: &RateLimiter) -> Result<Vec<u8>> {
+    let mut file = File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {:?}", path))?;
+    let len = file.metadata().await?.len() as usize;
+    read_to_end_throttled(&mut file, len, limiter).await
+}
+
+/// Reads `reader` to the end in [`THROTTLE_CHUNK`] pieces, each paid for
+/// once read. `capacity` is the expected length.
+pub async fn read_to_end_throttled<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    capacity: usize,
+    limiter: &RateLimiter,
+) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(capacity);
+    let mut chunk = vec![0u8; THROTTLE_CHUNK];
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        limiter.acquire(read).await;
+        buf.extend_from_slice(&chunk[..read]);
+    }
+    Ok(buf)
+}
+
+/// Writes `data` to `file` in [`THROTTLE_CHUNK`] pieces, each paid for first.
+pub async fn write_throttled(file: &mut File, data: &[u8], limiter: &RateLimiter) -> Result<()> {
+    for chunk in data.chunks(THROTTLE_CHUNK) {
+        limiter.acquire(chunk.len()).await;
+        file.write_all(chunk).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,47 @@
+//! Self-verifying shard files.
+//!
+//! With a trailer, every shard file ends in [`TRAILER_LEN`] bytes: the magic
+//! [`TRAILER_MAGIC`] followed by the little-endian CRC-32 of everything before
+//! it. A tool can then validate a single shard file without the metadata.
+//!
+//! The trailer is appended last, after encryption, so checksums, the manifest
+//! and [`crate::io::metadata::ShardMetadata::stored_len`] all describe the
+//! file including it, while `shard_len` never does. Decode strips it before
+//! anything else and treats a shard with a bad trailer as missing.
+
+use serde::{Deserialize, Serialize};
+
+pub const TRAILER_MAGIC: [u8; 4] = *b"RSEt";
+
+/// Bytes the trailer adds to every shard file.
+pub const TRAILER_LEN: usize = TRAILER_MAGIC.len() + 4;
+
+/// Trailer format recorded in the metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShardTrailer {
+    /// [`TRAILER_MAGIC`] then the CRC-32 of the body.
+    #[serde(rename = "crc32")]
+    Crc32,
+}
+
+/// Appends the trailer for the current contents of `shard`.
+pub fn append_trailer(shard: &mut Vec<u8>) {
+    let crc = crc32fast::hash(shard);
+    shard.extend_from_slice(&TRAILER_MAGIC);
+    shard.extend_from_slice(&crc.to_le_bytes());
+}
+
+/// The body of a shard file, if it ends in a trailer that matches it.
+pub fn check_trailer(file: &[u8]) -> Option<&[u8]> {
+    let body_len = file.len().checked_sub(TRAILER_LEN)?;
+    let (body, trailer) = file.split_at(body_len);
+    let (magic, crc) = trailer.split_at(TRAILER_MAGIC.len());
+    (magic == TRAILER_MAGIC && crc == crc32fast::hash(body).to_le_bytes()).then_some(body)
+}
+
+/// Removes a valid trailer from `file`, or returns `None` if it has none.
+pub fn strip_trailer(mut file: Vec<u8>) -> Option<Vec<u8>> {
+    let body_len = check_trailer(&file)?.len();
+    file.truncate(body_len);
+    Some(file)
+}
@@ -0,0 +1,366 @@
+use anyhow::{Result, anyhow};
+use std::collections::BTreeSet;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tracing::{info, instrument};
+
+use crate::{
+    algorithm::shuffle::permutation,
+    cli::commands::Commands,
+    error::RseError,
+    io::{
+        manifest::{MANIFEST_FILE, Manifest, ManifestMismatch},
+        metadata::ShardMetadata,
+        trailer::check_trailer,
+        volumes::volume_shard_name,
+    },
+};
+
+/// A random subset of a set's shards, checked in place of all of them on
+/// large sets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardSample {
+    pub seed: u64,
+    /// How many shards the sample was drawn from.
+    pub population: usize,
+    /// The sampled entries of the candidates, ascending.
+    pub indices: Vec<usize>,
+}
+
+impl ShardSample {
+    /// Picks `count` of `candidates` (all of them if there are fewer) by a
+    /// shuffle seeded with `seed`, so the same seed picks the same shards.
+    pub fn choose(candidates: &[usize], count: usize, seed: u64) -> Self {
+        let mut indices: Vec<usize> = permutation(candidates.len(), seed)
+            .into_iter()
+            .take(count)
+            .map(|pos| candidates[pos])
+            .collect();
+        indices.sort_unstable();
+        Self {
+            seed,
+            population: candidates.len(),
+            indices,
+        }
+    }
+
+    /// Uses `seed`, or a time-derived one if `None`.
+    pub(crate) fn seed_or_random(seed: Option<u64>) -> u64 {
+        seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos() as u64)
+        })
+    }
+
+    pub(crate) fn print(&self) {
+        info!("Sampling shards with seed {}", self.seed);
+        println!(
+            "Sampled {} of {} shards (seed {}): {:?}",
+            self.indices.len(),
+            self.population,
+            self.seed,
+            self.indices
+        );
+    }
+
+    /// Prints the health of the whole set extrapolated from `failures`
+    /// failing shards among the sample.
+    pub(crate) fn print_estimate(&self, failures: usize) {
+        let sampled = self.indices.len().max(1);
+        let intact = 100.0 * (sampled - failures.min(sampled)) as f64 / sampled as f64;
+        let failing = (failures * self.population).div_ceil(sampled);
+        println!(
+            "Estimated health: {:.1}% of shard files intact (about {} of {} failing)",
+            intact, failing, self.population
+        );
+    }
+}
+
+/// Checks `input` against its manifest, reporting each mismatch. Only files
+/// in `only` are checked, if given. Returns the failing file names.
+async fn verify_manifest(
+    input: &Path,
+    manifest_path: &Path,
+    manifest: &Manifest,
+    only: Option<&BTreeSet<String>>,
+) -> Result<Vec<String>> {
+    info!("Verifying {:?} against {:?}", input, manifest_path);
+    // The volumes of a split shard file are kept with the file.
+    let keep = |file: &str| {
+        only.is_none_or(|only| {
+            only.contains(file) || volume_shard_name(file).is_some_and(|name| only.contains(name))
+        })
+    };
+    let mismatches = manifest.verify_files(input, keep).await?;
+
+    let mut failed = Vec::with_capacity(mismatches.len());
+    for mismatch in mismatches {
+        match mismatch {
+            ManifestMismatch::Missing(file) => {
+                println!("MISSING   {}", file);
+                failed.push(file);
+            }
+            ManifestMismatch::SizeMismatch {
+                file,
+                expected,
+                actual,
+            } => {
+                println!(
+                    "SIZE      {} (expected {} bytes, found {})",
+                    file, expected, actual
+                );
+                failed.push(file);
+            }
+            ManifestMismatch::HashMismatch(file) => {
+                println!("CORRUPT   {}", file);
+                failed.push(file);
+            }
+        }
+    }
+    let checked = manifest.shards.keys().filter(|file| keep(file)).count();
+    println!(
+        "{} of {} shard files match the manifest",
+        checked - failed.len(),
+        checked
+    );
+    Ok(failed)
+}
+
+/// State of one shard file after a full read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShardHealth {
+    Healthy,
+    Missing,
+    /// Present but unusable, for the given reason.
+    Corrupt(String),
+}
+
+/// Reads shard `index` from `dir` and checks its size, checksum and trailer.
+pub async fn check_shard(dir: &Path, meta: &ShardMetadata, index: usize) -> ShardHealth {
+    let problem = match meta.read_shard(dir, index).await {
+        Err(e) if e.kind() == ErrorKind::NotFound => return ShardHealth::Missing,
+        Err(e) => format!("unreadable: {}", e),
+        Ok(data) if data.len() != meta.stored_len(index) => {
+            format!("{} bytes, expected {}", data.len(), meta.stored_len(index))
+        }
+        Ok(data)
+            if meta
+                .checksums
+                .as_ref()
+                .is_some_and(|c| !c.matches(index, &data)) =>
+        {
+            "checksum mismatch".to_string()
+        }
+        Ok(data) if meta.shard_trailer.is_some() && check_trailer(&data).is_none() => {
+            "bad trailer".to_string()
+        }
+        Ok(_) => return ShardHealth::Healthy,
+    };
+    ShardHealth::Corrupt(problem)
+}
+
+/// Per-shard outcome of a full read of shard files with [`check_shard`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChecksumScan {
+    pub intact: Vec<usize>,
+    pub corrupt: Vec<usize>,
+    pub missing: Vec<usize>,
+}
+
+impl ChecksumScan {
+    /// Checks shards `indices` of the set in `input` against their
+    /// checksums, as [`ChecksumScan::scan`] does. `None` if the set has no
+    /// checksums.
+    pub async fn run(
+        input: &Path,
+        meta: &ShardMetadata,
+        indices: &[usize],
+        print_intact: bool,
+    ) -> Result<Option<Self>> {
+        let Some(checksums) = &meta.checksums else {
+            return Ok(None);
+        };
+        info!(
+            "Verifying {:?} against {:?} checksums",
+            input, checksums.algorithm
+        );
+        let scan = Self::scan(input, meta, indices, print_intact).await;
+        println!(
+            "{} of {} shard files match their {:?} checksums",
+            scan.intact.len(),
+            indices.len(),
+            checksums.algorithm
+        );
+        Ok(Some(scan))
+    }
+
+    /// Reads shards `indices` of the set in `input` and checks each with
+    /// [`check_shard`], printing a line for each one that is missing or
+    /// corrupt, and every intact one too with `print_intact`.
+    pub async fn scan(
+        input: &Path,
+        meta: &ShardMetadata,
+        indices: &[usize],
+        print_intact: bool,
+    ) -> Self {
+        let mut scan = Self::default();
+        for &i in indices {
+            let name = meta.shard_file_name(i);
+            match check_shard(input, meta, i).await {
+                ShardHealth::Healthy => {
+                    if print_intact {
+                        println!("OK        {}", name);
+                    }
+                    scan.intact.push(i);
+                }
+                ShardHealth::Missing => {
+                    println!("MISSING   {}", name);
+                    scan.missing.push(i);
+                }
+                ShardHealth::Corrupt(problem) => {
+                    println!("CORRUPT   {} ({})", name, problem);
+                    scan.corrupt.push(i);
+                }
+            }
+        }
+        scan
+    }
+
+    /// Shards that are corrupt or missing, ascending.
+    pub fn lost(&self) -> Vec<usize> {
+        let mut lost: Vec<usize> = self.corrupt.iter().chain(&self.missing).copied().collect();
+        lost.sort_unstable();
+        lost
+    }
+}
+
+/// Checks shards `indices` stored in `input` against the checksums in its
+/// metadata. Returns the failing file names, or `None` if the set was
+/// encoded without checksums.
+pub(crate) async fn verify_checksums(
+    input: &Path,
+    meta: &ShardMetadata,
+    indices: &[usize],
+) -> Result<Option<Vec<String>>> {
+    let Some(scan) = ChecksumScan::run(input, meta, indices, false).await? else {
+        return Ok(None);
+    };
+    Ok(Some(
+        scan.lost()
+            .iter()
+            .map(|&i| meta.shard_file_name(i))
+            .collect(),
+    ))
+}
+
+/// Indices of the shards the metadata says are stored in the directory.
+pub(crate) fn stored_indices(meta: &ShardMetadata) -> Vec<usize> {
+    (0..meta.total_shards())
+        .filter(|&i| meta.is_stored_here(i))
+        .collect()
+}
+
+#[instrument(skip(args))]
+pub async fn handle_verify(args: Commands) -> Result<()> {
+    let Commands::Verify {
+        input,
+        manifest,
+        count,
+        seed,
+    } = args
+    else {
+        unreachable!()
+    };
+
+    // An explicit --manifest must exist; the default one is optional when
+    // the metadata carries checksums.
+    let manifest_path = manifest
+        .clone()
+        .unwrap_or_else(|| input.join(MANIFEST_FILE));
+    let manifest = if manifest.is_some() || fs::try_exists(&manifest_path).await.unwrap_or(false) {
+        Some(Manifest::read(&manifest_path).await?)
+    } else {
+        None
+    };
+
+    let meta = match ShardMetadata::read(&input).await {
+        Ok(meta) => Some(meta),
+        Err(_) if manifest.is_some() => None,
+        Err(e) => return Err(e),
+    };
+
+    // Shards are sampled by index when the metadata is readable, otherwise
+    // by position among the manifest's files.
+    let sample = count.map(|count| {
+        let seed = ShardSample::seed_or_random(seed);
+        match (&meta, &manifest) {
+            (Some(meta), _) => ShardSample::choose(&stored_indices(meta), count, seed),
+            (None, Some(manifest)) => {
+                let positions: Vec<usize> = (0..manifest.shards.len()).collect();
+                ShardSample::choose(&positions, count, seed)
+            }
+            (None, None) => unreachable!(),
+        }
+    });
+    if let Some(sample) = &sample {
+        sample.print();
+    }
+    let sampled_files: Option<BTreeSet<String>> = sample.as_ref().map(|sample| match &meta {
+        Some(meta) => sample
+            .indices
+            .iter()
+            .map(|&i| meta.shard_file_name(i))
+            .collect(),
+        None => {
+            let files: Vec<&String> = manifest.as_ref().unwrap().shards.keys().collect();
+            sample
+                .indices
+                .iter()
+                .map(|&pos| files[pos].clone())
+                .collect()
+        }
+    });
+
+    let manifest_failures = match &manifest {
+        Some(manifest) => {
+            Some(verify_manifest(&input, &manifest_path, manifest, sampled_files.as_ref()).await?)
+        }
+        None => None,
+    };
+    let checksum_failures = match &meta {
+        Some(meta) => {
+            let indices = match &sample {
+                Some(sample) => sample.indices.clone(),
+                None => stored_indices(meta),
+            };
+            verify_checksums(&input, meta, &indices).await?
+        }
+        None => None,
+    };
+
+    if manifest_failures.is_none() && checksum_failures.is_none() {
+        return Err(anyhow!(
+            "Nothing to verify: {:?} has no manifest and was encoded without checksums",
+            input
+        ));
+    }
+    let failed: BTreeSet<String> = manifest_failures
+        .into_iter()
+        .chain(checksum_failures)
+        .flatten()
+        .collect();
+    if let Some(sample) = &sample {
+        sample.print_estimate(failed.len());
+    }
+    if !failed.is_empty() {
+        return Err(RseError::Corruption(format!(
+            "{} shard file(s) failed verification",
+            failed.len()
+        ))
+        .into());
+    }
+    Ok(())
+}
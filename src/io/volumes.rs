@@ -0,0 +1,144 @@
+//! Shard files split into fixed-size volumes.
+//!
+//! With a volume size, shard file `shard_XX.dat` is stored as
+//! `shard_XX.dat.000`, `shard_XX.dat.001`, ... instead, each holding
+//! `volume_size` bytes except the last, which holds the rest. An empty shard
+//! file is a single empty volume.
+//!
+//! Splitting is the very last step on write and reassembly the first on
+//! read, so checksums, trailers and
+//! [`crate::io::metadata::ShardMetadata::stored_len`] describe the
+//! reassembled file. The manifest lists every volume file on its own, as
+//! that is what external tools see on disk.
+
+use anyhow::Result;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::io::{
+    atomic::{WriteOptions, write_atomic},
+    throttle::RateLimiter,
+};
+
+/// Name of volume `volume` of shard file `shard_file`.
+pub fn volume_file_name(shard_file: &str, volume: usize) -> String {
+    format!("{}.{:03}", shard_file, volume)
+}
+
+/// Path of volume `volume` of the shard file at `shard_path`.
+pub fn volume_path(shard_path: &Path, volume: usize) -> PathBuf {
+    let mut path = shard_path.as_os_str().to_owned();
+    path.push(format!(".{:03}", volume));
+    PathBuf::from(path)
+}
+
+/// The shard file name a volume file belongs to, or `None` if `file` does not
+/// end in a three-digit volume suffix.
+pub fn volume_shard_name(file: &str) -> Option<&str> {
+    let (name, suffix) = file.rsplit_once('.')?;
+    (suffix.len() == 3 && suffix.bytes().all(|b| b.is_ascii_digit())).then_some(name)
+}
+
+/// Number of volumes a `len`-byte shard file is split into.
+pub fn volume_count(len: usize, volume_size: usize) -> usize {
+    len.div_ceil(volume_size).max(1)
+}
+
+/// `data` cut into volumes of `volume_size` bytes, the last one short.
+pub fn split_volumes(data: &[u8], volume_size: usize) -> Vec<&[u8]> {
+    if data.is_empty() {
+        return vec![data];
+    }
+    data.chunks(volume_size).collect()
+}
+
+/// Writes `data` as the volumes of the shard file at `shard_path`, each
+/// through [`write_atomic`].
+pub async fn write_volumes(
+    shard_path: &Path,
+    data: &[u8],
+    volume_size: usize,
+    opts: &WriteOptions,
+    limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    for (v, volume) in split_volumes(data, volume_size).into_iter().enumerate() {
+        write_atomic(&volume_path(shard_path, v), volume, opts, limiter).await?;
+    }
+    Ok(())
+}
+
+/// Writes the shard file at `shard_path`, split into volumes of
+/// `volume_size` bytes if given.
+pub async fn write_shard_file(
+    shard_path: &Path,
+    data: &[u8],
+    volume_size: Option<usize>,
+    opts: &WriteOptions,
+    limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    match volume_size {
+        Some(size) => write_volumes(shard_path, data, size, opts, limiter).await,
+        None => write_atomic(shard_path, data, opts, limiter).await,
+    }
+}
+
+/// Removes the shard file at `shard_path`, or every volume of it if split.
+/// A file that is already gone is not an error.
+pub async fn remove_shard_file(shard_path: &Path, volume_size: Option<usize>) -> io::Result<()> {
+    let result = match volume_size {
+        Some(_) => {
+            let mut v = 0;
+            loop {
+                match fs::remove_file(volume_path(shard_path, v)).await {
+                    Ok(()) => v += 1,
+                    Err(e) if e.kind() == ErrorKind::NotFound => break Ok(()),
+                    Err(e) => break Err(e),
+                }
+            }
+        }
+        None => fs::remove_file(shard_path).await,
+    };
+    match result {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Reassembles the first `count` volumes of the shard file at `shard_path`.
+/// Stops early at a missing volume, so the result comes out short; fails with
+/// [`ErrorKind::NotFound`] only if the first volume is missing.
+pub async fn read_volumes(shard_path: &Path, count: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for v in 0..count {
+        match fs::read(volume_path(shard_path, v)).await {
+            Ok(volume) => data.extend_from_slice(&volume),
+            Err(e) if e.kind() == ErrorKind::NotFound && v > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(data)
+}
+
+/// Reads the shard file at `shard_path`, reassembled from `volumes` volumes
+/// if it is split.
+pub async fn read_shard_file(shard_path: &Path, volumes: Option<usize>) -> io::Result<Vec<u8>> {
+    match volumes {
+        Some(count) => read_volumes(shard_path, count).await,
+        None => fs::read(shard_path).await,
+    }
+}
+
+/// Total size of the first `count` volumes, with the same handling of
+/// missing volumes as [`read_volumes`].
+pub async fn volumes_len(shard_path: &Path, count: usize) -> io::Result<u64> {
+    let mut len = 0;
+    for v in 0..count {
+        match fs::metadata(volume_path(shard_path, v)).await {
+            Ok(volume) => len += volume.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound && v > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
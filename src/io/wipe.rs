@@ -0,0 +1,80 @@
+//! Wiping sensitive buffers when they are dropped.
+//!
+//! Freed heap memory keeps its contents until it is reused, so the input,
+//! its shards and the decoded output could otherwise be read back from a
+//! core dump or by later code in the same process long after a run. With the
+//! `zeroize` feature, the buffers the encode and decode pipelines own are
+//! held in a [`Wiping`] guard, which overwrites them with zeros (spare
+//! capacity included) when it drops them. This covers the input and its
+//! data shards, every shard written or read, and the assembled output, but
+//! not copies made outside the pipeline, such as the OS page cache or the
+//! buffers a library caller keeps.
+//!
+//! Without the feature, [`Wipe::wipe`] does nothing and the guard costs
+//! nothing.
+
+use std::ops::{Deref, DerefMut};
+
+/// Buffers that can be overwritten with zeros.
+pub trait Wipe {
+    /// Zeroes the buffer, with the `zeroize` feature; a no-op without it.
+    fn wipe(&mut self);
+}
+
+impl Wipe for Vec<u8> {
+    fn wipe(&mut self) {
+        #[cfg(feature = "zeroize")]
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+impl Wipe for Vec<Vec<u8>> {
+    fn wipe(&mut self) {
+        #[cfg(feature = "zeroize")]
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+impl Wipe for Vec<Option<Vec<u8>>> {
+    fn wipe(&mut self) {
+        #[cfg(feature = "zeroize")]
+        zeroize::Zeroize::zeroize(self);
+    }
+}
+
+/// Owns a buffer and wipes it when dropped.
+pub struct Wiping<T: Wipe>(T);
+
+impl<T: Wipe> Wiping<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Gives up the buffer without wiping it, to hand it to the caller.
+    pub fn into_inner(mut self) -> T
+    where
+        T: Default,
+    {
+        std::mem::take(&mut self.0)
+    }
+}
+
+impl<T: Wipe> Deref for Wiping<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Wipe> DerefMut for Wiping<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Wipe> Drop for Wiping<T> {
+    fn drop(&mut self) {
+        self.0.wipe();
+    }
+}
@@ -0,0 +1,5618 @@
+//! # Litiaina Reed Solomon Erasure GF(2^8)
+//!
+//! ## Usage
+//!
+//! ### Encoding a file
+//!
+//! ```bash
+//! RUST_LOG=info cargo run --release -- encode --input my_large_file.bin --output shards_out --data-shards 10 --parity-shards 4
+//! ```
+//!
+//! ### Reconstructing a file
+//!
+//! After removing a few shards from `shards_out`...
+//! ```bash
+//! RUST_LOG=info cargo run --release -- decode --input shards_out --output recovered_file.bin
+//! ```
+
+pub mod algorithm;
+#[cfg(feature = "full")]
+pub mod cli;
+pub mod codec;
+pub mod error;
+pub mod io;
+// The lean tests use only some of the fixtures.
+#[cfg(test)]
+#[cfg_attr(not(feature = "full"), allow(dead_code))]
+mod test_support;
+
+#[cfg(feature = "full")]
+use crate::{
+    cli::commands::Commands,
+    codec::execution::Execution,
+    io::{
+        compare::handle_compare, decoding::handle_decode, dump_matrix::handle_dump_matrix,
+        encoding::handle_encode, info::handle_info, reshape::handle_reshape, scrub::handle_scrub,
+        self_healing::handle_repair, serve::handle_serve, suggest::handle_suggest,
+        verify::handle_verify,
+    },
+};
+
+/// Dispatches a parsed command to its handler, running its encoding and
+/// reconstruction loops in `execution` mode.
+#[cfg(feature = "full")]
+pub async fn run(command: Commands, execution: &Execution) -> anyhow::Result<()> {
+    match command {
+        Commands::Encode { .. } => handle_encode(command, execution).await,
+        Commands::Decode { .. } => handle_decode(command, execution).await,
+        Commands::Info { .. } => handle_info(command).await,
+        Commands::Verify { .. } => handle_verify(command).await,
+        Commands::Scrub { .. } => handle_scrub(command, execution).await,
+        Commands::Repair { .. } => handle_repair(command, execution).await,
+        Commands::Compare { .. } => handle_compare(command).await,
+        Commands::DumpMatrix { .. } => handle_dump_matrix(command).await,
+        Commands::Reshape { .. } => handle_reshape(command, execution).await,
+        Commands::Serve { .. } => handle_serve(command, execution).await,
+        Commands::Suggest { .. } => handle_suggest(command).await,
+    }
+}
+
+#[cfg(all(test, feature = "full"))]
+mod tests {
+    use crate::test_support::{datasets, mixed_shards, random_input, random_shards, test_seed};
+    use crate::{
+        algorithm::{
+            field::GaloisField,
+            gf_matrix::{add_independent_row, identity, submatrix, transpose, unit_row},
+            gf256::{Gf256, xor_slice},
+            shuffle::permutation,
+        },
+        cli::logging::{self, LogFormat},
+        codec::{
+            channel::reconstruct_from_channel,
+            correct::{ErrorCorrection, correct_errors},
+            encode_shards::{shard_encoding, shard_encoding_lazy},
+            execution::Execution,
+            layout::ShardLayout,
+            locate::{CorruptionCheck, locate_corruption},
+            lrc::LrcCodec,
+            matrix::{
+                InversionScratch, Matrix, MatrixType, build_cauchy, build_vandermonde, check_mds,
+                format_matrix_hex, invert_matrix, invert_matrix_into, matrix_from_hex_rows,
+                matrix_to_bytes, matrix_to_hex_rows, mul_matrix_matrix, mul_matrix_vec,
+                mul_vec_matrix,
+            },
+            reconstruct_shards::{
+                Codec, CodecHandle, ReconstructReport, SurvivorSelection, sparsest_survivors,
+            },
+        },
+        error::{EXIT_CORRUPTION, EXIT_FAILURE, EXIT_INVALID_ARGS, EXIT_UNRECOVERABLE, exit_code},
+        io::{
+            atomic::{StagedFile, WriteOptions},
+            blockwise::decode_blockwise,
+            budget::shard_concurrency,
+            checksum::ChecksumAlgo,
+            compare::{ShardComparison, compare_dirs},
+            compression::Compression,
+            consistency::ShardSizeReport,
+            decoding::{DecodeOptions, assemble_data_shards, decode_dir, decode_dir_with_gaps},
+            encoding::{EncodeOptions, ParityEncoder, check_free_space, verify_drops},
+            info::ShardStatus,
+            manifest::{MANIFEST_FILE, Manifest, ManifestMismatch},
+            metadata::{ShardMetadata, interleaved_parity_order, shard_file_name},
+            objects::{frame_objects, object_dir},
+            partition::weighted_split,
+            report::OperationReport,
+            retry::RetryPolicy,
+            scramble::{scramble, unscramble},
+            scrub::{repair_shards, scrub_dir},
+            self_healing::{HEADER_LEN, SELF_HEALING_MAGIC, repair_self_healing},
+            serve::{ServeOptions, ServerState, router},
+            shard_log::SHARD_LOG_FILE,
+            sidecar::{ShardSidecar, sidecar_path},
+            split::{SPLIT_FILE, SplitInfo, decode_split},
+            store::{
+                FilesystemStore, ShardStore, decode_from_store, encode_to_store, prefetch_shards,
+            },
+            stream::{encode_stream, stream_shard_len},
+            suggest::suggest,
+            throttle::RateLimiter,
+            trailer::{ShardTrailer, TRAILER_LEN, check_trailer},
+            verify::{ChecksumScan, ShardSample},
+            volumes::volume_file_name,
+            wipe::{Wipe, Wiping},
+        },
+    };
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use clap::Parser;
+    use futures_util::future::BoxFuture;
+    use http_body_util::BodyExt;
+    use indicatif::ProgressBar;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+    use tower::ServiceExt;
+
+    /// Parses `args` as a whitespace-separated command line (without the
+    /// program name) and runs it. Temp paths never contain spaces.
+    async fn run_cli(args: &str) -> Result<()> {
+        let cli = crate::cli::commands::Cli::try_parse_from(
+            std::iter::once("litiaina-rse").chain(args.split_whitespace()),
+        )?;
+        let execution = cli.execution()?;
+        crate::run(cli.command, &execution).await
+    }
+
+    fn p(path: &Path) -> String {
+        path.display().to_string()
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() -> Result<()> {
+        let gf = Gf256::new();
+        let k = 10;
+        let m = 4;
+        let shard_len = 8192;
+        let seed = test_seed();
+
+        for dataset in datasets(k, shard_len, seed) {
+            let data_shards = &dataset.shards;
+            let pb = ProgressBar::new(m as u64);
+            let parities = shard_encoding(
+                &gf,
+                &build_vandermonde(&gf, k, m),
+                data_shards,
+                &pb,
+                &Execution::default(),
+            )?;
+            assert_eq!(parities.len(), m);
+
+            let mut shards_opt: Vec<Option<Vec<u8>>> = data_shards
+                .iter()
+                .chain(&parities)
+                .cloned()
+                .map(Some)
+                .collect();
+            shards_opt[1] = None;
+            shards_opt[3] = None;
+            shards_opt[k] = None;
+            shards_opt[k + 2] = None;
+
+            let codec = Codec::new(k, m);
+            codec.reconstruct(&mut shards_opt)?;
+
+            for i in 0..k {
+                assert_eq!(
+                    &data_shards[i],
+                    shards_opt[i].as_ref().unwrap(),
+                    "Shard {} of the {} dataset (seed {}) was not reconstructed correctly",
+                    i,
+                    dataset.name,
+                    seed
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_matrix_roundtrip() -> Result<()> {
+        let k = 4;
+        let m = 3;
+        let shard_len = 1024;
+        let data_shards = random_shards(k, shard_len, test_seed());
+
+        // Two parity rows dedicated to shards 0 and 1, one global row.
+        let matrix = vec![vec![1, 1, 0, 0], vec![1, 2, 0, 0], vec![1, 1, 1, 1]];
+
+        let codec = Codec::new(k, m);
+        let parities = codec.encode_with_matrix(&data_shards, &matrix)?;
+        assert_eq!(parities.len(), m);
+
+        let mut shards_opt: Vec<Option<Vec<u8>>> = data_shards
+            .iter()
+            .chain(parities.iter())
+            .cloned()
+            .map(Some)
+            .collect();
+        shards_opt[0] = None;
+        shards_opt[1] = None;
+        shards_opt[k + 2] = None;
+
+        codec.reconstruct_with_matrix(&mut shards_opt, &matrix)?;
+        for i in 0..k {
+            assert_eq!(shards_opt[i].as_ref().unwrap(), &data_shards[i]);
+        }
+        assert_eq!(shards_opt[k + 2].as_ref().unwrap(), &parities[2]);
+
+        assert!(
+            codec
+                .encode_with_matrix(&data_shards, &matrix[..2])
+                .is_err()
+        );
+        let bad_cols = vec![vec![1, 1, 0], vec![1, 2, 0], vec![1, 1, 1]];
+        assert!(codec.encode_with_matrix(&data_shards, &bad_cols).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_matrix_skips_dependent_survivors() -> Result<()> {
+        let (k, m) = (2, 2);
+        let data_shards = &random_shards(k, 512, test_seed());
+        // Parity 0 repeats data shard 0, so shards 0 and 2 are dependent.
+        let matrix = vec![vec![1, 0], vec![1, 1]];
+        let codec = Codec::with_matrix(k, m, matrix.clone())?;
+        let parities = codec.encode(data_shards)?;
+
+        let full: Vec<Option<Vec<u8>>> = data_shards
+            .iter()
+            .chain(&parities)
+            .cloned()
+            .map(Some)
+            .collect();
+        let mut shards_opt = full.clone();
+        shards_opt[1] = None;
+        let report = codec.reconstruct_with_report(&mut shards_opt)?;
+        assert_eq!(report.survivors, vec![0, 3]);
+        assert_eq!(shards_opt, full);
+
+        let mut shards_opt = full.clone();
+        shards_opt[1] = None;
+        codec.reconstruct_with_matrix(&mut shards_opt, &matrix)?;
+        assert_eq!(shards_opt, full);
+
+        // Shards 0 and 2 alone carry nothing of data shard 1.
+        let mut shards_opt = full.clone();
+        shards_opt[1] = None;
+        shards_opt[3] = None;
+        assert!(codec.reconstruct(&mut shards_opt).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_systematic_decodes_non_systematic_set() -> Result<()> {
+        let gf = Gf256::new();
+        let (k, m) = (5, 3);
+        let n = k + m;
+        let seed = test_seed();
+        let data_shards = &random_shards(k, 777, seed);
+
+        // Every row is coded, so no shard holds data bytes directly.
+        let generator = build_cauchy(&gf, k, n);
+        let pb = ProgressBar::hidden();
+        let coded = shard_encoding(&gf, &generator, data_shards, &pb, &Execution::default())?;
+        assert!((0..k).all(|i| coded[i] != data_shards[i]));
+
+        let codec = Codec::new(k, m);
+        let expected_parity = codec.encode_with_matrix(data_shards, codec.encode_matrix())?;
+        for trial in 0..8 {
+            let survivors = &permutation(n, seed ^ trial)[..k];
+            let shards_opt: Vec<Option<Vec<u8>>> = (0..n)
+                .map(|i| survivors.contains(&i).then(|| coded[i].clone()))
+                .collect();
+            let systematic = codec.to_systematic(&shards_opt, &generator)?;
+            assert_eq!(&systematic[..k], data_shards, "survivors {:?}", survivors);
+            assert_eq!(systematic[k..], expected_parity[..]);
+        }
+
+        let mut too_few: Vec<Option<Vec<u8>>> = coded.into_iter().map(Some).collect();
+        too_few[..m + 1].fill(None);
+        assert!(codec.to_systematic(&too_few, &generator).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparsest_survivor_selection() -> Result<()> {
+        let gf = Gf256::new();
+        let (k, m) = (4, 4);
+        let mut matrix = build_cauchy(&gf, k, 2);
+        matrix.push(vec![1, 1, 0, 0]);
+        matrix.push(vec![0, 0, 1, 1]);
+        let data_shards = &random_shards(k, 512, test_seed());
+        let codec = Codec::new(k, m).with_survivor_selection(SurvivorSelection::Sparsest);
+        let parities = codec.encode_with_matrix(data_shards, &matrix)?;
+
+        // Data shards 1 and 3 lost: each local XOR row covers one of them.
+        let present = [0, 2, 4, 5, 6, 7];
+        assert_eq!(
+            sparsest_survivors::<Gf256>(k, &matrix, &present),
+            [0, 2, 6, 7]
+        );
+        // A row touching no lost data shard is only a last resort.
+        assert_eq!(
+            sparsest_survivors::<Gf256>(k, &matrix, &[2, 3, 4, 6, 7]),
+            [2, 3, 4, 6]
+        );
+
+        let mut shards_opt: Vec<Option<Vec<u8>>> = data_shards
+            .iter()
+            .chain(&parities)
+            .cloned()
+            .map(Some)
+            .collect();
+        shards_opt[1] = None;
+        shards_opt[3] = None;
+        codec.reconstruct_with_matrix(&mut shards_opt, &matrix)?;
+        for i in 0..k {
+            assert_eq!(shards_opt[i].as_ref(), Some(&data_shards[i]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_free_space_check() {
+        let dir = std::env::temp_dir()
+            .join("litiaina_rse_space_check")
+            .join("not_created");
+        assert!(check_free_space(&dir, 1).is_ok());
+        let err = check_free_space(&dir, u64::MAX).unwrap_err();
+        assert!(err.to_string().contains("need"));
+    }
+
+    #[test]
+    fn test_matrix_products_match_naive() {
+        let gf = Gf256::new();
+        let seed = test_seed();
+        for &(rows, cols) in &[(1, 1), (4, 7), (16, 16), (32, 5)] {
+            let mat: Matrix = random_shards(rows, cols, seed);
+            let vec_r = random_input(rows, seed ^ 1);
+            let vec_c = random_input(cols, seed ^ 2);
+
+            let naive_vm: Vec<u8> = (0..cols)
+                .map(|j| (0..rows).fold(0, |acc, i| acc ^ gf.mul(vec_r[i], mat[i][j])))
+                .collect();
+            assert_eq!(mul_vec_matrix(&gf, &vec_r, &mat), naive_vm);
+
+            let naive_mv: Vec<u8> = (0..rows)
+                .map(|i| (0..cols).fold(0, |acc, j| acc ^ gf.mul(mat[i][j], vec_c[j])))
+                .collect();
+            assert_eq!(mul_matrix_vec(&gf, &mat, &vec_c), naive_mv);
+
+            let product = mul_matrix_matrix(&gf, &vec![vec_r.clone()], &mat);
+            assert_eq!(product, vec![naive_vm]);
+        }
+    }
+
+    #[test]
+    fn test_shard_status_distinguishes_lost_from_not_stored() {
+        let mut meta = ShardMetadata::new(1000, 4, 2);
+        meta.stored_shards = Some(vec![0, 1, 2, 3, 4]);
+
+        let present = [true, false, true, true, false, false];
+        let status = ShardStatus::classify(&meta, &present);
+        assert_eq!(status.present, vec![0, 2, 3]);
+        assert_eq!(status.missing_expected, vec![1, 4]);
+        assert_eq!(status.not_stored_here, vec![5]);
+
+        meta.stored_shards = None;
+        let status = ShardStatus::classify(&meta, &present);
+        assert_eq!(status.missing_expected, vec![1, 4, 5]);
+        assert!(status.not_stored_here.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter_caps_throughput() {
+        let rate = 4.0 * 1024.0 * 1024.0;
+        let limiter = RateLimiter::new(rate);
+        let start = tokio::time::Instant::now();
+        // The first second of tokens is available immediately; 12 MiB at
+        // 4 MiB/s should therefore take 2 seconds of (paused) clock time.
+        for _ in 0..12 {
+            limiter.acquire(1024 * 1024).await;
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        assert!(elapsed >= 1.99, "finished too quickly: {elapsed:.2}s");
+        assert!(elapsed < 2.1, "finished too slowly: {elapsed:.2}s");
+    }
+
+    #[test]
+    fn test_codec_try_new_rejects_invalid_params() {
+        assert!(Codec::try_new(0, 4).is_err());
+        assert!(Codec::try_new(4, 0).is_err());
+        assert!(Codec::try_new(0, 0).is_err());
+        assert!(Codec::try_new(200, 57).is_err());
+        assert!(Codec::try_new(200, 56).is_ok());
+        assert!(Codec::try_new(1, 1).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_manifest_detects_tampering() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut manifest = Manifest::new();
+        for (i, content) in [b"alpha".as_slice(), b"bravo", b"charlie"]
+            .iter()
+            .enumerate()
+        {
+            let name = format!("shard_{:02}.dat", i);
+            std::fs::write(dir.path().join(&name), content)?;
+            manifest.add(name, content);
+        }
+        assert!(manifest.verify_dir(dir.path()).await?.is_empty());
+
+        std::fs::write(dir.path().join("shard_01.dat"), b"brav0")?;
+        std::fs::write(dir.path().join("shard_02.dat"), b"char")?;
+        std::fs::remove_file(dir.path().join("shard_00.dat"))?;
+        let mismatches = manifest.verify_dir(dir.path()).await?;
+        assert_eq!(
+            mismatches,
+            vec![
+                ManifestMismatch::Missing("shard_00.dat".into()),
+                ManifestMismatch::HashMismatch("shard_01.dat".into()),
+                ManifestMismatch::SizeMismatch {
+                    file: "shard_02.dat".into(),
+                    expected: 7,
+                    actual: 4,
+                },
+            ]
+        );
+
+        // Entries that reach outside the shard directory are rejected before
+        // any file is read, whether the manifest is built or read from disk.
+        std::fs::write(dir.path().join("secret"), b"alpha")?;
+        let shard_dir = dir.path().join("shards");
+        std::fs::create_dir(&shard_dir)?;
+        let escaping = [
+            "../secret".to_string(),
+            dir.path().join("secret").to_string_lossy().into_owned(),
+            "sub/shard_00.dat".to_string(),
+            "sub\\shard_00.dat".to_string(),
+            ".".to_string(),
+        ];
+        for name in escaping {
+            let mut crafted = Manifest::new();
+            crafted.add(name.clone(), b"alpha");
+            let err = crafted.verify_dir(&shard_dir).await.unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_CORRUPTION, "{name}: {err:#}");
+
+            let path = shard_dir.join(MANIFEST_FILE);
+            std::fs::write(&path, serde_json::to_string(&crafted)?)?;
+            let err = Manifest::read(&path).await.unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_CORRUPTION, "{name}: {err:#}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy_encoding_matches_batch() -> Result<()> {
+        let gf = Gf256::new();
+        let (k, m) = (6, 5);
+        let data_shards = random_shards(k, 777, test_seed());
+        let matrix = build_vandermonde(&gf, k, m);
+
+        let batch = shard_encoding(
+            &gf,
+            &matrix,
+            &data_shards,
+            &ProgressBar::hidden(),
+            &Execution::default(),
+        )?;
+        let lazy = shard_encoding_lazy(&gf, &matrix, &data_shards)?;
+        assert_eq!(lazy.len(), m);
+        assert_eq!(lazy.collect::<Vec<_>>(), batch);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "log table has no valid entry")]
+    fn test_mul_rejects_incomplete_log_table() {
+        let mut gf = Gf256::new();
+        gf.log[0x53] = -1;
+        gf.mul(0x53, 0x02);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "log table has no valid entry")]
+    fn test_inv_rejects_incomplete_log_table() {
+        let mut gf = Gf256::new();
+        gf.log[0x07] = -1;
+        let _ = gf.inv(0x07);
+    }
+
+    #[test]
+    fn test_batch_inv_matches_inv() -> Result<()> {
+        let gf = Gf256::new();
+        let mut elems = random_shards(1, 600, test_seed()).remove(0);
+        elems.retain(|&a| a != 0);
+        for len in [0, 1, 2, 17, elems.len()] {
+            let batch = gf.batch_inv(&elems[..len])?;
+            let single = elems[..len]
+                .iter()
+                .map(|&a| gf.inv(a))
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(batch, single);
+        }
+        let all: Vec<u8> = (1..=255).collect();
+        for (a, a_inv) in all.iter().zip(gf.batch_inv(&all)?) {
+            assert_eq!(gf.mul(*a, a_inv), 1);
+        }
+
+        let err = gf.batch_inv(&[3, 9, 0, 4]).unwrap_err();
+        assert!(err.to_string().contains("element 2"), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compare_reports_differing_and_missing_shards() -> Result<()> {
+        let a = tempfile::tempdir()?;
+        let b = tempfile::tempdir()?;
+        let meta = ShardMetadata::new(8, 2, 2);
+        for dir in [a.path(), b.path()] {
+            meta.write(dir).await?;
+            std::fs::write(dir.join("shard_00.dat"), b"same")?;
+        }
+        std::fs::write(a.path().join("shard_01.dat"), b"aaaa")?;
+        std::fs::write(b.path().join("shard_01.dat"), b"bbbb")?;
+        std::fs::write(a.path().join("shard_02.dat"), b"only")?;
+
+        let report = compare_dirs(a.path(), b.path()).await?;
+        assert_eq!(
+            report.shards,
+            vec![
+                ShardComparison::Identical,
+                ShardComparison::Differs,
+                ShardComparison::OnlyInA,
+                ShardComparison::MissingInBoth,
+            ]
+        );
+        assert!(!report.is_identical());
+
+        ShardMetadata::new(9, 2, 2).write(b.path()).await?;
+        assert!(compare_dirs(a.path(), b.path()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compressed_encode_decode_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let text: Vec<u8> = b"the quick brown fox jumps over the lazy dog\n"
+            .iter()
+            .copied()
+            .cycle()
+            .take(50_000)
+            .collect();
+        std::fs::write(&input, &text)?;
+        let output = dir.path().join("output.txt");
+
+        // The input compressors are the same as for --compress-shards.
+        for (name, compression) in [("zstd", Compression::Zstd), ("gzip", Compression::Gzip)] {
+            let shards = dir.path().join(name);
+            run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 --compress {}",
+                p(&input),
+                p(&shards),
+                name
+            ))
+            .await?;
+            let meta = ShardMetadata::read(&shards).await?;
+            assert_eq!(meta.compression, Some(compression));
+            assert_eq!(meta.uncompressed_len, Some(text.len()));
+            assert!(meta.orig_len < text.len());
+
+            std::fs::remove_file(shards.join("shard_01.dat"))?;
+            run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+            assert_eq!(std::fs::read(&output)?, text, "{name}");
+        }
+
+        // Incompressible input is stored as-is rather than expanded.
+        let noise = random_input(4096, test_seed());
+        std::fs::write(&input, &noise)?;
+        let noise_shards = dir.path().join("noise");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --compress zstd",
+            p(&input),
+            p(&noise_shards)
+        ))
+        .await?;
+        let meta = ShardMetadata::read(&noise_shards).await?;
+        assert_eq!(meta.compression, None);
+        assert_eq!(meta.orig_len, noise.len());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reconstruct_from_channel() -> Result<()> {
+        let gf = Gf256::new();
+        let (k, m) = (4, 3);
+        let data_shards = random_shards(k, 512, test_seed());
+        let parities = shard_encoding(
+            &gf,
+            &build_vandermonde(&gf, k, m),
+            &data_shards,
+            &ProgressBar::hidden(),
+            &Execution::default(),
+        )?;
+        let all: Vec<Vec<u8>> = data_shards.iter().chain(&parities).cloned().collect();
+        let codec = std::sync::Arc::new(Codec::new(k, m));
+
+        // Shards arrive out of order with a duplicate; the receiver is
+        // dropped after k distinct shards, so later sends fail.
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let sender = {
+            let all = all.clone();
+            tokio::spawn(async move {
+                let mut sent = 0;
+                for idx in [5, 2, 5, 0, 6, 1, 3] {
+                    if tx.send((idx, all[idx].clone())).await.is_err() {
+                        break;
+                    }
+                    sent += 1;
+                }
+                sent
+            })
+        };
+        let recovered = reconstruct_from_channel(codec.clone(), rx).await?;
+        for (i, shard) in recovered.iter().enumerate() {
+            assert_eq!(shard.as_ref().unwrap(), &all[i]);
+        }
+        assert!(
+            sender.await? < 7,
+            "receiver should stop once k shards arrive"
+        );
+
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        tx.send((0, all[0].clone())).await?;
+        tx.send((1, all[1].clone())).await?;
+        drop(tx);
+        assert!(reconstruct_from_channel(codec, rx).await.is_err());
+
+        // Both parity rows of this non-MDS matrix are equal, so the first two
+        // shards to arrive are not enough and a data shard is waited for.
+        let matrix = vec![vec![1, 1], vec![1, 1]];
+        let data_shards = random_shards(2, 64, test_seed());
+        let parities = shard_encoding(
+            &gf,
+            &matrix,
+            &data_shards,
+            &ProgressBar::hidden(),
+            &Execution::default(),
+        )?;
+        let all: Vec<Vec<u8>> = data_shards.iter().chain(&parities).cloned().collect();
+        let codec = std::sync::Arc::new(Codec::with_matrix(2, 2, matrix)?);
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        for idx in [2, 3, 1] {
+            tx.send((idx, all[idx].clone())).await?;
+        }
+        let recovered = reconstruct_from_channel(codec.clone(), rx).await?;
+        for (i, shard) in recovered.iter().enumerate() {
+            assert_eq!(shard.as_ref().unwrap(), &all[i]);
+        }
+        assert!(tx.is_closed());
+
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        tx.send((2, all[2].clone())).await?;
+        tx.send((3, all[3].clone())).await?;
+        drop(tx);
+        assert!(reconstruct_from_channel(codec, rx).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_assembly_rejects_inconsistent_length() {
+        let shards: Vec<Option<Vec<u8>>> = (0..4).map(|i| Some(vec![i as u8; 100])).collect();
+        let pb = ProgressBar::hidden();
+
+        let assembled = assemble_data_shards(&shards, 397, 100, &pb).unwrap();
+        assert_eq!(assembled.len(), 397);
+
+        let err = assemble_data_shards(&shards, 10, 10usize.div_ceil(4), &pb).unwrap_err();
+        assert!(err.to_string().contains("inconsistent"), "{err}");
+        let err = assemble_data_shards(&shards, 401, 100, &pb).unwrap_err();
+        assert!(err.to_string().contains("inconsistent"), "{err}");
+
+        // An aligned set pads every shard well past `ceil(orig_len / k)`.
+        let assembled = assemble_data_shards(&shards, 10, 100, &pb).unwrap();
+        assert_eq!(assembled, vec![0; 10]);
+    }
+
+    #[test]
+    fn test_format_matrix_hex() {
+        let matrix = vec![vec![0x01, 0xab], vec![0x00, 0xff]];
+        assert_eq!(
+            format_matrix_hex(&matrix),
+            "       0  1\n   0: 01 ab\n   1: 00 ff\n"
+        );
+    }
+
+    #[test]
+    fn test_cauchy_every_survivor_set_is_invertible() -> Result<()> {
+        let (k, m) = (4, 3);
+        let codec = Codec::try_with_matrix_type(k, m, MatrixType::Cauchy)?;
+        assert_eq!(codec.encode_matrix(), &build_cauchy(&Gf256::new(), k, m));
+        for mask in 0u32..(1 << (k + m)) {
+            if mask.count_ones() as usize != k {
+                continue;
+            }
+            let survivors: Vec<usize> = (0..k + m).filter(|i| mask & (1 << i) != 0).collect();
+            codec.inverse_matrix(&survivors)?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rotated_stripes_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(10_007, test_seed());
+        std::fs::write(&input, &data)?;
+        let plain = dir.path().join("plain");
+        let rotated = dir.path().join("rotated");
+        let output = dir.path().join("output.bin");
+
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&plain)
+        ))
+        .await?;
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --rotate-stripes 100",
+            p(&input),
+            p(&rotated)
+        ))
+        .await?;
+        assert_eq!(
+            ShardMetadata::read(&rotated).await?.stripe_rotation,
+            Some(100)
+        );
+        // The second stripe of shard 5 (parity) lands in device file 0.
+        let parity = std::fs::read(plain.join("shard_05.dat"))?;
+        let device0 = std::fs::read(rotated.join("shard_00.dat"))?;
+        assert_eq!(device0[100..200], parity[100..200]);
+
+        // Every stripe loses a different pair of shards.
+        std::fs::remove_file(rotated.join("shard_00.dat"))?;
+        std::fs::remove_file(rotated.join("shard_03.dat"))?;
+        run_cli(&format!("decode -i {} -o {}", p(&rotated), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_inverse_cache_export_import() -> Result<()> {
+        let codec = Codec::try_with_matrix_type(4, 2, MatrixType::Cauchy)?;
+        codec.inverse_matrix(&[0, 1, 2, 4])?;
+        codec.inverse_matrix(&[1, 3, 4, 5])?;
+        let exported = codec.export_cache();
+        assert_eq!(exported.len(), 2);
+
+        let fresh = Codec::try_with_matrix_type(4, 2, MatrixType::Cauchy)?;
+        assert_eq!(fresh.import_cache(exported.clone())?, 2);
+        assert_eq!(fresh.export_cache(), exported);
+
+        // Inverses for a different encoding matrix are rejected.
+        let other = Codec::try_new(4, 2)?;
+        assert!(other.import_cache(exported.clone()).is_err());
+        assert!(other.export_cache().is_empty());
+
+        let mut wrong_shape = exported.clone();
+        wrong_shape[0].1.pop();
+        assert!(fresh.import_cache(wrong_shape).is_err());
+        let mut unsorted = exported;
+        unsorted[0].0.reverse();
+        assert!(fresh.import_cache(unsorted).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_codec_handles_share_cache() -> Result<()> {
+        let codec = Codec::new(4, 2);
+        let data = datasets(4, 1000, test_seed()).remove(0).shards;
+        let parity = codec.encode(&data)?;
+        let full: Vec<Option<Vec<u8>>> = data.iter().chain(&parity).cloned().map(Some).collect();
+        let lose = |lost: &[usize]| {
+            let mut shards = full.clone();
+            for &i in lost {
+                shards[i] = None;
+            }
+            shards
+        };
+
+        let handle: CodecHandle = codec.handle();
+        let clone = handle.clone();
+        assert!(std::ptr::eq(handle.field(), codec.field()));
+        assert!(std::ptr::eq(clone.encode_matrix(), codec.encode_matrix()));
+        assert_eq!(clone.encode(&data)?, parity);
+
+        // An inverse computed through one clone is a hit for every other.
+        let mut shards = lose(&[0, 3]);
+        let report = handle.reconstruct_with_report(&mut shards)?;
+        assert!(!report.cache_hit);
+        assert_eq!(shards, full);
+        let mut shards = lose(&[0, 3]);
+        assert!(clone.reconstruct_with_report(&mut shards)?.cache_hit);
+        assert_eq!(codec.cached_inverses(), 1);
+
+        // Clones moved into threads fill the same cache.
+        std::thread::scope(|scope| {
+            for lost in [[1, 2], [0, 5], [2, 4]] {
+                let handle = clone.clone();
+                let mut shards = lose(&lost);
+                scope.spawn(move || {
+                    handle.reconstruct(&mut shards).unwrap();
+                });
+            }
+        });
+        assert_eq!(codec.cached_inverses(), 4);
+        assert_eq!(handle.cached_inverses(), 4);
+        assert!(codec.mac_passes() > 0);
+        assert_eq!(handle.mac_passes(), codec.mac_passes());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_strict_decode_rejects_overlong_shard() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(4000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.txt");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+
+        let parity = shards.join("shard_05.dat");
+        let mut contents = std::fs::read(&parity)?;
+        contents.extend_from_slice(b"garbage");
+        std::fs::write(&parity, contents)?;
+
+        run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        let err = run_cli(&format!(
+            "decode -i {} -o {} --strict",
+            p(&shards),
+            p(&output)
+        ))
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("Shard 5 is 1007 bytes"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_interleaved_layout_matches_row_major() -> Result<()> {
+        let (k, m) = (10, 4);
+        let codec = Codec::try_with_matrix_type(k, m, MatrixType::Cauchy)?;
+        let data = random_shards(k, 1000, test_seed());
+        let mut full = data.clone();
+        full.extend(codec.encode_with_matrix(&data, codec.encode_matrix())?);
+
+        for lost in [vec![0, 3, 7, 12], vec![1, 10], vec![13]] {
+            let damaged: Vec<Option<Vec<u8>>> = full
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (!lost.contains(&i)).then(|| s.clone()))
+                .collect();
+            let mut row_major = damaged.clone();
+            codec.reconstruct_with_layout(&mut row_major, ShardLayout::RowMajor)?;
+            let mut interleaved = damaged;
+            codec.reconstruct_with_layout(&mut interleaved, ShardLayout::Interleaved)?;
+            assert_eq!(interleaved, row_major);
+            assert!(
+                interleaved
+                    .iter()
+                    .zip(&full)
+                    .all(|(a, b)| a.as_ref() == Some(b))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_parity_ranges_matches_full_encode() -> Result<()> {
+        let (k, m, shard_len) = (6, 3, 512);
+        let codec = Codec::try_new(k, m)?;
+        let old = random_input(k * shard_len, test_seed());
+        let to_shards = |bytes: &[u8]| -> Vec<Vec<u8>> {
+            bytes.chunks(shard_len).map(|c| c.to_vec()).collect()
+        };
+        let old_data = to_shards(&old);
+        let mut parities = codec.encode_with_matrix(&old_data, codec.encode_matrix())?;
+
+        let mut new = old.clone();
+        let ranges = [10..20, 1000..1100, 3000..3001];
+        for range in ranges.clone() {
+            for b in &mut new[range] {
+                *b = b.wrapping_mul(3).wrapping_add(1);
+            }
+        }
+        let changed = codec.shards_for_ranges(&ranges, shard_len);
+        assert_eq!(changed, vec![0, 1, 2, 5]);
+
+        let new_data = to_shards(&new);
+        codec.update_parity_ranges(&old_data, &new_data, &changed, &mut parities)?;
+        assert_eq!(
+            parities,
+            codec.encode_with_matrix(&new_data, codec.encode_matrix())?
+        );
+        assert!(
+            codec
+                .update_parity_ranges(&old_data, &new_data, &[k], &mut parities)
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_errors_map_to_exit_codes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        std::fs::write(&input, vec![7u8; 3000])?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.txt");
+
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 0 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+
+        run_cli(&format!(
+            "encode -i {} -o {} -d 3 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        std::fs::write(shards.join("shard_04.dat"), b"too long for a shard")?;
+        let err = run_cli(&format!(
+            "decode -i {} -o {} --strict",
+            p(&shards),
+            p(&output)
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+
+        for i in 0..3 {
+            std::fs::remove_file(shards.join(format!("shard_{:02}.dat", i)))?;
+        }
+        let err = run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+
+        let err = run_cli(&format!("info -i {}", p(&dir.path().join("missing"))))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_FAILURE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scrambled_encode_decode_roundtrip() -> Result<()> {
+        let data: Vec<u8> = (0..=255u8).cycle().take(10_000).collect();
+        let scrambled = scramble(&data, 42);
+        assert_ne!(scrambled, data);
+        assert_ne!(scramble(&data, 43), scrambled);
+        assert_eq!(unscramble(&scrambled, 42), data);
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.bin");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --scramble 42",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        assert_eq!(ShardMetadata::read(&shards).await?.scramble_seed, Some(42));
+        let first = std::fs::read(shards.join("shard_00.dat"))?;
+        assert_eq!(first, scrambled[..first.len()]);
+
+        std::fs::remove_file(shards.join("shard_02.dat"))?;
+        run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cached_mul_tables_match_mul_table() {
+        let gf = Gf256::new();
+        for factor in 0..=255u8 {
+            assert_eq!(*gf.mul_table_ref(factor), gf.mul_table(factor));
+        }
+        assert!(std::ptr::eq(gf.mul_tables_all(), gf.mul_tables_all()));
+    }
+
+    #[tokio::test]
+    async fn test_reshape_changes_parameters() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(20_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let reshaped = dir.path().join("reshaped");
+        let output = dir.path().join("output.txt");
+
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        std::fs::remove_file(shards.join("shard_01.dat"))?;
+        run_cli(&format!(
+            "reshape -i {} -o {} -d 6 -p 3",
+            p(&shards),
+            p(&reshaped)
+        ))
+        .await?;
+
+        let meta = ShardMetadata::read(&reshaped).await?;
+        assert_eq!((meta.data_shards, meta.parity_shards), (6, 3));
+        assert!(reshaped.join("shard_08.dat").exists());
+
+        std::fs::remove_file(reshaped.join("shard_00.dat"))?;
+        std::fs::remove_file(reshaped.join("shard_07.dat"))?;
+        run_cli(&format!("decode -i {} -o {}", p(&reshaped), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // A source set that can no longer be recovered is refused.
+        for i in 2..4 {
+            std::fs::remove_file(shards.join(format!("shard_{:02}.dat", i)))?;
+        }
+        let err = run_cli(&format!(
+            "reshape -i {} -o {} -d 3 -p 2",
+            p(&shards),
+            p(&dir.path().join("again"))
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_weighted_shards_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(10_001, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.txt");
+
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --shard-weights 4,2,1,1",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let sizes: Vec<u64> = (0..6)
+            .map(|i| std::fs::metadata(shards.join(format!("shard_{:02}.dat", i))).map(|m| m.len()))
+            .collect::<std::io::Result<_>>()?;
+        assert_eq!(sizes, [5001, 2500, 1250, 1250, 5001, 5001]);
+        assert_eq!(weighted_split(10, &[1, 0, 3]), [3, 0, 7]);
+
+        // --low-memory writes the same shorter data shards.
+        let low_memory = dir.path().join("low_memory");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --shard-weights 4,2,1,1 --low-memory",
+            p(&input),
+            p(&low_memory)
+        ))
+        .await?;
+        for i in 0..6 {
+            let name = shard_file_name(i);
+            assert_eq!(
+                std::fs::read(low_memory.join(&name))?,
+                std::fs::read(shards.join(&name))?,
+                "shard {i}"
+            );
+        }
+
+        std::fs::remove_file(shards.join("shard_00.dat"))?;
+        std::fs::remove_file(shards.join("shard_05.dat"))?;
+        run_cli(&format!(
+            "decode -i {} -o {} --strict",
+            p(&shards),
+            p(&output)
+        ))
+        .await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --shard-weights 1,1",
+            p(&input),
+            p(&dir.path().join("bad"))
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parallel_files_encode() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let shards = dir.path().join("shards");
+        let mut inputs = Vec::new();
+        for (i, len) in [3_000usize, 17_001, 0].into_iter().enumerate() {
+            let input = dir.path().join(format!("file{}.bin", i));
+            let data = random_input(len, test_seed() ^ i as u64);
+            std::fs::write(&input, &data)?;
+            inputs.push((input, data));
+        }
+        let input_args: Vec<String> = inputs.iter().map(|(path, _)| p(path)).collect();
+
+        run_cli(&format!(
+            "encode -i {} -o {} -d 3 -p 2 --parallel-files 2",
+            input_args.join(" "),
+            p(&shards)
+        ))
+        .await?;
+
+        for (i, (_, data)) in inputs.iter().enumerate() {
+            let file_shards = shards.join(format!("file{}.bin", i));
+            std::fs::remove_file(file_shards.join("shard_01.dat"))?;
+            let output = dir.path().join(format!("out{}.bin", i));
+            run_cli(&format!("decode -i {} -o {}", p(&file_shards), p(&output))).await?;
+            assert_eq!(std::fs::read(&output)?, *data);
+        }
+
+        // Several inputs without --parallel-files are rejected.
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 3 -p 2",
+            input_args.join(" "),
+            p(&dir.path().join("again"))
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_input_range_encodes_a_slice() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("disk.img");
+        let data = random_input(10_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --input-offset 3000 --input-length 4321",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        assert_eq!(ShardMetadata::read(&shards).await?.orig_len, 4321);
+        assert_eq!(
+            decode_dir(&shards, &DecodeOptions::default()).await?,
+            &data[3000..7321]
+        );
+
+        // Without a length, the slice runs to the end of the file.
+        let tail = dir.path().join("tail");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --input-offset 9000",
+            p(&input),
+            p(&tail)
+        ))
+        .await?;
+        assert_eq!(
+            decode_dir(&tail, &DecodeOptions::default()).await?,
+            &data[9000..]
+        );
+
+        for range in [
+            "--input-offset 10001",
+            "--input-offset 9000 --input-length 1001",
+        ] {
+            let err = run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 {}",
+                p(&input),
+                p(&dir.path().join("bad")),
+                range
+            ))
+            .await
+            .unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{}", range);
+            assert!(format!("{:#}", err).contains("past the end"), "{:#}", err);
+        }
+        Ok(())
+    }
+
+    /// Shard store keeping everything in memory, to check that encode and
+    /// decode go through [`ShardStore`] alone.
+    #[derive(Default)]
+    struct MemoryStore {
+        shards: std::sync::Mutex<std::collections::HashMap<usize, Vec<u8>>>,
+        meta: std::sync::Mutex<Option<ShardMetadata>>,
+    }
+
+    impl ShardStore for MemoryStore {
+        fn read_shard(&self, index: usize) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+            Box::pin(async move { Ok(self.shards.lock().unwrap().get(&index).cloned()) })
+        }
+
+        fn write_shard<'a>(&'a self, index: usize, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.shards.lock().unwrap().insert(index, data.to_vec());
+                Ok(())
+            })
+        }
+
+        fn read_metadata(&self) -> BoxFuture<'_, Result<Option<ShardMetadata>>> {
+            Box::pin(async move { Ok(self.meta.lock().unwrap().clone()) })
+        }
+
+        fn write_metadata<'a>(&'a self, meta: &'a ShardMetadata) -> BoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                *self.meta.lock().unwrap() = Some(meta.clone());
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shard_store_roundtrip() -> Result<()> {
+        let data = mixed_shards(1, 50_000, test_seed()).concat();
+        let opts = EncodeOptions {
+            data_shards: 5,
+            parity_shards: 3,
+            checksum_algo: ChecksumAlgo::Blake3,
+            compress_shards: Some(Compression::Zstd),
+            shard_trailer: true,
+            ..Default::default()
+        };
+        let store = MemoryStore::default();
+        encode_to_store(data.clone(), &store, &opts).await?;
+        assert_eq!(store.shards.lock().unwrap().len(), 8);
+
+        // Up to m shards can go missing or be damaged.
+        store.shards.lock().unwrap().remove(&0);
+        store.shards.lock().unwrap().remove(&6);
+        store.shards.lock().unwrap().get_mut(&3).unwrap()[0] ^= 1;
+        let decoded = decode_from_store(&store, &DecodeOptions::default()).await?;
+        assert_eq!(decoded, data);
+
+        store.shards.lock().unwrap().remove(&1);
+        let err = decode_from_store(&store, &DecodeOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        assert!(
+            decode_from_store(&MemoryStore::default(), &DecodeOptions::default())
+                .await
+                .is_err()
+        );
+
+        // The filesystem store reads and writes what the CLI does.
+        let dir = tempfile::tempdir()?;
+        let shards = dir.path().join("shards");
+        encode_to_store(data.clone(), &FilesystemStore::new(&shards), &opts).await?;
+        std::fs::remove_file(shards.join(shard_file_name(2)))?;
+        assert_eq!(decode_dir(&shards, &DecodeOptions::default()).await?, data);
+        let input = dir.path().join("input.bin");
+        std::fs::write(&input, &data)?;
+        let cli_shards = dir.path().join("cli");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 5 -p 3 --volume-size 4096",
+            p(&input),
+            p(&cli_shards)
+        ))
+        .await?;
+        let decoded = decode_from_store(
+            &FilesystemStore::new(&cli_shards),
+            &DecodeOptions::default(),
+        )
+        .await?;
+        assert_eq!(decoded, data);
+
+        // The store follows a set rewritten under it with another layout.
+        let fs_store = FilesystemStore::new(&shards);
+        assert!(
+            fs_store
+                .read_metadata()
+                .await?
+                .unwrap()
+                .volume_size
+                .is_none()
+        );
+        std::fs::remove_dir_all(&shards)?;
+        run_cli(&format!(
+            "encode -i {} -o {} -d 5 -p 3 --volume-size 4096",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        assert!(
+            fs_store
+                .read_metadata()
+                .await?
+                .unwrap()
+                .volume_size
+                .is_some()
+        );
+        assert!(fs_store.read_shard(0).await?.is_some());
+        assert_eq!(
+            decode_from_store(&fs_store, &DecodeOptions::default()).await?,
+            data
+        );
+
+        // Shards are written and removed where the metadata places them:
+        // shard 0 goes back in volumes.
+        let first_volume = shards.join(volume_file_name(&shard_file_name(0), 0));
+        let shard = fs_store.read_shard(0).await?.unwrap();
+        fs_store.remove_shard(0).await?;
+        assert!(!first_volume.exists());
+        fs_store.write_shard(0, &shard).await?;
+        assert!(first_volume.exists() && !shards.join(shard_file_name(0)).exists());
+        assert_eq!(decode_dir(&shards, &DecodeOptions::default()).await?, data);
+
+        // Parity computed one row at a time is the same with any store.
+        let low_memory = MemoryStore::default();
+        let opts = EncodeOptions {
+            low_memory: true,
+            ..opts
+        };
+        encode_to_store(data.clone(), &low_memory, &opts).await?;
+        assert_eq!(low_memory.shards.lock().unwrap().len(), 8);
+        assert_eq!(
+            decode_from_store(&low_memory, &DecodeOptions::default()).await?,
+            data
+        );
+
+        let err = encode_to_store(
+            data,
+            &store,
+            &EncodeOptions {
+                shard_log: true,
+                ..opts
+            },
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    /// A remote store whose shards each take their own time to arrive. It
+    /// also measures the bytes its reads hold while in flight.
+    #[derive(Default)]
+    struct SlowStore {
+        inner: MemoryStore,
+        delays_ms: Vec<u64>,
+        started: std::sync::Mutex<Vec<usize>>,
+        canceled: std::sync::Mutex<Vec<usize>>,
+        reading_bytes: AtomicUsize,
+        peak_reading_bytes: AtomicUsize,
+    }
+
+    /// Records a fetch as canceled if it is dropped before it finishes.
+    struct FetchGuard<'a> {
+        store: &'a SlowStore,
+        index: usize,
+        done: bool,
+    }
+
+    impl Drop for FetchGuard<'_> {
+        fn drop(&mut self) {
+            if !self.done {
+                self.store.canceled.lock().unwrap().push(self.index);
+            }
+        }
+    }
+
+    impl ShardStore for SlowStore {
+        fn read_shard(&self, index: usize) -> BoxFuture<'_, Result<Option<Vec<u8>>>> {
+            Box::pin(async move {
+                self.started.lock().unwrap().push(index);
+                let mut guard = FetchGuard {
+                    store: self,
+                    index,
+                    done: false,
+                };
+                // The buffer exists from the start of the read, as it would
+                // while a remote response streams in.
+                let shard = self.inner.read_shard(index).await?;
+                let len = shard.as_ref().map_or(0, Vec::len);
+                let reading = self.reading_bytes.fetch_add(len, Ordering::SeqCst) + len;
+                self.peak_reading_bytes.fetch_max(reading, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(self.delays_ms[index])).await;
+                self.reading_bytes.fetch_sub(len, Ordering::SeqCst);
+                guard.done = true;
+                Ok(shard)
+            })
+        }
+
+        fn write_shard<'a>(&'a self, index: usize, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+            self.inner.write_shard(index, data)
+        }
+
+        fn read_metadata(&self) -> BoxFuture<'_, Result<Option<ShardMetadata>>> {
+            self.inner.read_metadata()
+        }
+
+        fn write_metadata<'a>(&'a self, meta: &'a ShardMetadata) -> BoxFuture<'a, Result<()>> {
+            self.inner.write_metadata(meta)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_prefetch_stops_at_k_shards() -> Result<()> {
+        let data = random_input(40_000, test_seed());
+        let opts = EncodeOptions {
+            data_shards: 4,
+            parity_shards: 3,
+            ..Default::default()
+        };
+        // Shards 6, 2, 5 and 0 arrive first; the rest would take 10 s.
+        let mut store = SlowStore {
+            delays_ms: vec![160, 10_000, 60, 10_000, 10_000, 110, 10],
+            ..Default::default()
+        };
+        encode_to_store(data.clone(), &store, &opts).await?;
+        let meta = store.read_metadata().await?.unwrap();
+        let start = Instant::now();
+        let (shards_opt, report) =
+            prefetch_shards(&store, &meta, &DecodeOptions::default()).await?;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(report.arrived, vec![6, 2, 5, 0]);
+        assert_eq!(report.canceled, vec![1, 3, 4]);
+        assert!(report.rejected.is_empty());
+        assert_eq!(store.started.lock().unwrap().len(), 7);
+        let mut canceled = store.canceled.lock().unwrap().clone();
+        canceled.sort();
+        assert_eq!(canceled, vec![1, 3, 4]);
+        assert_eq!(shards_opt.iter().filter(|s| s.is_some()).count(), 4);
+        assert_eq!(
+            decode_from_store(&store, &DecodeOptions::default()).await?,
+            data
+        );
+
+        // A damaged early shard does not count; the next one to arrive does.
+        store.inner.shards.lock().unwrap().get_mut(&2).unwrap()[0] ^= 1;
+        store.delays_ms[4] = 210;
+        let (_, report) = prefetch_shards(&store, &meta, &DecodeOptions::default()).await?;
+        assert_eq!(report.arrived, vec![6, 5, 0, 4]);
+        assert_eq!(report.rejected, vec![2]);
+
+        // Four shards covering both local groups but only one global parity
+        // cannot rebuild the data: data 0 and 1 with the local parity of
+        // their own group leave group two short. Wait for one more.
+        let opts = EncodeOptions {
+            data_shards: 4,
+            parity_shards: 2,
+            local_groups: Some(2),
+            ..Default::default()
+        };
+        let store = SlowStore {
+            delays_ms: vec![10, 60, 10_000, 10_000, 160, 210, 110, 10_000],
+            ..Default::default()
+        };
+        encode_to_store(data.clone(), &store, &opts).await?;
+        let meta = store.read_metadata().await?.unwrap();
+        let (_, report) = prefetch_shards(&store, &meta, &DecodeOptions::default()).await?;
+        assert_eq!(report.arrived, vec![0, 1, 6, 4, 5]);
+        assert_eq!(report.canceled, vec![2, 3, 7]);
+        assert_eq!(
+            decode_from_store(&store, &DecodeOptions::default()).await?,
+            data
+        );
+
+        // Two parity shards with equal rows of a non-MDS matrix only count
+        // once; a data shard is still needed.
+        let opts = EncodeOptions {
+            data_shards: 2,
+            parity_shards: 2,
+            matrix_type: MatrixType::Custom,
+            custom_matrix: Some(vec![vec![1, 1], vec![1, 1]]),
+            ..Default::default()
+        };
+        let store = SlowStore {
+            delays_ms: vec![10_000, 160, 10, 60],
+            ..Default::default()
+        };
+        encode_to_store(data.clone(), &store, &opts).await?;
+        let meta = store.read_metadata().await?.unwrap();
+        let (_, report) = prefetch_shards(&store, &meta, &DecodeOptions::default()).await?;
+        assert_eq!(report.arrived, vec![2, 3, 1]);
+        assert_eq!(report.canceled, vec![0]);
+        assert_eq!(
+            decode_from_store(&store, &DecodeOptions::default()).await?,
+            data
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_too_many_tiny_shards_need_force() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(100, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let args = format!("encode -i {} -o {} -d 250 -p 5", p(&input), p(&shards));
+
+        let err = run_cli(&args).await.unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        assert!(format!("{:#}", err).contains("--force"));
+        assert!(!shards.exists());
+
+        run_cli(&format!("{} --force", args)).await?;
+        assert_eq!(decode_dir(&shards, &DecodeOptions::default()).await?, data);
+
+        // Streamed input is checked before the first stripe is written, and
+        // framed objects one by one: the large first object passes, the tiny
+        // second one is refused before its set is written.
+        let streamed = dir.path().join("streamed");
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 250 -p 5 --stream-stripe 4096",
+            p(&input),
+            p(&streamed)
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{err:#}");
+        assert!(!streamed.exists());
+
+        let stream = dir.path().join("objects.bin");
+        let large = random_input(250 * 4096, test_seed());
+        std::fs::write(&stream, frame_objects(&[large, data]))?;
+        let objects = dir.path().join("objects");
+        let args = format!(
+            "encode -i {} -o {} -d 250 -p 5 --framed-objects",
+            p(&stream),
+            p(&objects)
+        );
+        let err = run_cli(&args).await.unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{err:#}");
+        assert!(object_dir(&objects, 0).exists());
+        assert!(!object_dir(&objects, 1).exists());
+        run_cli(&format!("{} --force", args)).await?;
+        assert!(object_dir(&objects, 1).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_framed_objects_encode_one_set_each() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let shards = dir.path().join("shards");
+        let mut random = random_shards(4, 5_000, test_seed()).concat();
+        random.truncate(10_003);
+        // An empty object and one shorter than k sit between ordinary ones.
+        let objects: Vec<Vec<u8>> = vec![random, Vec::new(), b"ab".to_vec(), vec![7; 4_096]];
+        let stream = dir.path().join("objects.bin");
+        std::fs::write(&stream, frame_objects(&objects))?;
+
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --framed-objects",
+            p(&stream),
+            p(&shards)
+        ))
+        .await?;
+
+        for (id, object) in objects.iter().enumerate() {
+            let set = object_dir(&shards, id);
+            assert_eq!(ShardMetadata::read(&set).await?.orig_len, object.len());
+            std::fs::remove_file(set.join("shard_00.dat"))?;
+            std::fs::remove_file(set.join("shard_05.dat"))?;
+            let output = dir.path().join(format!("out{}.bin", id));
+            run_cli(&format!("decode -i {} -o {}", p(&set), p(&output))).await?;
+            assert_eq!(std::fs::read(&output)?, *object);
+        }
+        assert!(!object_dir(&shards, objects.len()).exists());
+
+        // A stream cut off inside an object is rejected.
+        let mut truncated = frame_objects(&objects);
+        truncated.truncate(truncated.len() - 1);
+        std::fs::write(&stream, truncated)?;
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --framed-objects",
+            p(&stream),
+            p(&dir.path().join("again"))
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_encode_roundtrip() -> Result<()> {
+        assert_eq!(stream_shard_len(0, 4, 100), 0);
+        assert_eq!(stream_shard_len(10, 4, 100), 3);
+        assert_eq!(stream_shard_len(400, 4, 100), 100);
+        assert_eq!(stream_shard_len(1_001, 4, 100), 251);
+
+        let dir = tempfile::tempdir()?;
+        let data = random_input((6 << 20) + 12_345, test_seed());
+
+        // The generated stream is piped through in uneven writes, so stripes
+        // are assembled from several reads.
+        let (mut reader, mut writer) = tokio::io::duplex(64 << 10);
+        let source = data.clone();
+        let feeder = tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            for chunk in source.chunks(50_001) {
+                writer.write_all(chunk).await?;
+            }
+            Ok::<_, std::io::Error>(())
+        });
+        let shards = dir.path().join("shards");
+        let opts = EncodeOptions {
+            data_shards: 4,
+            parity_shards: 2,
+            stream_stripe: Some(256 << 10),
+            ..Default::default()
+        };
+        let encoder = ParityEncoder::new(&opts)?;
+        let (len, _) = encode_stream(&mut reader, &shards, &opts, None, &encoder).await?;
+        feeder.await??;
+        assert_eq!(len, data.len());
+        let meta = ShardMetadata::read(&shards).await?;
+        assert_eq!(meta.orig_len, data.len());
+        assert_eq!(meta.stream_stripe, Some(256 << 10));
+        assert_eq!(
+            std::fs::metadata(shards.join("shard_05.dat"))?.len() as usize,
+            stream_shard_len(data.len(), 4, 256 << 10)
+        );
+
+        std::fs::remove_file(shards.join("shard_01.dat"))?;
+        std::fs::remove_file(shards.join("shard_03.dat"))?;
+        let output = dir.path().join("output.bin");
+        run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // A stream shorter than one stripe, or empty, is laid out as a plain
+        // encode of the same bytes.
+        for len in [1_000, 0] {
+            let input = dir.path().join(format!("short{}.bin", len));
+            std::fs::write(&input, &data[..len])?;
+            let streamed = dir.path().join(format!("streamed{}", len));
+            let plain = dir.path().join(format!("plain{}", len));
+            run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 --stream-stripe 4096",
+                p(&input),
+                p(&streamed)
+            ))
+            .await?;
+            run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2",
+                p(&input),
+                p(&plain)
+            ))
+            .await?;
+            for i in 0..6 {
+                assert_eq!(
+                    std::fs::read(streamed.join(shard_file_name(i)))?,
+                    std::fs::read(plain.join(shard_file_name(i)))?
+                );
+            }
+            let output = dir.path().join(format!("short{}.out", len));
+            run_cli(&format!("decode -i {} -o {}", p(&streamed), p(&output))).await?;
+            assert_eq!(std::fs::read(&output)?, &data[..len]);
+        }
+
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --stream-stripe 4096 --compress zstd",
+            p(&dir.path().join("short0.bin")),
+            p(&dir.path().join("rejected"))
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_report_file_records_operation() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_shards(4, 3_000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let report_path = dir.path().join("encode.json");
+
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --report-file {}",
+            p(&input),
+            p(&shards),
+            p(&report_path)
+        ))
+        .await?;
+        let report: OperationReport = serde_json::from_slice(&std::fs::read(&report_path)?)?;
+        let meta = ShardMetadata::read(&shards).await?;
+        assert_eq!(report.operation, "encode");
+        assert!(report.success);
+        assert_eq!(report.failed_stage, None);
+        assert_eq!(report.inputs, vec![input.clone()]);
+        assert_eq!(report.output, shards);
+        assert_eq!(
+            (report.data_shards, report.parity_shards),
+            (Some(4), Some(2))
+        );
+        assert_eq!(report.bytes, Some(data.len()));
+        assert_eq!(report.input_sha256, meta.input_sha256);
+        assert_eq!(report.shards_written, (0..6).collect::<Vec<_>>());
+        assert_eq!(report.checksums, meta.checksums);
+        assert!(report.durations.total_secs > 0.0);
+
+        std::fs::remove_file(shards.join("shard_01.dat"))?;
+        std::fs::remove_file(shards.join("shard_04.dat"))?;
+        let output = dir.path().join("output.bin");
+        let report_path = dir.path().join("decode.json");
+        run_cli(&format!(
+            "decode -i {} -o {} --report-file {}",
+            p(&shards),
+            p(&output),
+            p(&report_path)
+        ))
+        .await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        let report: OperationReport = serde_json::from_slice(&std::fs::read(&report_path)?)?;
+        assert!(report.success);
+        assert_eq!(report.shards_read, vec![0, 2, 3, 5]);
+        assert_eq!(report.shards_reconstructed, vec![1]);
+        assert_eq!(report.bytes, Some(data.len()));
+
+        // A failed run still leaves a report saying where it stopped.
+        std::fs::remove_file(shards.join("shard_00.dat"))?;
+        std::fs::remove_file(shards.join("shard_02.dat"))?;
+        let err = run_cli(&format!(
+            "decode -i {} -o {} --report-file {}",
+            p(&shards),
+            p(&output),
+            p(&report_path)
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        let report: OperationReport = serde_json::from_slice(&std::fs::read(&report_path)?)?;
+        assert!(!report.success);
+        assert_eq!(report.failed_stage.as_deref(), Some("decoding"));
+        assert!(report.error.unwrap().contains("have 2, need 4"));
+        assert!(report.shards_read.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_failed_shard_write_reports_index() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        std::fs::write(&input, random_shards(4, 2_000, test_seed()).concat())?;
+
+        for (flags, blocked) in [("", 3), ("--keep-partial", 1), ("--low-memory", 5)] {
+            let shards = dir.path().join(format!("shards{}", blocked));
+            // A directory where the shard file should go makes that one
+            // write fail while the others succeed.
+            std::fs::create_dir_all(shards.join(shard_file_name(blocked)))?;
+            let err = run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 {}",
+                p(&input),
+                p(&shards),
+                flags
+            ))
+            .await
+            .unwrap_err();
+            let message = format!("{:#}", err);
+            assert!(
+                message.contains("Failed to write 1 of 6 shard files"),
+                "{}",
+                message
+            );
+            assert!(message.contains(&shard_file_name(blocked)), "{}", message);
+            assert!(!ShardMetadata::exists(&shards).await);
+
+            let others = (0..6).filter(|&i| i != blocked);
+            if flags == "--keep-partial" {
+                assert!(
+                    others
+                        .clone()
+                        .all(|i| shards.join(shard_file_name(i)).is_file())
+                );
+            } else {
+                assert!(
+                    others
+                        .clone()
+                        .all(|i| !shards.join(shard_file_name(i)).exists())
+                );
+            }
+            // No temporary files are left behind either way.
+            let stray = std::fs::read_dir(&shards)?
+                .filter_map(|e| e.ok())
+                .any(|e| e.file_name().to_string_lossy().ends_with(".tmp"));
+            assert!(!stray);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shard_log_roundtrip_and_append() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = mixed_shards(1, 20_000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let args = format!(
+            "encode -i {} -o {} -d 4 -p 2 --shard-log",
+            p(&input),
+            p(&shards)
+        );
+        run_cli(&args).await?;
+
+        let meta = ShardMetadata::read(&shards).await?;
+        let entries = meta.shard_log.clone().unwrap();
+        let log = shards.join(SHARD_LOG_FILE);
+        let log_len = std::fs::metadata(&log)?.len();
+        assert_eq!(
+            entries.last().unwrap().unwrap().offset + meta.shard_len() as u64,
+            log_len
+        );
+        assert!(!shards.join(shard_file_name(0)).exists());
+        assert_eq!(decode_dir(&shards, &DecodeOptions::default()).await?, data);
+
+        // Encoding again appends a second copy instead of rewriting the log.
+        run_cli(&args).await?;
+        let meta = ShardMetadata::read(&shards).await?;
+        assert_eq!(meta.shard_log_entry(0).unwrap().offset, log_len);
+        assert_eq!(std::fs::metadata(&log)?.len(), 2 * log_len);
+        assert_eq!(decode_dir(&shards, &DecodeOptions::default()).await?, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_truncated_shard_log_loses_trailing_shards() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(20_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --shard-log",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let meta = ShardMetadata::read(&shards).await?;
+        let log = std::fs::OpenOptions::new()
+            .write(true)
+            .open(shards.join(SHARD_LOG_FILE))?;
+
+        // Cut the log inside shard 4: shard 5 lies past its end and shard 4
+        // is short, so both are rebuilt from the rest.
+        let cut = meta.shard_log_entry(4).unwrap().offset + 10;
+        log.set_len(cut)?;
+        assert!(!meta.shard_exists(&shards, 5).await?);
+        assert_eq!(meta.shard_file_len(&shards, 4).await?, 10);
+        assert_eq!(decode_dir(&shards, &DecodeOptions::default()).await?, data);
+
+        // One more shard lost is beyond the parity.
+        log.set_len(meta.shard_log_entry(3).unwrap().offset)?;
+        let err = decode_dir(&shards, &DecodeOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sidecar_metadata_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = mixed_shards(4, 3_000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --sidecar-metadata",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+
+        // meta.json only describes the set; each checksum sits by its shard.
+        let descriptor = ShardMetadata::read_descriptor(&shards).await?;
+        assert!(descriptor.checksums.is_none());
+        assert_eq!(descriptor.sidecar_checksums, Some(ChecksumAlgo::Xxh3));
+        let meta = ShardMetadata::read(&shards).await?;
+        let checksums = meta.checksums.as_ref().unwrap();
+        for i in 0..6 {
+            let shard_path = shards.join(shard_file_name(i));
+            let sidecar = ShardSidecar::read(&shard_path).await?.unwrap();
+            assert_eq!(sidecar.shard, i);
+            assert_eq!(sidecar.size, meta.stored_len(i));
+            assert_eq!(sidecar.checksum, checksums.shards[i]);
+            assert!(checksums.matches(i, &std::fs::read(&shard_path)?));
+        }
+
+        let output = dir.path().join("output.bin");
+        let decode = format!("decode -i {} -o {}", p(&shards), p(&output));
+        run_cli(&decode).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // A corrupted shard fails its sidecar checksum and is rebuilt, and a
+        // shard whose sidecar is lost is not trusted either.
+        let path = shards.join(shard_file_name(1));
+        let mut contents = std::fs::read(&path)?;
+        contents[100] ^= 0x20;
+        std::fs::write(&path, &contents)?;
+        std::fs::remove_file(sidecar_path(&shards.join(shard_file_name(3))))?;
+        run_cli(&decode).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        let meta = ShardMetadata::read(&shards).await?;
+        assert_eq!(meta.checksums.unwrap().shards[3], "");
+
+        // A sidecar recording the wrong file size is not trusted either.
+        contents[100] ^= 0x20;
+        std::fs::write(&path, &contents)?;
+        let shard_path = shards.join(shard_file_name(0));
+        let mut sidecar = ShardSidecar::read(&shard_path).await?.unwrap();
+        sidecar.size += 1;
+        sidecar.write(&shard_path, &WriteOptions::default()).await?;
+        let meta = ShardMetadata::read(&shards).await?;
+        assert_eq!(meta.checksums.unwrap().shards[0], "");
+        run_cli(&decode).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --sidecar-metadata --checksum-algo none",
+            p(&input),
+            p(&dir.path().join("again"))
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_refuses_mixed_encodings() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_shards(4, 3_000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let encode = |name: &str, flags: &str| {
+            let shards = dir.path().join(name);
+            let cmd = format!(
+                "encode -i {} -o {} -d 4 -p 2 {}",
+                p(&input),
+                p(&shards),
+                flags
+            );
+            async move { run_cli(&cmd).await.map(|()| shards) }
+        };
+        let output = p(&dir.path().join("output.bin"));
+        let mixed = |err: &anyhow::Error| {
+            assert_eq!(exit_code(err), EXIT_CORRUPTION, "{:#}", err);
+            assert!(err.to_string().contains("different encodings"), "{:#}", err);
+        };
+
+        // A fallback copy made with another matrix, or another version of the
+        // same one, is refused instead of being mixed in.
+        let old = encode("old", "").await?;
+        let new = encode("new", "--matrix-type cauchy").await?;
+        let copy = encode("copy", "").await?;
+        let decode = |fallback: &Path| {
+            format!(
+                "decode -i {} -o {} --fallback-dir {}",
+                p(&old),
+                output,
+                p(fallback)
+            )
+        };
+        mixed(&run_cli(&decode(&new)).await.unwrap_err());
+        let meta_path = copy.join("meta.json");
+        let mut meta: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&meta_path)?)?;
+        meta["provenance"]["construction"] = 2.into();
+        std::fs::write(&meta_path, meta.to_string())?;
+        let err = run_cli(&decode(&copy)).await.unwrap_err();
+        mixed(&err);
+        assert!(err.to_string().contains("construction 2"), "{}", err);
+        meta["provenance"]["construction"] = 1.into();
+        std::fs::write(&meta_path, meta.to_string())?;
+        run_cli(&decode(&copy)).await?;
+
+        // A sidecar vouches for its own shard, so the shard's checksum alone
+        // would let a shard from the other encoding through.
+        let old = encode("old_sidecars", "--sidecar-metadata").await?;
+        let new = encode("new_sidecars", "--sidecar-metadata --matrix-type cauchy").await?;
+        for file in [
+            shard_file_name(5),
+            sidecar_path(Path::new(&shard_file_name(5)))
+                .display()
+                .to_string(),
+        ] {
+            std::fs::copy(new.join(&file), old.join(&file))?;
+        }
+        let err = run_cli(&format!("decode -i {} -o {}", p(&old), output))
+            .await
+            .unwrap_err();
+        mixed(&err);
+        assert!(err.to_string().contains(&shard_file_name(5)), "{}", err);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_diagnoses_inconsistent_shard_set() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(8_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.txt");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --checksum-algo none",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+
+        // Truncated shards are rebuilt when enough others survive...
+        for i in [0, 4] {
+            let path = shards.join(format!("shard_{:02}.dat", i));
+            let contents = std::fs::read(&path)?;
+            std::fs::write(&path, &contents[..1000])?;
+        }
+        let meta = ShardMetadata::read(&shards).await?;
+        let mut shards_opt: Vec<Option<Vec<u8>>> = (0..6)
+            .map(|i| std::fs::read(shards.join(format!("shard_{:02}.dat", i))).ok())
+            .collect();
+        shards_opt[3] = None;
+        let report = ShardSizeReport::check(&meta, &shards_opt);
+        assert_eq!(
+            report.diagnosis().as_deref(),
+            Some("metadata present, 2 shard files truncated [0, 4], 1 shard file missing [3]")
+        );
+        run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // ...and named in the error when too few do.
+        std::fs::remove_file(shards.join("shard_03.dat"))?;
+        let err = run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("2 shard files truncated"), "{err}");
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+
+        // Without metadata the shard files are counted, and k/m can be supplied.
+        let intact = dir.path().join("intact");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&intact)
+        ))
+        .await?;
+        std::fs::remove_file(intact.join("meta.json"))?;
+        std::fs::remove_file(intact.join("shard_01.dat"))?;
+        let err = run_cli(&format!("decode -i {} -o {}", p(&intact), p(&output)))
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("metadata missing, 5 shard files present"),
+            "{err}"
+        );
+        run_cli(&format!(
+            "decode -i {} -o {} -d 4 -p 2",
+            p(&intact),
+            p(&output)
+        ))
+        .await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_waits_for_growing_shard() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(12_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.txt");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+
+        // Shard 0 is needed once both parity shards are gone, and arrives late.
+        std::fs::remove_file(shards.join("shard_04.dat"))?;
+        std::fs::remove_file(shards.join("shard_05.dat"))?;
+        let shard_0 = shards.join("shard_00.dat");
+        let full = std::fs::read(&shard_0)?;
+        std::fs::write(&shard_0, &full[..1000])?;
+
+        let writer = {
+            let (shard_0, full) = (shard_0.clone(), full.clone());
+            tokio::spawn(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                std::fs::write(&shard_0, &full[..2000])?;
+                tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                std::fs::write(&shard_0, &full)
+            })
+        };
+        run_cli(&format!(
+            "decode -i {} -o {} --wait-for-shards 10",
+            p(&shards),
+            p(&output)
+        ))
+        .await?;
+        writer.await??;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // The wait is bounded: a shard that never completes counts as missing.
+        std::fs::write(&shard_0, &full[..1000])?;
+        let started = std::time::Instant::now();
+        let err = run_cli(&format!(
+            "decode -i {} -o {} --wait-for-shards 0.3",
+            p(&shards),
+            p(&output)
+        ))
+        .await
+        .unwrap_err();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_parity_files_roundtrip() -> Result<()> {
+        assert_eq!(interleaved_parity_order(4, 2), [0, 1, 4, 2, 3, 5]);
+        assert_eq!(
+            interleaved_parity_order(10, 4),
+            [0, 1, 2, 10, 3, 4, 11, 5, 6, 7, 12, 8, 9, 13]
+        );
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(9_999, test_seed());
+        std::fs::write(&input, &data)?;
+        let plain = dir.path().join("plain");
+        let interleaved = dir.path().join("interleaved");
+        let output = dir.path().join("output.txt");
+        for (out, flag) in [(&plain, ""), (&interleaved, "--interleave-parity")] {
+            run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 {}",
+                p(&input),
+                p(out),
+                flag
+            ))
+            .await?;
+        }
+
+        // Same logical shards, different files.
+        let meta = ShardMetadata::read(&interleaved).await?;
+        assert_eq!(meta.disk_order.as_deref(), Some(&[0, 1, 4, 2, 3, 5][..]));
+        assert_eq!(
+            std::fs::read(interleaved.join("shard_02.dat"))?,
+            std::fs::read(plain.join("shard_04.dat"))?
+        );
+        assert!(compare_dirs(&plain, &interleaved).await?.is_identical());
+
+        // Lose data shard 2 (file 3) and parity shard 0 (file 2).
+        std::fs::remove_file(interleaved.join("shard_02.dat"))?;
+        std::fs::remove_file(interleaved.join("shard_03.dat"))?;
+        run_cli(&format!("decode -i {} -o {}", p(&interleaved), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_require_tolerance_gates_encode() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(7_000, test_seed());
+        std::fs::write(&input, &data)?;
+
+        let low = dir.path().join("low");
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 6 -p 2 --require-tolerance 3 --verify-after-encode",
+            p(&input),
+            p(&low)
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        assert!(!low.exists());
+
+        let high = dir.path().join("high");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 6 -p 4 --require-tolerance 3 --verify-after-encode",
+            p(&input),
+            p(&high)
+        ))
+        .await?;
+        assert_eq!(std::fs::read_dir(&high)?.count(), 11);
+
+        // The same rehearsal through the decoder: any 3 of the 10 shards can go.
+        for first in 0..8 {
+            let opts = DecodeOptions {
+                ignore_shards: vec![first, first + 1, first + 2],
+                ..Default::default()
+            };
+            assert_eq!(decode_dir(&high, &opts).await?, data);
+        }
+
+        // A seed fixes which shards verification drops.
+        let dropped = verify_drops(10, 3, 42);
+        assert_eq!(dropped.len(), 3);
+        assert!(dropped.windows(2).all(|w| w[0] < w[1] && w[1] < 10));
+        assert_eq!(verify_drops(10, 3, 42), dropped);
+        run_cli(&format!(
+            "encode -i {} -o {} -d 6 -p 4 --require-tolerance 3 --verify-after-encode \
+             --verify-seed 42",
+            p(&input),
+            p(&dir.path().join("seeded"))
+        ))
+        .await?;
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 6 -p 4 --verify-seed 42",
+            p(&input),
+            p(&dir.path().join("unverified"))
+        ))
+        .await
+        .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("--verify-after-encode"),
+            "{err:#}"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_ok_zero_fills_lost_data() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        // No zero bytes, so zero-filled gaps in the output stand out.
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 250 + 1) as u8).collect();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.txt");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        for i in [1, 2, 4] {
+            std::fs::remove_file(shards.join(format!("shard_{:02}.dat", i)))?;
+        }
+
+        let err = run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+
+        run_cli(&format!(
+            "decode -i {} -o {} --partial-ok",
+            p(&shards),
+            p(&output)
+        ))
+        .await?;
+        let partial = std::fs::read(&output)?;
+        assert_eq!(partial.len(), data.len());
+        assert_eq!(partial[..2500], data[..2500]);
+        assert!(partial[2500..7500].iter().all(|&b| b == 0));
+        assert_eq!(partial[7500..], data[7500..]);
+
+        let opts = DecodeOptions {
+            partial_ok: true,
+            ..Default::default()
+        };
+        let decoded = decode_dir_with_gaps(&shards, &opts).await?;
+        assert_eq!(decoded.data, partial);
+        assert_eq!(decoded.missing_ranges.len(), 1);
+        assert_eq!(decoded.missing_ranges[0], 2500..7500);
+
+        // Compressed shards do not map to input bytes, so nothing partial is possible.
+        let compressed = dir.path().join("compressed");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --compress zstd",
+            p(&input),
+            p(&compressed)
+        ))
+        .await?;
+        for i in [0, 1, 2] {
+            std::fs::remove_file(compressed.join(format!("shard_{:02}.dat", i)))?;
+        }
+        let err = decode_dir_with_gaps(&compressed, &opts).await.unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        assert!(err.to_string().contains("compressed"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dropped_buffers_are_wiped() {
+        /// Records whether it was wiped.
+        #[derive(Default)]
+        struct Probe(Arc<std::sync::atomic::AtomicBool>);
+
+        impl Wipe for Probe {
+            fn wipe(&mut self) {
+                self.0.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+
+        let wiped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        drop(Wiping::new(Probe(wiped.clone())));
+        assert!(wiped.load(std::sync::atomic::Ordering::SeqCst));
+
+        // Handing the buffer on leaves it as it is.
+        let shards = vec![Some(vec![7u8; 16]), None];
+        assert_eq!(Wiping::new(shards.clone()).into_inner(), shards);
+
+        // Best effort: a buffer's memory, including spare capacity, reads
+        // back as zeros once wiped, and is untouched without the feature.
+        let mut buf = Vec::with_capacity(256);
+        buf.extend_from_slice(&[0xab; 200]);
+        let (ptr, capacity) = (buf.as_ptr(), buf.capacity());
+        buf.wipe();
+        let (written, expected) = match cfg!(feature = "zeroize") {
+            true => (capacity, 0),
+            false => (200, 0xab),
+        };
+        // SAFETY: wiping neither frees nor shrinks the allocation, and the
+        // first `written` bytes of it have been written, by
+        // extend_from_slice or by the wipe.
+        let memory = unsafe { std::slice::from_raw_parts(ptr, written) };
+        assert!(memory.iter().all(|&b| b == expected));
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_shards_roundtrip_and_wrong_key() -> Result<()> {
+        let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let wrong = "ff112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(6_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.txt");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --encrypt-key {}",
+            p(&input),
+            p(&shards),
+            key
+        ))
+        .await?;
+
+        let meta_json = std::fs::read_to_string(shards.join("meta.json"))?;
+        assert!(meta_json.contains("chacha20-poly1305") && !meta_json.contains(key));
+        let shard_0 = std::fs::read(shards.join("shard_00.dat"))?;
+        assert_eq!(shard_0.len(), 1500 + 16);
+        assert_ne!(shard_0[..1500], data[..1500]);
+        // Checksums cover the encrypted files, so verify needs no key.
+        run_cli(&format!("verify -i {}", p(&shards))).await?;
+
+        // One lost and one tampered shard are both recovered from parity.
+        std::fs::remove_file(shards.join("shard_01.dat"))?;
+        let mut tampered = shard_0;
+        tampered[10] ^= 0x01;
+        std::fs::write(shards.join("shard_00.dat"), &tampered)?;
+        run_cli(&format!(
+            "decode -i {} -o {} --encrypt-key {}",
+            p(&shards),
+            p(&output),
+            key
+        ))
+        .await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        for args in [String::new(), format!("--encrypt-key {}", wrong)] {
+            let err = run_cli(&format!(
+                "decode -i {} -o {} {}",
+                p(&shards),
+                p(&output),
+                args
+            ))
+            .await
+            .unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{err}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_product_code_recovers_burst_beyond_row_parity() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(12_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        // Rows of 4 data + 2 parity; 3 data rows + 1 parity row: 24 shards.
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --product-code 3,1 --require-tolerance 5 \
+             --verify-after-encode",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let meta = ShardMetadata::read(&shards).await?;
+        assert_eq!((meta.data_shards, meta.parity_shards), (12, 12));
+        let geometry = meta.product_code.unwrap();
+        for i in 0..24 {
+            let (row, col) = geometry.cell(i);
+            assert_eq!(geometry.index(row, col), i);
+        }
+
+        // Row 0 is a plain 4+2 codeword; losing 3 of its shards defeats it.
+        let row: Vec<usize> = (0..6).map(|col| geometry.index(0, col)).collect();
+        let mut row_shards: Vec<Option<Vec<u8>>> = row
+            .iter()
+            .map(|&i| std::fs::read(shards.join(shard_file_name(i))).ok())
+            .collect();
+        assert!(row_shards.iter().all(|s| s.is_some()));
+        row_shards[0] = None;
+        row_shards[1] = None;
+        row_shards[4] = None;
+        assert!(Codec::try_new(4, 2)?.reconstruct(&mut row_shards).is_err());
+
+        // The whole of row 0 plus a data shard of row 2 is recoverable: the
+        // columns rebuild row 0 once row 2 has been repaired.
+        let mut lost = row.clone();
+        lost.push(geometry.index(2, 1));
+        let opts = DecodeOptions {
+            ignore_shards: lost,
+            ..Default::default()
+        };
+        assert_eq!(decode_dir(&shards, &opts).await?, data);
+
+        // A 3x2 block defeats both the rows and the columns it touches.
+        let block: Vec<usize> = (0..2)
+            .flat_map(|row| (0..3).map(move |col| geometry.index(row, col)))
+            .collect();
+        let opts = DecodeOptions {
+            ignore_shards: block,
+            ..Default::default()
+        };
+        let err = decode_dir(&shards, &opts).await.unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_local_groups_repair_from_one_group() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = mixed_shards(1, 9_000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        // 6 data, 2 global parity, local parity for data 0..3 and 3..6.
+        run_cli(&format!(
+            "encode -i {} -o {} -d 6 -p 2 --local-groups 2 --require-tolerance 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let meta = ShardMetadata::read(&shards).await?;
+        assert_eq!((meta.data_shards, meta.parity_shards), (6, 4));
+        assert_eq!(meta.local_groups, Some(2));
+
+        // A single loss is rebuilt from the three other shards of its group.
+        let lrc = LrcCodec::new(6, 2, 2)?;
+        assert_eq!(lrc.group_members(1), Some(vec![0, 1, 2, 8]));
+        let original: Vec<Vec<u8>> = (0..10)
+            .map(|i| std::fs::read(shards.join(shard_file_name(i))))
+            .collect::<std::io::Result<_>>()?;
+        let mut group_only: Vec<Option<Vec<u8>>> = vec![None; 10];
+        for i in [0, 2, 8] {
+            group_only[i] = Some(original[i].clone());
+        }
+        assert_eq!(lrc.repair_locally(&mut group_only), vec![1]);
+        assert_eq!(group_only[1].as_ref(), Some(&original[1]));
+
+        // Four losses: one repaired locally, two from global parity, and the
+        // local parity recomputed afterwards.
+        let mut lost: Vec<Option<Vec<u8>>> = original.iter().cloned().map(Some).collect();
+        for i in [0, 1, 3, 8] {
+            lost[i] = None;
+        }
+        let report = lrc.reconstruct(&mut lost)?;
+        assert_eq!(report.global, vec![0, 1]);
+        assert_eq!(report.local, vec![3, 8]);
+        assert!(
+            lost.iter()
+                .zip(&original)
+                .all(|(s, o)| s.as_ref() == Some(o))
+        );
+
+        let opts = DecodeOptions {
+            ignore_shards: vec![0, 1, 3],
+            ..Default::default()
+        };
+        assert_eq!(decode_dir(&shards, &opts).await?, data);
+
+        // Three losses in one group exceed the two global parity shards.
+        let opts = DecodeOptions {
+            ignore_shards: vec![0, 1, 2],
+            ..Default::default()
+        };
+        let err = decode_dir(&shards, &opts).await.unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 6 -p 2 --local-groups 2 --require-tolerance 3",
+            p(&input),
+            p(&dir.path().join("other"))
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_existing_only_skips_intact_matching_sets() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        std::fs::write(&input, vec![7u8; 5_000])?;
+        let shards = dir.path().join("shards");
+        let encode = format!(
+            "encode -i {} -o {} -d 4 -p 2 --skip-existing",
+            p(&input),
+            p(&shards)
+        );
+        run_cli(&encode).await?;
+        let first = shards.join("shard_00.dat");
+        let written = std::fs::metadata(&first)?.modified()?;
+
+        run_cli(&encode).await?;
+        assert_eq!(std::fs::metadata(&first)?.modified()?, written);
+
+        // A truncated shard means the set is not intact: encode again.
+        std::fs::write(shards.join("shard_05.dat"), b"short")?;
+        run_cli(&encode).await?;
+        assert_eq!(std::fs::read(shards.join("shard_05.dat"))?.len(), 1250);
+
+        // Different k/m or different input bytes are not a match either.
+        run_cli(&format!(
+            "encode -i {} -o {} -d 5 -p 2 --skip-existing",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        assert_eq!(ShardMetadata::read(&shards).await?.data_shards, 5);
+        std::fs::write(&input, vec![9u8; 5_000])?;
+        run_cli(&format!(
+            "encode -i {} -o {} -d 5 -p 2 --skip-existing",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        assert_eq!(
+            decode_dir(&shards, &DecodeOptions::default()).await?,
+            vec![9u8; 5_000]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_roundtrip_with_deleted_shards() -> Result<()> {
+        let app = router(Arc::new(ServerState::new(4, 2)?));
+        let send = |request: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await?;
+                let status = response.status();
+                let body = response.into_body().collect().await?.to_bytes();
+                Ok::<_, anyhow::Error>((status, body))
+            }
+        };
+        let data = random_input(10_001, test_seed());
+
+        let (status, body) = send(Request::post("/sets").body(Body::from(data.clone()))?).await?;
+        assert_eq!(status, StatusCode::OK);
+        let set: serde_json::Value = serde_json::from_slice(&body)?;
+        let id = set["id"].as_u64().unwrap();
+        assert_eq!(set["orig_len"], 10_001);
+
+        for index in [0, 3] {
+            let uri = format!("/sets/{}/shards/{}", id, index);
+            let (status, body) = send(Request::delete(uri).body(Body::empty())?).await?;
+            assert_eq!(status, StatusCode::OK);
+            let set: serde_json::Value = serde_json::from_slice(&body)?;
+            assert!(set["missing"].as_array().unwrap().contains(&index.into()));
+        }
+        // Twice, so the second reconstruction is served from the warm cache.
+        for _ in 0..2 {
+            let uri = format!("/sets/{}", id);
+            let (status, body) = send(Request::get(uri).body(Body::empty())?).await?;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, data);
+        }
+        let (_, body) = send(Request::get("/stats").body(Body::empty())?).await?;
+        let stats: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(stats["cached_inverses"], 1);
+        assert_eq!(stats["sets"], 1);
+
+        let uri = format!("/sets/{}/shards/1", id);
+        send(Request::delete(uri).body(Body::empty())?).await?;
+        let (status, _) = send(Request::get(format!("/sets/{}", id)).body(Body::empty())?).await?;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = send(Request::get("/sets/99").body(Body::empty())?).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serve_caps_bodies_and_sets_and_decodes_posted_shards() -> Result<()> {
+        let opts = ServeOptions {
+            max_body_bytes: 20_000,
+            max_sets: 2,
+            ..Default::default()
+        };
+        let app = router(Arc::new(ServerState::with_options(4, 2, opts)?));
+        let send = |request: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await?;
+                let status = response.status();
+                let body = response.into_body().collect().await?.to_bytes();
+                Ok::<_, anyhow::Error>((status, body))
+            }
+        };
+        let data = random_input(10_001, test_seed());
+
+        let (status, _) = send(Request::post("/sets").body(Body::from(vec![0u8; 20_001]))?).await?;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        for _ in 0..3 {
+            let request = Request::post("/sets").body(Body::from(data.clone()))?;
+            assert_eq!(send(request).await?.0, StatusCode::OK);
+        }
+        let (_, body) = send(Request::get("/stats").body(Body::empty())?).await?;
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body)?["sets"],
+            2
+        );
+        let (status, _) = send(Request::get("/sets/1").body(Body::empty())?).await?;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Post the surviving shards of set 3 as a whole set to decode.
+        let mut posted = Vec::new();
+        for index in [0, 2, 3, 5] {
+            let uri = format!("/sets/3/shards/{}", index);
+            posted.extend_from_slice(&send(Request::get(uri).body(Body::empty())?).await?.1);
+        }
+        let decode = |query: &str, body: Vec<u8>| {
+            Request::post(format!("/decode?orig_len=10001&{}", query)).body(Body::from(body))
+        };
+        let (status, body) = send(decode("missing=1,4", posted.clone())?).await?;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, data);
+        let (status, _) = send(decode("missing=1", posted.clone())?).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(decode("missing=1,1", posted.clone())?).await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(decode("missing=0,1,4", posted[2_501..].to_vec())?).await?;
+        assert_eq!(status, StatusCode::CONFLICT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encode_records_provenance() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        std::fs::write(&input, b"provenance")?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 3 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        run_cli(&format!("info -i {}", p(&shards))).await?;
+
+        let provenance = ShardMetadata::read(&shards).await?.provenance.unwrap();
+        assert_eq!(provenance.tool_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(provenance.field, "GF(2^8)/0x11d");
+        assert_eq!(provenance.matrix_type, MatrixType::Vandermonde);
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+        assert!(now.as_secs() - provenance.encoded_at.unwrap() < 60);
+        let shown = provenance.to_string();
+        assert!(shown.contains(env!("CARGO_PKG_VERSION")) && shown.contains("Vandermonde"));
+
+        // Sets from before provenance was recorded still read.
+        let mut meta: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(shards.join("meta.json"))?)?;
+        meta.as_object_mut().unwrap().remove("provenance");
+        std::fs::write(shards.join("meta.json"), meta.to_string())?;
+        assert!(ShardMetadata::read(&shards).await?.provenance.is_none());
+        run_cli(&format!("info -i {}", p(&shards))).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reproducible_encode_is_byte_identical() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        std::fs::write(&input, mixed_shards(1, 100_000, test_seed()).concat())?;
+        let files = |shards: &Path| -> Result<Vec<(String, Vec<u8>)>> {
+            let mut files = std::fs::read_dir(shards)?
+                .map(|entry| {
+                    let entry = entry?;
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    Ok((name, std::fs::read(entry.path())?))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            files.sort();
+            Ok(files)
+        };
+        let encode = |name: &str, flags: &str| {
+            let shards = dir.path().join(name);
+            let cmd = format!(
+                "encode -i {} -o {} -d 6 -p 3 --compress zstd --compress-shards gzip \
+                 --shard-trailer --manifest {}",
+                p(&input),
+                p(&shards),
+                flags
+            );
+            async move { run_cli(&cmd).await.map(|()| shards) }
+        };
+
+        let first = encode("first", "--reproducible").await?;
+        let second = encode("second", "--reproducible --low-memory").await?;
+        assert_eq!(files(&first)?, files(&second)?);
+        // Splitting, parity, shard compression and trailers run serially.
+        let serial = encode("serial", "--reproducible --no-parallel").await?;
+        assert_eq!(files(&first)?, files(&serial)?);
+        let provenance = ShardMetadata::read(&first).await?.provenance.unwrap();
+        assert!(provenance.encoded_at.is_none() && provenance.hostname.is_none());
+
+        // Without the flag only the encode time and host can differ.
+        let plain = encode("plain", "").await?;
+        let meta_json = |shards: &Path| -> Result<serde_json::Value> {
+            let mut meta: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(shards.join("meta.json"))?)?;
+            let provenance = meta["provenance"].as_object_mut().unwrap();
+            provenance.remove("encoded_at");
+            provenance.remove("hostname");
+            Ok(meta)
+        };
+        assert_eq!(meta_json(&plain)?, meta_json(&first)?);
+        let without_meta = |shards: &Path| -> Result<Vec<(String, Vec<u8>)>> {
+            let mut files = files(shards)?;
+            files.retain(|(name, _)| name != "meta.json");
+            Ok(files)
+        };
+        assert_eq!(without_meta(&plain)?, without_meta(&first)?);
+
+        let err = encode(
+            "encrypted",
+            &format!("--reproducible --encrypt-key {}", "ab".repeat(32)),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_falls_back_to_good_shard_copies() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(9_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let primary = dir.path().join("primary");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&primary)
+        ))
+        .await?;
+        let (stale, cold) = (dir.path().join("stale"), dir.path().join("cold"));
+        for copy in [&stale, &cold] {
+            std::fs::create_dir(copy)?;
+            for i in 0..6 {
+                let name = shard_file_name(i);
+                std::fs::copy(primary.join(&name), copy.join(&name))?;
+            }
+        }
+        let flip = |path: std::path::PathBuf| -> Result<()> {
+            let mut bytes = std::fs::read(&path)?;
+            bytes[100] ^= 0x01;
+            std::fs::write(path, bytes)?;
+            Ok(())
+        };
+        // Exactly 4 good copies remain: 0 and 1 only in `cold` (the stale
+        // copy of 0 is damaged too), 2 only in `stale`, 3 only in `primary`.
+        for i in 0..3 {
+            flip(primary.join(shard_file_name(i)))?;
+        }
+        flip(stale.join(shard_file_name(0)))?;
+        let remove = |dir: &Path, indices: &[usize]| -> Result<()> {
+            for &i in indices {
+                std::fs::remove_file(dir.join(shard_file_name(i)))?;
+            }
+            Ok(())
+        };
+        remove(&primary, &[4, 5])?;
+        remove(&stale, &[1, 3, 4, 5])?;
+        remove(&cold, &[2, 3, 4, 5])?;
+
+        let out = dir.path().join("out.txt");
+        assert!(
+            run_cli(&format!("decode -i {} -o {}", p(&primary), p(&out)))
+                .await
+                .is_err()
+        );
+        run_cli(&format!(
+            "decode -i {} -o {} --fallback-dir {} --fallback-dir {}",
+            p(&primary),
+            p(&out),
+            p(&stale),
+            p(&cold)
+        ))
+        .await?;
+        assert_eq!(std::fs::read(&out)?, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_count_checks_only_the_sample() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        std::fs::write(&input, vec![0x5au8; 20_000])?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 10 -p 6 --manifest",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let corrupt = 3;
+        let path = shards.join(shard_file_name(corrupt));
+        let mut contents = std::fs::read(&path)?;
+        contents[0] ^= 0x01;
+        std::fs::write(&path, contents)?;
+
+        let all: Vec<usize> = (0..16).collect();
+        let mut outcomes = [false, false];
+        for seed in 0..16u64 {
+            let sample = ShardSample::choose(&all, 4, seed);
+            assert_eq!(sample, ShardSample::choose(&all, 4, seed));
+            assert_eq!(sample.indices.len(), 4);
+            assert!(sample.indices.windows(2).all(|w| w[0] < w[1]));
+
+            let hit = sample.indices.contains(&corrupt);
+            let result = run_cli(&format!(
+                "verify -i {} --count 4 --seed {}",
+                p(&shards),
+                seed
+            ))
+            .await;
+            assert_eq!(result.is_err(), hit, "seed {}: {:?}", seed, sample.indices);
+            if let Err(err) = result {
+                assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+            }
+            outcomes[hit as usize] = true;
+        }
+        assert_eq!(outcomes, [true, true], "no seed sampled both ways");
+
+        assert_eq!(ShardSample::choose(&all, 40, 1).indices, all);
+        assert!(run_cli(&format!("verify -i {}", p(&shards))).await.is_err());
+        run_cli(&format!("info -i {} --count 5 --seed 7", p(&shards))).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shard_trailer_roundtrip_and_bad_trailer() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(9999, test_seed());
+        std::fs::write(&input, &data)?;
+        let output = dir.path().join("output.bin");
+
+        for (set, extra) in ["", "--low-memory", "--checksum-algo none"]
+            .iter()
+            .enumerate()
+        {
+            let shards = dir.path().join(format!("shards{}", set));
+            run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 --shard-trailer {}",
+                p(&input),
+                p(&shards),
+                extra
+            ))
+            .await?;
+            let meta = ShardMetadata::read(&shards).await?;
+            assert_eq!(meta.shard_trailer, Some(ShardTrailer::Crc32));
+            for i in 0..6 {
+                // Each file validates on its own, and the trailer is not shard data.
+                let file = std::fs::read(shards.join(shard_file_name(i)))?;
+                assert_eq!(file.len(), meta.shard_len() + TRAILER_LEN);
+                assert_eq!(meta.stored_len(i), file.len());
+                assert!(check_trailer(&file).is_some(), "{extra}: shard {i}");
+            }
+            run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+            assert_eq!(std::fs::read(&output)?, data, "{extra}");
+        }
+
+        // Without checksums only the trailer can tell a damaged shard. Two bad
+        // trailers are rebuilt from parity; a third leaves too few shards.
+        let shards = dir.path().join("shards2");
+        for (n, i) in [0, 5, 2].into_iter().enumerate() {
+            let path = shards.join(shard_file_name(i));
+            let mut file = std::fs::read(&path)?;
+            let last = file.len() - 1;
+            file[last] ^= 0x40;
+            std::fs::write(&path, file)?;
+
+            let result = run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await;
+            if n < 2 {
+                result?;
+                assert_eq!(std::fs::read(&output)?, data);
+            } else {
+                assert_eq!(exit_code(&result.unwrap_err()), EXIT_UNRECOVERABLE);
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_shard_write_leaves_no_partial_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(7000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let tmp = dir.path().join("tmp");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --tmp-dir {} --fsync",
+            p(&input),
+            p(&shards),
+            p(&tmp)
+        ))
+        .await?;
+        assert_eq!(
+            std::fs::read_dir(&tmp)?.count(),
+            0,
+            "temporary files left over"
+        );
+
+        // A crash after the temporary write but before the rename: the
+        // final paths still hold the old shard, or nothing for a new file.
+        let opts = WriteOptions {
+            tmp_dir: None,
+            fsync: false,
+        };
+        let existing = shards.join(shard_file_name(1));
+        let before = std::fs::read(&existing)?;
+        let staged = StagedFile::write(&existing, &[0xee; 100], &opts, None).await?;
+        assert!(staged.temp_path().exists());
+        assert_eq!(std::fs::read(&existing)?, before);
+        let fresh = shards.join(shard_file_name(6));
+        let staged_fresh = StagedFile::write(&fresh, &[0xee; 100], &opts, None).await?;
+        assert!(!fresh.exists());
+        drop((staged, staged_fresh));
+
+        let output = dir.path().join("output.bin");
+        run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        let staged = StagedFile::write(&existing, &before, &opts, None).await?;
+        let temp = staged.temp_path().to_path_buf();
+        staged.commit(&opts).await?;
+        assert!(!temp.exists());
+        assert_eq!(std::fs::read(&existing)?, before);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_locate_corruption_finds_silently_corrupted_shard() -> Result<()> {
+        let (k, m) = (4, 3);
+        let codec = Codec::new(k, m);
+        let data_shards = &random_shards(k, 3000, test_seed());
+        let parities = codec.encode_with_matrix(data_shards, codec.encode_matrix())?;
+        let clean: Vec<Option<Vec<u8>>> = data_shards
+            .iter()
+            .chain(&parities)
+            .cloned()
+            .map(Some)
+            .collect();
+        assert_eq!(
+            locate_corruption(&codec, &clean)?,
+            CorruptionCheck::Consistent
+        );
+
+        // k + 2 present: every single corrupted shard is pinned down.
+        for bad in 0..k + m - 1 {
+            let mut shards = clean.clone();
+            shards[k + m - 1] = None;
+            shards[bad].as_mut().unwrap()[1234] ^= 0x10;
+            assert_eq!(
+                locate_corruption(&codec, &shards)?,
+                CorruptionCheck::Located(bad)
+            );
+            // With only k + 1 present it is detected but not located.
+            shards[if bad == 0 { 1 } else { 0 }] = None;
+            assert_eq!(
+                locate_corruption(&codec, &shards)?,
+                CorruptionCheck::Unlocated
+            );
+        }
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(12_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d {} -p {} --checksum-algo none",
+            p(&input),
+            p(&shards),
+            k,
+            m
+        ))
+        .await?;
+        std::fs::remove_file(shards.join(shard_file_name(5)))?;
+        let path = shards.join(shard_file_name(2));
+        let mut contents = std::fs::read(&path)?;
+        contents[10] ^= 0x01;
+        std::fs::write(&path, contents)?;
+
+        let output = dir.path().join("output.bin");
+        let err = run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("SHA-256"), "{err}");
+        run_cli(&format!(
+            "decode -i {} -o {} --locate-corruption",
+            p(&shards),
+            p(&output)
+        ))
+        .await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_force_reconstruct_catches_corrupted_data_shard() -> Result<()> {
+        let (k, m) = (5, 2);
+        let codec = Codec::new(k, m);
+        let data_shards = &random_shards(k, 2_000, test_seed());
+        let parities = codec.encode_with_matrix(data_shards, codec.encode_matrix())?;
+        let clean: Vec<Option<Vec<u8>>> = data_shards
+            .iter()
+            .chain(&parities)
+            .cloned()
+            .map(Some)
+            .collect();
+        assert!(codec.cross_check_data(&clean)?.is_empty());
+        for bad in 0..k {
+            let mut shards = clean.clone();
+            shards[bad].as_mut().unwrap()[777] ^= 0x04;
+            assert!(codec.cross_check_data(&shards)?.contains(&bad));
+        }
+        // Every parity shard is needed to rebuild lost data: nothing to spare.
+        let mut shards = clean.clone();
+        shards[0] = None;
+        shards[1] = None;
+        assert!(codec.cross_check_data(&shards).is_err());
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = mixed_shards(k, 2_000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d {} -p {} --checksum-algo none",
+            p(&input),
+            p(&shards),
+            k,
+            m
+        ))
+        .await?;
+        let output = dir.path().join("output.bin");
+        let decode = format!(
+            "decode -i {} -o {} --force-reconstruct",
+            p(&shards),
+            p(&output)
+        );
+        run_cli(&decode).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // A lost data shard uses up one parity shard; the other still checks.
+        std::fs::remove_file(shards.join(shard_file_name(4)))?;
+        run_cli(&decode).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        let path = shards.join(shard_file_name(2));
+        let mut contents = std::fs::read(&path)?;
+        contents[1_500] ^= 0x01;
+        std::fs::write(&path, contents)?;
+        let err = run_cli(&decode).await.unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+        assert!(err.to_string().contains("rebuilt from parity"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shard_reads_retry_transient_errors() -> Result<()> {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        let policy = RetryPolicy {
+            retries: 2,
+            backoff: std::time::Duration::from_millis(1),
+        };
+        let attempts = AtomicU32::new(0);
+        let flaky = || {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match attempt {
+                    0 => Err(std::io::Error::other("transient EIO")),
+                    _ => Ok(vec![1, 2, 3]),
+                }
+            }
+        };
+        assert_eq!(policy.read("flaky", flaky).await?, Some(vec![1, 2, 3]));
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 2);
+
+        let not_found = || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async { Err(std::io::Error::from(std::io::ErrorKind::NotFound)) }
+        };
+        assert_eq!(policy.read("absent", not_found).await?, None);
+        assert_eq!(
+            attempts.swap(0, Ordering::SeqCst),
+            1,
+            "not found is not retried"
+        );
+
+        let broken = || {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            async { Err(std::io::Error::other("dead disk")) }
+        };
+        assert!(policy.read("broken", broken).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // A shard that never reads successfully becomes an erasure instead
+        // of aborting the decode.
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(5000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let unreadable = shards.join(shard_file_name(1));
+        std::fs::remove_file(&unreadable)?;
+        std::fs::create_dir(&unreadable)?;
+        let output = dir.path().join("output.bin");
+        run_cli(&format!(
+            "decode -i {} -o {} --read-retries 1 --retry-backoff-ms 1",
+            p(&shards),
+            p(&output)
+        ))
+        .await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        Ok(())
+    }
+
+    /// Log writer collecting everything written to it, for inspecting the
+    /// events of a `--log-format json` run.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn events(&self) -> Result<Vec<serde_json::Value>> {
+            let output = String::from_utf8(self.0.lock().unwrap().clone())?;
+            Ok(output
+                .lines()
+                .map(serde_json::from_str)
+                .collect::<Result<_, _>>()?)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_log_format_emits_json_lines() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        std::fs::write(&input, b"structured logs")?;
+        let cli = crate::cli::commands::Cli::try_parse_from(
+            format!(
+                "litiaina-rse --log-format json --log-level info encode -i {} -o {} -d 2 -p 1",
+                p(&input),
+                p(&dir.path().join("shards"))
+            )
+            .split_whitespace(),
+        )?;
+        assert_eq!(cli.log_format, LogFormat::Json);
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = logging::subscriber(cli.log_format, cli.log_level, move || writer.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let execution = cli.execution()?;
+        crate::run(cli.command, &execution).await?;
+
+        let events = captured.events()?;
+        assert!(!events.is_empty());
+        assert!(events.iter().all(|e| e["level"] != "DEBUG"));
+        assert!(
+            events
+                .iter()
+                .any(|e| e["span"]["name"] == "handle_encode" && e["fields"]["message"].is_string())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_final_log_line_reports_throughput() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(1 << 20, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.bin");
+
+        for (args, span) in [
+            (
+                format!("encode -i {} -o {} -d 4 -p 2", p(&input), p(&shards)),
+                "handle_encode",
+            ),
+            (
+                format!("decode -i {} -o {}", p(&shards), p(&output)),
+                "handle_decode",
+            ),
+        ] {
+            let cli = crate::cli::commands::Cli::try_parse_from(
+                format!("litiaina-rse --log-format json --log-level info {}", args)
+                    .split_whitespace(),
+            )?;
+            let captured = Captured::default();
+            let writer = captured.clone();
+            let subscriber =
+                logging::subscriber(cli.log_format, cli.log_level, move || writer.clone());
+            let _guard = tracing::subscriber::set_default(subscriber);
+            let execution = cli.execution()?;
+            crate::run(cli.command, &execution).await?;
+
+            let events = captured.events()?;
+            let last = events
+                .iter()
+                .rfind(|e| e["span"]["name"] == span && e["fields"]["mib_per_sec"].is_number())
+                .unwrap_or_else(|| panic!("{} logged no throughput", span));
+            assert_eq!(last["fields"]["bytes"], data.len());
+            for field in ["mib_per_sec", "read_mib_per_sec", "compute_mib_per_sec"] {
+                assert!(
+                    last["fields"][field].as_f64().unwrap() > 0.0,
+                    "{} {}",
+                    span,
+                    field
+                );
+            }
+        }
+        assert_eq!(std::fs::read(&output)?, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memory_budget_bounds_shards_in_flight() -> Result<()> {
+        assert_eq!(shard_concurrency(None, 1 << 30, 12), 12);
+        assert_eq!(shard_concurrency(Some(1 << 20), 1 << 30, 12), 1);
+        assert_eq!(shard_concurrency(Some(1 << 30), 4096, 12), 12);
+        assert_eq!(shard_concurrency(Some(10 << 20), 3 << 20, 12), 3);
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(8 << 20, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.bin");
+
+        // 1 MiB shards within a 3.5 MiB budget: three at a time out of 12.
+        let budget = 7 << 19;
+        for (args, span) in [
+            (
+                format!(
+                    "encode -i {} -o {} -d 8 -p 4 --memory-budget {}",
+                    p(&input),
+                    p(&shards),
+                    budget
+                ),
+                "handle_encode",
+            ),
+            (
+                format!(
+                    "decode -i {} -o {} --memory-budget {}",
+                    p(&shards),
+                    p(&output),
+                    budget
+                ),
+                "handle_decode",
+            ),
+        ] {
+            let cli = crate::cli::commands::Cli::try_parse_from(
+                format!("litiaina-rse --log-format json --log-level info {}", args)
+                    .split_whitespace(),
+            )?;
+            let captured = Captured::default();
+            let writer = captured.clone();
+            let subscriber =
+                logging::subscriber(cli.log_format, cli.log_level, move || writer.clone());
+            let _guard = tracing::subscriber::set_default(subscriber);
+            let execution = cli.execution()?;
+            crate::run(cli.command, &execution).await?;
+
+            let events = captured.events()?;
+            let event = events
+                .iter()
+                .find(|e| {
+                    e["span"]["name"] == span && e["fields"]["shard_buffers_limit"].is_number()
+                })
+                .unwrap_or_else(|| panic!("{} logged no shard buffer peak", span));
+            assert_eq!(event["fields"]["shard_buffers_limit"], 3, "{}", span);
+            let peak = event["fields"]["shard_buffers_peak"].as_u64().unwrap();
+            assert!((1..=3).contains(&peak), "{} peaked at {}", span, peak);
+        }
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // Measured by the store rather than the limiter: the reads in flight
+        // hold at most the budget, while every shard read is kept.
+        let store = SlowStore {
+            delays_ms: vec![20; 12],
+            ..Default::default()
+        };
+        let opts = EncodeOptions {
+            data_shards: 8,
+            parity_shards: 4,
+            ..Default::default()
+        };
+        encode_to_store(data.clone(), &store, &opts).await?;
+        let meta = store.read_metadata().await?.unwrap();
+        let opts = DecodeOptions {
+            memory_budget: Some(budget),
+            ..Default::default()
+        };
+        let (shards_opt, report) = prefetch_shards(&store, &meta, &opts).await?;
+        let shard_len = meta.shard_len();
+        let peak = store.peak_reading_bytes.load(Ordering::SeqCst);
+        assert!(
+            peak <= budget && peak > shard_len,
+            "peaked at {} bytes",
+            peak
+        );
+        assert_eq!(report.arrived.len(), 8);
+        assert_eq!(
+            shards_opt.iter().flatten().map(Vec::len).sum::<usize>(),
+            8 * shard_len
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blockwise_decode_matches_full_shard_decode() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(10_007, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 5 -p 3 --checksum-algo blake3",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let shard_len = ShardMetadata::read(&shards).await?.shard_len();
+
+        // A bit flip fails the checksum, so shard 4 is rebuilt too.
+        let flipped = shards.join(shard_file_name(4));
+        let mut bytes = std::fs::read(&flipped)?;
+        bytes[shard_len / 2] ^= 0x10;
+        std::fs::write(&flipped, &bytes)?;
+
+        let output = dir.path().join("output.bin");
+        for ignore_shards in [vec![], vec![0, 6], vec![1, 3]] {
+            let opts = DecodeOptions {
+                ignore_shards,
+                ..Default::default()
+            };
+            let full = decode_dir(&shards, &opts).await?;
+            assert_eq!(full, data);
+            for block_size in [1, 7, 512, shard_len, shard_len + 1, 1 << 20] {
+                let written = decode_blockwise(&shards, &opts, block_size, &output).await?;
+                assert_eq!(written, data.len());
+                assert_eq!(
+                    std::fs::read(&output)?,
+                    full,
+                    "block size {block_size}, ignoring {:?}",
+                    opts.ignore_shards
+                );
+            }
+        }
+
+        let opts = DecodeOptions {
+            ignore_shards: vec![0, 1, 2],
+            ..Default::default()
+        };
+        let err = decode_blockwise(&shards, &opts, 64, &output)
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+
+        run_cli(&format!(
+            "decode -i {} -o {} --block-size 100",
+            p(&shards),
+            p(&output)
+        ))
+        .await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        let scrambled = dir.path().join("scrambled");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --scramble 7",
+            p(&input),
+            p(&scrambled)
+        ))
+        .await?;
+        let err = decode_blockwise(&scrambled, &DecodeOptions::default(), 64, &output)
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blockwise_decode_skips_dependent_survivors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(3_001, test_seed());
+        std::fs::write(&input, &data)?;
+        let output = dir.path().join("output.bin");
+
+        // The first two parity rows of this non-MDS matrix are equal: with
+        // both data shards gone, shard 3 adds nothing to shard 2, and shard 4
+        // is read in its place.
+        let shards = dir.path().join("shards");
+        let opts = EncodeOptions {
+            data_shards: 2,
+            parity_shards: 3,
+            matrix_type: MatrixType::Custom,
+            custom_matrix: Some(vec![vec![1, 1], vec![1, 1], vec![1, 2]]),
+            ..Default::default()
+        };
+        encode_to_store(data.clone(), &FilesystemStore::new(&shards), &opts).await?;
+        let opts = DecodeOptions {
+            ignore_shards: vec![0, 1],
+            ..Default::default()
+        };
+        assert_eq!(
+            decode_blockwise(&shards, &opts, 100, &output).await?,
+            data.len()
+        );
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // Without shard 4 the set cannot be recovered.
+        let opts = DecodeOptions {
+            ignore_shards: vec![0, 1, 4],
+            ..Default::default()
+        };
+        let err = decode_blockwise(&shards, &opts, 100, &output)
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+
+        // Without checksums a damaged data shard is only caught by the input
+        // SHA-256, and neither the output nor a temporary file is left behind.
+        let unchecked = dir.path().join("unchecked");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 2 -p 1 --checksum-algo none",
+            p(&input),
+            p(&unchecked)
+        ))
+        .await?;
+        let damaged = unchecked.join(shard_file_name(0));
+        let mut bytes = std::fs::read(&damaged)?;
+        bytes[7] ^= 0x01;
+        std::fs::write(&damaged, bytes)?;
+        std::fs::remove_file(&output)?;
+        let err = decode_blockwise(&unchecked, &DecodeOptions::default(), 100, &output)
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+        assert!(!output.exists());
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compressed_shard_files_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        // Structured first half, random second half: the first data shards
+        // compress, the last one must be stored as is.
+        let mut data: Vec<u8> = (0..8_000u32).map(|i| (i % 16) as u8).collect();
+        data.extend(random_input(8_000, test_seed()));
+        std::fs::write(&input, &data)?;
+
+        for (name, extra) in [
+            ("zstd", "--compress-shards zstd"),
+            ("gzip", "--compress-shards gzip --shard-trailer"),
+            ("lowmem", "--compress-shards zstd --low-memory"),
+        ] {
+            let shards = dir.path().join(name);
+            run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 {}",
+                p(&input),
+                p(&shards),
+                extra
+            ))
+            .await?;
+            let meta = ShardMetadata::read(&shards).await?;
+            let sizes = &meta.shard_compression.as_ref().unwrap().sizes;
+            assert_eq!(sizes.len(), 6);
+            assert!(sizes[0].is_some_and(|len| len < meta.shard_len()), "{name}");
+            assert_eq!(sizes[3], None, "{name}");
+            for i in 0..6 {
+                let file_len = std::fs::metadata(shards.join(shard_file_name(i)))?.len();
+                assert_eq!(file_len as usize, meta.stored_len(i));
+            }
+
+            for ignore_shards in [vec![], vec![0, 4], vec![2, 3]] {
+                let opts = DecodeOptions {
+                    ignore_shards,
+                    ..Default::default()
+                };
+                assert_eq!(decode_dir(&shards, &opts).await?, data, "{name}");
+            }
+        }
+
+        // Without checksums, a compressed shard that no longer decompresses
+        // is rebuilt like a missing one.
+        let shards = dir.path().join("unchecked");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --compress-shards zstd --checksum-algo none",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let first = shards.join(shard_file_name(0));
+        let garbage = vec![0xff; std::fs::metadata(&first)?.len() as usize];
+        std::fs::write(&first, garbage)?;
+        let output = dir.path().join("output.bin");
+        run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_split_output_concatenates_to_input() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = mixed_shards(1, 10_001, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+
+        let split_dir = dir.path().join("split");
+        let opts = DecodeOptions {
+            ignore_shards: vec![1, 5],
+            ..Default::default()
+        };
+        let split = decode_split(&shards, &opts, &split_dir, true).await?;
+        assert_eq!(
+            (split.orig_len, split.data_shards, split.parity_shards),
+            (10_001, 4, 2)
+        );
+        let recorded: SplitInfo =
+            serde_json::from_slice(&std::fs::read(split_dir.join(SPLIT_FILE))?)?;
+        assert_eq!(recorded, split);
+        // Every file is written atomically: no temporary file is left over.
+        assert_eq!(std::fs::read_dir(&split_dir)?.count(), 6 + 1);
+
+        let mut joined = Vec::new();
+        for i in 0..4 {
+            let shard = std::fs::read(split_dir.join(shard_file_name(i)))?;
+            assert_eq!(shard.len(), split.shard_len);
+            joined.extend(shard);
+        }
+        // The last data shard carries the padding.
+        assert!(joined[split.orig_len..].iter().all(|&b| b == 0));
+        joined.truncate(split.orig_len);
+        assert_eq!(joined, data);
+        for i in 4..6 {
+            assert_eq!(
+                std::fs::read(split_dir.join(shard_file_name(i)))?,
+                std::fs::read(shards.join(shard_file_name(i)))?
+            );
+        }
+
+        let data_only = dir.path().join("data_only");
+        run_cli(&format!(
+            "decode -i {} --split-output {}",
+            p(&shards),
+            p(&data_only)
+        ))
+        .await?;
+        assert!(data_only.join(shard_file_name(3)).exists());
+        assert!(!data_only.join(shard_file_name(4)).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_verify_checksums_only() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        std::fs::write(&input, random_input(6_000, test_seed()))?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let check = format!("decode -i {} --verify-checksums-only", p(&shards));
+
+        // Missing shards are reported but are not a failure.
+        std::fs::remove_file(shards.join(shard_file_name(1)))?;
+        run_cli(&check).await?;
+
+        let flipped = shards.join(shard_file_name(4));
+        let mut bytes = std::fs::read(&flipped)?;
+        bytes[10] ^= 1;
+        std::fs::write(&flipped, &bytes)?;
+        let meta = ShardMetadata::read(&shards).await?;
+        let scan = ChecksumScan::run(&shards, &meta, &[0, 1, 2, 3, 4, 5], true)
+            .await?
+            .unwrap();
+        assert_eq!(
+            (scan.intact, scan.corrupt, scan.missing),
+            (vec![0, 2, 3, 5], vec![4], vec![1])
+        );
+        let err = run_cli(&check).await.unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+
+        let unchecked = dir.path().join("unchecked");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --checksum-algo none",
+            p(&input),
+            p(&unchecked)
+        ))
+        .await?;
+        let err = run_cli(&format!(
+            "decode -i {} --verify-checksums-only",
+            p(&unchecked)
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_shard_len_must_match_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(6_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let output = dir.path().join("output.bin");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let decode = |shard_len: usize| {
+            format!(
+                "decode -i {} -o {} -d 4 -p 2 --shard-len {}",
+                p(&shards),
+                p(&output),
+                shard_len
+            )
+        };
+
+        run_cli(&decode(1_500)).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+        let err = run_cli(&decode(1_400)).await.unwrap_err();
+        assert!(err.to_string().contains("--shard-len 1400"), "{err}");
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+
+        // Without metadata the supplied length is used, and a shard file of
+        // another size is named instead of being skipped.
+        std::fs::remove_file(shards.join("meta.json"))?;
+        let short = shards.join(shard_file_name(2));
+        let bytes = std::fs::read(&short)?;
+        std::fs::write(&short, &bytes[..1_000])?;
+        let err = run_cli(&decode(1_500)).await.unwrap_err();
+        assert!(
+            err.to_string().contains("1000 bytes, expected 1500"),
+            "{err}"
+        );
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+        std::fs::remove_file(&short)?;
+        run_cli(&decode(1_500)).await?;
+        assert_eq!(&std::fs::read(&output)?[..data.len()], &data[..]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_matrix_types_roundtrip_through_metadata() -> Result<()> {
+        let (k, m) = (5, 3);
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = mixed_shards(1, 7_000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let output = dir.path().join("output.bin");
+
+        // Cauchy rows in reverse order: MDS, but unlike either built-in matrix.
+        let mut custom = build_cauchy(&Gf256::new(), k, m);
+        custom.reverse();
+        let matrix_file = dir.path().join("matrix.txt");
+        std::fs::write(&matrix_file, matrix_to_hex_rows(&custom).join("\n"))?;
+
+        let mut parity_files = Vec::new();
+        for (name, flag, matrix_type) in [
+            (
+                "vandermonde",
+                "--matrix-type vandermonde".to_string(),
+                MatrixType::Vandermonde,
+            ),
+            (
+                "cauchy",
+                "--matrix-type cauchy".to_string(),
+                MatrixType::Cauchy,
+            ),
+            (
+                "custom",
+                format!("--matrix-file {}", p(&matrix_file)),
+                MatrixType::Custom,
+            ),
+        ] {
+            let shards = dir.path().join(name);
+            run_cli(&format!(
+                "encode -i {} -o {} -d {} -p {} {}",
+                p(&input),
+                p(&shards),
+                k,
+                m,
+                flag
+            ))
+            .await?;
+            let meta = ShardMetadata::read(&shards).await?;
+            assert_eq!(meta.matrix_type, Some(matrix_type));
+            assert_eq!(
+                meta.codec()?.encode_matrix(),
+                &match matrix_type {
+                    MatrixType::Custom => custom.clone(),
+                    ty => ty.build(&Gf256::new(), k, m)?,
+                }
+            );
+            parity_files.push(std::fs::read(shards.join(shard_file_name(k)))?);
+
+            for i in [0, 2, 4] {
+                std::fs::remove_file(shards.join(shard_file_name(i)))?;
+            }
+            run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+            assert_eq!(std::fs::read(&output)?, data, "{name}");
+        }
+        assert_ne!(parity_files[0], parity_files[1]);
+        assert_ne!(parity_files[1], parity_files[2]);
+
+        // A matrix that is not MDS, or has the wrong shape, is refused.
+        for rows in [
+            "0101010101\n0101010101\n0203040506",
+            "0102030405\n0607080910",
+        ] {
+            std::fs::write(&matrix_file, rows)?;
+            let err = run_cli(&format!(
+                "encode -i {} -o {} -d {} -p {} --matrix-file {}",
+                p(&input),
+                p(&dir.path().join("rejected")),
+                k,
+                m,
+                p(&matrix_file)
+            ))
+            .await
+            .unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{err:#}");
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "lean")]
+    #[tokio::test]
+    async fn test_lean_path_matches_full_path() -> Result<()> {
+        use crate::io::lean;
+
+        let (k, m) = (6, 3);
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = mixed_shards(1, 10_001, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let (full, lean_dir) = (dir.path().join("full"), dir.path().join("lean"));
+        run_cli(&format!(
+            "encode -i {} -o {} -d {} -p {}",
+            p(&input),
+            p(&full),
+            k,
+            m
+        ))
+        .await?;
+        lean::encode_file(&input, &lean_dir, k, m)?;
+        for i in 0..k + m {
+            assert_eq!(
+                std::fs::read(full.join(shard_file_name(i)))?,
+                std::fs::read(lean_dir.join(shard_file_name(i)))?
+            );
+        }
+
+        // Each build decodes the other's set after losing m shards.
+        for (set, removed) in [(&full, [0, 4, 7]), (&lean_dir, [1, 2, 8])] {
+            for i in removed {
+                std::fs::remove_file(set.join(shard_file_name(i)))?;
+            }
+        }
+        let output = dir.path().join("output.bin");
+        assert_eq!(lean::decode_file(&full, &output)?, data.len());
+        assert_eq!(std::fs::read(&output)?, data);
+        run_cli(&format!("decode -i {} -o {}", p(&lean_dir), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        let compressed = dir.path().join("compressed");
+        run_cli(&format!(
+            "encode -i {} -o {} -d {} -p {} --compress-shards gzip",
+            p(&input),
+            p(&compressed),
+            k,
+            m
+        ))
+        .await?;
+        let err = lean::decode_file(&compressed, &output).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_volumes_roundtrip_with_short_last_volume() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(9999, test_seed());
+        std::fs::write(&input, &data)?;
+        let output = dir.path().join("output.bin");
+
+        // 2500-byte shards in 1000-byte volumes: 1000, 1000 and 500 bytes.
+        for (set, extra) in ["--manifest", "--low-memory --shard-trailer"]
+            .iter()
+            .enumerate()
+        {
+            let shards = dir.path().join(format!("shards{}", set));
+            run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 --volume-size 1000 {}",
+                p(&input),
+                p(&shards),
+                extra
+            ))
+            .await?;
+            let meta = ShardMetadata::read(&shards).await?;
+            assert_eq!(meta.volume_size, Some(1000));
+            for i in 0..6 {
+                let name = shard_file_name(i);
+                assert!(!shards.join(&name).exists());
+                let sizes: Vec<usize> = (0..meta.volumes(i).unwrap())
+                    .map(|v| {
+                        std::fs::read(shards.join(volume_file_name(&name, v))).map(|f| f.len())
+                    })
+                    .collect::<std::io::Result<_>>()?;
+                let last = meta.stored_len(i) - 2000;
+                assert_eq!(sizes, [1000, 1000, last], "{extra}: shard {i}");
+                assert!(!shards.join(volume_file_name(&name, 3)).exists());
+            }
+            run_cli(&format!("verify -i {}", p(&shards))).await?;
+            run_cli(&format!("info -i {}", p(&shards))).await?;
+
+            // A lost middle volume truncates its shard; a shard with no
+            // volumes left is missing. Both are rebuilt.
+            std::fs::remove_file(shards.join(volume_file_name(&shard_file_name(1), 1)))?;
+            for v in 0..3 {
+                std::fs::remove_file(shards.join(volume_file_name(&shard_file_name(4), v)))?;
+            }
+            run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+            assert_eq!(std::fs::read(&output)?, data, "{extra}");
+        }
+
+        let err = run_cli(&format!(
+            "decode -i {} -o {} --block-size 512",
+            p(&dir.path().join("shards0")),
+            p(&output)
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --volume-size 0",
+            p(&input),
+            p(&dir.path().join("rejected"))
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_swapped_shard_files_are_detected() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = mixed_shards(1, 8000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+
+        let (a, b) = (
+            shards.join(shard_file_name(1)),
+            shards.join(shard_file_name(4)),
+        );
+        let tmp = dir.path().join("swap.tmp");
+        std::fs::rename(&a, &tmp)?;
+        std::fs::rename(&b, &a)?;
+        std::fs::rename(&tmp, &b)?;
+
+        let output = dir.path().join("output.bin");
+        for extra in ["", "--block-size 700"] {
+            let err = run_cli(&format!(
+                "decode -i {} -o {} {}",
+                p(&shards),
+                p(&output),
+                extra
+            ))
+            .await
+            .unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_CORRUPTION, "{extra}: {err:#}");
+            assert!(
+                format!("{err:#}").contains("shard_01.dat holds the contents of shard_04.dat"),
+                "{err:#}"
+            );
+        }
+        assert!(!output.exists());
+
+        // Putting the names back decodes as usual.
+        std::fs::rename(&a, &tmp)?;
+        std::fs::rename(&b, &a)?;
+        std::fs::rename(&tmp, &b)?;
+        run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        // A copy of a shard that is still in place is damage, not a reorder:
+        // it is rebuilt from parity.
+        std::fs::copy(&b, &a)?;
+        for extra in ["", "--block-size 700"] {
+            std::fs::remove_file(&output)?;
+            run_cli(&format!(
+                "decode -i {} -o {} {}",
+                p(&shards),
+                p(&output),
+                extra
+            ))
+            .await?;
+            assert_eq!(std::fs::read(&output)?, data, "{extra}");
+        }
+
+        // With the original gone, the copy is misplaced again.
+        std::fs::remove_file(&b)?;
+        let err = run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION, "{err:#}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_align_pads_shards_to_block_multiple() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("output.bin");
+        // 10001 bytes give 2501-byte shards before alignment; 5000 bytes fill
+        // only the first two data shards, leaving two of pure padding.
+        for len in [10_001, 5000] {
+            let input = dir.path().join(format!("input{}.bin", len));
+            let data = random_input(len, test_seed());
+            std::fs::write(&input, &data)?;
+            for extra in ["", "--low-memory"] {
+                let shards = dir.path().join(format!("shards{}{}", len, extra));
+                run_cli(&format!(
+                    "encode -i {} -o {} -d 4 -p 2 --align 4096 {}",
+                    p(&input),
+                    p(&shards),
+                    extra
+                ))
+                .await?;
+                let meta = ShardMetadata::read(&shards).await?;
+                assert_eq!((meta.shard_align, meta.shard_len()), (Some(4096), 4096));
+                for i in 0..6 {
+                    let file = std::fs::read(shards.join(shard_file_name(i)))?;
+                    assert_eq!(file.len() % 4096, 0, "{len} {extra}: shard {i}");
+                }
+
+                for i in [0, 5] {
+                    std::fs::remove_file(shards.join(shard_file_name(i)))?;
+                }
+                for decode_extra in ["", "--block-size 1000"] {
+                    run_cli(&format!(
+                        "decode -i {} -o {} {}",
+                        p(&shards),
+                        p(&output),
+                        decode_extra
+                    ))
+                    .await?;
+                    assert_eq!(
+                        std::fs::read(&output)?,
+                        data,
+                        "{len} {extra} {decode_extra}"
+                    );
+                }
+            }
+        }
+
+        let input = dir.path().join("input5000.bin");
+        for flags in ["--align 0", "--align 512 --shard-weights 1,1,1,1"] {
+            let err = run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 {}",
+                p(&input),
+                p(&dir.path().join("rejected")),
+                flags
+            ))
+            .await
+            .unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{flags}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scrub_reports_margin_and_repairs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = mixed_shards(1, 6000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 4 --shard-trailer --shard-weights 3,1,1,1",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let originals: Vec<Vec<u8>> = (0..8)
+            .map(|i| std::fs::read(shards.join(shard_file_name(i))))
+            .collect::<std::io::Result<_>>()?;
+
+        // One bit flip, one truncation, one deletion: 3 of 8 lost, 1 of 4
+        // tolerated losses left.
+        let flipped = shards.join(shard_file_name(1));
+        let mut bytes = originals[1].clone();
+        bytes[10] ^= 0x08;
+        std::fs::write(&flipped, bytes)?;
+        std::fs::write(shards.join(shard_file_name(5)), &originals[5][..100])?;
+        std::fs::remove_file(shards.join(shard_file_name(6)))?;
+
+        let meta = ShardMetadata::read(&shards).await?;
+        let report = scrub_dir(&shards, &meta).await?;
+        assert_eq!(
+            report.scan,
+            ChecksumScan {
+                intact: vec![0, 2, 3, 4, 7],
+                corrupt: vec![1, 5],
+                missing: vec![6],
+            }
+        );
+        assert_eq!(report.scan.lost(), [1, 5, 6]);
+        assert_eq!((report.margin(), report.tolerance), (Some(1), 4));
+
+        // A margin of 1 is not below 1, so nothing is rewritten yet.
+        run_cli(&format!("scrub -i {} --repair-below 1", p(&shards))).await?;
+        assert!(!shards.join(shard_file_name(6)).exists());
+        run_cli(&format!("scrub -i {} --repair-below 2", p(&shards))).await?;
+        for (i, original) in originals.iter().enumerate() {
+            assert_eq!(
+                &std::fs::read(shards.join(shard_file_name(i)))?,
+                original,
+                "shard {i}"
+            );
+        }
+        let report = scrub_dir(&shards, &meta).await?;
+        assert_eq!((report.scan.intact.len(), report.margin()), (8, Some(4)));
+
+        for i in [0, 2, 4, 6, 7] {
+            std::fs::remove_file(shards.join(shard_file_name(i)))?;
+        }
+        let err = run_cli(&format!("scrub -i {}", p(&shards)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+
+        let encrypted = dir.path().join("encrypted");
+        let key = "ab".repeat(32);
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --encrypt-key {}",
+            p(&input),
+            p(&encrypted),
+            key
+        ))
+        .await?;
+        run_cli(&format!("scrub -i {}", p(&encrypted))).await?;
+        let err = run_cli(&format!("scrub -i {} --repair-below 1", p(&encrypted)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_repair_resumes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_shards(4, 2_500, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 3",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let originals: Vec<Vec<u8>> = (0..7)
+            .map(|i| std::fs::read(shards.join(shard_file_name(i))))
+            .collect::<std::io::Result<_>>()?;
+        let meta = ShardMetadata::read(&shards).await?;
+        let lost = [1, 4, 5];
+        for &i in &lost {
+            std::fs::remove_file(shards.join(shard_file_name(i)))?;
+        }
+
+        // The first run got as far as writing shard 1, was cut off while
+        // staging shard 4, and left shard 5 half-copied under its real name.
+        std::fs::write(shards.join(shard_file_name(1)), &originals[1])?;
+        let temp = shards.join(format!(".{}.999-0.tmp", shard_file_name(4)));
+        std::fs::write(&temp, &originals[4][..500])?;
+        std::fs::write(shards.join(shard_file_name(5)), &originals[5][..1_000])?;
+
+        let repaired = repair_shards(&shards, &meta, &lost, false, &Execution::default()).await?;
+        assert_eq!(repaired, [4, 5]);
+        assert!(!temp.exists());
+        for (i, original) in originals.iter().enumerate() {
+            assert_eq!(
+                &std::fs::read(shards.join(shard_file_name(i)))?,
+                original,
+                "shard {i}"
+            );
+        }
+
+        // Running it once more finds nothing left to do.
+        assert!(
+            repair_shards(&shards, &meta, &lost, false, &Execution::default())
+                .await?
+                .is_empty()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_self_healing_file_repairs_itself() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(10_000, test_seed());
+        std::fs::write(&input, &data)?;
+        let archive = dir.path().join("archive.rse");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --self-healing",
+            p(&input),
+            p(&archive)
+        ))
+        .await?;
+        let original = std::fs::read(&archive)?;
+        // The input lies as it is right after the header.
+        assert_eq!(original[..8], SELF_HEALING_MAGIC);
+        assert_eq!(original[HEADER_LEN..HEADER_LEN + data.len()], data[..]);
+        run_cli(&format!("repair -i {} --self", p(&archive))).await?;
+        assert_eq!(std::fs::read(&archive)?, original);
+
+        // Damage the header, a data and a parity region, and the first copy
+        // of the manifest.
+        let region_len = data.len() / 4;
+        let manifest_at = HEADER_LEN + 6 * region_len;
+        let mut damaged = original.clone();
+        damaged[3] ^= 0xff;
+        damaged[HEADER_LEN + 100] ^= 0x01;
+        damaged[HEADER_LEN + 5 * region_len + 7] ^= 0x80;
+        damaged[manifest_at + 20] ^= 0x10;
+        std::fs::write(&archive, &damaged)?;
+        let report = repair_self_healing(&archive, &Execution::default()).await?;
+        assert_eq!(report.regions, [0, 5]);
+        assert_eq!(report.copies, ["header", "manifest"]);
+        assert_eq!(std::fs::read(&archive)?, original);
+
+        // A file cut off inside the second manifest copy, or with bytes
+        // appended, is laid out from its first header and put back to size.
+        let manifest_len = (original.len() - manifest_at - HEADER_LEN) / 2;
+        let cut = manifest_at + manifest_len + 5;
+        let mut longer = original.clone();
+        longer.extend_from_slice(b"appended by a careless tool");
+        for (damaged, copies) in [
+            (&original[..cut], vec!["header copy", "manifest copy"]),
+            (&longer[..], vec![]),
+        ] {
+            std::fs::write(&archive, damaged)?;
+            let report = repair_self_healing(&archive, &Execution::default()).await?;
+            assert_eq!(report.resized_from, Some(damaged.len()));
+            assert!(report.regions.is_empty());
+            assert_eq!(report.copies, copies);
+            assert_eq!(std::fs::read(&archive)?, original);
+        }
+        // With bytes appended and the first header damaged, the copy at the
+        // end is still found where it belongs.
+        longer[3] ^= 0xff;
+        std::fs::write(&archive, &longer)?;
+        let report = repair_self_healing(&archive, &Execution::default()).await?;
+        assert_eq!(report.copies, ["header"]);
+        assert_eq!(std::fs::read(&archive)?, original);
+
+        // Three damaged regions are more than two parity regions can cover;
+        // the file is left as it was.
+        let mut damaged = original.clone();
+        for i in [1, 2, 4] {
+            damaged[HEADER_LEN + i * region_len] ^= 0x01;
+        }
+        std::fs::write(&archive, &damaged)?;
+        let err = run_cli(&format!("repair -i {} --self", p(&archive)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        assert_eq!(std::fs::read(&archive)?, damaged);
+
+        // Without either header, nothing can be located.
+        let mut damaged = original.clone();
+        let last = damaged.len() - 1;
+        damaged[0] ^= 0x01;
+        damaged[last] ^= 0x01;
+        std::fs::write(&archive, &damaged)?;
+        let err = run_cli(&format!("repair -i {} --self", p(&archive)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+
+        let err = run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --self-healing --compress-shards zstd",
+            p(&input),
+            p(&archive)
+        ))
+        .await
+        .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+
+        // Without --self, only a file that starts with a header is taken for
+        // a self-healing one, and a shard directory is pointed to scrub.
+        let err = run_cli(&format!("repair -i {}", p(&archive)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        assert!(format!("{err:#}").contains("--self"), "{err:#}");
+        let err = run_cli(&format!("repair -i {}", p(dir.path())))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        assert!(format!("{err:#}").contains("scrub"), "{err:#}");
+        std::fs::write(&archive, &original)?;
+        run_cli(&format!("repair -i {}", p(&archive))).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_parity_catches_wrong_present_parity() -> Result<()> {
+        let (k, m) = (4, 3);
+        let codec = Codec::new(k, m);
+        let data_shards = mixed_shards(k, 2_000, test_seed());
+        let parities = codec.encode(&data_shards)?;
+        let mut shards: Vec<Option<Vec<u8>>> =
+            data_shards.into_iter().chain(parities).map(Some).collect();
+        shards[1] = None;
+        let mut checked = shards.clone();
+        codec.reconstruct_checked(&mut checked)?;
+
+        // Shard 1 is rebuilt from shards 0, 2, 3 and 4; parity shard 6 is
+        // not needed for that, so only the post-check sees that it is wrong.
+        shards[6].as_mut().unwrap()[123] ^= 0x40;
+        let mut unchecked = shards.clone();
+        codec.reconstruct(&mut unchecked)?;
+        assert_eq!(unchecked[1], checked[1]);
+        let err = codec.reconstruct_checked(&mut shards).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+        assert!(err.to_string().contains("[6]"), "{}", err);
+
+        // Scrub repair checks the same way before rewriting anything.
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        std::fs::write(&input, random_input(9_000, test_seed()))?;
+        let set = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 3 --checksum-algo none",
+            p(&input),
+            p(&set)
+        ))
+        .await?;
+        let repair = format!("scrub -i {} --repair-below 3", p(&set));
+        let original = std::fs::read(set.join(shard_file_name(0)))?;
+        std::fs::remove_file(set.join(shard_file_name(0)))?;
+        run_cli(&format!("{} --verify-parity", repair)).await?;
+        assert_eq!(std::fs::read(set.join(shard_file_name(0)))?, original);
+
+        let parity_path = set.join(shard_file_name(6));
+        let mut parity = std::fs::read(&parity_path)?;
+        parity[10] ^= 1;
+        std::fs::write(&parity_path, parity)?;
+        std::fs::remove_file(set.join(shard_file_name(0)))?;
+        let err = run_cli(&format!("{} --verify-parity", repair))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+        assert!(!set.join(shard_file_name(0)).exists());
+        run_cli(&repair).await?;
+        assert!(set.join(shard_file_name(0)).exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checksum_algorithms_detect_bit_flip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.txt");
+        let data = random_input(6000, test_seed());
+        std::fs::write(&input, &data)?;
+        let output = dir.path().join("output.txt");
+
+        for algo in ["crc32", "xxh3", "blake3"] {
+            let shards = dir.path().join(algo);
+            run_cli(&format!(
+                "encode -i {} -o {} -d 4 -p 2 --checksum-algo {}",
+                p(&input),
+                p(&shards),
+                algo
+            ))
+            .await?;
+            run_cli(&format!("verify -i {}", p(&shards))).await?;
+
+            let shard = shards.join("shard_01.dat");
+            let mut contents = std::fs::read(&shard)?;
+            contents[100] ^= 0x08;
+            std::fs::write(&shard, contents)?;
+
+            let err = run_cli(&format!("verify -i {}", p(&shards)))
+                .await
+                .unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_CORRUPTION, "{algo}");
+            // Decode drops the corrupt shard and rebuilds it from parity.
+            run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+            assert_eq!(std::fs::read(&output)?, data, "{algo}");
+        }
+
+        let shards = dir.path().join("none");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 4 -p 2 --checksum-algo none",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        assert!(ShardMetadata::read(&shards).await?.checksums.is_none());
+        assert!(run_cli(&format!("verify -i {}", p(&shards))).await.is_err());
+        let shard = shards.join("shard_01.dat");
+        let mut contents = std::fs::read(&shard)?;
+        contents[100] ^= 0x08;
+        std::fs::write(&shard, contents)?;
+        // Without checksums the flipped shard is used as-is; only the
+        // whole-file hash catches it.
+        let err = run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output)))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("SHA-256"), "{err}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruction_work_scales_with_missing_shards() -> Result<()> {
+        let (k, m) = (6, 4);
+        let codec = Codec::try_with_matrix_type(k, m, MatrixType::Cauchy)?;
+        let data: Vec<Vec<u8>> = (0..k).map(|i| vec![i as u8 * 3 + 1; 64]).collect();
+        let mut full = data.clone();
+        full.extend(codec.encode_with_matrix(&data, codec.encode_matrix())?);
+        let lose = |lost: &[usize]| -> Vec<Option<Vec<u8>>> {
+            full.iter()
+                .enumerate()
+                .map(|(i, s)| (!lost.contains(&i)).then(|| s.clone()))
+                .collect()
+        };
+
+        // Two lost data shards with every parity shard present: two recovery rows.
+        let mut shards = lose(&[1, 4]);
+        codec.reconstruct(&mut shards)?;
+        assert_eq!(codec.mac_passes(), 2 * k);
+        assert!(shards.iter().zip(&full).all(|(a, b)| a.as_ref() == Some(b)));
+
+        let before = codec.mac_passes();
+        codec.reconstruct(&mut lose(&[2]))?;
+        assert_eq!(codec.mac_passes() - before, k);
+
+        // Data-only recovery skips lost parity entirely.
+        let mut shards = lose(&[0, 3, 7, 9]);
+        let before = codec.mac_passes();
+        codec.reconstruct_data(&mut shards)?;
+        assert_eq!(codec.mac_passes() - before, 2 * k);
+        assert!(shards[7].is_none() && shards[9].is_none());
+        assert!(
+            shards[..k]
+                .iter()
+                .zip(&data)
+                .all(|(a, b)| a.as_ref() == Some(b))
+        );
+
+        let before = codec.mac_passes();
+        codec.reconstruct(&mut lose(&[0, 3, 7, 9]))?;
+        assert_eq!(codec.mac_passes() - before, 4 * k);
+
+        // Nothing missing among the targets: no work at all.
+        let before = codec.mac_passes();
+        codec.reconstruct_data(&mut lose(&[8]))?;
+        assert_eq!(codec.mac_passes(), before);
+
+        // The interleaved layout also reads each survivor once per lost shard.
+        let before = codec.mac_passes();
+        codec.reconstruct_with_layout(&mut lose(&[1, 4]), ShardLayout::Interleaved)?;
+        assert_eq!(codec.mac_passes() - before, 2 * k);
+
+        // So do the inversion-free paths for a single parity or data shard.
+        for (k, m, lost) in [(k, 1, vec![2]), (k, 1, vec![k]), (1, 3, vec![0, 2])] {
+            let codec = Codec::try_with_matrix_type(k, m, MatrixType::Cauchy)?;
+            let data: Vec<Vec<u8>> = (0..k).map(|i| vec![i as u8 * 5 + 2; 64]).collect();
+            let full: Vec<Vec<u8>> = data.iter().cloned().chain(codec.encode(&data)?).collect();
+            let mut shards: Vec<Option<Vec<u8>>> = full
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (!lost.contains(&i)).then(|| s.clone()))
+                .collect();
+            codec.reconstruct(&mut shards)?;
+            assert_eq!(codec.mac_passes(), lost.len() * k, "k={k} m={m}");
+            assert!(shards.iter().zip(&full).all(|(a, b)| a.as_ref() == Some(b)));
+        }
+        Ok(())
+    }
+
+    /// GF(2^8) through the trait's default `mul_acc`, without lookup tables.
+    struct PlainGf256(Gf256);
+
+    impl GaloisField for PlainGf256 {
+        type Elem = u8;
+        const ORDER: usize = 256;
+        const ZERO: u8 = 0;
+        const ONE: u8 = 1;
+
+        fn element(i: usize) -> u8 {
+            i as u8
+        }
+        fn add(&self, a: u8, b: u8) -> u8 {
+            a ^ b
+        }
+        fn mul(&self, a: u8, b: u8) -> u8 {
+            self.0.mul(a, b)
+        }
+        fn inv(&self, a: u8) -> Result<u8> {
+            self.0.inv(a)
+        }
+        fn exp(&self, power: usize) -> u8 {
+            self.0.exp[power % 255]
+        }
+    }
+
+    #[test]
+    fn test_generic_codec_matches_concrete_gf256() -> Result<()> {
+        let gf = Gf256::new();
+        let (k, m) = (5, 3);
+        let vandermonde = build_vandermonde(&gf, k, m);
+        for (r, row) in vandermonde.iter().enumerate() {
+            for (c, &cell) in row.iter().enumerate() {
+                assert_eq!(cell, gf.exp[(r + k) * c % 255]);
+            }
+        }
+
+        let data = random_shards(k, 300, test_seed());
+        let concrete = Codec::try_new(k, m)?;
+        let parities = concrete.encode_with_matrix(&data, concrete.encode_matrix())?;
+        for (r, parity) in parities.iter().enumerate() {
+            for (b, &byte) in parity.iter().enumerate() {
+                let naive = (0..k).fold(0u8, |acc, j| acc ^ gf.mul(vandermonde[r][j], data[j][b]));
+                assert_eq!(byte, naive);
+            }
+        }
+
+        for matrix_type in [MatrixType::Vandermonde, MatrixType::Cauchy] {
+            let concrete = Codec::try_with_matrix_type(k, m, matrix_type)?;
+            let plain = Codec::with_field(PlainGf256(Gf256::new()), k, m, matrix_type)?;
+            assert_eq!(plain.encode_matrix(), concrete.encode_matrix());
+            let expected = concrete.encode_with_matrix(&data, concrete.encode_matrix())?;
+            assert_eq!(
+                plain.encode_with_matrix(&data, plain.encode_matrix())?,
+                expected
+            );
+
+            let mut full = data.clone();
+            full.extend(expected);
+            let damaged: Vec<Option<Vec<u8>>> = full
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (![0, 2, 6].contains(&i)).then(|| s.clone()))
+                .collect();
+            let (mut a, mut b) = (damaged.clone(), damaged);
+            concrete.reconstruct(&mut a)?;
+            plain.reconstruct(&mut b)?;
+            assert_eq!(a, b);
+            assert!(a.iter().zip(&full).all(|(x, y)| x.as_ref() == Some(y)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_exported_matrix_reproduces_reconstruction() -> Result<()> {
+        let (k, m) = (5, 3);
+        let gf = Gf256::new();
+        let codec = Codec::try_new(k, m)?;
+        let data = random_shards(k, 64, test_seed());
+        let mut shards: Vec<Option<Vec<u8>>> = data.iter().cloned().map(Some).collect();
+        shards.extend(
+            shard_encoding(
+                &gf,
+                codec.encode_matrix(),
+                &data,
+                &ProgressBar::hidden(),
+                &Execution::default(),
+            )?
+            .into_iter()
+            .map(Some),
+        );
+
+        let hex_rows = matrix_to_hex_rows(codec.encode_matrix());
+        assert_eq!(hex_rows.len(), m);
+        assert!(hex_rows.iter().all(|row| row.len() == 2 * k));
+        assert_eq!(
+            matrix_to_bytes(codec.encode_matrix()),
+            codec.encode_matrix().concat()
+        );
+        let imported = matrix_from_hex_rows(&hex_rows)?;
+        assert_eq!(&imported, codec.encode_matrix());
+        assert!(matrix_from_hex_rows(&["0a0", "0b"]).is_err());
+
+        // Recover the data from survivors 0, 2, 5, 6, 7 using only the
+        // exported matrix, as another implementation would.
+        let survivors = [0, 2, 5, 6, 7];
+        let submatrix: Matrix = survivors
+            .iter()
+            .map(|&s| {
+                if s < k {
+                    (0..k).map(|c| u8::from(c == s)).collect()
+                } else {
+                    imported[s - k].clone()
+                }
+            })
+            .collect();
+        let inverse = invert_matrix(&gf, &submatrix)?;
+        let recovered: Vec<Vec<u8>> = (0..64)
+            .map(|byte| {
+                let column: Vec<u8> = survivors
+                    .iter()
+                    .map(|&s| shards[s].as_ref().unwrap()[byte])
+                    .collect();
+                mul_matrix_vec(&gf, &inverse, &column)
+            })
+            .collect();
+
+        for i in [1, 3, 4] {
+            shards[i] = None;
+        }
+        codec.reconstruct(&mut shards)?;
+        for (i, shard) in shards[..k].iter().enumerate() {
+            let column: Vec<u8> = recovered.iter().map(|bytes| bytes[i]).collect();
+            assert_eq!(shard.as_deref(), Some(column.as_slice()));
+            assert_eq!(column, data[i]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruct_report_contents() -> Result<()> {
+        let (k, m) = (4, 2);
+        let gf = Gf256::new();
+        let codec = Codec::try_new(k, m)?;
+        let data = random_shards(k, 32, test_seed());
+        let mut full: Vec<Option<Vec<u8>>> = data.iter().cloned().map(Some).collect();
+        full.extend(
+            shard_encoding(
+                &gf,
+                codec.encode_matrix(),
+                &data,
+                &ProgressBar::hidden(),
+                &Execution::default(),
+            )?
+            .into_iter()
+            .map(Some),
+        );
+        let lose = |lost: &[usize]| {
+            let mut shards = full.clone();
+            for &i in lost {
+                shards[i] = None;
+            }
+            shards
+        };
+
+        let mut shards = lose(&[1, 4]);
+        let report = codec.reconstruct_with_report(&mut shards)?;
+        assert_eq!(shards, full);
+        assert_eq!(report.recovered, [1, 4]);
+        assert_eq!(report.survivors, [0, 2, 3, 5]);
+        assert!(!report.cache_hit);
+        assert!(
+            report.to_string().starts_with(
+                "recovered shards [1, 4] from survivors [0, 2, 3, 5] (inverse computed)"
+            ),
+            "{report}"
+        );
+
+        // The same pattern again hits the cache; the data-only variant leaves
+        // parity shard 4 missing.
+        let report = codec.reconstruct_data(&mut lose(&[1, 4]))?;
+        assert_eq!(report.recovered, [1]);
+        assert!(report.cache_hit);
+
+        let report = codec.reconstruct_with_report(&mut full.clone())?;
+        assert_eq!(report, ReconstructReport::default());
+        assert_eq!(report.to_string(), "nothing to recover");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invert_matrix_into_reuses_buffers() -> Result<()> {
+        let gf = Gf256::new();
+        let mut scratch = InversionScratch::new();
+        let mut inverse = Matrix::new();
+        // Shrinking and growing sizes, so stale scratch contents would show.
+        for (n, offset) in [(32, 0), (5, 3), (17, 1), (32, 7)] {
+            let mat = build_cauchy(&gf, n, n + offset)[offset..offset + n].to_vec();
+            invert_matrix_into(&gf, &mat, &mut scratch, &mut inverse)?;
+            assert_eq!(inverse, invert_matrix(&gf, &mat)?);
+            assert_eq!(mul_matrix_matrix(&gf, &inverse, &mat), identity::<Gf256>(n));
+        }
+
+        let singular = vec![vec![1, 1], vec![2, 2]];
+        let err = invert_matrix_into(&gf, &singular, &mut scratch, &mut inverse).unwrap_err();
+        assert_eq!(err.to_string(), "Matrix is singular and cannot be inverted");
+        Ok(())
+    }
+
+    #[test]
+    fn test_gf_matrix_algebra_identities() -> Result<()> {
+        let gf = Gf256::new();
+        for (n, cols) in [(1, 3), (4, 4), (9, 5), (16, 16)] {
+            // Square Cauchy blocks are always invertible.
+            let a = build_cauchy(&gf, n, n);
+            let a_inv = invert_matrix(&gf, &a)?;
+            assert_eq!(mul_matrix_matrix(&gf, &a, &a_inv), identity::<Gf256>(n));
+            assert_eq!(mul_matrix_matrix(&gf, &a_inv, &a), identity::<Gf256>(n));
+            assert_eq!(mul_matrix_matrix(&gf, &identity::<Gf256>(n), &a), a);
+            assert_eq!(
+                invert_matrix(&gf, &identity::<Gf256>(n))?,
+                identity::<Gf256>(n)
+            );
+
+            let b = build_cauchy(&gf, cols, n);
+            assert_eq!(b.len(), n);
+            assert_eq!(transpose(&transpose(&b)), b);
+            assert_eq!(
+                transpose(&mul_matrix_matrix(&gf, &a, &b)),
+                mul_matrix_matrix(&gf, &transpose(&b), &transpose(&a))
+            );
+            let v = random_input(n, test_seed());
+            assert_eq!(
+                mul_vec_matrix(&gf, &v, &b),
+                mul_matrix_vec(&gf, &transpose(&b), &v)
+            );
+        }
+
+        let a = vec![vec![1, 2, 3], vec![4, 5, 6], vec![7, 8, 9]];
+        assert_eq!(
+            submatrix(&a, &[2, 0], &[1, 2]),
+            vec![vec![8, 9], vec![2, 3]]
+        );
+        let all: Vec<usize> = (0..3).collect();
+        assert_eq!(submatrix(&a, &all, &all), a);
+        assert_eq!(
+            transpose(&a),
+            vec![vec![1, 4, 7], vec![2, 5, 8], vec![3, 6, 9]]
+        );
+        assert!(transpose::<u8>(&[]).is_empty());
+
+        // A row in the span of the basis is not added; the unit rows of the
+        // columns it misses complete it.
+        let mut basis = Vec::new();
+        assert!(add_independent_row(&gf, &mut basis, a[0].clone())?);
+        assert!(add_independent_row(&gf, &mut basis, a[1].clone())?);
+        let sum: Vec<u8> = a[0]
+            .iter()
+            .zip(&a[1])
+            .map(|(&x, &y)| gf.add(x, y))
+            .collect();
+        assert!(!add_independent_row(&gf, &mut basis, sum)?);
+        assert!(!add_independent_row(&gf, &mut basis, vec![0; 3])?);
+        let added = (0..3)
+            .filter(|&i| add_independent_row(&gf, &mut basis, unit_row::<Gf256>(3, i)).unwrap())
+            .count();
+        assert_eq!((added, basis.len()), (1, 3));
+        assert_eq!(unit_row::<Gf256>(3, 1), identity::<Gf256>(3)[1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_xor_slice_matches_byte_loop() {
+        let src = random_input(67, test_seed());
+        // Lengths around the word size, and a destination longer than the source.
+        for (dst_len, src_len) in [(0, 0), (7, 7), (8, 8), (9, 9), (67, 67), (40, 13)] {
+            let init = random_input(dst_len, test_seed() ^ 1);
+            let mut expected = init.clone();
+            for (d, &s) in expected.iter_mut().zip(&src[..src_len]) {
+                *d ^= s;
+            }
+            let mut dst = init.clone();
+            xor_slice(&mut dst, &src[..src_len]);
+            assert_eq!(dst, expected, "dst_len={dst_len} src_len={src_len}");
+
+            let mut dst = init;
+            Gf256::new().mul_acc(1, &src[..src_len], &mut dst);
+            assert_eq!(dst, expected);
+        }
+    }
+
+    #[test]
+    fn test_check_mds_names_failing_survivors() -> Result<()> {
+        let gf = Gf256::new();
+        // Exhaustive for small codes, sampled for large ones.
+        for (k, m) in [(4, 2), (5, 6), (10, 8), (200, 56)] {
+            check_mds(&gf, &build_cauchy(&gf, k, m), k)?;
+        }
+        check_mds(&gf, &build_vandermonde(&gf, 10, 4), 10)?;
+
+        // The plain Vandermonde rows are not MDS for every k/m.
+        let err = check_mds(&gf, &build_vandermonde(&gf, 5, 6), 5).unwrap_err();
+        assert!(
+            err.to_string().contains("survivors [2, 3, 5, 8, 10]"),
+            "{}",
+            err
+        );
+        let mut shards_opt: Vec<Option<Vec<u8>>> = vec![None; 11];
+        let codec = Codec::new(5, 6);
+        let data: Vec<Vec<u8>> = (0..5).map(|i| vec![i as u8 + 1; 16]).collect();
+        let parity = codec.encode_with_matrix(&data, codec.encode_matrix())?;
+        for i in [2, 3, 5, 8, 10] {
+            shards_opt[i] = Some(if i < 5 {
+                data[i].clone()
+            } else {
+                parity[i - 5].clone()
+            });
+        }
+        assert!(codec.reconstruct(&mut shards_opt).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_serial_execution_matches_parallel() -> Result<()> {
+        let gf = Gf256::new();
+        let (k, m) = (8, 5);
+        let seed = test_seed();
+        let data_shards = &mixed_shards(k, 40_000, seed);
+        let matrix = build_vandermonde(&gf, k, m);
+        let run = |execution: Execution| -> Result<_> {
+            let codec = Codec::new(k, m).with_execution(execution.clone());
+            let parities = shard_encoding(
+                &gf,
+                &matrix,
+                data_shards,
+                &ProgressBar::hidden(),
+                &execution,
+            )?;
+            let mut shards_opt: Vec<Option<Vec<u8>>> = data_shards
+                .iter()
+                .chain(&parities)
+                .cloned()
+                .map(Some)
+                .collect();
+            for i in [0, 2, 5, 9, 12] {
+                shards_opt[i] = None;
+            }
+            let mut interleaved = shards_opt.clone();
+            codec.reconstruct(&mut shards_opt)?;
+            codec.reconstruct_with_layout(&mut interleaved, ShardLayout::Interleaved)?;
+            Ok((parities, shards_opt, interleaved))
+        };
+
+        let parallel = run(Execution::Parallel)?;
+        let serial = run(Execution::Serial)?;
+        assert!(serial == parallel, "serial output differs (seed {})", seed);
+        let capped = Execution::with_threads(2)?;
+        assert_eq!(capped.threads(), 2);
+        assert!(
+            run(capped)? == parallel,
+            "capped output differs (seed {})",
+            seed
+        );
+        assert!(Execution::with_threads(1)?.is_serial());
+
+        let dir = tempfile::tempdir()?;
+        let err = run_cli(&format!("info -i {} --max-threads 0", p(dir.path())))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        assert!(
+            run_cli(&format!(
+                "info -i {} --max-threads 2 --no-parallel",
+                p(dir.path())
+            ))
+            .await
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_single_parity_fast_path_matches_matrix_path() -> Result<()> {
+        let gf = Gf256::new();
+        let k = 6;
+        let seed = test_seed();
+        let data_shards = &random_shards(k, 10_001, seed);
+
+        for matrix_type in [MatrixType::Xor, MatrixType::Vandermonde, MatrixType::Cauchy] {
+            let codec = Codec::try_with_matrix_type(k, 1, matrix_type)?;
+            let row = &codec.encode_matrix()[0];
+            let parity = codec.encode_with_matrix(data_shards, codec.encode_matrix())?;
+            let mut expected = vec![0u8; data_shards[0].len()];
+            for (&coef, shard) in row.iter().zip(data_shards) {
+                for (e, &b) in expected.iter_mut().zip(shard) {
+                    *e ^= gf.mul(coef, b);
+                }
+            }
+            assert_eq!(
+                parity,
+                vec![expected],
+                "{:?} parity (seed {})",
+                matrix_type,
+                seed
+            );
+
+            // The general path: invert the survivors' generator rows.
+            let generator: Matrix = (0..k)
+                .map(|i| (0..k).map(|c| u8::from(c == i)).collect())
+                .chain([row.clone()])
+                .collect();
+            let all: Vec<Vec<u8>> = data_shards.iter().chain(&parity).cloned().collect();
+            for lost in 0..=k {
+                let survivors: Vec<usize> = (0..=k).filter(|&i| i != lost).collect();
+                let a: Matrix = survivors.iter().map(|&i| generator[i].clone()).collect();
+                let recovery = mul_vec_matrix(&gf, &generator[lost], &invert_matrix(&gf, &a)?);
+                let mut general = vec![0u8; all[0].len()];
+                for (&coef, &i) in recovery.iter().zip(&survivors) {
+                    gf.mul_acc(coef, &all[i], &mut general);
+                }
+
+                let mut shards_opt: Vec<Option<Vec<u8>>> = all.iter().cloned().map(Some).collect();
+                shards_opt[lost] = None;
+                let report = codec.reconstruct_with_report(&mut shards_opt)?;
+                assert_eq!(report.recovered, vec![lost]);
+                assert_eq!(shards_opt[lost].as_ref(), Some(&general));
+                assert_eq!(
+                    general, all[lost],
+                    "{:?} lost {} (seed {})",
+                    matrix_type, lost, seed
+                );
+            }
+            assert_eq!(codec.cached_inverses(), 0, "no inversion for m == 1");
+        }
+        assert!(Codec::try_with_matrix_type(k, 2, MatrixType::Xor).is_err());
+        // A custom matrix has no construction: building one is an error, not
+        // a panic, for the codec and for encode options alike.
+        let err = MatrixType::Custom.build(&Gf256::new(), k, 1).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        let Err(err) = Codec::try_with_matrix_type(k, 1, MatrixType::Custom) else {
+            panic!("a custom matrix type needs its rows");
+        };
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        let opts = EncodeOptions {
+            data_shards: k,
+            parity_shards: 1,
+            matrix_type: MatrixType::Custom,
+            ..Default::default()
+        };
+        assert!(ParityEncoder::new(&opts).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruct_borrowed_from_one_buffer() -> Result<()> {
+        let (k, m) = (6, 3);
+        let codec = Codec::new(k, m);
+        let data_shards = &mixed_shards(k, 5000, test_seed());
+        let parities = codec.encode_with_matrix(data_shards, codec.encode_matrix())?;
+        let originals: Vec<Vec<u8>> = data_shards.iter().chain(&parities).cloned().collect();
+
+        // Every shard lives in one backing buffer, as with a mapped file.
+        let shard_len = originals[0].len();
+        let buffer: Vec<u8> = originals.concat();
+        let lost = [4, 1, 7];
+        let survivors: Vec<(usize, &[u8])> = (0..k + m)
+            .rev()
+            .filter(|i| !lost.contains(i))
+            .map(|i| (i, &buffer[i * shard_len..(i + 1) * shard_len]))
+            .collect();
+
+        let recovered = codec.reconstruct_borrowed(&survivors, &lost)?;
+        let expected: Vec<(usize, Vec<u8>)> =
+            lost.iter().map(|&i| (i, originals[i].clone())).collect();
+        assert_eq!(recovered, expected);
+
+        let mut shards_opt: Vec<Option<Vec<u8>>> = originals.iter().cloned().map(Some).collect();
+        for i in lost {
+            shards_opt[i] = None;
+        }
+        codec.reconstruct(&mut shards_opt)?;
+        for (i, shard) in &recovered {
+            assert_eq!(shards_opt[*i].as_ref(), Some(shard));
+        }
+
+        assert!(
+            codec
+                .reconstruct_borrowed(&survivors, &[])
+                .unwrap()
+                .is_empty()
+        );
+        let err = codec
+            .reconstruct_borrowed(&survivors[..k - 1], &[4])
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        assert!(codec.reconstruct_borrowed(&survivors, &[0]).is_err());
+        assert!(codec.reconstruct_borrowed(&survivors, &[9]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggestions_meet_constraints() -> Result<()> {
+        let gf = Gf256::new();
+        for (tolerance, max_overhead) in [(1, 0.1), (2, 0.3), (3, 0.5), (4, 0.2)] {
+            let suggestions = suggest(tolerance, max_overhead, 8)?;
+            assert_eq!(suggestions.len(), 8);
+            for s in &suggestions {
+                let (k, m) = (s.data_shards, s.parity_shards);
+                assert!(m >= tolerance);
+                assert!(m as f64 / (k + m) as f64 <= max_overhead, "{s:?}");
+                assert!(k + m <= 256);
+                check_mds(&gf, &s.matrix_type.build(&gf, k, m)?, k)?;
+            }
+            assert!(
+                suggestions
+                    .windows(2)
+                    .all(|w| w[0].data_shards + w[0].parity_shards
+                        <= w[1].data_shards + w[1].parity_shards)
+            );
+        }
+        // 2 of 7 shards is the least a 2-loss, 30% budget allows.
+        let best = suggest(2, 0.3, 1)?[0];
+        assert_eq!((best.data_shards, best.parity_shards), (5, 2));
+
+        assert!(suggest(100, 0.2, 8)?.is_empty());
+        for (tolerance, max_overhead) in [(0, 0.3), (2, 0.0), (2, 1.0), (2, f64::NAN)] {
+            let err = suggest(tolerance, max_overhead, 8).unwrap_err();
+            assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_into_matches_encode() -> Result<()> {
+        for (k, m, matrix_type) in [
+            (6, 3, MatrixType::Vandermonde),
+            (5, 4, MatrixType::Cauchy),
+            (7, 1, MatrixType::Xor),
+        ] {
+            let codec = Codec::try_with_matrix_type(k, m, matrix_type)?;
+            // One pooled buffer set, reused with stale contents.
+            let mut pool = vec![vec![0xa5u8; 1000]; m];
+            for seed in [test_seed(), test_seed() ^ 1] {
+                let data = &random_shards(k, 1000, seed);
+                let expected = codec.encode(data)?;
+                let slices: Vec<&[u8]> = data.iter().map(Vec::as_slice).collect();
+                let mut out: Vec<&mut [u8]> = pool.iter_mut().map(Vec::as_mut_slice).collect();
+                codec.encode_into(&slices, &mut out)?;
+                assert_eq!(pool, expected, "{matrix_type:?}");
+            }
+        }
+
+        let codec = Codec::try_new(4, 2)?;
+        let data = vec![vec![1u8; 64]; 4];
+        let slices: Vec<&[u8]> = data.iter().map(Vec::as_slice).collect();
+        let (mut a, mut b, mut short) = (vec![0u8; 64], vec![0u8; 64], vec![0u8; 63]);
+        assert!(codec.encode_into(&slices, &mut [&mut a[..]]).is_err());
+        assert!(
+            codec
+                .encode_into(&slices, &mut [&mut a[..], &mut short[..]])
+                .is_err()
+        );
+        assert!(
+            codec
+                .encode_into(&slices[..3], &mut [&mut a[..], &mut b[..]])
+                .is_err()
+        );
+        codec.encode_into(&slices, &mut [&mut a[..], &mut b[..]])?;
+        assert_eq!(vec![a, b], codec.encode(&data)?);
+        Ok(())
+    }
+
+    /// Encodes random data for a grid of k/m up to 256 shards, loses `m`
+    /// random shards a few times for each and checks the data comes back.
+    /// Cauchy must always recover; Vandermonde failures are printed as
+    /// `(k, m, survivors)`. Run with `--ignored --nocapture` to see them.
+    #[test]
+    #[ignore = "slow parameter sweep; run with --ignored"]
+    fn test_reconstruct_across_parameter_grid() -> Result<()> {
+        const KS: [usize; 20] = [
+            1, 2, 3, 4, 5, 6, 8, 10, 12, 16, 20, 32, 50, 64, 100, 128, 170, 200, 240, 255,
+        ];
+        const MS: [usize; 14] = [1, 2, 3, 4, 5, 6, 8, 10, 16, 20, 32, 64, 128, 255];
+        const TRIALS: u64 = 4;
+        let seed = test_seed();
+
+        let mut failures = Vec::new();
+        for matrix_type in [MatrixType::Vandermonde, MatrixType::Cauchy] {
+            for (k, m) in KS
+                .iter()
+                .flat_map(|&k| MS.iter().map(move |&m| (k, m)))
+                .filter(|&(k, m)| k + m <= 256)
+            {
+                let n = k + m;
+                let codec = Codec::try_with_matrix_type(k, m, matrix_type)?;
+                let mut full = random_shards(k, 32, seed ^ (n as u64));
+                full.extend(codec.encode(&full)?);
+                for trial in 0..TRIALS {
+                    let mut lost =
+                        permutation(n, seed ^ (k as u64) << 16 ^ (m as u64) << 32 ^ trial);
+                    lost.truncate(m);
+                    let mut shards: Vec<Option<Vec<u8>>> = full.iter().cloned().map(Some).collect();
+                    for &i in &lost {
+                        shards[i] = None;
+                    }
+                    let recovered = codec.reconstruct(&mut shards).is_ok()
+                        && shards.iter().zip(&full).all(|(s, f)| s.as_ref() == Some(f));
+                    if !recovered {
+                        let survivors: Vec<usize> = (0..n).filter(|i| !lost.contains(i)).collect();
+                        failures.push((matrix_type, k, m, survivors));
+                    }
+                }
+            }
+        }
+
+        for (matrix_type, k, m, survivors) in &failures {
+            eprintln!("{matrix_type:?}: ({k}, {m}, {survivors:?})");
+        }
+        let vandermonde: std::collections::BTreeSet<(usize, usize)> = failures
+            .iter()
+            .filter(|f| f.0 == MatrixType::Vandermonde)
+            .map(|f| (f.1, f.2))
+            .collect();
+        eprintln!(
+            "Vandermonde fails for {} (k, m): {:?}",
+            vandermonde.len(),
+            vandermonde
+        );
+        assert!(
+            failures.iter().all(|f| f.0 == MatrixType::Vandermonde),
+            "Cauchy failed to reconstruct"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_correct_errors_without_knowing_the_bad_shard() -> Result<()> {
+        let (k, m) = (5, 4);
+        let codec = Codec::try_with_matrix_type(k, m, MatrixType::Cauchy)?;
+        let data_shards = &random_shards(k, 2000, test_seed());
+        let clean: Vec<Option<Vec<u8>>> = data_shards
+            .iter()
+            .chain(&codec.encode(data_shards)?)
+            .cloned()
+            .map(Some)
+            .collect();
+        assert_eq!(
+            correct_errors(&codec, &mut clean.clone())?,
+            ErrorCorrection::default()
+        );
+
+        // Up to m/2 corrupted shards, in data or parity, are found and fixed.
+        for bad in [vec![2], vec![7], vec![0, 8], vec![3, 4]] {
+            let mut shards = clean.clone();
+            for &i in &bad {
+                for pos in [0, 17, 1999] {
+                    shards[i].as_mut().unwrap()[pos] ^= 0x5a + i as u8;
+                }
+            }
+            let report = correct_errors(&codec, &mut shards)?;
+            assert_eq!(report.shards, bad);
+            assert_eq!(report.symbols, 3 * bad.len());
+            assert_eq!(shards, clean);
+        }
+
+        // A missing shard costs one check: one error is still corrected, and
+        // the erasure is then rebuilt as usual.
+        let mut shards = clean.clone();
+        shards[1] = None;
+        shards[6].as_mut().unwrap()[99] ^= 0xff;
+        assert_eq!(correct_errors(&codec, &mut shards)?.shards, vec![6]);
+        codec.reconstruct(&mut shards)?;
+        assert_eq!(shards, clean);
+
+        let mut shards = clean.clone();
+        for i in [0, 1, 2] {
+            shards[i].as_mut().unwrap()[5] ^= 1;
+        }
+        let err = correct_errors(&codec, &mut shards).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+
+        let vandermonde = Codec::new(k, m);
+        let err = correct_errors(&vandermonde, &mut clean.clone()).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decode_correct_errors_fixes_corrupted_shards() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_shards(5, 2000, test_seed()).concat();
+        std::fs::write(&input, &data)?;
+        let encode = |shards: &Path, matrix: &str| {
+            format!(
+                "encode -i {} -o {} -d 5 -p 4 --matrix-type {} --checksum-algo none",
+                p(&input),
+                p(shards),
+                matrix
+            )
+        };
+        let shards = dir.path().join("cauchy");
+        run_cli(&encode(&shards, "cauchy")).await?;
+        // Two shards corrupted at different places, one of them missing
+        // entirely: the remaining checks still correct one error per byte.
+        for (i, pos) in [(1, 10), (6, 20)] {
+            let path = shards.join(shard_file_name(i));
+            let mut shard = std::fs::read(&path)?;
+            shard[pos] ^= 0x3c;
+            std::fs::write(&path, shard)?;
+        }
+        std::fs::remove_file(shards.join(shard_file_name(8)))?;
+
+        let output = dir.path().join("out.bin");
+        let decode = |shards: &Path| format!("decode -i {} -o {}", p(shards), p(&output));
+        let err = run_cli(&decode(&shards)).await.unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION, "{err:#}");
+        run_cli(&format!("{} --correct-errors", decode(&shards))).await?;
+        assert_eq!(std::fs::read(&output)?, data);
+
+        let vandermonde = dir.path().join("vandermonde");
+        run_cli(&encode(&vandermonde, "vandermonde")).await?;
+        let err = run_cli(&format!("{} --correct-errors", decode(&vandermonde)))
+            .await
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{err:#}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruction_mac_count_matches_actual_passes() -> Result<()> {
+        let shards_for = |codec: &Codec, missing: &[usize]| -> Result<Vec<Option<Vec<u8>>>> {
+            let k = codec.data_shards();
+            let data = random_shards(k, 500, test_seed());
+            let parity = codec.encode(&data)?;
+            Ok(data
+                .into_iter()
+                .chain(parity)
+                .enumerate()
+                .map(|(i, s)| (!missing.contains(&i)).then_some(s))
+                .collect())
+        };
+        let patterns: [&[usize]; 6] = [&[], &[3], &[7], &[0, 1], &[2, 6, 9], &[0, 4, 5, 8]];
+        for (matrix_type, selection) in [
+            (MatrixType::Vandermonde, SurvivorSelection::FirstK),
+            (MatrixType::Vandermonde, SurvivorSelection::Sparsest),
+            (MatrixType::Cauchy, SurvivorSelection::Sparsest),
+        ] {
+            let codec =
+                Codec::try_with_matrix_type(6, 4, matrix_type)?.with_survivor_selection(selection);
+            for missing in patterns {
+                let predicted = codec.reconstruction_mac_count(missing)?;
+                assert!(predicted <= missing.len() * codec.data_shards());
+                for layout in [ShardLayout::RowMajor, ShardLayout::Interleaved] {
+                    let before = codec.nonzero_mac_passes();
+                    codec.reconstruct_with_layout(&mut shards_for(&codec, missing)?, layout)?;
+                    assert_eq!(
+                        codec.nonzero_mac_passes() - before,
+                        predicted,
+                        "{matrix_type:?} {selection:?} {layout:?} missing {missing:?}"
+                    );
+                }
+            }
+        }
+
+        for matrix_type in [MatrixType::Xor, MatrixType::Vandermonde] {
+            let codec = Codec::try_with_matrix_type(5, 1, matrix_type)?;
+            for missing in [2, 5] {
+                let predicted = codec.reconstruction_mac_count(&[missing])?;
+                let before = codec.nonzero_mac_passes();
+                codec.reconstruct(&mut shards_for(&codec, &[missing])?)?;
+                assert_eq!(codec.nonzero_mac_passes() - before, predicted);
+            }
+        }
+
+        // Predicting leaves the inverse cache alone.
+        let codec = Codec::new(6, 4);
+        codec.reconstruction_mac_count(&[0, 1])?;
+        assert_eq!(codec.cached_inverses(), 0);
+        let err = codec
+            .reconstruction_mac_count(&[0, 1, 2, 3, 4])
+            .unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_UNRECOVERABLE);
+        assert!(codec.reconstruction_mac_count(&[1, 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruct_many_missing_parity_shards() -> Result<()> {
+        let gf = Gf256::new();
+        let (k, m) = (10, 8);
+        for matrix_type in [MatrixType::Vandermonde, MatrixType::Cauchy] {
+            let codec = Codec::try_with_matrix_type(k, m, matrix_type)?;
+            let data = random_shards(k, 257, test_seed());
+            let full: Vec<Vec<u8>> = data.iter().cloned().chain(codec.encode(&data)?).collect();
+            let patterns: [&[usize]; 3] = [&[10, 12, 13, 15, 17], &[0, 11, 3, 16, 14], &[17, 10]];
+            for missing in patterns {
+                for layout in [ShardLayout::RowMajor, ShardLayout::Interleaved] {
+                    let mut shards: Vec<Option<Vec<u8>>> = full
+                        .iter()
+                        .enumerate()
+                        .map(|(i, s)| (!missing.contains(&i)).then(|| s.clone()))
+                        .collect();
+                    codec.reconstruct_with_layout(&mut shards, layout)?;
+                    assert_eq!(
+                        shards.into_iter().flatten().collect::<Vec<_>>(),
+                        full,
+                        "{matrix_type:?} {layout:?} {missing:?}"
+                    );
+                }
+            }
+        }
+
+        // The whole-matrix product matches row-by-row products.
+        let a = build_cauchy(&gf, 10, 7);
+        let b = invert_matrix(&gf, &build_cauchy(&gf, 10, 10))?;
+        let rows: Matrix = a.iter().map(|row| mul_vec_matrix(&gf, row, &b)).collect();
+        assert_eq!(mul_matrix_matrix(&gf, &a, &b), rows);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "timing comparison; run with --ignored --nocapture"]
+    fn test_recovery_rows_product_timing() -> Result<()> {
+        let gf = Gf256::new();
+        let (k, m, rounds) = (128, 64, 200);
+        let encode_rows = build_cauchy(&gf, k, m);
+        let a_inv = invert_matrix(&gf, &build_cauchy(&gf, k, k))?;
+
+        let started = std::time::Instant::now();
+        for _ in 0..rounds {
+            let rows: Matrix = encode_rows
+                .iter()
+                .map(|row| mul_vec_matrix(&gf, row, &a_inv))
+                .collect();
+            std::hint::black_box(rows);
+        }
+        let per_row = started.elapsed();
+        let started = std::time::Instant::now();
+        for _ in 0..rounds {
+            std::hint::black_box(mul_matrix_matrix(&gf, &encode_rows, &a_inv));
+        }
+        let product = started.elapsed();
+        println!(
+            "{} recovery rows for k={}: per row {:.2?}, one product {:.2?} ({} rounds)",
+            m, k, per_row, product, rounds
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_short_parity_shard_is_named() -> Result<()> {
+        let (k, m) = (4, 2);
+        let codec = Codec::new(k, m);
+        let data_shards = random_shards(k, 1_000, test_seed());
+        let parities = codec.encode(&data_shards)?;
+        let mut shards: Vec<Option<Vec<u8>>> =
+            data_shards.into_iter().chain(parities).map(Some).collect();
+        shards[1] = None;
+        shards[4].as_mut().unwrap().pop();
+
+        let err = codec.reconstruct(&mut shards.clone()).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION);
+        assert!(
+            err.to_string().starts_with("Shard 4 has 999 elements"),
+            "{}",
+            err
+        );
+        assert!(codec.reconstruct_data(&mut shards.clone()).is_err());
+
+        // A short data shard among full-length parity is named the same way.
+        shards[4] = None;
+        shards[2].as_mut().unwrap().truncate(10);
+        let err = codec.reconstruct(&mut shards).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Shard 2 has 10 elements"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reconstruct_with_survivors_uses_given_set() -> Result<()> {
+        let (k, m) = (4, 4);
+        let codec = Codec::new(k, m);
+        let data_shards = mixed_shards(k, 1_500, test_seed());
+        let parities = codec.encode(&data_shards)?;
+        let all: Vec<&[u8]> = data_shards
+            .iter()
+            .chain(&parities)
+            .map(Vec::as_slice)
+            .collect();
+
+        // Three parity shards and one data shard, listed out of order.
+        let survivors = [6, 1, 4, 5];
+        let survivor_data: Vec<&[u8]> = survivors.iter().map(|&i| all[i]).collect();
+        let missing = [3, 0, 7, 2];
+        let recovered = codec.reconstruct_with_survivors(&survivors, &survivor_data, &missing)?;
+        for (&i, shard) in missing.iter().zip(&recovered) {
+            assert_eq!(shard.as_slice(), all[i], "shard {}", i);
+        }
+
+        assert!(
+            codec
+                .reconstruct_with_survivors(&survivors[..3], &survivor_data[..3], &missing)
+                .is_err()
+        );
+        assert!(
+            codec
+                .reconstruct_with_survivors(&[1, 1, 4, 5], &survivor_data, &[0])
+                .is_err()
+        );
+
+        // Identical parity rows make any survivor set holding both singular.
+        let codec = Codec::with_matrix(2, 2, vec![vec![1, 2], vec![1, 2]])?;
+        let parities = codec.encode_with_matrix(&data_shards[..2], codec.encode_matrix())?;
+        let err = codec
+            .reconstruct_with_survivors(&[2, 3], &[&parities[0], &parities[1]], &[0, 1])
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("[2, 3]"), "{:#}", err);
+        let recovered =
+            codec.reconstruct_with_survivors(&[1, 3], &[&data_shards[1], &parities[1]], &[0])?;
+        assert_eq!(recovered, vec![data_shards[0].clone()]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_single_data_shard_is_replication() -> Result<()> {
+        let data = random_shards(1, 2_345, test_seed());
+        let codec = Codec::new(1, 3);
+        let parities = codec.encode(&data)?;
+        assert!(parities.iter().all(|parity| *parity == data[0]));
+
+        for survivor in 0..4 {
+            let mut shards: Vec<Option<Vec<u8>>> = vec![None; 4];
+            shards[survivor] = Some(data[0].clone());
+            let report = codec.reconstruct_with_report(&mut shards)?;
+            assert_eq!(report.survivors, vec![survivor]);
+            assert!(shards.iter().all(|s| s.as_ref() == Some(&data[0])));
+        }
+        // Copies need no matrix inversion.
+        assert_eq!(codec.cached_inverses(), 0);
+        assert_eq!(codec.reconstruction_mac_count(&[0, 1, 2])?, 3);
+
+        // Cauchy parity is a scaled copy, still recoverable from any shard.
+        let cauchy = Codec::try_with_matrix_type(1, 3, MatrixType::Cauchy)?;
+        let parities = cauchy.encode(&data)?;
+        let mut shards = vec![None, None, Some(parities[1].clone()), None];
+        cauchy.reconstruct(&mut shards)?;
+        assert_eq!(shards[0].as_ref(), Some(&data[0]));
+        assert_eq!(shards[3].as_ref(), Some(&parities[2]));
+
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        std::fs::write(&input, &data[0])?;
+        let shards = dir.path().join("shards");
+        run_cli(&format!(
+            "encode -i {} -o {} -d 1 -p 3",
+            p(&input),
+            p(&shards)
+        ))
+        .await?;
+        let meta = ShardMetadata::read(&shards).await?;
+        assert_eq!(meta.shard_len(), data[0].len());
+        for i in 0..4 {
+            assert_eq!(std::fs::read(shards.join(shard_file_name(i)))?, data[0]);
+        }
+        for i in 0..3 {
+            std::fs::remove_file(shards.join(shard_file_name(i)))?;
+        }
+        let output = dir.path().join("output.bin");
+        run_cli(&format!("decode -i {} -o {}", p(&shards), p(&output))).await?;
+        assert_eq!(std::fs::read(&output)?, data[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_suggest_shards_completes_partial_sets() -> Result<()> {
+        let fetch = |codec: &Codec, present: &[usize], suggested: &[usize]| -> Result<()> {
+            let k = codec.data_shards();
+            let data = random_shards(k, 300, test_seed());
+            let full: Vec<Vec<u8>> = data.iter().cloned().chain(codec.encode(&data)?).collect();
+            let mut shards: Vec<Option<Vec<u8>>> = (0..codec.total_shards())
+                .map(|i| (present.contains(&i) || suggested.contains(&i)).then(|| full[i].clone()))
+                .collect();
+            codec.reconstruct(&mut shards)?;
+            assert_eq!(shards.into_iter().flatten().collect::<Vec<_>>(), full);
+            Ok(())
+        };
+
+        let codec = Codec::try_with_matrix_type(6, 4, MatrixType::Cauchy)?;
+        let cases: [(&[usize], &[usize]); 5] = [
+            (&[], &[0, 1, 2, 3, 4, 5]),
+            (&[1, 7, 9], &[0, 2, 3]),
+            (&[6, 7, 8, 9], &[0, 1]),
+            (&[0, 2, 4, 5, 8], &[1]),
+            (&[9, 0, 1, 2, 3, 4, 5], &[]),
+        ];
+        for (present, expected) in cases {
+            assert_eq!(codec.shards_needed(present), expected.len(), "{present:?}");
+            let suggested = codec.suggest_shards(present)?;
+            assert_eq!(suggested, expected, "{present:?}");
+            fetch(&codec, present, &suggested)?;
+        }
+        assert_eq!(codec.shards_needed(&[3, 3, 42]), 5);
+        assert!(codec.suggest_shards(&[3, 3]).is_err());
+        assert!(codec.suggest_shards(&[10]).is_err());
+
+        // Two proportional parity rows count as one: a data shard is still
+        // needed although k shards are present.
+        let codec = Codec::with_matrix(2, 2, vec![vec![1, 1], vec![2, 2]])?;
+        assert_eq!(codec.shards_needed(&[2, 3]), 0);
+        assert_eq!(codec.suggest_shards(&[2, 3])?, [0]);
+        fetch(&codec, &[2, 3], &[0])?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_singular_matrix_inversion() {
+        let gf = Gf256::new();
+        let singular_matrix = vec![vec![1, 1], vec![2, 2]];
+        let result = invert_matrix(&gf, &singular_matrix);
+        assert!(result.is_err());
+    }
+}
+
+/// Tests of the `lean` path that need no part of the full build, so a
+/// `--no-default-features --features lean` build runs them too.
+#[cfg(all(test, feature = "lean"))]
+mod lean_tests {
+    use crate::{
+        error::{EXIT_CORRUPTION, EXIT_INVALID_ARGS, exit_code},
+        io::{
+            files::shard_path,
+            lean::{LeanMetadata, decode_file, encode_file},
+        },
+        test_support::{datasets, random_input, test_seed},
+    };
+    use anyhow::Result;
+    use std::fs;
+
+    #[test]
+    fn test_lean_roundtrip_rebuilds_lost_and_short_shards() -> Result<()> {
+        let (k, m) = (5, 3);
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let dataset = datasets(1, 7001, test_seed()).remove(3);
+        let data = dataset.shards.concat();
+        fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        let meta = encode_file(&input, &shards, k, m)?;
+        assert_eq!((meta.orig_len, meta.data_shards), (data.len(), k));
+
+        // Every file was renamed into place; no temporary is left behind.
+        let mut names: Vec<String> = fs::read_dir(&shards)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<_>>()?;
+        names.sort();
+        assert_eq!(names.len(), k + m + 1, "{names:?}");
+        assert!(names.iter().all(|name| !name.ends_with(".tmp")));
+
+        fs::remove_file(shard_path(&shards, 0))?;
+        fs::remove_file(shard_path(&shards, 6))?;
+        let short = fs::read(shard_path(&shards, 2))?;
+        fs::write(shard_path(&shards, 2), &short[..short.len() - 1])?;
+        let output = dir.path().join("output.bin");
+        assert_eq!(decode_file(&shards, &output)?, data.len());
+        assert_eq!(fs::read(&output)?, data, "{}", dataset.name);
+
+        fs::remove_file(shard_path(&shards, 1))?;
+        assert!(decode_file(&shards, &output).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lean_rejects_unknown_metadata_and_corrupt_shards() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("input.bin");
+        let data = random_input(3000, test_seed());
+        fs::write(&input, &data)?;
+        let shards = dir.path().join("shards");
+        encode_file(&input, &shards, 4, 2)?;
+        let output = dir.path().join("output.bin");
+
+        // A same-sized but damaged shard is not routed around; the input
+        // hash catches it.
+        let mut shard = fs::read(shard_path(&shards, 1))?;
+        shard[10] ^= 0xff;
+        fs::write(shard_path(&shards, 1), &shard)?;
+        let err = decode_file(&shards, &output).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_CORRUPTION, "{err:#}");
+        assert!(!output.exists());
+
+        // Keys that change how a set decodes need the full build; keys that
+        // do not are ignored.
+        let meta_path = shards.join("meta.json");
+        let mut meta: LeanMetadata = serde_json::from_slice(&fs::read(&meta_path)?)?;
+        shard[10] ^= 0xff;
+        fs::write(shard_path(&shards, 1), &shard)?;
+        meta.other
+            .insert("checksums".into(), serde_json::json!(null));
+        fs::write(&meta_path, serde_json::to_vec(&meta)?)?;
+        assert_eq!(decode_file(&shards, &output)?, data.len());
+        meta.other.insert("compression".into(), "zstd".into());
+        fs::write(&meta_path, serde_json::to_vec(&meta)?)?;
+        let err = decode_file(&shards, &output).unwrap_err();
+        assert_eq!(exit_code(&err), EXIT_INVALID_ARGS, "{err:#}");
+        Ok(())
+    }
+}
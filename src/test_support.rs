@@ -0,0 +1,103 @@
+//! Reproducible shard data for tests.
+//!
+//! Every dataset is derived from one seed, taken from `RSE_TEST_SEED` when set
+//! and fixed otherwise. The seed is printed so a failing run can be replayed
+//! exactly with `RSE_TEST_SEED=<seed> cargo test`.
+
+use crate::algorithm::shuffle::splitmix64;
+
+const DEFAULT_SEED: u64 = 0x5eed_f00d;
+
+/// Seed for this test run.
+pub fn test_seed() -> u64 {
+    let seed = std::env::var("RSE_TEST_SEED")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_SEED);
+    eprintln!("test data seed: {} (set RSE_TEST_SEED to reproduce)", seed);
+    seed
+}
+
+/// Content of one generated shard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    Zeros,
+    Ones,
+    Random,
+    /// `(shard + 1) * offset`, the pattern the roundtrip test started with.
+    Ramp,
+}
+
+/// `k` shards of `shard_len` bytes.
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    pub name: String,
+    pub shards: Vec<Vec<u8>>,
+}
+
+pub fn shard(pattern: Pattern, index: usize, shard_len: usize, state: &mut u64) -> Vec<u8> {
+    match pattern {
+        Pattern::Zeros => vec![0; shard_len],
+        Pattern::Ones => vec![0xff; shard_len],
+        Pattern::Random => (0..shard_len.div_ceil(8))
+            .flat_map(|_| splitmix64(state).to_le_bytes())
+            .take(shard_len)
+            .collect(),
+        Pattern::Ramp => (0..shard_len)
+            .map(|j| ((index + 1) as u8).wrapping_mul(j as u8))
+            .collect(),
+    }
+}
+
+/// Uniform datasets of each pattern, plus one mixing all patterns across
+/// shards in a seed-chosen order.
+pub fn datasets(k: usize, shard_len: usize, seed: u64) -> Vec<Dataset> {
+    let mut state = seed;
+    let patterns = [
+        Pattern::Zeros,
+        Pattern::Ones,
+        Pattern::Random,
+        Pattern::Ramp,
+    ];
+    let mut sets: Vec<Dataset> = patterns
+        .iter()
+        .map(|&pattern| Dataset {
+            name: format!("{:?}", pattern),
+            shards: (0..k)
+                .map(|i| shard(pattern, i, shard_len, &mut state))
+                .collect(),
+        })
+        .collect();
+    let mixed = (0..k)
+        .map(|i| {
+            let pattern = patterns[(splitmix64(&mut state) % patterns.len() as u64) as usize];
+            shard(pattern, i, shard_len, &mut state)
+        })
+        .collect();
+    sets.push(Dataset {
+        name: "Mixed".into(),
+        shards: mixed,
+    });
+    sets
+}
+
+/// The shards of the `Random` dataset alone.
+pub fn random_shards(k: usize, shard_len: usize, seed: u64) -> Vec<Vec<u8>> {
+    let mut state = seed;
+    (0..k)
+        .map(|i| shard(Pattern::Random, i, shard_len, &mut state))
+        .collect()
+}
+
+/// The shards of the `Mixed` dataset alone.
+pub fn mixed_shards(k: usize, shard_len: usize, seed: u64) -> Vec<Vec<u8>> {
+    datasets(k, shard_len, seed)
+        .pop()
+        .expect("the mixed dataset comes last")
+        .shards
+}
+
+/// `len` random bytes, e.g. the content of an input file.
+pub fn random_input(len: usize, seed: u64) -> Vec<u8> {
+    random_shards(1, len, seed).concat()
+}